@@ -0,0 +1,7 @@
+fn main() {
+    // modules loaded at run time link against the runtime's own `hl_*`
+    // functions, which executables don't export otherwise
+    if std::env::var_os("CARGO_FEATURE_DYNLOAD").is_some() {
+        println!("cargo:rustc-link-arg-bins=-rdynamic");
+    }
+}
@@ -0,0 +1,96 @@
+/* The runtime's entry points for compiled code, see src/ffi.rs.
+ *
+ * Nodes live on the state's stack: arguments are pushed last first, and
+ * builders pop what they are built from, the first on top. Calls that can
+ * fail return HL_OK or HL_ERROR, the error kept by the state; a native
+ * function given HL_ERROR returns it in turn, leaving the stack as it is.
+ * Strings are UTF-8, with their length in bytes. */
+
+#ifndef HASKELITE_H
+#define HASKELITE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define HL_OK 0
+#define HL_ERROR 1
+
+typedef struct hl_state hl_state;
+
+/* A builtin, as found by hl_builtin. */
+typedef struct hl_builtin hl_builtin_t;
+
+/* A function compiled to native code. It finds its arguments on top of
+ * the stack, the first on top, and replaces them with its result. */
+typedef int32_t (*hl_native_fn)(hl_state *state);
+
+/* The definition of a native function, living as long as the program.
+ * The name goes last, NUL-terminated, for the runtime to find the
+ * definition by. */
+typedef struct hl_fn_def {
+    hl_native_fn fn;
+    size_t arity;
+    /* How many leading arguments are forced before the function is entered */
+    size_t strict;
+    char name[];
+} hl_fn_def;
+
+hl_state *hl_state_new(void);
+void hl_state_free(hl_state *state);
+
+/* The message of the last error, valid until the next call, or NULL */
+const char *hl_last_error(hl_state *state);
+/* Raises an `error` call, for a native function to return HL_ERROR with */
+int32_t hl_fail(hl_state *state, const char *msg, size_t len);
+
+void hl_push_int(hl_state *state, int64_t value);
+void hl_push_double(hl_state *state, double value);
+int32_t hl_push_char(hl_state *state, uint32_t code_point);
+void hl_push_bool(hl_state *state, bool value);
+void hl_push_nil(hl_state *state);
+void hl_push_unit(hl_state *state);
+int32_t hl_push_str(hl_state *state, const char *str, size_t len);
+int32_t hl_push_fn(hl_state *state, const hl_fn_def *def);
+
+/* The builtin of the Haskell name, or NULL */
+const hl_builtin_t *hl_builtin(const char *name, size_t len);
+void hl_push_builtin(hl_state *state, const hl_builtin_t *builtin);
+
+int32_t hl_register_global(hl_state *state, const hl_fn_def *def);
+int32_t hl_push_global(hl_state *state, const char *name, size_t len);
+
+void hl_mk_ap(hl_state *state);
+void hl_mk_thunk(hl_state *state);
+void hl_mk_cons(hl_state *state);
+void hl_mk_data(hl_state *state, uint32_t tag, size_t arity);
+void hl_mk_tuple(hl_state *state, size_t arity);
+int32_t hl_mk_pap(hl_state *state, const hl_fn_def *def, size_t arity);
+
+void hl_stack_pop(hl_state *state);
+void hl_stack_swap(hl_state *state);
+size_t hl_stack_size(hl_state *state);
+/* Pushes a copy of the node n below the top, 0 for the top */
+void hl_stack_dup(hl_state *state, size_t n);
+/* Moves the node n below the top to the top */
+void hl_stack_bring_up(hl_state *state, size_t n);
+
+/* Reduces the node on top of the stack to WHNF in place */
+int32_t hl_eval(hl_state *state);
+int32_t hl_deep_eval(hl_state *state);
+/* Replaces the node on top of the stack with its fields, the first on top */
+int32_t hl_case_split(hl_state *state, uint32_t *tag);
+/* Replaces the IO action on top of the stack with its result */
+int32_t hl_run_io(hl_state *state);
+
+/* Force the node on top of the stack, leaving it there */
+int32_t hl_peek_int(hl_state *state, int64_t *value);
+int32_t hl_peek_double(hl_state *state, double *value);
+int32_t hl_peek_char(hl_state *state, uint32_t *code_point);
+int32_t hl_peek_bool(hl_state *state, bool *value);
+
+/* Exported by a module for the runtime to load, see src/dynload.rs: it
+ * registers the module's definitions and returns HL_OK. */
+int32_t hl_module_init(hl_state *state);
+
+#endif
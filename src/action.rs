@@ -0,0 +1,415 @@
+use crate::builtins::*;
+use crate::gc::*;
+#[cfg(feature = "std")]
+use crate::handle::eprint_line;
+use crate::exception::is_catchable;
+
+// `IO a` values are descriptions of actions, built by pure code and only
+// performed by `State::run_io`, which runs them one after the other in
+// the order the binds put them in.
+
+pub enum IoAction {
+    /// `return x`, performing nothing.
+    Return(Node),
+    /// `m >>= k`, performing `m` and then the action `k` makes of its result.
+    Bind(Node, Node),
+    /// An effect performed by running `fn_def` on `args`, in application order,
+    /// leaving its result on the stack.
+    Prim(FnDef, Gc<Fields>),
+    /// `catch m handler`, performing `m` and, should it throw, the action
+    /// `handler` makes of the exception instead.
+    Catch(Node, Node)
+}
+
+impl Trace for IoAction {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        match self {
+            IoAction::Return(node) => node.mark_refs(worklist),
+            IoAction::Bind(m, k) | IoAction::Catch(m, k) => {
+                m.mark_refs(worklist);
+                k.mark_refs(worklist);
+            },
+            IoAction::Prim(_, args) => args.mark_into(worklist)
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        match self {
+            IoAction::Prim(fn_def, _) => ("IO action", Some(fn_def.name)),
+            _ => ("IO action", None)
+        }
+    }
+}
+
+impl State {
+    pub fn push_io(&mut self, action: IoAction) {
+        let action = self.alloc(action);
+        self.stack_push(Node::IO(action));
+    }
+
+    /// Pops the `fn_def.arity` arguments of a primitive IO action off the
+    /// stack (first argument on top) and pushes the action performing it.
+    pub fn mk_io_prim(&mut self, fn_def: FnDef) {
+        let args = self.alloc_fields(fn_def.arity);
+        self.push_io(IoAction::Prim(fn_def, args));
+    }
+
+    /// `()`, the result of actions performed only for their effect.
+    pub fn push_unit(&mut self) {
+        self.mk_tuple(0);
+    }
+
+    /// Performs the IO action `main` and everything it binds, returning
+    /// the result of the last one.
+    ///
+    /// Continuations waiting for the result of the action being performed
+    /// are kept in a frame of their own, so long chains of binds do not
+    /// grow the native stack. On failure the stacks are restored to how
+    /// they were before the call, with the effects performed so far done.
+    /// Either way stdout and stderr are flushed before returning.
+    pub fn run_io(&mut self, main: Node) -> EvalResult<Node> {
+        let depth = self.stacks.len();
+        self.stack_enter_new();
+        self.stack_push(main);
+        let result = self.run_io_frame();
+        match &result {
+            Ok(_) => self.stack_exit(),
+            Err(_) => self.stacks.truncate(depth)
+        }
+        // what the program wrote has to be out by the time it returns
+        let flushed = self.flush_std_handles();
+        result.and_then(|result| flushed.map(|_| result))
+    }
+
+    /// Without `std` there are no handles to flush.
+    #[cfg(not(feature = "std"))]
+    pub fn flush_std_handles(&mut self) -> EvalResult {
+        Ok(())
+    }
+
+    /// Runs the action on top of the current frame, the continuations
+    /// waiting for it below. An exception thrown while performing it
+    /// unwinds the frame to the innermost `catch` around it, if any.
+    ///
+    /// The outermost driver also runs the threads the program forks, the
+    /// frame belonging to whichever thread is running, until the main
+    /// thread is done. Nested ones only run the action they were given.
+    fn run_io_frame(&mut self) -> EvalResult<Node> {
+        let depth = self.stacks.len();
+        let scheduling = self.start_scheduling();
+        let result = self.run_io_threads(depth, scheduling);
+        if scheduling {
+            self.stop_scheduling();
+        }
+        result
+    }
+
+    fn run_io_threads(&mut self, depth: usize, scheduling: bool) -> EvalResult<Node> {
+        // an exception to raise in place of the running thread's action
+        let mut pending = None;
+        loop {
+            let waiting = self.stack_size() - 1;
+            let err = match pending.take() {
+                Some(err) => err,
+                None => {
+                    match self.perform_io_step() {
+                        Ok(Some(result)) if !scheduling || self.on_main_thread() => return Ok(result),
+                        Ok(Some(_)) => {
+                            // a forked thread is done
+                            pending = self.resume_thread(depth);
+                            continue;
+                        },
+                        Ok(None) => match self.scheduler.blocked.take() {
+                            Some(blocked) if scheduling => {
+                                // the action is tried again once the thread can go on
+                                self.stacks[depth - 1].truncate(waiting + 1);
+                                self.suspend_thread(depth, Some(blocked));
+                                pending = self.resume_thread(depth);
+                                continue;
+                            },
+                            Some(blocked) => blocked.error(),
+                            None => {
+                                if scheduling {
+                                    self.tick_thread(depth);
+                                }
+                                continue;
+                            }
+                        },
+                        Err(err) => err
+                    }
+                }
+            };
+
+            self.stacks.truncate(depth);
+            self.stacks[depth - 1].truncate(waiting);
+            if let Err(err) = self.unwind_io(err) {
+                // cancelling stops the whole program, not just the thread
+                if !scheduling || self.on_main_thread() || *err.cause() == EvalError::Cancelled {
+                    return Err(err);
+                }
+                // an uncaught exception only ends the thread it was thrown in
+                #[cfg(feature = "std")]
+                if !matches!(err.cause(), EvalError::BlockedIndefinitely | EvalError::BlockedIndefinitelyOnStm) {
+                    eprint_line(&format!("runtime: {}", self.uncaught(err)));
+                }
+                pending = self.resume_thread(depth);
+            }
+        }
+    }
+
+    /// Performs one step of the action on top of the current frame,
+    /// returning the result of the whole once no continuations are left.
+    fn perform_io_step(&mut self) -> EvalResult<Option<Node>> {
+        self.eval()?;
+        let action = match *self.stack_peek() {
+            Node::IO(action) => action,
+            node => return Err(EvalError::type_mismatch("run_io", "IO action", &node))
+        };
+
+        match *action {
+            IoAction::Return(value) => {
+                self.stack_pop();
+                self.stack_push(value);
+            },
+            IoAction::Bind(m, k) => {
+                self.stack_pop();
+                self.stack_push(k);
+                self.stack_push(m);
+                return Ok(None);
+            },
+            IoAction::Prim(fn_def, args) => {
+                // the action stays below its arguments while it is performed,
+                // to be tried again if it blocks
+                for arg in args.iter().rev() {
+                    self.stack_push(*arg);
+                }
+                self.call(&fn_def)?;
+                if self.scheduler.blocked.is_some() {
+                    return Ok(None);
+                }
+                self.stack_swap();
+                self.stack_pop();
+            },
+            IoAction::Catch(m, _) => {
+                // the action stays below `m`, marking where to unwind to
+                self.stack_push(m);
+                return Ok(None);
+            }
+        }
+
+        // the action's result is on top, pass it to the next continuation,
+        // past the handlers of the catches it returned out of
+        loop {
+            if self.stack_size() == 1 {
+                return Ok(Some(self.stack_pop()));
+            }
+            if !is_catch(self.stack_peek_nth(1)) {
+                break;
+            }
+            self.stack_swap();
+            self.stack_pop();
+        }
+        self.stack_swap();
+        self.mk_ap();
+        Ok(None)
+    }
+
+    /// Hands the exception `err` to the handler of the innermost `catch`
+    /// waiting in the current frame, dropping the continuations above it,
+    /// or fails with it if there is none or it cannot be caught.
+    fn unwind_io(&mut self, err: EvalError) -> EvalResult {
+        let catch = self.stacks.last().unwrap().iter().rposition(is_catch);
+        let Some(catch) = catch.filter(|_| is_catchable(&err)) else {
+            return Err(err);
+        };
+        self.stacks.last_mut().unwrap().truncate(catch + 1);
+        let handler = match *self.stack_peek() {
+            Node::IO(action) => match *action {
+                IoAction::Catch(_, handler) => handler,
+                _ => unreachable!()
+            },
+            _ => unreachable!()
+        };
+        self.push_exception(err);
+        self.stack_push(handler);
+        self.mk_ap();
+        // the handler's action takes the place of the catch
+        self.stack_swap();
+        self.stack_pop();
+        Ok(())
+    }
+}
+
+/// Whether a continuation is the mark a `catch` leaves while its action runs.
+fn is_catch(node: &Node) -> bool {
+    match node {
+        Node::IO(action) => matches!(**action, IoAction::Catch(..)),
+        _ => false
+    }
+}
+
+pub static FN_RETURN_IO: FnDef = FnDef {
+    name: "return",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_return_io
+};
+
+pub static FN_BIND_IO: FnDef = FnDef {
+    name: ">>=",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_bind_io
+};
+
+pub static FN_THEN_IO: FnDef = FnDef {
+    name: ">>",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_then_io
+};
+
+/// `\_ -> n`, the continuation of `m >> n`.
+static FN_THEN_CONT: FnDef = FnDef {
+    name: ">>",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_then_cont
+};
+
+pub fn eval_return_io(state: &mut State) -> EvalResult {
+    let value = state.stack_pop();
+    state.push_io(IoAction::Return(value));
+    Ok(())
+}
+
+pub fn eval_bind_io(state: &mut State) -> EvalResult {
+    let m = state.stack_pop();
+    let k = state.stack_pop();
+    state.push_io(IoAction::Bind(m, k));
+    Ok(())
+}
+
+pub fn eval_then_io(state: &mut State) -> EvalResult {
+    state.stack_swap();
+    state.mk_pap(FN_THEN_CONT, 1);
+    let k = state.stack_pop();
+    let m = state.stack_pop();
+    state.push_io(IoAction::Bind(m, k));
+    Ok(())
+}
+
+fn eval_then_cont(state: &mut State) -> EvalResult {
+    let n = state.stack_pop();
+    state.stack_pop();
+    state.stack_push(n);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FN_RETURN_SUCC: FnDef = FnDef {
+        name: "returnSucc",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_return_succ
+    };
+
+    /// `\x -> return (x + 1)`
+    fn eval_return_succ(state: &mut State) -> EvalResult {
+        state.push_int(1);
+        state.stack_swap();
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        Ok(())
+    }
+
+    static FN_COUNTDOWN: FnDef = FnDef {
+        name: "countdown",
+        arity: 1,
+        strict: 1,
+        fn_ref: eval_countdown
+    };
+
+    /// `countdown n = if n == 0 then return 0 else return () >> countdown (n - 1)`
+    fn eval_countdown(state: &mut State) -> EvalResult {
+        let Node::Int(n) = state.stack_pop() else {
+            unreachable!()
+        };
+        if n == 0 {
+            state.push_int(0);
+            state.push_fn(FN_RETURN_IO);
+            state.mk_ap();
+            return Ok(());
+        }
+        state.push_int(n - 1);
+        state.push_fn(FN_COUNTDOWN);
+        state.mk_ap();
+        state.push_unit();
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        state.push_fn(FN_THEN_IO);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    #[test]
+    fn binds_pass_results_on() {
+        let mut state = State::new();
+        // return 1 >>= returnSucc >>= returnSucc
+        state.push_fn(FN_RETURN_SUCC);
+        state.push_fn(FN_RETURN_SUCC);
+        state.push_int(1);
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let result = state.run_io(main).and_then(|result| state.try_eval(result));
+        assert!(matches!(result, Ok(Node::Int(3))));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn long_chains_of_binds_need_no_native_stack() {
+        let run = || {
+            let mut state = State::new();
+            state.push_int(10_000);
+            state.push_fn(FN_COUNTDOWN);
+            state.mk_ap();
+            let main = state.stack_pop();
+            matches!(state.run_io(main), Ok(Node::Int(0)))
+        };
+        let thread = std::thread::Builder::new().stack_size(32 * 1024).spawn(run);
+        assert!(thread.expect("a thread").join().expect("no overflow"));
+    }
+
+    #[test]
+    fn failures_restore_the_stacks() {
+        let mut state = State::new();
+        state.push_int(7);
+        // return 1 >> 'c'
+        state.push_char('c');
+        state.push_int(1);
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        state.push_fn(FN_THEN_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let err = state.run_io(main).expect_err("not an action");
+        assert!(matches!(err.cause(), EvalError::TypeMismatch { context: "run_io", .. }));
+        assert_eq!(state.stack_size(), 1);
+        assert!(matches!(state.stack_peek(), Node::Int(7)));
+    }
+}
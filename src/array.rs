@@ -0,0 +1,438 @@
+use core::convert::TryFrom;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use crate::builtins::*;
+use crate::gc::*;
+
+// Arrays are indexed by `Int`s between the bounds they were made with,
+// both included, and hold their elements boxed, i.e. unevaluated until
+// they are read and forced. `IOArray`s are written in place by IO actions,
+// immutable `Array`s are made all at once from a list and only read.
+
+pub struct Array {
+    pub lo: i64,
+    pub hi: i64,
+    pub elems: Vec<Node>
+}
+
+impl Array {
+    /// An array of `elem` repeated over the bounds, empty if `hi < lo`.
+    pub fn new(lo: i64, hi: i64, elem: Node) -> EvalResult<Self> {
+        let len = if hi < lo {
+            0
+        } else {
+            hi.checked_sub(lo)
+                .and_then(|span| usize::try_from(span).ok())
+                .and_then(|span| span.checked_add(1))
+                .ok_or_else(|| EvalError::Error(format!("array bounds too large: ({},{})", lo, hi)))?
+        };
+        Ok(Array { lo, hi, elems: vec![elem; len] })
+    }
+
+    /// Where the element at index `i` is kept, failing like `Ix`'s `index`
+    /// if it is out of the bounds.
+    pub fn offset(&self, i: i64) -> EvalResult<usize> {
+        if i < self.lo || i > self.hi {
+            return Err(EvalError::Error(
+                format!("Ix{{Int}}.index: Index ({}) out of range (({},{}))", i, self.lo, self.hi)));
+        }
+        Ok((i - self.lo) as usize)
+    }
+}
+
+impl Trace for Array {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        for elem in self.elems.iter() {
+            elem.mark_refs(worklist);
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("array", None)
+    }
+}
+
+impl State {
+    /// Forces the `(lo, hi)` pair of `Int` bounds on top of the stack, popping it.
+    pub fn pop_bounds(&mut self, context: &'static str) -> EvalResult<(i64, i64)> {
+        self.eval()?;
+        let bounds = match self.stack_pop() {
+            Node::Tuple(fields) if fields.len() == 2 => fields,
+            node => return Err(EvalError::type_mismatch(context, "pair of bounds", &node))
+        };
+        self.stack_push(bounds[1]);
+        self.stack_push(bounds[0]);
+        let lo = self.pop_index(context)?;
+        let hi = self.pop_index(context)?;
+        Ok((lo, hi))
+    }
+
+    /// Forces the `Int` index on top of the stack, popping it.
+    pub fn pop_index(&mut self, context: &'static str) -> EvalResult<i64> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::Int(i) => Ok(i),
+            node => Err(EvalError::type_mismatch(context, "Int", &node))
+        }
+    }
+
+    pub fn push_bounds(&mut self, lo: i64, hi: i64) {
+        self.push_int(hi);
+        self.push_int(lo);
+        self.mk_tuple(2);
+    }
+
+    /// Forces the `Array` on top of the stack, leaving it there, and gives it.
+    fn peek_array(&mut self, context: &'static str) -> EvalResult<Gc<Array>> {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::Array(array) => Ok(array),
+            node => Err(EvalError::type_mismatch(context, "array", &node))
+        }
+    }
+
+    /// Forces the `IOArray` on top of the stack, leaving it there, and gives it.
+    fn peek_io_array(&mut self, context: &'static str) -> EvalResult<Gc<Array>> {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::IOArray(array) => Ok(array),
+            node => Err(EvalError::type_mismatch(context, "IOArray", &node))
+        }
+    }
+}
+
+pub static FN_LIST_ARRAY: FnDef = FnDef {
+    name: "listArray",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_list_array
+};
+
+pub static FN_INDEX: FnDef = FnDef {
+    name: "!",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_index
+};
+
+pub static FN_BOUNDS: FnDef = FnDef {
+    name: "bounds",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_bounds
+};
+
+pub static FN_ELEMS: FnDef = FnDef {
+    name: "elems",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_elems
+};
+
+/// Stands for the elements `listArray` was not given enough of.
+static FN_UNDEFINED_ELEMENT: FnDef = FnDef {
+    name: "listArray",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_undefined_element
+};
+
+pub static FN_NEW_ARRAY: FnDef = FnDef {
+    name: "newArray",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_new_array
+};
+
+pub static FN_READ_ARRAY: FnDef = FnDef {
+    name: "readArray",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_read_array
+};
+
+pub static FN_WRITE_ARRAY: FnDef = FnDef {
+    name: "writeArray",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_write_array
+};
+
+pub static FN_GET_BOUNDS: FnDef = FnDef {
+    name: "getBounds",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_get_bounds
+};
+
+static IO_NEW_ARRAY: FnDef = FnDef {
+    name: "newArray",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_new_array
+};
+
+static IO_READ_ARRAY: FnDef = FnDef {
+    name: "readArray",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_read_array
+};
+
+static IO_WRITE_ARRAY: FnDef = FnDef {
+    name: "writeArray",
+    arity: 3,
+    strict: 0,
+    fn_ref: perform_write_array
+};
+
+static IO_GET_BOUNDS: FnDef = FnDef {
+    name: "getBounds",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_get_bounds
+};
+
+/// Forces the list's spine as far as the bounds go, but none of the elements.
+pub fn eval_list_array(state: &mut State) -> EvalResult {
+    let (lo, hi) = state.pop_bounds("listArray")?;
+    // the array is filled in on the stack, keeping what it has been given rooted
+    let mut array = state.alloc(Array::new(lo, hi, Node::FnDef(FN_UNDEFINED_ELEMENT))?);
+    state.stack_push(Node::Array(array));
+    state.stack_swap();
+    for i in 0..array.elems.len() {
+        state.eval()?;
+        match state.stack_pop() {
+            Node::Cons(head, tail) => {
+                array.as_mut().elems[i] = *head;
+                state.stack_push(*tail);
+            },
+            Node::Nil => {
+                state.push_nil();
+                break;
+            },
+            node => return Err(EvalError::type_mismatch("listArray", "list", &node))
+        }
+    }
+    // what is left of the list
+    state.stack_pop();
+    Ok(())
+}
+
+fn eval_undefined_element(_state: &mut State) -> EvalResult {
+    Err(EvalError::Error("(Array.!): undefined array element".to_string()))
+}
+
+pub fn eval_index(state: &mut State) -> EvalResult {
+    let array = state.peek_array("!")?;
+    state.stack_swap();
+    let i = state.pop_index("!")?;
+    state.stack_pop();
+    state.stack_push(array.elems[array.offset(i)?]);
+    Ok(())
+}
+
+pub fn eval_bounds(state: &mut State) -> EvalResult {
+    let array = state.peek_array("bounds")?;
+    state.stack_pop();
+    state.push_bounds(array.lo, array.hi);
+    Ok(())
+}
+
+pub fn eval_elems(state: &mut State) -> EvalResult {
+    let array = state.peek_array("elems")?;
+    state.push_nil();
+    for elem in array.elems.iter().rev() {
+        state.stack_push(*elem);
+        state.mk_cons();
+    }
+    state.stack_swap();
+    state.stack_pop();
+    Ok(())
+}
+
+pub fn eval_new_array(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_NEW_ARRAY);
+    Ok(())
+}
+
+pub fn eval_read_array(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_READ_ARRAY);
+    Ok(())
+}
+
+pub fn eval_write_array(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_WRITE_ARRAY);
+    Ok(())
+}
+
+pub fn eval_get_bounds(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_BOUNDS);
+    Ok(())
+}
+
+/// Every element starts out as the same initial value, unevaluated.
+fn perform_new_array(state: &mut State) -> EvalResult {
+    let (lo, hi) = state.pop_bounds("newArray")?;
+    let elem = state.stack_pop();
+    let array = Array::new(lo, hi, elem)?;
+    let array = state.alloc(array);
+    state.stack_push(Node::IOArray(array));
+    Ok(())
+}
+
+fn perform_read_array(state: &mut State) -> EvalResult {
+    let array = state.peek_io_array("readArray")?;
+    state.stack_swap();
+    let i = state.pop_index("readArray")?;
+    state.stack_pop();
+    state.stack_push(array.elems[array.offset(i)?]);
+    Ok(())
+}
+
+fn perform_write_array(state: &mut State) -> EvalResult {
+    let mut array = state.peek_io_array("writeArray")?;
+    state.stack_swap();
+    let i = state.pop_index("writeArray")?;
+    state.stack_pop();
+    let offset = array.offset(i)?;
+    array.as_mut().elems[offset] = state.stack_pop();
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_get_bounds(state: &mut State) -> EvalResult {
+    let array = state.peek_io_array("getBounds")?;
+    state.stack_pop();
+    state.push_bounds(array.lo, array.hi);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    /// Performs the action on top of the stack, popping it, and renders the result.
+    fn perform(state: &mut State) -> EvalResult<String> {
+        let action = state.stack_pop();
+        let result = state.run_io(action)?;
+        state.render(result)
+    }
+
+    /// Pushes `fn_def arr`, for the `IOArray` `n` below the top, applied to
+    /// the values given.
+    fn push_array_action(state: &mut State, fn_def: FnDef, n: usize, args: &[&str]) {
+        for arg in args.iter().rev() {
+            push_value(state, arg);
+        }
+        state.push(*state.stack_peek_nth(n + args.len()));
+        state.push_fn(fn_def);
+        for _ in 0..=args.len() {
+            state.mk_ap();
+        }
+    }
+
+    #[test]
+    fn io_arrays_are_written_in_place() {
+        let mut state = State::new();
+        push_value(&mut state, "'x'");
+        push_value(&mut state, "(0,4)");
+        state.push_fn(FN_NEW_ARRAY);
+        state.mk_ap();
+        state.mk_ap();
+        let action = state.stack_pop();
+        let array = state.run_io(action).expect("an array");
+        state.push(array);
+
+        push_array_action(&mut state, FN_WRITE_ARRAY, 0, &["2", "'y'"]);
+        assert_eq!(perform(&mut state).ok().as_deref(), Some("()"));
+        push_array_action(&mut state, FN_READ_ARRAY, 0, &["2"]);
+        assert_eq!(perform(&mut state).ok().as_deref(), Some("'y'"));
+        push_array_action(&mut state, FN_READ_ARRAY, 0, &["0"]);
+        assert_eq!(perform(&mut state).ok().as_deref(), Some("'x'"));
+        push_array_action(&mut state, FN_GET_BOUNDS, 0, &[]);
+        assert_eq!(perform(&mut state).ok().as_deref(), Some("(0,4)"));
+
+        push_array_action(&mut state, FN_READ_ARRAY, 0, &["5"]);
+        let err = perform(&mut state).expect_err("out of range");
+        assert_eq!(err.cause().to_string(), "Ix{Int}.index: Index (5) out of range ((0,4))");
+        assert_eq!(state.stack_size(), 1);
+
+        assert!(Array::new(i64::MIN, i64::MAX, Node::Nil).is_err());
+        assert!(Array::new(1, 0, Node::Nil).expect("an empty array").elems.is_empty());
+    }
+
+    /// Pushes `listArray bounds xs`, for the list on top of the stack.
+    fn mk_list_array(state: &mut State, bounds: &str) {
+        push_value(state, bounds);
+        state.push_fn(FN_LIST_ARRAY);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+    }
+
+    /// `arr ! i`, for the array on top of the stack, which is kept.
+    fn index(state: &mut State, i: i64) -> EvalResult<String> {
+        state.push_int(i);
+        state.push(*state.stack_peek_nth(1));
+        state.push_fn(FN_INDEX);
+        state.mk_ap();
+        state.mk_ap();
+        let elem = state.stack_pop();
+        state.render(elem)
+    }
+
+    /// Applies `fn_def` to the array on top of the stack, which is kept.
+    fn apply_to_array(state: &mut State, fn_def: FnDef) -> EvalResult<String> {
+        state.push(*state.stack_peek());
+        state.push_fn(fn_def);
+        state.apply(1)?;
+        let result = state.stack_pop();
+        state.render(result)
+    }
+
+    /// Pushes `error msg`.
+    fn push_error(state: &mut State, msg: &str) {
+        push_value(state, &format!("{:?}", msg));
+        state.push_fn(FN_ERROR);
+        state.mk_ap();
+    }
+
+    #[test]
+    fn arrays_are_indexed_between_their_bounds() {
+        let mut state = State::new();
+        push_value(&mut state, "[10,20,30,40]");
+        mk_list_array(&mut state, "(1,3)");
+        assert_eq!(index(&mut state, 1).ok().as_deref(), Some("10"));
+        assert_eq!(index(&mut state, 3).ok().as_deref(), Some("30"));
+        let err = index(&mut state, 4).expect_err("out of range");
+        assert_eq!(err.cause().to_string(), "Ix{Int}.index: Index (4) out of range ((1,3))");
+        assert_eq!(apply_to_array(&mut state, FN_BOUNDS).ok().as_deref(), Some("(1,3)"));
+        assert_eq!(apply_to_array(&mut state, FN_ELEMS).ok().as_deref(), Some("[10,20,30]"));
+    }
+
+    #[test]
+    fn elements_are_lazy_and_the_spine_is_forced_to_the_bounds() {
+        let mut state = State::new();
+        // 1 : error "element" : error "spine", for two elements
+        push_error(&mut state, "spine");
+        push_error(&mut state, "element");
+        state.mk_cons();
+        state.push_int(1);
+        state.mk_cons();
+        mk_list_array(&mut state, "(0,1)");
+        assert_eq!(index(&mut state, 0).ok().as_deref(), Some("1"));
+        let err = index(&mut state, 1).expect_err("an undefined element");
+        assert_eq!(err.cause().to_string(), "element");
+        state.stack_pop();
+
+        // [1], for two elements
+        push_value(&mut state, "[1]");
+        mk_list_array(&mut state, "(0,1)");
+        assert_eq!(index(&mut state, 0).ok().as_deref(), Some("1"));
+        let err = index(&mut state, 1).expect_err("a missing element");
+        assert_eq!(err.cause().to_string(), "(Array.!): undefined array element");
+    }
+}
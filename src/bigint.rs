@@ -0,0 +1,121 @@
+// `Integer`'s arbitrary-precision arithmetic. With the `num-bigint`
+// feature, on by default, it is `num_bigint`'s; without it, e.g. to build
+// with no dependencies, a small implementation of the operations the
+// builtins use, schoolbook multiplication and Knuth's long division. Both
+// have the same API, and the tests below hold both to it.
+
+#[cfg(feature = "num-bigint")]
+mod num;
+#[cfg(feature = "num-bigint")]
+pub use self::num::BigInt;
+
+#[cfg(not(feature = "num-bigint"))]
+mod native;
+#[cfg(not(feature = "num-bigint"))]
+pub use self::native::BigInt;
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::convert::TryFrom;
+    use super::BigInt;
+
+    /// xorshift64*, enough to spread the cases over the digits' edges.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// Mostly values near the 32- and 64-bit boundaries and small ones.
+        fn i64(&mut self) -> i64 {
+            let n = self.next();
+            match n % 4 {
+                0 => (n >> 2) as i64 % 1000,
+                1 => (n >> 32) as i64 * if n & 4 == 0 { 1 } else { -1 },
+                2 => [i64::MIN, i64::MAX, u32::MAX as i64, -(u32::MAX as i64), 0, 1, -1][(n >> 8) as usize % 7],
+                _ => n as i64
+            }
+        }
+
+        /// A decimal number of up to 60 digits.
+        fn digits(&mut self) -> String {
+            let len = 1 + self.next() % 60;
+            let mut s: String = (0..len).map(|_| char::from(b'0' + (self.next() % 10) as u8)).collect();
+            if self.next().is_multiple_of(2) {
+                s.insert(0, '-');
+            }
+            s
+        }
+    }
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    fn from_i128(n: i128) -> BigInt {
+        big(&n.to_string())
+    }
+
+    #[test]
+    fn arithmetic_matches_i128() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..5000 {
+            let (a, b) = (rng.i64(), rng.i64());
+            let (ba, bb) = (BigInt::from(a), BigInt::from(b));
+            let (a, b) = (a as i128, b as i128);
+            assert_eq!(&ba + &bb, from_i128(a + b), "{} + {}", a, b);
+            assert_eq!(&ba - &bb, from_i128(a - b), "{} - {}", a, b);
+            assert_eq!(&ba * &bb, from_i128(a * b), "{} * {}", a, b);
+            assert_eq!(-&ba, from_i128(-a));
+            assert_eq!(ba.cmp(&bb), a.cmp(&b));
+            if b != 0 {
+                let (q, r) = ba.div_rem(&bb).unwrap();
+                assert_eq!((q, r), (from_i128(a / b), from_i128(a % b)), "{} divRem {}", a, b);
+            }
+            let product = &ba * &bb;
+            assert_eq!(product.to_i64(), i64::try_from(a * b).ok());
+            assert_eq!(product.to_i64_wrapping(), (a * b) as i64);
+            assert_eq!(ba.to_string(), a.to_string());
+        }
+    }
+
+    #[test]
+    fn long_division_inverts_multiplication() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let (a, b, r) = (big(&rng.digits()), big(&rng.digits()), big(&rng.digits()));
+            if b.is_zero() || r.abs() >= b.abs() {
+                continue;
+            }
+            // a * b + r has the sign of a * b when |r| < |b|, unless a is 0
+            if a.is_zero() || a.is_negative() != b.is_negative() || r.is_negative() {
+                continue;
+            }
+            let n = &(&a * &b) + &r;
+            assert_eq!(n.div_rem(&b), Some((a.clone(), r.clone())), "({}) * ({}) + {}", a, b, r);
+            assert_eq!(big(&n.to_string()), n);
+        }
+    }
+
+    #[test]
+    fn gcd_and_signs() {
+        assert_eq!(BigInt::from(-12).gcd(&BigInt::from(18)), BigInt::from(6));
+        assert_eq!(BigInt::zero().gcd(&BigInt::from(-5)), BigInt::from(5));
+        assert_eq!(BigInt::from(-7).signum(), BigInt::from(-1));
+        assert_eq!(BigInt::zero().signum(), BigInt::zero());
+        assert_eq!(BigInt::from(i64::MIN).abs().to_string(), "9223372036854775808");
+        assert!(!(-&BigInt::zero()).is_negative());
+        assert_eq!(BigInt::from(0).div_rem(&BigInt::zero()), None);
+        assert_eq!(big("-0"), BigInt::zero());
+        assert_eq!(big("123456789012345678901234567890").cmp(&big("-1")), Ordering::Greater);
+        assert_eq!(big("4294967296").digits(), vec![0, 1]);
+        assert!("+1".parse::<BigInt>().is_err());
+        assert!("1_000".parse::<BigInt>().is_err());
+        assert_eq!(big("18446744073709551616").to_f64(), 18446744073709551616.0);
+    }
+}
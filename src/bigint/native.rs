@@ -0,0 +1,406 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::{Add, Sub, Mul, Div, Rem, Neg};
+use core::str::FromStr;
+use core::convert::TryFrom;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
+/// An arbitrary-precision signed integer, stored as
+/// a sign and little-endian base 2^32 magnitude.
+///
+/// The magnitude never has trailing (most significant) zero digits,
+/// and zero is never negative.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>
+}
+
+const BASE: u64 = 1 << 32;
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt { negative: false, mag: Vec::new() }
+    }
+
+    /// The bytes taken up by the magnitude's digits.
+    pub fn heap_bytes(&self) -> usize {
+        self.mag.capacity() * core::mem::size_of::<u32>()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The magnitude's base 2^32 digits, least significant first.
+    pub fn digits(&self) -> Vec<u32> {
+        self.mag.clone()
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt { negative: false, mag: self.mag.clone() }
+    }
+
+    pub fn signum(&self) -> BigInt {
+        match (self.is_zero(), self.negative) {
+            (true, _) => BigInt::zero(),
+            (false, negative) => BigInt::from(if negative { -1 } else { 1 })
+        }
+    }
+
+    fn from_mag(negative: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let negative = negative && !mag.is_empty();
+        BigInt { negative, mag }
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let mag = self.low_u64() as i128;
+        i64::try_from(if self.negative { -mag } else { mag }).ok()
+    }
+
+    /// The value truncated to its low 64 bits (two's complement),
+    /// as Haskell's `fromInteger :: Integer -> Int` does.
+    pub fn to_i64_wrapping(&self) -> i64 {
+        let low = self.low_u64();
+        if self.negative {
+            (low as i64).wrapping_neg()
+        } else {
+            low as i64
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mag = self.mag.iter().rev().fold(0.0, |acc, digit| acc * BASE as f64 + *digit as f64);
+        if self.negative { -mag } else { mag }
+    }
+
+    fn low_u64(&self) -> u64 {
+        let lo = *self.mag.first().unwrap_or(&0) as u64;
+        let hi = *self.mag.get(1).unwrap_or(&0) as u64;
+        hi << 32 | lo
+    }
+
+    /// The greatest common divisor, always non-negative.
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let mut a = self.abs();
+        let mut b = other.abs();
+        while !b.is_zero() {
+            let (_, r) = a.div_rem(&b).unwrap();
+            a = b;
+            b = r;
+        }
+        a
+    }
+
+    /// Truncated division, returning `(quotient, remainder)`
+    /// where the remainder has the sign of the dividend.
+    /// Returns `None` when dividing by zero.
+    pub fn div_rem(&self, rhs: &BigInt) -> Option<(BigInt, BigInt)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (q, r) = div_rem_mag(&self.mag, &rhs.mag);
+        Some((
+            BigInt::from_mag(self.negative != rhs.negative, q),
+            BigInt::from_mag(self.negative, r)
+        ))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(val: i64) -> Self {
+        let mag = val.unsigned_abs();
+        BigInt::from_mag(val < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s)
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid integer literal: {:?}", s));
+        }
+
+        let mut mag = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk_val: u32 = core::str::from_utf8(chunk).unwrap().parse().unwrap();
+            mul_small_add(&mut mag, 10u32.pow(chunk.len() as u32), chunk_val);
+        }
+        Ok(BigInt::from_mag(negative, mag))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            chunks.push(div_small(&mut mag, 1_000_000_000));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_mag(!self.negative, self.mag.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::from_mag(self.negative, add_mag(&self.mag, &rhs.mag));
+        }
+        match cmp_mag(&self.mag, &rhs.mag) {
+            Ordering::Less => BigInt::from_mag(rhs.negative, sub_mag(&rhs.mag, &self.mag)),
+            _ => BigInt::from_mag(self.negative, sub_mag(&self.mag, &rhs.mag))
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        let mut mag = vec![0u32; self.mag.len() + rhs.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in rhs.mag.iter().enumerate() {
+                let t = a as u64 * b as u64 + mag[i + j] as u64 + carry;
+                mag[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            mag[i + rhs.mag.len()] = carry as u32;
+        }
+        BigInt::from_mag(self.negative != rhs.negative, mag)
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, rhs: &BigInt) -> BigInt {
+        self.div_rem(rhs).expect("attempt to divide by zero").0
+    }
+}
+
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, rhs: &BigInt) -> BigInt {
+        self.div_rem(rhs).expect("attempt to calculate the remainder with a divisor of zero").1
+    }
+}
+
+/* ******************** *
+ * Magnitude Arithmetic *
+ * ******************** */
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let t = digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(t as u32);
+        carry = t >> 32;
+    }
+    result.push(carry as u32);
+    result
+}
+
+/// Requires `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let t = digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        result.push(t as u32);
+        borrow = (t < 0) as i64;
+    }
+    result
+}
+
+fn mul_small_add(mag: &mut Vec<u32>, mul: u32, add: u32) {
+    let mut carry = add as u64;
+    for digit in mag.iter_mut() {
+        let t = *digit as u64 * mul as u64 + carry;
+        *digit = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+/// Divides `mag` in place, returning the remainder.
+fn div_small(mag: &mut Vec<u32>, div: u32) -> u32 {
+    let mut rem = 0u64;
+    for digit in mag.iter_mut().rev() {
+        let t = rem << 32 | *digit as u64;
+        *digit = (t / div as u64) as u32;
+        rem = t % div as u64;
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    rem as u32
+}
+
+/// Long division of magnitudes (Knuth, TAOCP vol. 2, algorithm D).
+fn div_rem_mag(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(u, v) == Ordering::Less {
+        return (Vec::new(), u.to_vec());
+    }
+    if v.len() == 1 {
+        let mut q = u.to_vec();
+        let r = div_small(&mut q, v[0]);
+        return (q, vec![r]);
+    }
+
+    // normalize so the divisor's top digit has its high bit set
+    let shift = v.last().unwrap().leading_zeros();
+    let v = shl_bits(v, shift);
+    let mut u = shl_bits(u, shift);
+    u.push(0);
+
+    let n = v.len();
+    let m = u.len() - n - 1;
+    let mut q = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        let num = (u[j + n] as u64) << 32 | u[j + n - 1] as u64;
+        let mut qhat = num / v[n - 1] as u64;
+        let mut rhat = num % v[n - 1] as u64;
+        while qhat >= BASE || qhat * v[n - 2] as u64 > (rhat << 32 | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += v[n - 1] as u64;
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        // multiply and subtract
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let p = qhat * v[i] as u64 + carry;
+            carry = p >> 32;
+            let t = u[i + j] as i64 - borrow - (p & 0xffff_ffff) as i64;
+            u[i + j] = t as u32;
+            borrow = (t < 0) as i64;
+        }
+        let t = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = t as u32;
+
+        // qhat was one too large, add the divisor back
+        if t < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let t = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+
+        q[j] = qhat as u32;
+    }
+
+    u.truncate(n);
+    (q, shr_bits(&u, shift))
+}
+
+fn shl_bits(mag: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return mag.to_vec();
+    }
+    let mut result = Vec::with_capacity(mag.len() + 1);
+    let mut carry = 0u32;
+    for &digit in mag {
+        result.push(digit << shift | carry);
+        carry = digit >> (32 - shift);
+    }
+    if carry > 0 {
+        result.push(carry);
+    }
+    result
+}
+
+fn shr_bits(mag: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return mag.to_vec();
+    }
+    let mut result = vec![0u32; mag.len()];
+    for i in 0..mag.len() {
+        let hi = mag.get(i + 1).map_or(0, |digit| digit << (32 - shift));
+        result[i] = mag[i] >> shift | hi;
+    }
+    result
+}
@@ -0,0 +1,152 @@
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::{Add, Sub, Mul, Div, Rem, Neg};
+use core::str::FromStr;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::format;
+use num_bigint::Sign;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+/// An arbitrary-precision signed integer, a `num_bigint::BigInt`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct BigInt(num_bigint::BigInt);
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt(num_bigint::BigInt::zero())
+    }
+
+    /// The bytes taken up by the magnitude's digits.
+    pub fn heap_bytes(&self) -> usize {
+        self.0.bits().div_ceil(64) as usize * core::mem::size_of::<u64>()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.sign() == Sign::Minus
+    }
+
+    /// The magnitude's base 2^32 digits, least significant first.
+    pub fn digits(&self) -> Vec<u32> {
+        self.0.magnitude().to_u32_digits()
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt(self.0.abs())
+    }
+
+    pub fn signum(&self) -> BigInt {
+        BigInt(self.0.signum())
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        self.0.to_i64()
+    }
+
+    /// The value truncated to its low 64 bits (two's complement),
+    /// as Haskell's `fromInteger :: Integer -> Int` does.
+    pub fn to_i64_wrapping(&self) -> i64 {
+        let low = self.0.magnitude().iter_u64_digits().next().unwrap_or(0) as i64;
+        if self.is_negative() { low.wrapping_neg() } else { low }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::NAN)
+    }
+
+    /// The greatest common divisor, always non-negative.
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        BigInt(self.0.gcd(&other.0))
+    }
+
+    /// Truncated division, returning `(quotient, remainder)`
+    /// where the remainder has the sign of the dividend.
+    /// Returns `None` when dividing by zero.
+    pub fn div_rem(&self, rhs: &BigInt) -> Option<(BigInt, BigInt)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (q, r) = self.0.div_rem(&rhs.0);
+        Some((BigInt(q), BigInt(r)))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(val: i64) -> Self {
+        BigInt(num_bigint::BigInt::from(val))
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = String;
+
+    /// Only an optional `-` and decimal digits, which `num_bigint` parses
+    /// along with a `+` and underscores.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix('-').unwrap_or(s);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid integer literal: {:?}", s));
+        }
+        s.parse().map(BigInt).map_err(|_| format!("invalid integer literal: {:?}", s))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt(-&self.0)
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        BigInt(&self.0 + &rhs.0)
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        BigInt(&self.0 - &rhs.0)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt(&self.0 * &rhs.0)
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, rhs: &BigInt) -> BigInt {
+        BigInt(&self.0 / &rhs.0)
+    }
+}
+
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, rhs: &BigInt) -> BigInt {
+        BigInt(&self.0 % &rhs.0)
+    }
+}
@@ -0,0 +1,196 @@
+// Runs a program loaded when it starts, bytecode or a compiled module, so
+// that running one doesn't take a crate of its own. The runtime options
+// come before the program, as GHC's do within `+RTS ... -RTS`:
+//
+//     haskelite-run -K64m -s prog.hbc arg1 arg2
+//
+// and everything after it is the program's own argv.
+
+use std::io;
+use std::process;
+use std::thread;
+use runtime::builtins::*;
+use runtime::entry;
+use runtime::eventlog::EventLog;
+use runtime::tracer::Tracer;
+
+const USAGE: &str = "\
+usage: haskelite-run [OPTION]... PROGRAM [ARG]...
+Runs PROGRAM, a bytecode file (.hbc) or a compiled module.
+
+  -K<size>      run the program on a stack of <size>, e.g. -K64m
+  -M<size>      limit the heap to <size>, raising HeapOverflow past it
+  -s            print the runtime's counters to stderr when done
+  -l[<file>]    write an event log to <file>, PROGRAM.eventlog by default
+  -t            trace the functions called to stderr
+  -h, --help    print this and exit
+  --            end the options, e.g. for a program named -s
+
+Sizes are in bytes, or with a suffix k, m or g.";
+
+#[derive(Default)]
+struct Options {
+    stack_size: Option<usize>,
+    max_heap: Option<usize>,
+    stats: bool,
+    event_log: Option<String>,
+    trace: bool,
+    /// The program's argv, starting with its path.
+    argv: Vec<String>
+}
+
+impl Options {
+    /// The options given, or `None` if asked for help.
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                options.argv.extend(args.by_ref());
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                options.argv.push(arg);
+                options.argv.extend(args.by_ref());
+                break;
+            }
+            match arg.as_str() {
+                "-s" => options.stats = true,
+                "-t" => options.trace = true,
+                "-h" | "--help" => return Ok(None),
+                _ if arg.starts_with("-K") => options.stack_size = Some(parse_size(&arg[2..])?),
+                _ if arg.starts_with("-M") => options.max_heap = Some(parse_size(&arg[2..])?),
+                _ if arg.starts_with("-l") => options.event_log = Some(arg[2..].to_string()),
+                _ => return Err(format!("unknown option {}", arg))
+            }
+        }
+        if options.argv.is_empty() {
+            return Err("no program given".to_string());
+        }
+        Ok(Some(options))
+    }
+}
+
+/// `64m` and the like.
+fn parse_size(size: &str) -> Result<usize, String> {
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (digits, suffix) = size.split_at(split);
+    let unit = match suffix.to_ascii_lowercase().as_str() {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return Err(format!("bad size {:?}", size))
+    };
+    digits.parse::<usize>().ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("bad size {:?}", size))
+}
+
+/// Prints every function entered, with how many arguments it was given.
+struct CallTrace;
+
+impl Tracer for CallTrace {
+    fn on_builtin_call(&mut self, fn_def: &FnDef, args: &[Node]) {
+        eprintln!("call {}/{}", fn_def.name, args.len());
+    }
+}
+
+fn run(options: Options) -> i32 {
+    let mut state = State::new();
+    let path = options.argv[0].clone();
+    state.set_max_heap(options.max_heap);
+    if let Some(log_path) = options.event_log {
+        let log_path = if log_path.is_empty() { format!("{}.eventlog", path) } else { log_path };
+        match EventLog::create(&log_path) {
+            Ok(log) => state.set_tracer(Box::new(log)),
+            Err(err) => {
+                eprintln!("haskelite-run: {}: {}", log_path, err);
+                return 1;
+            }
+        }
+    } else if options.trace {
+        state.set_tracer(Box::new(CallTrace));
+    }
+    state.set_args(options.argv);
+
+    if let Err(err) = entry::load(&mut state, &path) {
+        eprintln!("haskelite-run: {}", err);
+        return 1;
+    }
+    let code = entry::run_main(&mut state, |state| state.global_main());
+    if options.stats || std::env::var_os(entry::STATS_VAR).is_some() {
+        eprintln!("{}", state.stats());
+    }
+    code
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(msg) => {
+            eprintln!("haskelite-run: {}\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    // the evaluator recurses on the native stack, so a deep program needs
+    // a thread with a bigger one
+    let code = match options.stack_size {
+        Some(stack_size) => thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || run(options))
+            .and_then(|thread| thread.join().map_err(|_| io::Error::other("the program panicked")))
+            .unwrap_or_else(|err| {
+                eprintln!("haskelite-run: {}", err);
+                1
+            }),
+        None => run(options)
+    };
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn runtime_options_come_before_the_program() {
+        let options = parse(&["-K64m", "-s", "-l", "prog.hbc", "-t", "x"]).unwrap().unwrap();
+        assert_eq!(options.stack_size, Some(64 << 20));
+        assert!(options.stats);
+        assert_eq!(options.event_log.as_deref(), Some(""));
+        assert!(!options.trace);
+        assert_eq!(options.argv, ["prog.hbc", "-t", "x"]);
+
+        let options = parse(&["-M1k", "--", "-s"]).unwrap().unwrap();
+        assert_eq!(options.max_heap, Some(1024));
+        assert!(!options.stats);
+        assert_eq!(options.argv, ["-s"]);
+        assert!(parse(&["-h", "prog.hbc"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        assert_eq!(parse(&[]).err().unwrap(), "no program given");
+        assert_eq!(parse(&["-s"]).err().unwrap(), "no program given");
+        assert_eq!(parse(&["-x", "prog.hbc"]).err().unwrap(), "unknown option -x");
+        assert!(parse(&["-K", "prog.hbc"]).is_err());
+    }
+
+    #[test]
+    fn sizes_take_a_suffix() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("2K"), Ok(2048));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("1t").is_err());
+        assert!(parse_size("m").is_err());
+        assert!(parse_size(&format!("{}g", usize::MAX)).is_err());
+    }
+}
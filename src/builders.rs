@@ -0,0 +1,227 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::builtins::*;
+
+// Building values from Rust, the other way round from `convert`. Values
+// held on the heap can't be made without the state, so they are pushed
+// with `State::push`; nodes given are pushed as they are, shared with
+// wherever else they are referred to, and left unevaluated.
+//
+//     state.push(vec![1, 2, 3]);
+//     hl_list![state; 1, "two", node];
+//     hl_tuple!(state; 1, 'c', hl_list![state; true]);
+//
+// each leaving the value built on top of the stack.
+
+/// A value that can be pushed as a node.
+pub trait IntoNode {
+    fn push_into(self, state: &mut State);
+}
+
+impl From<i64> for Node {
+    fn from(int: i64) -> Node {
+        Node::Int(int)
+    }
+}
+
+impl From<f64> for Node {
+    fn from(double: f64) -> Node {
+        Node::Double(double)
+    }
+}
+
+impl From<bool> for Node {
+    fn from(b: bool) -> Node {
+        Node::Bool(b)
+    }
+}
+
+impl From<char> for Node {
+    fn from(c: char) -> Node {
+        Node::Char(c)
+    }
+}
+
+impl IntoNode for Node {
+    fn push_into(self, state: &mut State) {
+        state.stack_push(self);
+    }
+}
+
+/// An element of `hl_list!` or `hl_tuple!` pushed already, by the macro
+/// building it.
+impl IntoNode for () {
+    fn push_into(self, _state: &mut State) {}
+}
+
+macro_rules! into_node_immediate {
+    ($($t:ty),*) => {
+        $(impl IntoNode for $t {
+            fn push_into(self, state: &mut State) {
+                state.stack_push(Node::from(self));
+            }
+        })*
+    };
+}
+
+into_node_immediate!(i64, f64, bool, char);
+
+impl IntoNode for &str {
+    fn push_into(self, state: &mut State) {
+        state.push_str(self);
+    }
+}
+
+impl IntoNode for String {
+    fn push_into(self, state: &mut State) {
+        state.push_str(&self);
+    }
+}
+
+impl IntoNode for BigInt {
+    fn push_into(self, state: &mut State) {
+        state.push_integer(self);
+    }
+}
+
+impl<T: IntoNode> IntoNode for Vec<T> {
+    fn push_into(self, state: &mut State) {
+        let len = self.len();
+        for elem in self {
+            elem.push_into(state);
+        }
+        state.mk_list(len);
+    }
+}
+
+impl<T: IntoNode> IntoNode for Option<T> {
+    fn push_into(self, state: &mut State) {
+        match self {
+            Some(value) => {
+                value.push_into(state);
+                state.mk_just();
+            },
+            None => state.push_nothing()
+        }
+    }
+}
+
+impl<A: IntoNode, B: IntoNode> IntoNode for (A, B) {
+    fn push_into(self, state: &mut State) {
+        self.1.push_into(state);
+        self.0.push_into(state);
+        state.mk_tuple(2);
+    }
+}
+
+impl<A: IntoNode, B: IntoNode, C: IntoNode> IntoNode for (A, B, C) {
+    fn push_into(self, state: &mut State) {
+        self.2.push_into(state);
+        self.1.push_into(state);
+        self.0.push_into(state);
+        state.mk_tuple(3);
+    }
+}
+
+impl State {
+    pub fn push<T: IntoNode>(&mut self, value: T) {
+        value.push_into(self);
+    }
+
+    /// Pops `len` elements off the stack, the last on top, and pushes the
+    /// list of them.
+    pub fn mk_list(&mut self, len: usize) {
+        self.push_nil();
+        for _ in 0..len {
+            self.stack_swap();
+            self.mk_cons();
+        }
+    }
+
+    /// Pops `arity` components off the stack, the last on top, and pushes
+    /// the tuple of them.
+    pub fn mk_tuple_in_order(&mut self, arity: usize) {
+        for n in 1..arity {
+            self.stack_bring_up(n);
+        }
+        self.mk_tuple(arity);
+    }
+}
+
+/// Pushes the list of the elements given, e.g. `hl_list![state; 1, 2]`.
+#[macro_export]
+macro_rules! hl_list {
+    ($state:expr; $($elem:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut len = 0;
+        $(
+            let elem = $elem;
+            $state.push(elem);
+            len += 1;
+        )*
+        $state.mk_list(len);
+    }};
+}
+
+/// Pushes the tuple of the components given, e.g. `hl_tuple!(state; 1, 'c')`.
+#[macro_export]
+macro_rules! hl_tuple {
+    ($state:expr; $($elem:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut arity = 0;
+        $(
+            let elem = $elem;
+            $state.push(elem);
+            arity += 1;
+        )*
+        $state.mk_tuple_in_order(arity);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_top(state: &mut State) -> String {
+        let node = state.stack_pop();
+        state.render(node).expect("a value")
+    }
+
+    #[test]
+    fn rust_values_are_pushed_as_nodes() {
+        let mut state = State::new();
+        state.push(vec![1, 2, 3]);
+        assert_eq!(render_top(&mut state), "[1,2,3]");
+        state.push(Vec::<i64>::new());
+        assert_eq!(render_top(&mut state), "[]");
+        state.push((Some('c'), None::<bool>, "two"));
+        assert_eq!(render_top(&mut state), "(Pack{1,1} 'c',Pack{0,0},\"two\")");
+        state.push((2.5, BigInt::from(i64::MAX)));
+        assert_eq!(render_top(&mut state), "(2.5,9223372036854775807)");
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn macros_build_in_order() {
+        let mut state = State::new();
+        state.push_int(3);
+        let node = state.stack_pop();
+        hl_list![state; 1, 2, node];
+        assert_eq!(render_top(&mut state), "[1,2,3]");
+        hl_tuple!(state; 1, 'c', hl_list![state; true]);
+        assert_eq!(render_top(&mut state), "(1,'c',[True])");
+        hl_list![state;];
+        assert_eq!(render_top(&mut state), "[]");
+    }
+
+    #[test]
+    fn tuples_in_order_take_the_last_component_from_the_top() {
+        let mut state = State::new();
+        state.push_int(1);
+        state.push_int(2);
+        state.push_int(3);
+        state.push_int(4);
+        state.mk_tuple_in_order(4);
+        assert_eq!(render_top(&mut state), "(1,2,3,4)");
+    }
+}
@@ -1,59 +1,394 @@
-use std::fmt;
-use std::fmt::Formatter;
+use core::fmt;
+use core::fmt::Formatter;
+use core::convert::TryFrom;
+use core::cmp::Ordering;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+#[cfg(feature = "std")]
+use crate::collections::HashMap;
 use crate::gc::*;
+use crate::globals::Globals;
+use crate::tracer::Tracer;
+use crate::instrument;
+pub use crate::action::IoAction;
+#[cfg(feature = "std")]
+pub use crate::handle::Handle;
+#[cfg(feature = "std")]
+use crate::handle::{StdHandles, eprint_line};
+use crate::random::StdGen;
+pub use crate::array::Array;
+pub use crate::map::MapTree;
+pub use crate::concurrent::MVar;
+pub use crate::stm::TVar;
+use crate::stm::Transaction;
+use crate::spark::SparkPool;
+use crate::cancel::Cancel;
+use crate::heaplimit::HeapLimit;
+pub use crate::weak::WeakRef;
+pub use crate::stable::StableName;
+use crate::weak::WeakTable;
+use crate::hashcons::HashCons;
+use crate::ffi::Ffi;
+#[cfg(feature = "std")]
+use crate::bytecode::Function;
+use crate::closure::Closures;
+use crate::concurrent::Scheduler;
+pub use crate::stats::RuntimeStats;
+pub use crate::census::HeapCensus;
+pub use crate::bigint::BigInt;
+pub use crate::ratio::Rational;
+pub use crate::error::{EvalError, EvalResult, ArithException, SrcLoc};
+#[cfg(feature = "std")]
+pub use crate::error::IoException;
 
+// A state is not `Send`: the nodes it hands out are plain pointers into
+// its heap, which has no locking, and copies of them kept on the thread a
+// state left could reach it while another thread reduces it.
 pub struct State {
     alloc: GcAlloc,
-    pub stacks: Vec<Stack>
+    pub stacks: Vec<Stack>,
+    pub globals: Globals,
+    overflow_policy: OverflowPolicy,
+    /// Reductions left before evaluation has to stop, unlimited if `None`.
+    fuel: Option<u64>,
+    /// Whether reductions made on the native stack, by functions evaluating
+    /// values themselves, use up fuel too or just run to completion.
+    nested_fuel: bool,
+    tracer: Option<Box<dyn Tracer>>,
+    /// The counters kept by the state itself, the collector keeps the rest.
+    pub(crate) stats: RuntimeStats,
+    /// Take a heap census every this many collections, if set.
+    census_interval: Option<u64>,
+    censuses: Vec<HeapCensus>,
+    /// The handles of the standard streams, once the program uses them.
+    #[cfg(feature = "std")]
+    pub(crate) stdio: Option<StdHandles>,
+    /// The command line the program sees, the process's unless set.
+    #[cfg(feature = "std")]
+    pub(crate) argv: Option<Vec<String>>,
+    /// The generator shared by the random IO functions, once they are used.
+    pub(crate) std_gen: Option<StdGen>,
+    /// The exception thrown by the program that is propagating as
+    /// `EvalError::Thrown`, kept alive until a handler catches it.
+    pub(crate) thrown: Option<Node>,
+    /// The threads forked by the program, run by the IO driver.
+    pub(crate) scheduler: Scheduler,
+    /// The log of the STM transaction being performed, if any.
+    pub(crate) transaction: Option<Transaction>,
+    /// The thunks sparked by `par`, waiting to be evaluated ahead of time.
+    pub(crate) sparks: SparkPool,
+    /// What stops the evaluation in progress early, if anything.
+    pub(crate) cancel: Cancel,
+    /// How big the heap may grow, if it is limited.
+    pub(crate) heap_limit: Option<HeapLimit>,
+    /// The `Integer`s in `SMALL_INTEGERS` made so far, by offset from the
+    /// first, empty until one is.
+    small_integers: Vec<Option<Gc<BigInt>>>,
+    /// The weak pointers whose keys may still be alive, and the finalizers
+    /// of those whose keys have died.
+    pub(crate) weaks: WeakTable,
+    /// The constructors and list cells to share, if hash-consing is on.
+    hash_cons: Option<HashCons>,
+    /// The name of the function being called, set by `call`, telling
+    /// functions that run many definitions, native or bytecode, which.
+    pub(crate) callee: &'static str,
+    /// What native code called through `ffi` is given.
+    pub(crate) ffi: Ffi,
+    /// The bytecode functions loaded, by name.
+    #[cfg(feature = "std")]
+    pub(crate) bytecode: HashMap<&'static str, &'static Function>,
+    /// The Rust closures the program can call back, by token.
+    pub(crate) closures: Closures
+}
+
+/// The `Integer` values of which only one copy is ever allocated.
+const SMALL_INTEGERS: core::ops::RangeInclusive<i64> = -128..=1024;
+
+/// The outcome of evaluating with a bounded number of reductions.
+pub enum StepResult {
+    /// Evaluation finished with a value in WHNF.
+    Done(Node),
+    /// The reductions ran out first, hand this back to
+    /// `State::resume_with_fuel` to carry on or `State::abandon` to give up.
+    Suspended(Suspension)
+}
+
+/// A paused evaluation. Its frames stay on the state's stacks, so anything
+/// evaluated in the meantime has to be finished before resuming it.
+pub struct Suspension {
+    resume: Vec<Resume>,
+    root_size: usize,
+    depth: usize
+}
+
+/// How `Int` arithmetic behaves when the result does not fit in 64 bits,
+/// independent of the build profile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// Raise `ArithException::Overflow`
+    Checked,
+    /// Wrap around in two's complement, as GHC's `Int` does
+    Wrapping,
+    /// Clamp to `minBound`/`maxBound`
+    Saturating
 }
 
 #[derive(Clone, Copy)]
 pub struct FnDef {
     pub name: &'static str,
     pub arity: usize,
+    /// How many leading arguments the function always evaluates. The unwinder
+    /// reduces these to WHNF itself before entering the function, so that
+    /// deep chains of strict calls don't recurse on the native stack.
+    pub strict: usize,
     pub fn_ref: StateFn
 }
 
+/// A node of the graph, copied rather than reference counted. Numbers and
+/// characters are held immediately and what is on the heap by one `Gc`
+/// pointer, but `FnDef`s are held by value, as compiled code builds them.
 #[derive(Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum Node {
     Int(i64),
+    Integer(Gc<BigInt>),
+    Ratio(Gc<Rational>),
+    Double(f64),
+    Bool(bool),
+    Char(char),
     FnDef(FnDef),
+    /// A function applied to fewer arguments than it takes, holding them in
+    /// application order. Closures are partial applications of lambda-lifted
+    /// functions to their free variables. Applied to all of them it is a
+    /// call, as made by `State::mk_call_thunk`, not a value.
+    Pap(FnDef, Gc<Fields>),
     App(Gc<Node>, Gc<Node>),
+    Nil,
+    Cons(Gc<Node>, Gc<Node>),
+    Data { tag: u32, fields: Gc<Fields> },
+    Tuple(Gc<Fields>),
+    Text(Gc<Text>),
     ThunkRef(Gc<Thunk>),
-    NodeRef(Gc<Node>)
+    /// An indirection to another node, followed wherever it is met.
+    NodeRef(Gc<Node>),
+    /// An IO action, performed only when `State::run_io` gets to it.
+    IO(Gc<IoAction>),
+    #[cfg(feature = "std")]
+    Handle(Gc<Handle>),
+    /// A mutable cell, holding what it was last written, read and written
+    /// only by IO actions. Unlike a `NodeRef` it is never followed through.
+    IORef(Gc<Node>),
+    /// A mutable array, written in place by IO actions.
+    IOArray(Gc<Array>),
+    /// An immutable array, its elements evaluated only when indexed.
+    Array(Gc<Array>),
+    /// A persistent map, its keys in normal form and values as given.
+    Map(Gc<MapTree>),
+    /// A persistent set, the keys of a tree without values.
+    Set(Gc<MapTree>),
+    /// A box shared between threads, full or empty.
+    MVar(Gc<MVar>),
+    /// A variable shared between threads, read and written by transactions.
+    TVar(Gc<TVar>),
+    /// A weak pointer, holding its value only while its key is alive.
+    Weak(Gc<WeakRef>),
+    /// The identity of a node, as it was when the name was made.
+    StableName(Gc<StableName>)
+}
+
+/// An updatable cell shared by every reference to a lazily evaluated
+/// expression, overwritten with the value once it has been forced.
+pub struct Thunk {
+    /// What the thunk stands for, e.g. the name of its binding,
+    /// shown when debugging and in errors raised by it.
+    pub label: Option<&'static str>,
+    /// Where the expression comes from in the source program.
+    pub loc: Option<SrcLoc>,
+    pub state: ThunkState
 }
 
-pub enum Thunk {
+/// How far a thunk has got with being forced.
+pub enum ThunkState {
+    /// Not forced yet, holds the Rust code computing the expression.
     UThunk(Box<dyn ThunkEval>),
+    /// Not forced yet, holds a Rust function and the arguments to call it
+    /// with, for code that needs no captures of its own: no box for them
+    /// and no vtable to go through.
+    UCall(ThunkFn, Fields),
+    /// Not forced yet, holds the graph to reduce.
+    Pending(Node),
+    /// Being forced, entering it again means the value depends on itself.
+    Blackhole,
+    /// Forced, holds the value in WHNF and nothing else: the expression,
+    /// and with it whatever the thunk's closure captured, is unreachable
+    /// from the thunk once it is updated, for the next collection to
+    /// reclaim unless something else still refers to it.
     EThunk(Node)
 }
 
+/// Rust code computing a suspended expression, e.g. a closure (see
+/// `closure::thunk_fn`). It is called once, when its thunk is first
+/// forced, and the node it gives is reduced in its place.
 pub trait ThunkEval {
     fn eval_thunk(&self) -> Node;
+
+    /// Marks the nodes it holds for the collector, which can't see them
+    /// otherwise. Those it doesn't mark have to be kept alive elsewhere.
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
 }
 
+/// Rust code computing a suspended expression from the arguments its
+/// thunk holds, see `State::push_thunk_call`.
+pub type ThunkFn = fn(&[Node]) -> Node;
+
+/// A packed UTF-8 string.
+pub type Text = String;
+
+/// The fields of a constructor node, in declaration order.
+pub type Fields = Vec<Node>;
+
 type Stack = Vec<Node>;
 
-pub type StateFn = fn(state: &mut State);
+pub type StateFn = fn(state: &mut State) -> EvalResult;
+
+impl Default for State {
+    fn default() -> Self {
+        State::new()
+    }
+}
 
 impl State {
     pub fn new() -> Self {
         let mut state = State {
             alloc: GcAlloc::new(),
-            stacks: Vec::new()
+            stacks: Vec::new(),
+            globals: Globals::new(),
+            overflow_policy: OverflowPolicy::Wrapping,
+            fuel: None,
+            nested_fuel: true,
+            tracer: None,
+            stats: RuntimeStats::default(),
+            census_interval: None,
+            censuses: Vec::new(),
+            #[cfg(feature = "std")]
+            stdio: None,
+            #[cfg(feature = "std")]
+            argv: None,
+            std_gen: None,
+            thrown: None,
+            scheduler: Scheduler::default(),
+            transaction: None,
+            sparks: SparkPool::default(),
+            cancel: Cancel::default(),
+            heap_limit: None,
+            small_integers: Vec::new(),
+            weaks: WeakTable::default(),
+            hash_cons: None,
+            callee: "",
+            ffi: Ffi::default(),
+            #[cfg(feature = "std")]
+            bytecode: HashMap::new(),
+            closures: Closures::default()
         };
         state.stack_enter_new();
         state
     }
 
+    /// Allocates thunks from a pool of blocks rather than one by one, see
+    /// `GcAlloc::set_pool_thunks`.
+    pub fn set_pool_thunks(&mut self, pool: bool) {
+        self.alloc.set_pool_thunks(pool);
+    }
+
+    /// Shares the fields of identical evaluated constructors, and the heads
+    /// and tails of identical list cells, rather than allocating them again.
+    /// Turning it off forgets what was allocated so far, still shared.
+    pub fn set_hash_consing(&mut self, hash_cons: bool) {
+        self.hash_cons = hash_cons.then(HashCons::default);
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    pub fn stats(&self) -> RuntimeStats {
+        RuntimeStats {
+            collections: self.alloc.collections(),
+            objects_freed: self.alloc.freed(),
+            peak_live_objects: self.alloc.peak_live(),
+            ..self.stats
+        }
+    }
+
+    /// Runs `fn_def` on the arguments on top of the stack.
+    pub(crate) fn call(&mut self, fn_def: &FnDef) -> EvalResult {
+        self.record_call(fn_def);
+        self.check_cancelled()?;
+        self.check_heap()?;
+        let _span = instrument::call_span(fn_def);
+        self.callee = fn_def.name;
+        (fn_def.fn_ref)(self).map_err(|err| err.called_from(fn_def.name))
+    }
+
+    /// Counts entering `fn_def` with its arguments on top of the stack,
+    /// telling the tracer if there is one.
+    fn record_call(&mut self, fn_def: &FnDef) {
+        self.stats.reductions += 1;
+        if let Some(tracer) = &mut self.tracer {
+            let stack = self.stacks.last().unwrap();
+            let args: Vec<Node> = stack.iter().rev().take(fn_def.arity).copied().collect();
+            tracer.on_builtin_call(fn_def, &args);
+        }
+    }
+
+    /// Applies `op` to two `Int`s according to the overflow policy.
+    fn int_arith(&self, op: &IntOp, vl: i64, vr: i64) -> EvalResult<i64> {
+        match self.overflow_policy {
+            OverflowPolicy::Checked => (op.checked)(vl, vr).ok_or_else(|| ArithException::Overflow.into()),
+            OverflowPolicy::Wrapping => Ok((op.wrapping)(vl, vr)),
+            OverflowPolicy::Saturating => Ok((op.saturating)(vl, vr))
+        }
+    }
+
+    /// Converts an unsigned `Int` result (e.g. `gcd minBound 0`)
+    /// according to the overflow policy.
+    fn int_from_u64(&self, v: u64) -> EvalResult<i64> {
+        match (i64::try_from(v), self.overflow_policy) {
+            (Ok(v), _) => Ok(v),
+            (Err(_), OverflowPolicy::Checked) => Err(ArithException::Overflow.into()),
+            (Err(_), OverflowPolicy::Wrapping) => Ok(v as i64),
+            (Err(_), OverflowPolicy::Saturating) => Ok(i64::MAX)
+        }
+    }
+
+    fn int_un_arith(&self, op: &IntUnOp, v: i64) -> EvalResult<i64> {
+        match self.overflow_policy {
+            OverflowPolicy::Checked => (op.checked)(v).ok_or_else(|| ArithException::Overflow.into()),
+            OverflowPolicy::Wrapping => Ok((op.wrapping)(v)),
+            OverflowPolicy::Saturating => Ok((op.saturating)(v))
+        }
+    }
+
     /* ***** *
      * Debug *
      * ***** */
 
+    #[cfg(feature = "std")]
     pub fn gc_dump(&self) {
         self.alloc.dump();
     }
 
+    #[cfg(feature = "std")]
     pub fn stack_dump(&self) {
         println!("--- BEGIN DUMP ---");
         for (stack_idx, stack) in self.stacks.iter().enumerate().rev() {
@@ -69,12 +404,20 @@ impl State {
      * GC    *
      * ***** */
 
-    fn alloc_node(&mut self, node: Node) -> Gc<Node> {
+    pub(crate) fn alloc<T: Trace + 'static>(&mut self, obj: T) -> Gc<T> {
+        let mut worklist = Vec::new();
+        obj.mark_refs(&mut worklist);
+        self.collect(worklist);
+
+        self.alloc.alloc(obj)
+    }
+
+    fn alloc_thunk(&mut self, thunk: Thunk) -> Gc<Thunk> {
         let mut worklist = Vec::new();
-        node.mark_refs(&mut worklist);
+        thunk.mark_refs(&mut worklist);
         self.collect(worklist);
 
-        self.alloc.alloc_node(node)
+        self.alloc.alloc_thunk(thunk)
     }
 
     fn alloc_nodes(&mut self, node1: Node, node2: Node) -> (Gc<Node>, Gc<Node>) {
@@ -83,20 +426,94 @@ impl State {
         node2.mark_refs(&mut worklist);
         self.collect(worklist);
 
-        let alloc1 = self.alloc.alloc_node(node1);
-        let alloc2 = self.alloc.alloc_node(node2);
+        let alloc1 = self.alloc.alloc(node1);
+        let alloc2 = self.alloc.alloc(node2);
 
         (alloc1, alloc2)
     }
 
+    /// `alloc_nodes` for the head and tail of a list cell, shared with those
+    /// of identical cells if hash-consing is on.
+    fn alloc_cell_nodes(&mut self, head: Node, tail: Node) -> (Gc<Node>, Gc<Node>) {
+        if self.hash_cons.is_none() {
+            return self.alloc_nodes(head, tail);
+        }
+        let mut worklist = Vec::new();
+        head.mark_refs(&mut worklist);
+        tail.mark_refs(&mut worklist);
+        self.collect(worklist);
+
+        let table = self.hash_cons.as_mut().expect("hash-consing is on");
+        let (head, head_shared) = table.share_node(&mut self.alloc, head);
+        let (tail, tail_shared) = table.share_node(&mut self.alloc, tail);
+        self.stats.objects_shared += head_shared as u64 + tail_shared as u64;
+        (head, tail)
+    }
+
     pub fn collect(&mut self, mut worklist: Worklist) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_gc_start();
+        }
         self.mark_stack_roots(&mut worklist);
+        self.globals.mark_roots(&mut worklist);
+        #[cfg(feature = "std")]
+        if let Some(stdio) = &self.stdio {
+            stdio.stdin.mark_into(&mut worklist);
+            stdio.stdout.mark_into(&mut worklist);
+            stdio.stderr.mark_into(&mut worklist);
+        }
+        if let Some(thrown) = &self.thrown {
+            thrown.mark_refs(&mut worklist);
+        }
+        self.scheduler.mark_roots(&mut worklist);
+        if let Some(transaction) = &self.transaction {
+            transaction.mark_roots(&mut worklist);
+        }
+        self.stats.sparks_fizzled += self.sparks.mark_roots(&mut worklist);
+        // weak pointers are looked at once everything else is marked
+        GcAlloc::mark(&mut worklist);
+        self.weaks.mark(&mut worklist);
+        if let Some(table) = &mut self.hash_cons {
+            table.prune();
+        }
+        self.closures.prune();
         self.alloc.collect(worklist);
+        if let Some(limit) = &mut self.heap_limit {
+            limit.observe(self.alloc.live_bytes());
+        }
+        self.weaks.run_native();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_gc_end(self.alloc.live());
+        }
+        if let Some(interval) = self.census_interval {
+            if self.alloc.collections().is_multiple_of(interval) {
+                self.censuses.push(HeapCensus::of(&self.alloc));
+            }
+        }
+    }
+
+    /// Collects garbage and takes a census of what is left on the heap.
+    pub fn heap_census(&mut self) -> HeapCensus {
+        self.collect(Vec::new());
+        HeapCensus::of(&self.alloc)
+    }
+
+    /// Has a heap census taken after every `interval` collections, or stops
+    /// taking them if `None`. They are kept until `take_censuses`.
+    pub fn set_census_interval(&mut self, interval: Option<u64>) {
+        self.census_interval = interval.filter(|&interval| interval > 0);
+    }
+
+    pub fn take_censuses(&mut self) -> Vec<HeapCensus> {
+        core::mem::take(&mut self.censuses)
     }
 
-    fn mark_stack_roots(&mut self, worklist: &mut Vec<Gc<Node>>) {
+    fn mark_stack_roots(&mut self, worklist: &mut Worklist) {
         for stack in self.stacks.iter_mut() {
             for node in stack.iter_mut() {
+                // collapsing indirections lets evaluated thunks only
+                // referred to from the stack be reclaimed
+                *node = node.short_circuit();
                 node.mark_refs(worklist);
             }
         }
@@ -122,18 +539,38 @@ impl State {
         self.get_cur_stack_mut().pop().unwrap()
     }
 
+    /// Swaps the top two nodes, e.g. to evaluate the second argument
+    /// while the first stays rooted on the stack.
+    pub fn stack_swap(&mut self) {
+        let stack = self.get_cur_stack_mut();
+        let len = stack.len();
+        stack.swap(len - 1, len - 2);
+    }
+
     pub fn stack_size(&self) -> usize {
         self.get_cur_stack().len()
     }
 
     pub fn stack_peek(&self) -> &Node {
+        self.get_cur_stack().last().unwrap()
+    }
+
+    /// Moves the node `n` below the top up to the top, the others keeping their order.
+    pub fn stack_bring_up(&mut self, n: usize) {
+        let stack = self.get_cur_stack_mut();
+        let node = stack.remove(stack.len() - 1 - n);
+        stack.push(node);
+    }
+
+    /// The node `n` below the top, the top itself being `0`.
+    pub fn stack_peek_nth(&self, n: usize) -> &Node {
         let stack = self.get_cur_stack();
-        stack.get(stack.len() - 1).unwrap()
+        &stack[stack.len() - 1 - n]
     }
 
     fn get_cur_stack(&self) -> &Stack {
         let len = self.stacks.len();
-        &self.stacks.get(len - 1).unwrap()
+        self.stacks.get(len - 1).unwrap()
     }
 
     fn get_cur_stack_mut(&mut self) -> &mut Stack {
@@ -149,6 +586,62 @@ impl State {
         self.stack_push(Node::FnDef(fn_def));
     }
 
+    pub fn push_integer(&mut self, integer_val: BigInt) {
+        let node = self.alloc_integer(integer_val);
+        self.stack_push(node);
+    }
+
+    /// Shares one `Integer` for each of the `SMALL_INTEGERS`, so the
+    /// loops counting with them do not allocate.
+    fn alloc_integer(&mut self, integer_val: BigInt) -> Node {
+        match integer_val.to_i64().filter(|i| SMALL_INTEGERS.contains(i)) {
+            Some(i) => {
+                let index = (i - SMALL_INTEGERS.start()) as usize;
+                if self.small_integers.is_empty() {
+                    self.small_integers = vec![None; SMALL_INTEGERS.count()];
+                }
+                let alloc = &mut self.alloc;
+                Node::Integer(*self.small_integers[index].get_or_insert_with(|| alloc.alloc_pinned(integer_val)))
+            },
+            None => Node::Integer(self.alloc(integer_val))
+        }
+    }
+
+    pub fn push_ratio(&mut self, ratio_val: Rational) {
+        let node = self.alloc_ratio(ratio_val);
+        self.stack_push(node);
+    }
+
+    fn alloc_ratio(&mut self, ratio_val: Rational) -> Node {
+        Node::Ratio(self.alloc(ratio_val))
+    }
+
+    pub fn push_double(&mut self, double_val: f64) {
+        self.stack_push(Node::Double(double_val));
+    }
+
+    pub fn push_char(&mut self, char_val: char) {
+        self.stack_push(Node::Char(char_val));
+    }
+
+    pub fn push_nil(&mut self) {
+        self.stack_push(Node::Nil);
+    }
+
+    pub fn push_text(&mut self, text_val: Text) {
+        let text = self.alloc(text_val);
+        self.stack_push(Node::Text(text));
+    }
+
+    /// Pushes a string literal as a fully built `[Char]` list.
+    pub fn push_str(&mut self, str_val: &str) {
+        self.push_nil();
+        for c in str_val.chars().rev() {
+            self.push_char(c);
+            self.mk_cons();
+        }
+    }
+
     /* ********** *
      * Statements *
      * ********** */
@@ -162,240 +655,3832 @@ impl State {
         self.stack_push(Node::App(nl, nr));
     }
 
-    pub fn eval(&mut self) {
-        // SPJ:321
+    /// Like `mk_ap`, but forces the argument to WHNF first.
+    pub fn mk_strict_ap(&mut self) -> EvalResult {
+        self.stack_swap();
+        self.eval()?;
+        self.stack_swap();
+        self.mk_ap();
+        Ok(())
+    }
 
-        match self.stack_peek() {
-            Node::FnDef(_) => {
-                if let Node::FnDef(fn_def) = self.stack_pop() {
-                    self.stack_enter_new();
-                    (fn_def.fn_ref)(self);
-                    let unwind_result = self.stack_pop();
-                    self.stack_exit();
-                    self.stack_push(unwind_result);
-                } else {
-                    unreachable!();
-                }
-            },
-            Node::App(_, _) => {
-                let app_head = self.stack_pop();  // copy the node
-                self.stack_enter_new();
-                self.stack_push(app_head);
-                self.unwind();
-                let unwind_result = self.stack_pop();
-                self.stack_exit();
-                self.stack_push(unwind_result);
-            },
-            _ => {}
+    pub fn mk_cons(&mut self) {
+        let raw_head = self.stack_pop();
+        let raw_tail = self.stack_pop();
+
+        let (head, tail) = self.alloc_cell_nodes(raw_head, raw_tail);
+
+        self.stack_push(Node::Cons(head, tail));
+    }
+
+    /// Pops `arity` arguments off the stack (first argument on top) and pushes
+    /// `fn_def` partially applied to them, e.g. a closure over free variables.
+    pub fn mk_pap(&mut self, fn_def: FnDef, arity: usize) {
+        let args = self.alloc_fields(arity);
+        self.stack_push(Node::Pap(fn_def, args));
+    }
+
+    /// Pops an expression off the stack and pushes a thunk for it, so that
+    /// it is evaluated at most once however many times it is referenced.
+    pub fn mk_thunk(&mut self) {
+        let expr = self.stack_pop();
+        let thunk = self.alloc_thunk(Thunk { label: None, loc: None, state: ThunkState::Pending(expr) });
+        self.stats.thunks_allocated += 1;
+        instrument::thunk_allocated(&thunk);
+        self.stack_push(Node::ThunkRef(thunk));
+    }
+
+    /// Pushes a thunk for the expression `eval` computes.
+    pub fn push_thunk_eval(&mut self, eval: Box<dyn ThunkEval>) {
+        let thunk = self.alloc_thunk(Thunk { label: None, loc: None, state: ThunkState::UThunk(eval) });
+        self.stats.thunks_allocated += 1;
+        instrument::thunk_allocated(&thunk);
+        self.stack_push(Node::ThunkRef(thunk));
+    }
+
+    /// Pops `arity` arguments off the stack (first argument on top) and
+    /// pushes a thunk for the expression `eval` computes from them.
+    pub fn push_thunk_call(&mut self, eval: ThunkFn, arity: usize) {
+        // allocated before the arguments leave the stack, where they are rooted
+        let mut thunk = self.alloc_thunk(Thunk { label: None, loc: None, state: ThunkState::Blackhole });
+        let stack = self.get_cur_stack_mut();
+        let args = stack.drain(stack.len() - arity..).rev().collect();
+        thunk.as_mut().state = ThunkState::UCall(eval, args);
+        self.stats.thunks_allocated += 1;
+        instrument::thunk_allocated(&thunk);
+        self.stack_push(Node::ThunkRef(thunk));
+    }
+
+    /// Pops `fn_def`'s arguments off the stack (first argument on top) and
+    /// pushes a thunk for calling it with them, which holds the function
+    /// and its arguments in one array rather than a spine of applications
+    /// to unwind: two allocations however many arguments there are.
+    pub fn mk_call_thunk(&mut self, fn_def: FnDef) {
+        if fn_def.arity == 0 {
+            self.push_fn(fn_def);
+        } else {
+            self.mk_pap(fn_def, fn_def.arity);
         }
+        self.mk_thunk();
     }
 
-    pub fn unwind(&mut self) {
-        // SPJ:322
+    /// Labels the thunk on top of the stack for debugging.
+    pub fn label_thunk(&mut self, label: &'static str) {
+        if let Node::ThunkRef(mut thunk) = *self.stack_peek() {
+            thunk.as_mut().label = Some(label);
+        }
+    }
 
-        while let Node::App(nl, _) = self.stack_peek() {
-            self.stack_push(**nl);
+    /// Records where the expression of the thunk on top of the stack comes
+    /// from, so errors raised while forcing it report that location.
+    pub fn locate_thunk(&mut self, loc: SrcLoc) {
+        if let Node::ThunkRef(mut thunk) = *self.stack_peek() {
+            thunk.as_mut().loc = Some(loc);
         }
+    }
 
-        if let Node::FnDef(_) = self.stack_peek() {
-            if let Node::FnDef(fn_def) = self.stack_pop() {
-                let stack_size = self.stack_size();
-                if stack_size >= fn_def.arity {
-                    let new_size = stack_size - fn_def.arity;
-                    let args_spine = self.get_cur_stack_mut().split_off(new_size);
-                    for arg_app in args_spine {
-                        if let Node::App(_, nr) = arg_app {
-                            self.stack_push(*nr);
-                        } else {
-                            unreachable!("Should only be applications on spine");
-                        }
-                    }
+    /// Builds a recursive binding such as `ones = 1 : ones`. The thunk for the
+    /// binding is allocated empty first and handed to `build`, which has to
+    /// push the binding's expression (referring to the thunk as it likes).
+    /// The thunk is then filled with it and left on the stack.
+    pub fn mk_recursive<F: FnOnce(&mut State, Node)>(&mut self, build: F) {
+        self.mk_letrec(1, |state, refs| build(state, refs[0]));
+    }
 
-                    (fn_def.fn_ref)(self);
-                }
-            } else {
-                unreachable!()
-            }
+    /// Builds `n` mutually recursive bindings, as `mk_recursive` does for one.
+    /// `build` gets the thunks of all of them and has to push their
+    /// expressions, the first binding's on top. The thunks are left on the
+    /// stack in the same order.
+    pub fn mk_letrec<F: FnOnce(&mut State, &[Node])>(&mut self, n: usize, build: F) {
+        // each thunk is pushed as soon as it exists, keeping it rooted
+        let mut refs = vec![Node::Nil; n];
+        for i in (0..n).rev() {
+            self.push_hole();
+            refs[i] = *self.stack_peek();
+        }
+
+        build(self, &refs);
+
+        // the rest of the expressions are above each binding's thunk
+        for _ in 0..n {
+            self.fill_hole(n - 1);
         }
     }
-}
 
-impl Node {
-    /// The function to 'mark' any contained GC references.
-    /// Sets the `marked` bit on the GC references,
-    /// as well as add them to the `worklist`.
-    pub fn mark_refs(&self, worklist: &mut Worklist) {
-        match self {
-            Node::Int(_) => {},
-            Node::FnDef(_) => {},
-            Node::App(mut nl, mut nr) => {
-                if !nl.is_marked() {
-                    nl.mark();
-                    worklist.push(nl);
-                }
+    /// Pushes an empty thunk, to be filled with `fill_hole` once the
+    /// expression referring to it is built.
+    pub fn push_hole(&mut self) {
+        let thunk = self.alloc_thunk(Thunk { label: None, loc: None, state: ThunkState::Blackhole });
+        self.stats.thunks_allocated += 1;
+        instrument::thunk_allocated(&thunk);
+        self.stack_push(Node::ThunkRef(thunk));
+    }
+
+    /// Pops an expression off the stack and fills the empty thunk `n` below
+    /// the top with it, the top being `0` once the expression is popped.
+    pub fn fill_hole(&mut self, n: usize) {
+        let expr = self.stack_pop();
+        if let Node::ThunkRef(mut thunk) = *self.stack_peek_nth(n) {
+            thunk.as_mut().state = ThunkState::Pending(expr);
+        }
+    }
+
+    /// Pops `arity` fields off the stack (first field on top)
+    /// and pushes the constructor node built from them.
+    pub fn mk_data(&mut self, tag: u32, arity: usize) {
+        let fields = self.alloc_constructor_fields(arity);
+        self.stack_push(Node::Data { tag, fields });
+    }
+
+    /// Pops `arity` components off the stack (first component on top)
+    /// and pushes the tuple built from them.
+    pub fn mk_tuple(&mut self, arity: usize) {
+        let fields = self.alloc_constructor_fields(arity);
+        self.stack_push(Node::Tuple(fields));
+    }
+
+    pub(crate) fn alloc_fields(&mut self, arity: usize) -> Gc<Fields> {
+        let new_size = self.stack_size() - arity;
+        let mut fields = self.get_cur_stack_mut().split_off(new_size);
+        fields.reverse();
+
+        self.alloc(fields)
+    }
 
-                if !nr.is_marked() {
-                    nr.mark();
-                    worklist.push(nr);
+    /// `alloc_fields` for a constructor, sharing the fields of an identical
+    /// one if hash-consing is on.
+    fn alloc_constructor_fields(&mut self, arity: usize) -> Gc<Fields> {
+        if self.hash_cons.is_none() {
+            return self.alloc_fields(arity);
+        }
+        let new_size = self.stack_size() - arity;
+        let mut fields = self.get_cur_stack_mut().split_off(new_size);
+        fields.reverse();
+        let mut worklist = Vec::new();
+        fields.mark_refs(&mut worklist);
+        self.collect(worklist);
+
+        let table = self.hash_cons.as_mut().expect("hash-consing is on");
+        let (fields, shared) = table.share_fields(&mut self.alloc, fields);
+        self.stats.objects_shared += shared as u64;
+        fields
+    }
+
+    /// Scrutinizes the node on top of the stack for a case expression.
+    /// Forces it to WHNF, replaces it with its fields (first field on top)
+    /// and returns its constructor tag.
+    ///
+    /// Lists, booleans and tuples are scrutinized as if they were 
+    /// `data [a] = [] | a : [a]`, `data Bool = False | True`
+    /// and single-constructor types respectively.
+    pub fn case_split(&mut self) -> EvalResult<u32> {
+        self.eval()?;
+        let tag = match self.stack_pop() {
+            Node::Data { tag, fields } => {
+                for field in fields.iter().rev() {
+                    self.stack_push(field.short_circuit());
                 }
+                tag
             },
-            Node::ThunkRef(_) => todo!(),
-            Node::NodeRef(mut node_ref) => {
-                if !node_ref.is_marked() {
-                    node_ref.mark();
-                    worklist.push(node_ref);
+            Node::Tuple(fields) => {
+                for field in fields.iter().rev() {
+                    self.stack_push(field.short_circuit());
                 }
+                0
             },
+            Node::Bool(b) => b as u32,
+            Node::Nil => 0,
+            Node::Cons(head, tail) => {
+                self.stack_push(tail.short_circuit());
+                self.stack_push(head.short_circuit());
+                1
+            },
+            node => return Err(EvalError::type_mismatch("case", "constructor", &node))
+        };
+        Ok(tag)
+    }
+
+    /// Scrutinizes the node on top of the stack and enters 
+    /// the alternative selected by its tag, with the fields on the stack.
+    pub fn case(&mut self, alts: &[StateFn]) -> EvalResult {
+        let tag = self.case_split()?;
+        match alts.get(tag as usize) {
+            Some(alt) => alt(self),
+            None => Err(EvalError::PatternMatchFail(format!("no alternative for constructor tag {}", tag)))
         }
     }
-}
 
-impl fmt::Display for Node {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Node::Int(i) => {
-                write!(f, "{}", i)
+    /// Evaluates `node` to normal form and displays it, e.g. to print a
+    /// program's result, whose parts may not have been evaluated yet.
+    pub fn render(&mut self, node: Node) -> EvalResult<String> {
+        let frame_size = self.stack_size();
+        self.stack_push(node);
+        match self.deep_eval() {
+            Ok(()) => Ok(self.stack_pop().to_string()),
+            Err(err) => {
+                self.get_cur_stack_mut().truncate(frame_size);
+                Err(err)
             }
-            Node::ThunkRef(t) => {
-                unreachable!("Asked to display thunk: {:?}", *t);
-            },
-            Node::App(_, _) => {
-                unreachable!("Asked to display application: {:?}", self);
-            },
-            Node::FnDef(_) => {
-                unreachable!("Asked to display function: {:?}", self);
-            }
-            Node::NodeRef(nref) => write!(f, "{}", nref.as_ref()),
         }
     }
-}
 
-impl fmt::Debug for Node {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Node::Int(i) => write!(f, "Int({})", i), 
-            Node::ThunkRef(t) => write!(f, "{:?}", t.as_ref()),
-            Node::App(el, er) => {
-                write!(f, "@({:?}, {:?})", el, er)
+    /// Evaluates the node on top of the stack to normal form, 
+    /// replacing it with the fully evaluated value.
+    pub fn deep_eval(&mut self) -> EvalResult {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::Cons(_, _) => {
+                // walk the spine iteratively, leaving the evaluated heads on the stack
+                let mut len = 0;
+                loop {
+                    match self.stack_pop() {
+                        Node::Cons(head, tail) => {
+                            self.stack_push(*tail);
+                            self.stack_push(*head);
+                            self.deep_eval()?;
+                            self.stack_swap();
+                            self.eval()?;
+                            len += 1;
+                        },
+                        Node::Nil => break,
+                        node => return Err(EvalError::type_mismatch("deepEval", "list", &node))
+                    }
+                }
+
+                self.push_nil();
+                for _ in 0..len {
+                    self.stack_swap();
+                    self.mk_cons();
+                }
             },
-            Node::FnDef(def) => write!(f, "fn<{}:{}>", def.name, def.arity),
-            Node::NodeRef(nref) => write!(f, "{}", nref.as_ref()),
+            Node::Data { fields, .. } | Node::Tuple(fields) => {
+                // the original node stays below the evaluated fields, keeping them rooted
+                for i in 0..fields.len() {
+                    self.stack_push(fields[i]);
+                    self.deep_eval()?;
+                }
+
+                let new_size = self.stack_size() - fields.len();
+                let new_fields = self.get_cur_stack_mut().split_off(new_size);
+                let new_fields = self.alloc(new_fields);
+                let node = match self.stack_pop() {
+                    Node::Data { tag, .. } => Node::Data { tag, fields: new_fields },
+                    _ => Node::Tuple(new_fields)
+                };
+                self.stack_push(node);
+            },
+            Node::Array(mut array) => {
+                // an array's elements are shared, evaluating them in place is
+                // no different from updating the thunks they are
+                for i in 0..array.elems.len() {
+                    self.stack_push(array.elems[i]);
+                    self.deep_eval()?;
+                    array.as_mut().elems[i] = self.stack_pop();
+                }
+            },
+            Node::Map(tree) => {
+                // the keys already are, the values are evaluated onto the stack
+                // below the map and make a tree of their own
+                let entries = tree.entries();
+                for (_, value) in entries.iter() {
+                    self.stack_push(*value);
+                    self.deep_eval()?;
+                    self.stack_swap();
+                }
+                self.stack_pop();
+                let new_size = self.stack_size() - entries.len();
+                let values = self.get_cur_stack_mut().split_off(new_size);
+                let tree = tree.with_values(&mut values.into_iter());
+                let tree = self.alloc(tree);
+                self.stack_push(Node::Map(tree));
+            },
+            _ => {}
         }
+        Ok(())
     }
-}
 
-impl fmt::Debug for Thunk {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Thunk::UThunk(_) => write!(f, "#[UNEVALED]"),
-            Thunk::EThunk(val) => write!(f, "#[{:?}]", val),
-        }
+    /// Structurally compares the two values on top of the stack, popping them.
+    /// Both are only forced as far as needed to find a difference.
+    pub fn deep_eq(&mut self) -> EvalResult<bool> {
+        let base = self.stack_size() - 2;
+        let equal = self.deep_eq_pairs(base);
+        // the pairs left pending once a difference or an error is found
+        self.get_cur_stack_mut().truncate(base);
+        equal
     }
-}
 
+    fn deep_eq_pairs(&mut self, base: usize) -> EvalResult<bool> {
+        // pending pairs of nodes to compare live on the stack, to keep them rooted
+        while self.stack_size() > base {
+            self.eval()?;
+            self.stack_swap();
+            self.eval()?;
 
-/* ***************** *
- * Builtin Functions *
- * ***************** */
+            let vr = self.stack_pop();
+            let vl = self.stack_pop();
+            let equal = match (vl, vr) {
+                (Node::Nil, Node::Nil) => true,
+                (Node::Nil, Node::Cons(_, _)) | (Node::Cons(_, _), Node::Nil) => false,
+                (Node::Cons(hl, tl), Node::Cons(hr, tr)) => {
+                    self.stack_push(*tr);
+                    self.stack_push(*tl);
+                    self.stack_push(*hr);
+                    self.stack_push(*hl);
+                    true
+                },
+                (Node::Data { tag: tl, fields: fl }, Node::Data { tag: tr, fields: fr }) => {
+                    tl == tr && self.push_field_pairs(&fl, &fr)
+                },
+                (Node::Tuple(fl), Node::Tuple(fr)) => self.push_field_pairs(&fl, &fr),
+                (Node::Array(al), Node::Array(ar)) => {
+                    al.lo == ar.lo && al.hi == ar.hi && self.push_field_pairs(&al.elems, &ar.elems)
+                },
+                (Node::Map(tl), Node::Map(tr)) => {
+                    let flatten = |tree: Gc<MapTree>| -> Fields {
+                        tree.entries().into_iter().flat_map(|(key, value)| [key, value]).collect()
+                    };
+                    self.push_field_pairs(&flatten(tl), &flatten(tr))
+                },
+                (Node::Set(tl), Node::Set(tr)) => {
+                    let keys = |tree: Gc<MapTree>| -> Fields {
+                        tree.entries().into_iter().map(|(key, _)| key).collect()
+                    };
+                    self.push_field_pairs(&keys(tl), &keys(tr))
+                },
+                (Node::Nil, _) | (Node::Cons(_, _), _) | (Node::Data { .. }, _) | (Node::Tuple(_), _)
+                | (Node::Array(_), _) | (Node::Map(_), _) | (Node::Set(_), _) => {
+                    return Err(EvalError::type_mismatch("deepEq", vl.kind(), &vr))
+                },
+                (vl, vr) => partial_cmp_nodes("deepEq", &vl, &vr)? == Some(Ordering::Equal)
+            };
 
-pub static FN_ADD: FnDef = FnDef {
-    name: "add",
-    arity: 2,
-    fn_ref: eval_add
-};
+            if !equal {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
 
-pub static FN_SUB: FnDef = FnDef {
-    name: "sub",
-    arity: 2,
-    fn_ref: eval_sub
-};
+    /// Queues the fields for comparison, first fields on top,
+    /// or returns false if the arities differ.
+    fn push_field_pairs(&mut self, fl: &Fields, fr: &Fields) -> bool {
+        if fl.len() != fr.len() {
+            return false;
+        }
+        for (l, r) in fl.iter().zip(fr.iter()).rev() {
+            self.stack_push(*r);
+            self.stack_push(*l);
+        }
+        true
+    }
 
-pub static FN_MUL: FnDef = FnDef {
-    name: "mul",
-    arity: 2,
-    fn_ref: eval_mul
-};
+    /// Structurally orders the two values on top of the stack, popping them:
+    /// lists lexicographically, constructors by tag and then by fields, and
+    /// arrays, maps and sets by their associations in order, as the derived
+    /// and library `Ord` instances do. Both are only forced as far as needed
+    /// to find a difference.
+    pub fn deep_cmp(&mut self) -> EvalResult<Ordering> {
+        let base = self.stack_size() - 2;
+        let ordering = self.deep_cmp_pairs(base);
+        // the pairs left pending once a difference or an error is found
+        self.get_cur_stack_mut().truncate(base);
+        ordering
+    }
 
-pub static FN_DIV: FnDef = FnDef {
-    name: "div",
-    arity: 2,
-    fn_ref: eval_div
-};
+    fn deep_cmp_pairs(&mut self, base: usize) -> EvalResult<Ordering> {
+        // pending pairs of nodes to compare live on the stack, to keep them rooted
+        while self.stack_size() > base {
+            self.eval()?;
+            self.stack_swap();
+            self.eval()?;
 
-macro_rules! bin_arith {
-    ($state:ident, $op:tt) => {
-        $state.eval(); 
-        let vl = $state.stack_pop();
+            let vr = self.stack_pop();
+            let vl = self.stack_pop();
+            let ordering = match (vl, vr) {
+                (Node::Nil, Node::Nil) => Ordering::Equal,
+                (Node::Nil, Node::Cons(_, _)) => Ordering::Less,
+                (Node::Cons(_, _), Node::Nil) => Ordering::Greater,
+                (Node::Cons(hl, tl), Node::Cons(hr, tr)) => {
+                    self.stack_push(*tr);
+                    self.stack_push(*tl);
+                    self.stack_push(*hr);
+                    self.stack_push(*hl);
+                    Ordering::Equal
+                },
+                (Node::Data { tag: tl, fields: fl }, Node::Data { tag: tr, fields: fr }) => {
+                    tl.cmp(&tr).then_with(|| self.push_ordered_pairs(&fl, &fr))
+                },
+                (Node::Tuple(fl), Node::Tuple(fr)) => self.push_ordered_pairs(&fl, &fr),
+                (Node::Array(al), Node::Array(ar)) => {
+                    let assocs = |array: &Array| -> Fields {
+                        array.elems.iter().enumerate()
+                            .flat_map(|(i, elem)| [Node::Int(array.lo + i as i64), *elem])
+                            .collect()
+                    };
+                    let (al, ar) = (assocs(&al), assocs(&ar));
+                    self.push_ordered_pairs(&al, &ar)
+                },
+                (Node::Map(tl), Node::Map(tr)) => {
+                    let flatten = |tree: Gc<MapTree>| -> Fields {
+                        tree.entries().into_iter().flat_map(|(key, value)| [key, value]).collect()
+                    };
+                    self.push_ordered_pairs(&flatten(tl), &flatten(tr))
+                },
+                (Node::Set(tl), Node::Set(tr)) => {
+                    let keys = |tree: Gc<MapTree>| -> Fields {
+                        tree.entries().into_iter().map(|(key, _)| key).collect()
+                    };
+                    self.push_ordered_pairs(&keys(tl), &keys(tr))
+                },
+                (Node::Nil, _) | (Node::Cons(_, _), _) | (Node::Data { .. }, _) | (Node::Tuple(_), _)
+                | (Node::Array(_), _) | (Node::Map(_), _) | (Node::Set(_), _) => {
+                    return Err(EvalError::type_mismatch("deepCompare", vl.kind(), &vr))
+                },
+                // unordered values (NaN) compare as `GT`, as with `compare`
+                (vl, vr) => partial_cmp_nodes("deepCompare", &vl, &vr)?.unwrap_or(Ordering::Greater)
+            };
 
-        $state.eval();
-        let vr = $state.stack_pop();
+            if ordering != Ordering::Equal {
+                return Ok(ordering);
+            }
+        }
+        Ok(Ordering::Equal)
+    }
 
-        if let Node::Int(vl) = vl {
-            if let Node::Int(vr) = vr {
-                $state.stack_push(Node::Int(vl $op vr));
-            } else {
-                panic!("Expecting integer for right operand: {:?}", vr)
+    /// Queues the fields for lexicographic comparison, first fields on top
+    /// and their counts below them all, so that of two sequences equal as
+    /// far as the shorter goes the shorter comes first.
+    fn push_ordered_pairs(&mut self, fl: &Fields, fr: &Fields) -> Ordering {
+        let (len_l, len_r) = (fl.len() as i64, fr.len() as i64);
+        self.stack_push(Node::Int(len_r));
+        self.stack_push(Node::Int(len_l));
+        for (l, r) in fl.iter().zip(fr.iter()).rev() {
+            self.stack_push(*r);
+            self.stack_push(*l);
+        }
+        Ordering::Equal
+    }
+
+    /// Forces the string (either a `[Char]` list or packed text)
+    /// on top of the stack, popping it.
+    pub fn eval_string(&mut self) -> EvalResult<String> {
+        let mut string = String::new();
+        loop {
+            self.eval()?;
+            match self.stack_pop() {
+                Node::Nil => return Ok(string),
+                Node::Cons(head, tail) => {
+                    self.stack_push(*tail);
+                    self.stack_push(*head);
+                    self.eval()?;
+                    match self.stack_pop() {
+                        Node::Char(c) => string.push(c),
+                        node => return Err(EvalError::type_mismatch("string", "Char", &node))
+                    }
+                },
+                Node::Text(text) if string.is_empty() => return Ok(text.as_ref().clone()),
+                node => return Err(EvalError::type_mismatch("string", "list", &node))
             }
-        } else {
-            panic!("Expecting integer for left operand: {:?}", vl)
         }
-    };
-}
+    }
 
-pub fn eval_add(state: &mut State) {
-    bin_arith!(state, +);
-}
+    /// Evaluates `node` to WHNF and returns it, for hosts embedding the
+    /// runtime. On failure the stacks are restored to how they were before
+    /// the call, so the state stays usable after reporting the error.
+    pub fn try_eval(&mut self, node: Node) -> EvalResult<Node> {
+        let frame_size = self.stack_size();
+        self.stack_push(node);
+        match self.eval() {
+            Ok(()) => Ok(self.stack_pop()),
+            Err(err) => {
+                self.get_cur_stack_mut().truncate(frame_size);
+                Err(err)
+            }
+        }
+    }
 
-pub fn eval_sub(state: &mut State) {
-    bin_arith!(state, -);
-}
+    /// Applies the function on top of the stack to the `n` arguments below it
+    /// (first argument next to the function) and evaluates the result to WHNF.
+    /// This is the one call shape needed for unknown functions: an exact call
+    /// enters the function straight away, an under-saturated one leaves a
+    /// partial application, and an over-saturated one applies the result of
+    /// the call to the arguments left over.
+    pub fn apply(&mut self, n: usize) -> EvalResult {
+        let mut remaining = n;
+        loop {
+            self.eval()?;
+            if remaining == 0 {
+                return Ok(());
+            }
 
-pub fn eval_mul(state: &mut State) {
-    bin_arith!(state, *);
-}
+            // the held arguments are only copied out if a new partial application needs them
+            let (fn_def, held_args) = match self.stack_pop() {
+                Node::FnDef(fn_def) => (fn_def, None),
+                Node::Pap(fn_def, args) => (fn_def, Some(args)),
+                node => return Err(EvalError::type_mismatch("apply", "function", &node))
+            };
+            let held_args: &[Node] = held_args.as_ref().map_or(&[], |args| args.as_slice());
+            let needed = fn_def.arity - held_args.len();
 
-pub fn eval_div(state: &mut State) {
-    bin_arith!(state, /);
-}
+            if remaining < needed {
+                let mut args = held_args.to_vec();
+                for _ in 0..remaining {
+                    args.push(self.stack_pop());
+                }
+                let args = self.alloc(args);
+                self.stack_push(Node::Pap(fn_def, args));
+                return Ok(());
+            }
 
-pub struct TracedThunk {
+            if !self.use_fuel(false) {
+                return Err(EvalError::FuelExhausted);
+            }
+            for arg in held_args.iter().rev() {
+                self.stack_push(*arg);
+            }
+            self.call(&fn_def)?;
+            remaining -= needed;
+        }
+    }
+
+    pub fn eval(&mut self) -> EvalResult {
+        // SPJ:321
+
+        let reducible = match self.stack_peek() {
+            Node::FnDef(_) | Node::App(_, _) | Node::ThunkRef(_) | Node::NodeRef(_) => true,
+            // a saturated call
+            node => !node.is_whnf()
+        };
+        if reducible {
+            let node = self.stack_pop();  // copy the node
+            let depth = self.stacks.len();
+            self.stack_enter_new();
+            self.stack_push(node);
+            if let Err(err) = self.unwind() {
+                // drop the frames of the failed evaluation
+                self.stacks.truncate(depth);
+                return Err(err);
+            }
+            let unwind_result = self.stack_pop();
+            self.stack_exit();
+            self.stack_push(unwind_result);
+        }
+        Ok(())
+    }
+
+    /// Evaluates `node` to WHNF performing at most `fuel` reductions, i.e.
+    /// function entries. If it runs out first, the evaluation is suspended
+    /// where it stopped and can be resumed with fresh fuel.
+    ///
+    /// Only the unwinder itself can be suspended: when a function evaluates
+    /// a value on its own (as `case` does with its scrutinee) that runs on
+    /// the native stack, and running out of fuel there fails with
+    /// `EvalError::FuelExhausted` instead. Thunks forced before either
+    /// happens keep their values.
+    pub fn eval_with_fuel(&mut self, node: Node, fuel: u64) -> EvalResult<StepResult> {
+        let depth = self.stacks.len();
+        self.stack_enter_new();
+        self.stack_push(node);
+        self.run_with_fuel(Vec::new(), 1, depth, fuel, true)
+    }
+
+    /// Continues a suspended evaluation with another `fuel` reductions.
+    pub fn resume_with_fuel(&mut self, suspension: Suspension, fuel: u64) -> EvalResult<StepResult> {
+        let Suspension { resume, root_size, depth } = suspension;
+        self.run_with_fuel(resume, root_size, depth, fuel, true)
+    }
+
+    /// Like `eval_with_fuel`, but with only the unwinder's own reductions
+    /// counted, so that it can always be suspended before the next one.
+    pub(crate) fn step_with_fuel(&mut self, node: Node, fuel: u64) -> EvalResult<StepResult> {
+        let depth = self.stacks.len();
+        self.stack_enter_new();
+        self.stack_push(node);
+        self.run_with_fuel(Vec::new(), 1, depth, fuel, false)
+    }
+
+    /// Like `resume_with_fuel`, counting reductions as `step_with_fuel` does.
+    pub(crate) fn resume_steps(&mut self, suspension: Suspension, fuel: u64) -> EvalResult<StepResult> {
+        let Suspension { resume, root_size, depth } = suspension;
+        self.run_with_fuel(resume, root_size, depth, fuel, false)
+    }
+
+    /// Drops a suspended evaluation, leaving the thunks it was forcing
+    /// to be evaluated again.
+    pub fn abandon(&mut self, suspension: Suspension) {
+        Self::restore_thunks(suspension.resume);
+        self.stacks.truncate(suspension.depth);
+    }
+
+    fn run_with_fuel(&mut self, mut resume: Vec<Resume>, root_size: usize, depth: usize,
+                     fuel: u64, nested_fuel: bool) -> EvalResult<StepResult> {
+        self.fuel = Some(fuel);
+        self.nested_fuel = nested_fuel;
+        let result = self.unwind_iter(&mut resume, root_size, true);
+        self.fuel = None;
+        self.nested_fuel = true;
+
+        match result {
+            Ok(None) => {
+                let value = self.stack_pop();
+                self.stack_exit();
+                Ok(StepResult::Done(value))
+            },
+            Ok(Some(root_size)) => Ok(StepResult::Suspended(Suspension { resume, root_size, depth })),
+            Err(err) => {
+                let err = Self::locate_error(err, &resume);
+                Self::restore_thunks(resume);
+                self.stacks.truncate(depth);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn unwind(&mut self) -> EvalResult {
+        // SPJ:322
+
+        let mut resume = Vec::new();
+        let root_size = self.stack_size();
+        match self.unwind_iter(&mut resume, root_size, false) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let err = Self::locate_error(err, &resume);
+                Self::restore_thunks(resume);
+                Err(err)
+            }
+        }
+    }
+
+    /// Adds what the unwinder was in the middle of to an error leaving it:
+    /// the thunks being forced and the functions waiting on strict arguments.
+    fn locate_error(err: EvalError, resume: &[Resume]) -> EvalError {
+        resume.iter().rev().fold(err, |err, cont| match cont {
+            Resume::Update { thunk, .. } => {
+                let err = match thunk.loc {
+                    Some(loc) => err.at(loc),
+                    None => err
+                };
+                match thunk.label {
+                    Some(label) => err.called_from(label),
+                    None => err
+                }
+            },
+            Resume::StrictArg { fn_def, .. } => err.called_from(fn_def.name)
+        })
+    }
+
+    /// Thunks that were being forced may be entered again after evaluation
+    /// stops, so they get back the expressions they were holding.
+    fn restore_thunks(resume: Vec<Resume>) {
+        for cont in resume {
+            if let Resume::Update { mut thunk, expr, .. } = cont {
+                thunk.as_mut().state = ThunkState::Pending(expr);
+            }
+        }
+    }
+
+    /// Reduces the expression on top of the stack to WHNF without recursing:
+    /// thunks and strict arguments are reduced in frames of their own, with
+    /// what to do once they are done kept on `resume` instead of the native
+    /// stack. A thunk is blackholed while it is being forced, so a value that
+    /// depends on itself fails with `<<loop>>` instead of looping forever.
+    ///
+    /// Returns the root of the current frame if the fuel ran out and
+    /// `suspendable` allowed stopping before the next reduction.
+    fn unwind_iter(&mut self, resume: &mut Vec<Resume>, mut root_size: usize, suspendable: bool) -> EvalResult<Option<usize>> {
+        loop {
+            match *self.stack_peek() {
+                Node::App(nl, _) => {
+                    self.stack_push(nl.short_circuit());
+                    continue;
+                },
+                Node::NodeRef(target) => {
+                    self.stack_pop();
+                    self.stack_push(target.short_circuit());
+                    continue;
+                },
+                Node::ThunkRef(mut thunk) => {
+                    #[cfg(feature = "std")]
+                    if self.sparks.workers.has_jobs() {
+                        // a spark given to the workers gets its value from them
+                        self.claim_spark(thunk);
+                    }
+                    // take the contents out of the cell before reducing them, so no borrow
+                    // of the cell is alive while nested evaluation enters it again
+                    let expr = match core::mem::replace(&mut thunk.as_mut().state, ThunkState::Blackhole) {
+                        ThunkState::EThunk(value) => {
+                            thunk.as_mut().state = ThunkState::EThunk(value);
+                            self.stack_pop();
+                            self.stack_push(value);
+                            continue;
+                        },
+                        ThunkState::Blackhole => return Err(EvalError::NonTermination(thunk.label)),
+                        ThunkState::Pending(expr) => expr,
+                        // the code is dropped here, with whatever it captured, and the
+                        // thunk holds the expression it gave from then on
+                        ThunkState::UThunk(eval) => eval.eval_thunk(),
+                        ThunkState::UCall(eval, args) => eval(&args)
+                    };
+                    // the thunk and a copy of the expression stay below the new
+                    // frame, so the thunk can be restored if evaluating it fails
+                    self.stack_push(expr);
+                    instrument::thunk_enter(&thunk);
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.on_thunk_enter(&thunk, &expr);
+                    }
+                    resume.push(Resume::Update { thunk, expr, root_size });
+                    self.stack_enter_new();
+                    self.stack_push(expr);
+                    root_size = 1;
+                    continue;
+                },
+                Node::FnDef(_) | Node::Pap(_, _) => {
+                    let (fn_def, held_args) = match *self.stack_peek() {
+                        Node::FnDef(fn_def) => (fn_def, None),
+                        Node::Pap(fn_def, args) => (fn_def, Some(args)),
+                        _ => unreachable!()
+                    };
+                    let held = held_args.map_or(0, |args| args.len());
+
+                    if self.stack_size() - root_size + held >= fn_def.arity {
+                        // the spine is still intact here, so unwinding it again resumes
+                        if !self.use_fuel(suspendable) {
+                            return if suspendable {
+                                Ok(Some(root_size))
+                            } else {
+                                Err(EvalError::FuelExhausted)
+                            };
+                        }
+
+                        self.stack_pop();
+                        // each application on the spine is replaced by its argument in place
+                        let new_size = self.stack_size() - (fn_def.arity - held);
+                        for slot in self.get_cur_stack_mut()[new_size..].iter_mut() {
+                            if let Node::App(_, nr) = *slot {
+                                *slot = *nr;
+                            } else {
+                                unreachable!("Should only be applications on spine");
+                            }
+                        }
+                        // the arguments a partial application already holds come first
+                        if let Some(args) = held_args {
+                            for arg in args.iter().rev() {
+                                self.stack_push(*arg);
+                            }
+                        }
+
+                        if self.enter_strict_arg(resume, fn_def, 0, root_size) {
+                            root_size = 1;
+                        } else {
+                            // the result may itself be reducible (e.g. a returned branch),
+                            // so keep unwinding from it
+                            self.enter_fn(fn_def, root_size)?;
+                        }
+                        continue;
+                    }
+
+                    // under-saturated applications are already in WHNF
+                    self.mk_spine_pap(root_size);
+                },
+                _ => {}
+            }
+
+            // the frame is in WHNF, hand its value back to whatever was waiting on it
+            match resume.pop() {
+                None => return Ok(None),
+                Some(Resume::Update { mut thunk, root_size: outer_root_size, .. }) => {
+                    let value = self.stack_pop();
+                    self.stack_exit();
+                    // while being forced the expression was held below the frame and in the
+                    // resume entry, in case it had to be put back, and both go here
+                    thunk.as_mut().state = ThunkState::EThunk(value);
+                    self.stats.thunks_updated += 1;
+                    instrument::thunk_update(&thunk);
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.on_thunk_update(&thunk);
+                    }
+                    self.stack_pop();
+                    self.stack_pop();
+                    self.stack_push(value);
+                    root_size = outer_root_size;
+                },
+                Some(Resume::StrictArg { fn_def, index, root_size: outer_root_size }) => {
+                    let value = self.stack_pop();
+                    self.stack_exit();
+                    let arg_idx = self.stack_size() - 1 - index;
+                    self.get_cur_stack_mut()[arg_idx] = value;
+
+                    if self.enter_strict_arg(resume, fn_def, index + 1, outer_root_size) {
+                        root_size = 1;
+                    } else {
+                        self.enter_fn(fn_def, outer_root_size)?;
+                        root_size = outer_root_size;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Enters `fn_def` with its arguments on top of the stack. If the spine
+    /// rooted at `root_size` had more arguments than the function takes, its
+    /// result is applied to the rest, leaving the new redex in the root's slot.
+    fn enter_fn(&mut self, fn_def: FnDef, root_size: usize) -> EvalResult {
+        let extra_args = self.stack_size() + 1 - root_size - fn_def.arity;
+        self.call(&fn_def)?;
+
+        for _ in 0..extra_args {
+            let result = self.stack_pop();
+            match self.stack_pop() {
+                Node::App(_, arg) => self.stack_push(*arg),
+                node => unreachable!("Should only be applications on spine: {:?}", node)
+            }
+            self.stack_push(result);
+            self.mk_ap();
+        }
+        Ok(())
+    }
+
+    /// Collects the under-saturated application unwound from `root_size`
+    /// into a single partial application, left in place of its root.
+    fn mk_spine_pap(&mut self, root_size: usize) {
+        if self.stack_size() == root_size {
+            // a bare function or partial application, already in WHNF
+            return;
+        }
+
+        let (fn_def, mut args) = match self.stack_pop() {
+            Node::FnDef(fn_def) => (fn_def, Vec::new()),
+            Node::Pap(fn_def, args) => (fn_def, args.to_vec()),
+            node => unreachable!("Asked to collect non-function spine: {:?}", node)
+        };
+        let spine = self.get_cur_stack_mut().split_off(root_size - 1);
+        for arg_app in spine.iter().rev() {
+            if let Node::App(_, nr) = arg_app {
+                args.push(**nr);
+            } else {
+                unreachable!("Should only be applications on spine");
+            }
+        }
+
+        let args = self.alloc(args);
+        self.stack_push(Node::Pap(fn_def, args));
+    }
+
+    /// Takes one unit of fuel for a reduction, `false` if there is none left.
+    /// `outer` tells the suspendable unwinder apart from nested evaluations.
+    fn use_fuel(&mut self, outer: bool) -> bool {
+        if !outer && !self.nested_fuel {
+            return true;
+        }
+        match &mut self.fuel {
+            None => true,
+            Some(0) => false,
+            Some(fuel) => {
+                *fuel -= 1;
+                true
+            }
+        }
+    }
+
+    /// Looks for the first of `fn_def`'s strict arguments from `from` on that
+    /// isn't in WHNF yet, and enters a new frame to reduce it. Returns `false`
+    /// once they all are and the function can be entered.
+    fn enter_strict_arg(&mut self, resume: &mut Vec<Resume>, fn_def: FnDef, from: usize, root_size: usize) -> bool {
+        for index in from..fn_def.strict {
+            let arg = self.get_cur_stack()[self.stack_size() - 1 - index];
+            if !arg.is_whnf() {
+                resume.push(Resume::StrictArg { fn_def, index, root_size });
+                self.stack_enter_new();
+                self.stack_push(arg);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// What the unwinder does once the expression it is reducing in a nested
+/// frame reaches WHNF.
+enum Resume {
+    /// Overwrite `thunk` with the value, then continue down the spine of the
+    /// enclosing frame, which is rooted at `root_size`.
+    Update { thunk: Gc<Thunk>, expr: Node, root_size: usize },
+    /// Put the value back as argument `index` of `fn_def`, then reduce the
+    /// remaining strict arguments before entering it from the spine rooted
+    /// at `root_size`.
+    StrictArg { fn_def: FnDef, index: usize, root_size: usize }
+}
+
+impl Trace for Node {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        match self {
+            Node::Int(_) => {},
+            Node::Integer(integer) => integer.mark_into(worklist),
+            Node::Ratio(ratio) => ratio.mark_into(worklist),
+            Node::Double(_) => {},
+            Node::Bool(_) => {},
+            Node::Char(_) => {},
+            Node::FnDef(_) => {},
+            Node::Nil => {},
+            Node::App(nl, nr) | Node::Cons(nl, nr) => {
+                nl.mark_into(worklist);
+                nr.mark_into(worklist);
+            },
+            Node::Data { fields, .. } | Node::Tuple(fields) | Node::Pap(_, fields) => fields.mark_into(worklist),
+            Node::Text(text) => text.mark_into(worklist),
+            Node::ThunkRef(thunk) => thunk.mark_into(worklist),
+            Node::NodeRef(node_ref) => node_ref.mark_into(worklist),
+            Node::IO(action) => action.mark_into(worklist),
+            #[cfg(feature = "std")]
+            Node::Handle(handle) => handle.mark_into(worklist),
+            Node::IORef(cell) => cell.mark_into(worklist),
+            Node::IOArray(array) | Node::Array(array) => array.mark_into(worklist),
+            Node::Map(tree) | Node::Set(tree) => tree.mark_into(worklist),
+            Node::MVar(mvar) => mvar.mark_into(worklist),
+            Node::TVar(tvar) => tvar.mark_into(worklist),
+            Node::Weak(weak) => weak.mark_into(worklist),
+            Node::StableName(name) => name.mark_into(worklist),
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        match self {
+            // a cell pointing at a thunk, counted apart from the thunk itself
+            Node::ThunkRef(_) => ("thunk reference", None),
+            node => (node.kind(), None)
+        }
+    }
+}
+
+impl Trace for Thunk {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        match &self.state {
+            ThunkState::Pending(node) | ThunkState::EThunk(node) => node.mark_refs(worklist),
+            ThunkState::UThunk(eval) => eval.mark_refs(worklist),
+            ThunkState::UCall(_, args) => {
+                for arg in args {
+                    arg.mark_refs(worklist);
+                }
+            },
+            ThunkState::Blackhole => {}
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("thunk", self.label)
+    }
+}
+
+impl Node {
+    /// Follows indirections, i.e. `NodeRef`s and thunks updated with their
+    /// value, through to the node they stand for.
+    pub fn short_circuit(self) -> Node {
+        let mut node = self;
+        loop {
+            node = match node {
+                Node::ThunkRef(thunk) => match thunk.state {
+                    ThunkState::EThunk(value) => value,
+                    _ => return node
+                },
+                Node::NodeRef(target) => *target,
+                _ => return node
+            };
+        }
+    }
+
+    /// Whether the node is a value that evaluation leaves as it is.
+    pub fn is_whnf(&self) -> bool {
+        match self {
+            Node::App(_, _) | Node::ThunkRef(_) | Node::NodeRef(_) => false,
+            Node::FnDef(fn_def) => fn_def.arity > 0,
+            Node::Pap(fn_def, args) => args.len() < fn_def.arity,
+            _ => true
+        }
+    }
+
+    /// A short name for the kind of node, used when reporting errors.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Node::Int(_) => "Int",
+            Node::Integer(_) => "Integer",
+            Node::Ratio(_) => "Rational",
+            Node::Double(_) => "Double",
+            Node::Bool(_) => "Bool",
+            Node::Char(_) => "Char",
+            Node::FnDef(_) | Node::Pap(_, _) => "function",
+            Node::App(_, _) => "application",
+            Node::Nil | Node::Cons(_, _) => "list",
+            Node::Data { .. } => "constructor",
+            Node::Tuple(_) => "tuple",
+            Node::Text(_) => "Text",
+            Node::ThunkRef(_) => "thunk",
+            Node::NodeRef(node_ref) => node_ref.kind(),
+            Node::IO(_) => "IO action",
+            #[cfg(feature = "std")]
+            Node::Handle(_) => "handle",
+            Node::IORef(_) => "IORef",
+            Node::IOArray(_) => "IOArray",
+            Node::Array(_) => "array",
+            Node::Map(_) => "Map",
+            Node::Set(_) => "Set",
+            Node::MVar(_) => "MVar",
+            Node::TVar(_) => "TVar",
+            Node::Weak(_) => "Weak",
+            Node::StableName(_) => "StableName"
+        }
+    }
+
+    /// The constructor tag of a value in WHNF, numbering lists, booleans and tuples
+    /// as `case_split` does.
+    pub fn con_tag(&self) -> Option<u32> {
+        match self {
+            Node::Data { tag, .. } => Some(*tag),
+            Node::Bool(b) => Some(*b as u32),
+            Node::Nil | Node::Tuple(_) => Some(0),
+            Node::Cons(_, _) => Some(1),
+            Node::NodeRef(node_ref) => node_ref.con_tag(),
+            _ => None
+        }
+    }
+}
+
+impl Trace for BigInt {
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("Integer", None)
+    }
+
+    fn heap_size(&self) -> usize {
+        core::mem::size_of::<BigInt>() + self.heap_bytes()
+    }
+}
+
+impl Trace for Rational {
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("Rational", None)
+    }
+
+    fn heap_size(&self) -> usize {
+        core::mem::size_of::<Rational>() + self.numerator().heap_bytes() + self.denominator().heap_bytes()
+    }
+}
+
+impl Trace for Text {
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("Text", None)
+    }
+
+    fn heap_size(&self) -> usize {
+        core::mem::size_of::<Text>() + self.capacity()
+    }
+}
+
+impl Trace for Fields {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        for field in self.iter() {
+            field.mark_refs(worklist);
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("fields", None)
+    }
+
+    fn heap_size(&self) -> usize {
+        core::mem::size_of::<Fields>() + self.capacity() * core::mem::size_of::<Node>()
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Int(i) => {
+                write!(f, "{}", i)
+            }
+            Node::Integer(i) => write!(f, "{}", i.as_ref()),
+            Node::Ratio(r) => write!(f, "{}", r.as_ref()),
+            Node::Double(d) => {
+                if d.is_nan() {
+                    write!(f, "NaN")
+                } else if d.is_infinite() {
+                    write!(f, "{}Infinity", if *d < 0.0 { "-" } else { "" })
+                } else {
+                    write!(f, "{:?}", d)
+                }
+            }
+            Node::Bool(b) => {
+                write!(f, "{}", if *b { "True" } else { "False" })
+            }
+            Node::Char(c) => write!(f, "{:?}", c),
+            // what isn't evaluated yet is left as it is, see `State::render`
+            Node::ThunkRef(t) => match t.state {
+                ThunkState::EThunk(value) => write!(f, "{}", value),
+                _ => write!(f, "<thunk>")
+            },
+            Node::App(_, _) => write!(f, "<thunk>"),
+            Node::FnDef(_) | Node::Pap(_, _) => write!(f, "<function>"),
+            Node::Nil => write!(f, "[]"),
+            Node::Cons(head, _) if matches!(head.as_ref(), Node::Char(_)) => {
+                write!(f, "\"")?;
+                let mut rest = *self;
+                while let Node::Cons(head, tail) = rest {
+                    match head.short_circuit() {
+                        Node::Char(c) => write!(f, "{}", c.escape_debug())?,
+                        node => write!(f, "{}", node)?
+                    }
+                    rest = tail.short_circuit();
+                }
+                if !matches!(rest, Node::Nil) {
+                    write!(f, "..")?;
+                }
+                write!(f, "\"")
+            }
+            Node::Cons(head, tail) => {
+                write!(f, "[{}", head)?;
+                let mut rest = tail.short_circuit();
+                while let Node::Cons(head, tail) = rest {
+                    write!(f, ",{}", head)?;
+                    rest = tail.short_circuit();
+                }
+                // the rest of a list not evaluated yet
+                if !matches!(rest, Node::Nil) {
+                    write!(f, ",..")?;
+                }
+                write!(f, "]")
+            }
+            Node::Data { tag, fields } => {
+                write!(f, "Pack{{{},{}}}", tag, fields.len())?;
+                for field in fields.iter() {
+                    match field {
+                        Node::Data { fields, .. } if !fields.is_empty() => write!(f, " ({})", field)?,
+                        _ => write!(f, " {}", field)?
+                    }
+                }
+                Ok(())
+            }
+            Node::Tuple(fields) => {
+                write!(f, "(")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", field)?;
+                }
+                write!(f, ")")
+            }
+            Node::Text(text) => write!(f, "\"{}\"", text.escape_debug()),
+            Node::NodeRef(nref) => write!(f, "{}", nref.as_ref()),
+            Node::IO(_) => write!(f, "<IO>"),
+            #[cfg(feature = "std")]
+            Node::Handle(handle) => write!(f, "{{handle: {}}}", handle.name),
+            Node::IORef(_) => write!(f, "<IORef>"),
+            Node::IOArray(_) => write!(f, "<IOArray>"),
+            Node::MVar(_) => write!(f, "<MVar>"),
+            Node::TVar(_) => write!(f, "<TVar>"),
+            Node::Weak(_) => write!(f, "<Weak>"),
+            Node::StableName(_) => write!(f, "<StableName>"),
+            Node::Array(array) => {
+                write!(f, "array ({},{}) [", array.lo, array.hi)?;
+                for (i, elem) in array.elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "({},{})", array.lo + i as i64, elem)?;
+                }
+                write!(f, "]")
+            },
+            Node::Map(tree) => {
+                write!(f, "fromList [")?;
+                for (i, (key, value)) in tree.entries().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "({},{})", key, value)?;
+                }
+                write!(f, "]")
+            },
+            Node::Set(tree) => {
+                write!(f, "fromList [")?;
+                for (i, (key, _)) in tree.entries().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", key)?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Int(i) => write!(f, "Int({})", i), 
+            Node::Integer(i) => write!(f, "Integer({})", i.as_ref()),
+            Node::Ratio(r) => write!(f, "Ratio({})", r.as_ref()),
+            Node::Double(d) => write!(f, "Double({:?})", d),
+            Node::Bool(b) => write!(f, "Bool({})", b),
+            Node::Char(c) => write!(f, "Char({:?})", c),
+            Node::ThunkRef(t) => write!(f, "{:?}", t.as_ref()),
+            Node::App(el, er) => {
+                write!(f, "@({:?}, {:?})", el, er)
+            },
+            Node::FnDef(def) => write!(f, "fn<{}:{}>", def.name, def.arity),
+            Node::Pap(def, args) => write!(f, "fn<{}:{}>{:?}", def.name, def.arity, args.as_ref()),
+            Node::Nil => write!(f, "[]"),
+            Node::Cons(head, tail) => write!(f, "({:?} : {:?})", head, tail),
+            Node::Data { tag, fields } => write!(f, "Pack{{{},{}}}{:?}", tag, fields.len(), fields.as_ref()),
+            Node::Tuple(fields) => write!(f, "Tuple{:?}", fields.as_ref()),
+            Node::Text(text) => write!(f, "Text({:?})", text.as_ref()),
+            Node::NodeRef(nref) => write!(f, "{}", nref.as_ref()),
+            Node::IO(action) => match action.as_ref() {
+                IoAction::Return(value) => write!(f, "IO(return {:?})", value),
+                IoAction::Bind(m, k) => write!(f, "IO({:?} >>= {:?})", m, k),
+                IoAction::Prim(def, args) => write!(f, "IO({}{:?})", def.name, args.as_ref()),
+                IoAction::Catch(m, handler) => write!(f, "IO(catch {:?} {:?})", m, handler)
+            },
+            #[cfg(feature = "std")]
+            Node::Handle(handle) => write!(f, "Handle({:?})", handle.name),
+            Node::IORef(cell) => write!(f, "IORef@{:x}", cell.addr()),
+            Node::IOArray(array) => write!(f, "IOArray@{:x}", array.addr()),
+            Node::MVar(mvar) => write!(f, "MVar@{:x}", mvar.addr()),
+            Node::TVar(tvar) => write!(f, "TVar@{:x}", tvar.addr()),
+            Node::Weak(weak) => write!(f, "Weak@{:x}", weak.addr()),
+            Node::StableName(name) => write!(f, "StableName@{:x}", name.addr()),
+            Node::Array(array) => write!(f, "Array({},{}){:?}", array.lo, array.hi, array.elems),
+            Node::Map(tree) => write!(f, "Map{:?}", tree.entries()),
+            Node::Set(tree) => write!(f, "Set{:?}", tree.entries().iter().map(|(key, _)| key).collect::<Vec<_>>()),
+        }
+    }
+}
+
+impl fmt::Debug for Thunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#[")?;
+        if let Some(label) = self.label {
+            write!(f, "{}: ", label)?;
+        }
+        match &self.state {
+            ThunkState::UThunk(_) | ThunkState::UCall(_, _) | ThunkState::Pending(_) => write!(f, "UNEVALED]"),
+            ThunkState::Blackhole => write!(f, "BLACKHOLE]"),
+            ThunkState::EThunk(val) => write!(f, "{:?}]", val),
+        }
+    }
+}
+
+
+/* ***************** *
+ * Builtin Functions *
+ * ***************** */
+
+pub static FN_ADD: FnDef = FnDef {
+    name: "add",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_add
+};
+
+pub static FN_SUB: FnDef = FnDef {
+    name: "sub",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_sub
+};
+
+pub static FN_MUL: FnDef = FnDef {
+    name: "mul",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_mul
+};
+
+pub static FN_FDIV: FnDef = FnDef {
+    name: "/",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_fdiv
+};
+
+pub static FN_DIV: FnDef = FnDef {
+    name: "div",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_div
+};
+
+pub static FN_MOD: FnDef = FnDef {
+    name: "mod",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_mod
+};
+
+pub static FN_QUOT: FnDef = FnDef {
+    name: "quot",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_quot
+};
+
+pub static FN_REM: FnDef = FnDef {
+    name: "rem",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_rem
+};
+
+pub static FN_DIV_MOD: FnDef = FnDef {
+    name: "divMod",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_div_mod
+};
+
+pub static FN_QUOT_REM: FnDef = FnDef {
+    name: "quotRem",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_quot_rem
+};
+
+pub static FN_NEGATE: FnDef = FnDef {
+    name: "negate",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_negate
+};
+
+pub static FN_ABS: FnDef = FnDef {
+    name: "abs",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_abs
+};
+
+pub static FN_SIGNUM: FnDef = FnDef {
+    name: "signum",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_signum
+};
+
+pub static FN_POW: FnDef = FnDef {
+    name: "^",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_pow
+};
+
+pub static FN_GCD: FnDef = FnDef {
+    name: "gcd",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_gcd
+};
+
+pub static FN_LCM: FnDef = FnDef {
+    name: "lcm",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_lcm
+};
+
+pub static FN_MIN: FnDef = FnDef {
+    name: "min",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_min
+};
+
+pub static FN_MAX: FnDef = FnDef {
+    name: "max",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_max
+};
+
+pub static FN_BIT_AND: FnDef = FnDef {
+    name: ".&.",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_bit_and
+};
+
+pub static FN_BIT_OR: FnDef = FnDef {
+    name: ".|.",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_bit_or
+};
+
+pub static FN_XOR: FnDef = FnDef {
+    name: "xor",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_xor
+};
+
+pub static FN_COMPLEMENT: FnDef = FnDef {
+    name: "complement",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_complement
+};
+
+pub static FN_SHIFT_L: FnDef = FnDef {
+    name: "shiftL",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_shift_l
+};
+
+pub static FN_SHIFT_R: FnDef = FnDef {
+    name: "shiftR",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_shift_r
+};
+
+pub static FN_TEST_BIT: FnDef = FnDef {
+    name: "testBit",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_test_bit
+};
+
+pub static FN_POP_COUNT: FnDef = FnDef {
+    name: "popCount",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_pop_count
+};
+
+pub static FN_EQ: FnDef = FnDef {
+    name: "eq",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_eq
+};
+
+pub static FN_NE: FnDef = FnDef {
+    name: "ne",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_ne
+};
+
+pub static FN_LT: FnDef = FnDef {
+    name: "lt",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_lt
+};
+
+pub static FN_LE: FnDef = FnDef {
+    name: "le",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_le
+};
+
+pub static FN_GT: FnDef = FnDef {
+    name: "gt",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_gt
+};
+
+pub static FN_GE: FnDef = FnDef {
+    name: "ge",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_ge
+};
+
+pub static FN_DEEP_EQ: FnDef = FnDef {
+    name: "deepEq",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_deep_eq
+};
+
+pub static FN_DEEP_COMPARE: FnDef = FnDef {
+    name: "deepCompare",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_deep_compare
+};
+
+pub static FN_COMPARE: FnDef = FnDef {
+    name: "compare",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_compare
+};
+
+pub static FN_THEN_CMP: FnDef = FnDef {
+    name: "thenCmp",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_then_cmp
+};
+
+pub static FN_DATA_TO_TAG: FnDef = FnDef {
+    name: "dataToTag",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_data_to_tag
+};
+
+pub static FN_SEQ: FnDef = FnDef {
+    name: "seq",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_seq
+};
+
+pub static FN_STRICT_APP: FnDef = FnDef {
+    name: "$!",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_strict_app
+};
+
+pub static FN_FORCE: FnDef = FnDef {
+    name: "force",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_force
+};
+
+pub static FN_DEEPSEQ: FnDef = FnDef {
+    name: "deepseq",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_deepseq
+};
+
+pub static FN_ERROR: FnDef = FnDef {
+    name: "error",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_error
+};
+
+pub static FN_UNDEFINED: FnDef = FnDef {
+    name: "undefined",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_undefined
+};
+
+pub static FN_TRACE: FnDef = FnDef {
+    name: "trace",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_trace
+};
+
+pub static FN_IF: FnDef = FnDef {
+    name: "if",
+    arity: 3,
+    strict: 1,
+    fn_ref: eval_if
+};
+
+pub static FN_NIL: FnDef = FnDef {
+    name: "nil",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_nil
+};
+
+pub static FN_CONS: FnDef = FnDef {
+    name: "cons",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_cons
+};
+
+pub static FN_HEAD: FnDef = FnDef {
+    name: "head",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_head
+};
+
+pub static FN_TAIL: FnDef = FnDef {
+    name: "tail",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_tail
+};
+
+pub static FN_PAIR: FnDef = FnDef {
+    name: "pair",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_pair
+};
+
+pub static FN_TUPLE3: FnDef = FnDef {
+    name: "tuple3",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_tuple3
+};
+
+pub static FN_TUPLE4: FnDef = FnDef {
+    name: "tuple4",
+    arity: 4,
+    strict: 0,
+    fn_ref: eval_tuple4
+};
+
+pub static FN_FST: FnDef = FnDef {
+    name: "fst",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_fst
+};
+
+pub static FN_SND: FnDef = FnDef {
+    name: "snd",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_snd
+};
+
+pub static FN_TO_INTEGER: FnDef = FnDef {
+    name: "toInteger",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_to_integer
+};
+
+pub static FN_FROM_INTEGER: FnDef = FnDef {
+    name: "fromInteger",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_from_integer
+};
+
+pub static FN_RATIO: FnDef = FnDef {
+    name: "%",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_ratio
+};
+
+pub static FN_NUMERATOR: FnDef = FnDef {
+    name: "numerator",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_numerator
+};
+
+pub static FN_DENOMINATOR: FnDef = FnDef {
+    name: "denominator",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_denominator
+};
+
+pub static FN_PACK: FnDef = FnDef {
+    name: "pack",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_pack
+};
+
+pub static FN_UNPACK: FnDef = FnDef {
+    name: "unpack",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_unpack
+};
+
+static FN_UNPACK_FROM: FnDef = FnDef {
+    name: "unpackFrom",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_unpack_from
+};
+
+pub static FN_TEXT_APPEND: FnDef = FnDef {
+    name: "append",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_text_append
+};
+
+pub static FN_TEXT_LENGTH: FnDef = FnDef {
+    name: "length",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_text_length
+};
+
+pub static FN_TEXT_INDEX: FnDef = FnDef {
+    name: "index",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_text_index
+};
+
+pub static FN_ORD: FnDef = FnDef {
+    name: "ord",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_ord
+};
+
+pub static FN_CHR: FnDef = FnDef {
+    name: "chr",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_chr
+};
+
+/// The overflow variants of an `Int` operation.
+struct IntOp {
+    checked: fn(i64, i64) -> Option<i64>,
+    wrapping: fn(i64, i64) -> i64,
+    saturating: fn(i64, i64) -> i64
+}
+
+static INT_ADD: IntOp = IntOp {
+    checked: i64::checked_add,
+    wrapping: i64::wrapping_add,
+    saturating: i64::saturating_add
+};
+
+static INT_SUB: IntOp = IntOp {
+    checked: i64::checked_sub,
+    wrapping: i64::wrapping_sub,
+    saturating: i64::saturating_sub
+};
+
+static INT_MUL: IntOp = IntOp {
+    checked: i64::checked_mul,
+    wrapping: i64::wrapping_mul,
+    saturating: i64::saturating_mul
+};
+
+/// The overflow variants of a unary `Int` operation.
+struct IntUnOp {
+    checked: fn(i64) -> Option<i64>,
+    wrapping: fn(i64) -> i64,
+    saturating: fn(i64) -> i64
+}
+
+static INT_NEGATE: IntUnOp = IntUnOp {
+    checked: i64::checked_neg,
+    wrapping: i64::wrapping_neg,
+    saturating: i64::saturating_neg
+};
+
+static INT_ABS: IntUnOp = IntUnOp {
+    checked: i64::checked_abs,
+    wrapping: i64::wrapping_abs,
+    saturating: i64::saturating_abs
+};
+
+static INT_SIGNUM: IntUnOp = IntUnOp {
+    checked: |v| Some(v.signum()),
+    wrapping: i64::signum,
+    saturating: i64::signum
+};
+
+/// Evaluates the numeric operand on top of the stack and pushes the result
+/// of the operation for its type, with `Int`s following the overflow policy.
+macro_rules! un_arith {
+    ($state:ident, $context:literal, $int_op:ident, $integer_fn:expr, $ratio_fn:expr, $double_fn:expr) => {{
+        $state.eval()?;
+        let result = match $state.stack_pop() {
+            Node::Int(v) => Node::Int($state.int_un_arith(&$int_op, v)?),
+            Node::Integer(v) => $state.alloc_integer($integer_fn(v.as_ref())),
+            Node::Ratio(v) => $state.alloc_ratio($ratio_fn(v.as_ref())),
+            Node::Double(v) => Node::Double($double_fn(v)),
+            node => return Err(EvalError::type_mismatch($context, "number", &node))
+        };
+        $state.stack_push(result);
+        Ok(())
+    }};
+}
+
+/// Evaluates the two numeric operands on top of the stack
+/// and pushes the result of `op`. Both operands must be of the same type,
+/// with `Int`s following the state's overflow policy through `int_op`.
+macro_rules! bin_arith {
+    ($state:ident, $op:tt, $int_op:ident) => {{
+        $state.eval()?; 
+        $state.stack_swap();
+        $state.eval()?;
+
+        let vr = $state.stack_pop();
+        let vl = $state.stack_pop();
+
+        let context = concat!("(", stringify!($op), ")");
+        let result = match (vl, vr) {
+            (Node::Int(vl), Node::Int(vr)) => Node::Int($state.int_arith(&$int_op, vl, vr)?),
+            (Node::Integer(vl), Node::Integer(vr)) => $state.alloc_integer(vl.as_ref() $op vr.as_ref()),
+            (Node::Ratio(vl), Node::Ratio(vr)) => $state.alloc_ratio(vl.as_ref() $op vr.as_ref()),
+            (Node::Double(vl), Node::Double(vr)) => Node::Double(vl $op vr),
+            (Node::Int(_), _) => return Err(EvalError::type_mismatch(context, "Int for right operand", &vr)),
+            (Node::Integer(_), _) => return Err(EvalError::type_mismatch(context, "Integer for right operand", &vr)),
+            (Node::Ratio(_), _) => return Err(EvalError::type_mismatch(context, "Rational for right operand", &vr)),
+            (Node::Double(_), _) => return Err(EvalError::type_mismatch(context, "Double for right operand", &vr)),
+            _ => return Err(EvalError::type_mismatch(context, "number for left operand", &vl))
+        };
+        $state.stack_push(result);
+        Ok(())
+    }};
+}
+
+/// Orders two evaluated operands of the same comparable type,
+/// `None` meaning they are unordered (only for NaN).
+pub(crate) fn partial_cmp_nodes(context: &'static str, vl: &Node, vr: &Node) -> EvalResult<Option<Ordering>> {
+    match (vl, vr) {
+        (Node::Int(vl), Node::Int(vr)) => Ok(vl.partial_cmp(vr)),
+        (Node::Integer(vl), Node::Integer(vr)) => Ok(vl.as_ref().partial_cmp(vr.as_ref())),
+        (Node::Ratio(vl), Node::Ratio(vr)) => Ok(vl.as_ref().partial_cmp(vr.as_ref())),
+        (Node::Double(vl), Node::Double(vr)) => Ok(vl.partial_cmp(vr)),
+        (Node::Bool(vl), Node::Bool(vr)) => Ok(vl.partial_cmp(vr)),
+        (Node::Char(vl), Node::Char(vr)) => Ok(vl.partial_cmp(vr)),
+        (Node::Text(vl), Node::Text(vr)) => Ok(vl.as_ref().partial_cmp(vr.as_ref())),
+        (Node::Int(_), _) => Err(EvalError::type_mismatch(context, "Int for right operand", vr)),
+        (Node::Integer(_), _) => Err(EvalError::type_mismatch(context, "Integer for right operand", vr)),
+        (Node::Ratio(_), _) => Err(EvalError::type_mismatch(context, "Rational for right operand", vr)),
+        (Node::Double(_), _) => Err(EvalError::type_mismatch(context, "Double for right operand", vr)),
+        (Node::Bool(_), _) => Err(EvalError::type_mismatch(context, "Bool for right operand", vr)),
+        (Node::Char(_), _) => Err(EvalError::type_mismatch(context, "Char for right operand", vr)),
+        (Node::Text(_), _) => Err(EvalError::type_mismatch(context, "Text for right operand", vr)),
+        _ => Err(EvalError::type_mismatch(context, "comparable left operand", vl))
+    }
+}
+
+/// Like `bin_arith!`, but for comparisons between values of the same comparable type.
+macro_rules! bin_cmp {
+    ($state:ident, $op:tt) => {{
+        $state.eval()?; 
+        $state.stack_swap();
+        $state.eval()?;
+
+        let vr = $state.stack_pop();
+        let vl = $state.stack_pop();
+
+        let context = concat!("(", stringify!($op), ")");
+        let result = match partial_cmp_nodes(context, &vl, &vr)? {
+            Some(ordering) => ordering $op Ordering::Equal,
+            // unordered values (NaN) are only ever unequal
+            None => stringify!($op) == "!="
+        };
+        $state.stack_push(Node::Bool(result));
+        Ok(())
+    }};
+}
+
+pub fn eval_add(state: &mut State) -> EvalResult {
+    bin_arith!(state, +, INT_ADD)
+}
+
+pub fn eval_sub(state: &mut State) -> EvalResult {
+    bin_arith!(state, -, INT_SUB)
+}
+
+pub fn eval_mul(state: &mut State) -> EvalResult {
+    bin_arith!(state, *, INT_MUL)
+}
+
+pub fn eval_negate(state: &mut State) -> EvalResult {
+    un_arith!(state, "negate", INT_NEGATE, |v: &BigInt| -v, |v: &Rational| -v, |v: f64| -v)
+}
+
+pub fn eval_abs(state: &mut State) -> EvalResult {
+    un_arith!(state, "abs", INT_ABS, BigInt::abs, Rational::abs, f64::abs)
+}
+
+pub fn eval_signum(state: &mut State) -> EvalResult {
+    // unlike `f64::signum`, zeros (and NaN) are their own sign
+    un_arith!(state, "signum", INT_SIGNUM, BigInt::signum, Rational::signum, |v: f64| {
+        if v > 0.0 { 1.0 } else if v < 0.0 { -1.0 } else { v }
+    })
+}
+
+/// Fractional division, for `Double`s and `Rational`s.
+pub fn eval_fdiv(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    let result = match (vl, vr) {
+        (Node::Double(vl), Node::Double(vr)) => Node::Double(vl / vr),
+        (Node::Ratio(_), Node::Ratio(vr)) if vr.numerator().is_zero() => {
+            return Err(ArithException::RatioZeroDenominator.into())
+        },
+        (Node::Ratio(vl), Node::Ratio(vr)) => state.alloc_ratio(vl.as_ref() / vr.as_ref()),
+        (Node::Double(_), _) => return Err(EvalError::type_mismatch("(/)", "Double for right operand", &vr)),
+        (Node::Ratio(_), _) => return Err(EvalError::type_mismatch("(/)", "Rational for right operand", &vr)),
+        _ => return Err(EvalError::type_mismatch("(/)", "fractional left operand", &vl))
+    };
+    state.stack_push(result);
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rounding {
+    /// Towards zero, as `quot` and `rem`
+    Truncate,
+    /// Towards negative infinity, as `div` and `mod`
+    Floor
+}
+
+/// Divides the two integral operands on top of the stack,
+/// replacing them with the remainder and the quotient (on top).
+fn divide(state: &mut State, context: &'static str, rounding: Rounding) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    match (vl, vr) {
+        (Node::Int(_), Node::Int(0)) => Err(ArithException::DivideByZero.into()),
+        (Node::Int(vl), Node::Int(vr)) => {
+            // only `minBound / (-1)` overflows
+            let (mut quot, mut rem) = match (vl.checked_div(vr), state.overflow_policy) {
+                (Some(quot), _) => (quot, vl % vr),
+                (None, OverflowPolicy::Checked) => return Err(ArithException::Overflow.into()),
+                (None, OverflowPolicy::Wrapping) => (vl.wrapping_div(vr), 0),
+                (None, OverflowPolicy::Saturating) => (vl.saturating_div(vr), 0)
+            };
+            if rounding == Rounding::Floor && rem != 0 && (rem < 0) != (vr < 0) {
+                quot -= 1;
+                rem += vr;
+            }
+            state.push_int(rem);
+            state.push_int(quot);
+            Ok(())
+        },
+        (Node::Integer(vl), Node::Integer(vr)) => {
+            let (mut quot, mut rem) = match vl.div_rem(&vr) {
+                Some(quot_rem) => quot_rem,
+                None => return Err(ArithException::DivideByZero.into())
+            };
+            if rounding == Rounding::Floor && !rem.is_zero() && rem.is_negative() != vr.is_negative() {
+                quot = &quot - &BigInt::from(1);
+                rem = &rem + vr.as_ref();
+            }
+            state.push_integer(rem);
+            state.push_integer(quot);
+            Ok(())
+        },
+        (Node::Int(_), _) => Err(EvalError::type_mismatch(context, "Int for right operand", &vr)),
+        (Node::Integer(_), _) => Err(EvalError::type_mismatch(context, "Integer for right operand", &vr)),
+        _ => Err(EvalError::type_mismatch(context, "integral left operand", &vl))
+    }
+}
+
+pub fn eval_div(state: &mut State) -> EvalResult {
+    divide(state, "div", Rounding::Floor)?;
+    let quot = state.stack_pop();
+    state.stack_pop();
+    state.stack_push(quot);
+    Ok(())
+}
+
+pub fn eval_mod(state: &mut State) -> EvalResult {
+    divide(state, "mod", Rounding::Floor)?;
+    state.stack_pop();
+    Ok(())
+}
+
+pub fn eval_quot(state: &mut State) -> EvalResult {
+    divide(state, "quot", Rounding::Truncate)?;
+    let quot = state.stack_pop();
+    state.stack_pop();
+    state.stack_push(quot);
+    Ok(())
+}
+
+pub fn eval_rem(state: &mut State) -> EvalResult {
+    divide(state, "rem", Rounding::Truncate)?;
+    state.stack_pop();
+    Ok(())
+}
+
+pub fn eval_div_mod(state: &mut State) -> EvalResult {
+    divide(state, "divMod", Rounding::Floor)?;
+    state.mk_tuple(2);
+    Ok(())
+}
+
+pub fn eval_quot_rem(state: &mut State) -> EvalResult {
+    divide(state, "quotRem", Rounding::Truncate)?;
+    state.mk_tuple(2);
+    Ok(())
+}
+
+/// `x ^ n`, for any numeric base and non-negative integral exponent,
+/// by repeated squaring.
+pub fn eval_pow(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let exponent = match state.stack_pop() {
+        Node::Int(n) if n >= 0 => n as u64,
+        Node::Integer(n) if !n.is_negative() => match n.to_i64() {
+            Some(n) => n as u64,
+            None => return Err(ArithException::Overflow.into())
+        },
+        Node::Int(_) | Node::Integer(_) => return Err(EvalError::Error("Negative exponent".to_string())),
+        node => return Err(EvalError::type_mismatch("(^)", "integral exponent", &node))
+    };
+
+    let result = match state.stack_pop() {
+        Node::Int(base) => Node::Int(pow_by_squaring(base, exponent, 1, |a, b| state.int_arith(&INT_MUL, a, b))?),
+        Node::Integer(base) => {
+            let result = pow_by_squaring(base.as_ref().clone(), exponent, BigInt::from(1), |a, b| Ok(&a * &b))?;
+            state.alloc_integer(result)
+        },
+        Node::Ratio(base) => {
+            let one = Rational::new(BigInt::from(1), BigInt::from(1)).unwrap();
+            let result = pow_by_squaring(base.as_ref().clone(), exponent, one, |a, b| Ok(&a * &b))?;
+            state.alloc_ratio(result)
+        },
+        Node::Double(base) => Node::Double(pow_by_squaring(base, exponent, 1.0, |a, b| Ok(a * b))?),
+        node => return Err(EvalError::type_mismatch("(^)", "numeric base", &node))
+    };
+    state.stack_push(result);
+    Ok(())
+}
+
+fn pow_by_squaring<T: Clone>(
+    mut base: T, 
+    mut exponent: u64, 
+    one: T, 
+    mut mul: impl FnMut(T, T) -> EvalResult<T>
+) -> EvalResult<T> {
+    let mut result = one;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base.clone())?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = mul(base.clone(), base)?;
+        }
+    }
+    Ok(result)
+}
+
+pub fn eval_gcd(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    let result = match (vl, vr) {
+        (Node::Int(vl), Node::Int(vr)) => Node::Int(state.int_from_u64(gcd_u64(vl.unsigned_abs(), vr.unsigned_abs()))?),
+        (Node::Integer(vl), Node::Integer(vr)) => state.alloc_integer(vl.gcd(&vr)),
+        (Node::Int(_), _) => return Err(EvalError::type_mismatch("gcd", "Int for right operand", &vr)),
+        (Node::Integer(_), _) => return Err(EvalError::type_mismatch("gcd", "Integer for right operand", &vr)),
+        _ => return Err(EvalError::type_mismatch("gcd", "integral left operand", &vl))
+    };
+    state.stack_push(result);
+    Ok(())
+}
+
+pub fn eval_lcm(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    let result = match (vl, vr) {
+        (Node::Int(0), Node::Int(_)) | (Node::Int(_), Node::Int(0)) => Node::Int(0),
+        (Node::Int(vl), Node::Int(vr)) => {
+            let gcd = gcd_u64(vl.unsigned_abs(), vr.unsigned_abs());
+            let quot = state.int_from_u64(vl.unsigned_abs() / gcd)?;
+            let vr = state.int_un_arith(&INT_ABS, vr)?;
+            Node::Int(state.int_arith(&INT_MUL, quot, vr)?)
+        },
+        (Node::Integer(vl), Node::Integer(vr)) if vl.is_zero() || vr.is_zero() => state.alloc_integer(BigInt::zero()),
+        (Node::Integer(vl), Node::Integer(vr)) => {
+            let gcd = vl.gcd(&vr);
+            state.alloc_integer((&(vl.as_ref() / &gcd) * vr.as_ref()).abs())
+        },
+        (Node::Int(_), _) => return Err(EvalError::type_mismatch("lcm", "Int for right operand", &vr)),
+        (Node::Integer(_), _) => return Err(EvalError::type_mismatch("lcm", "Integer for right operand", &vr)),
+        _ => return Err(EvalError::type_mismatch("lcm", "integral left operand", &vl))
+    };
+    state.stack_push(result);
+    Ok(())
+}
+
+fn gcd_u64(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+pub fn eval_min(state: &mut State) -> EvalResult {
+    select_by_order(state, "min", false)
+}
+
+pub fn eval_max(state: &mut State) -> EvalResult {
+    select_by_order(state, "max", true)
+}
+
+/// Replaces the two operands on top of the stack with the lesser 
+/// (or greater) one, following the Prelude's default definitions in terms of `(<=)`.
+fn select_by_order(state: &mut State, context: &'static str, greater: bool) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    let vl_le_vr = matches!(partial_cmp_nodes(context, &vl, &vr)?, Some(Ordering::Less) | Some(Ordering::Equal));
+    state.stack_push(if vl_le_vr == greater { vr } else { vl });
+    Ok(())
+}
+
+/// Forces the `Int` operand on top of the stack, popping it.
+fn pop_int(state: &mut State, context: &'static str) -> EvalResult<i64> {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Int(v) => Ok(v),
+        node => Err(EvalError::type_mismatch(context, "Int", &node))
+    }
+}
+
+/// Forces the two `Int` operands on top of the stack, popping them.
+fn pop_ints(state: &mut State, context: &'static str) -> EvalResult<(i64, i64)> {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    match (state.stack_pop(), state.stack_pop()) {
+        (Node::Int(vr), Node::Int(vl)) => Ok((vl, vr)),
+        (Node::Int(_), node) | (node, _) => Err(EvalError::type_mismatch(context, "Int", &node))
+    }
+}
+
+pub fn eval_bit_and(state: &mut State) -> EvalResult {
+    let (vl, vr) = pop_ints(state, "(.&.)")?;
+    state.push_int(vl & vr);
+    Ok(())
+}
+
+pub fn eval_bit_or(state: &mut State) -> EvalResult {
+    let (vl, vr) = pop_ints(state, "(.|.)")?;
+    state.push_int(vl | vr);
+    Ok(())
+}
+
+pub fn eval_xor(state: &mut State) -> EvalResult {
+    let (vl, vr) = pop_ints(state, "xor")?;
+    state.push_int(vl ^ vr);
+    Ok(())
+}
+
+pub fn eval_complement(state: &mut State) -> EvalResult {
+    let v = pop_int(state, "complement")?;
+    state.push_int(!v);
+    Ok(())
+}
+
+/// Shifting by 64 or more bits shifts every bit out,
+/// while negative shifts overflow as they do in GHC.
+pub fn eval_shift_l(state: &mut State) -> EvalResult {
+    let (v, shift) = pop_ints(state, "shiftL")?;
+    let result = match shift {
+        shift if shift < 0 => return Err(ArithException::Overflow.into()),
+        shift if shift >= 64 => 0,
+        shift => v << shift
+    };
+    state.push_int(result);
+    Ok(())
+}
+
+/// Arithmetic shift, so the sign is preserved.
+pub fn eval_shift_r(state: &mut State) -> EvalResult {
+    let (v, shift) = pop_ints(state, "shiftR")?;
+    let result = match shift {
+        shift if shift < 0 => return Err(ArithException::Overflow.into()),
+        shift if shift >= 64 => if v < 0 { -1 } else { 0 },
+        shift => v >> shift
+    };
+    state.push_int(result);
+    Ok(())
+}
+
+pub fn eval_test_bit(state: &mut State) -> EvalResult {
+    let (v, bit) = pop_ints(state, "testBit")?;
+    state.stack_push(Node::Bool((0..64).contains(&bit) && v & (1 << bit) != 0));
+    Ok(())
+}
+
+pub fn eval_pop_count(state: &mut State) -> EvalResult {
+    let v = pop_int(state, "popCount")?;
+    state.push_int(v.count_ones() as i64);
+    Ok(())
+}
+
+pub fn eval_eq(state: &mut State) -> EvalResult {
+    bin_cmp!(state, ==)
+}
+
+pub fn eval_ne(state: &mut State) -> EvalResult {
+    bin_cmp!(state, !=)
+}
+
+pub fn eval_lt(state: &mut State) -> EvalResult {
+    bin_cmp!(state, <)
+}
+
+pub fn eval_le(state: &mut State) -> EvalResult {
+    bin_cmp!(state, <=)
+}
+
+pub fn eval_gt(state: &mut State) -> EvalResult {
+    bin_cmp!(state, >)
+}
+
+pub fn eval_ge(state: &mut State) -> EvalResult {
+    bin_cmp!(state, >=)
+}
+
+pub fn eval_deep_eq(state: &mut State) -> EvalResult {
+    let equal = state.deep_eq()?;
+    state.stack_push(Node::Bool(equal));
+    Ok(())
+}
+
+/// `compare` for any two values of a type, structurally: see `State::deep_cmp`.
+pub fn eval_deep_compare(state: &mut State) -> EvalResult {
+    let ordering = state.deep_cmp()?;
+    state.push_ordering(ordering);
+    Ok(())
+}
+
+/// Unordered values (NaN) compare as `GT`, as with the Prelude's default `compare`.
+pub fn eval_compare(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    let ordering = partial_cmp_nodes("compare", &vl, &vr)?.unwrap_or(Ordering::Greater);
+    state.push_ordering(ordering);
+    Ok(())
+}
+
+/// The `Ordering` semigroup: the second comparison is only forced
+/// when the first is `EQ`, so derived lexicographic orderings stay lazy.
+pub fn eval_then_cmp(state: &mut State) -> EvalResult {
+    let first = state.pop_ordering()?;
+    if first != Ordering::Equal {
+        state.stack_pop();
+        state.push_ordering(first);
+    }
+    Ok(())
+}
+
+/// The constructor tag of an evaluated value, e.g. to compare
+/// constructors of a derived `Ord` instance before their fields.
+pub fn eval_data_to_tag(state: &mut State) -> EvalResult {
+    state.eval()?;
+    let node = state.stack_pop();
+    match node.con_tag() {
+        Some(tag) => state.push_int(tag as i64),
+        None => return Err(EvalError::type_mismatch("dataToTag", "constructor", &node))
+    }
+    Ok(())
+}
+
+/// Forces the first argument to WHNF and returns the second, unevaluated.
+pub fn eval_seq(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_pop();
+    Ok(())
+}
+
+/// `f $! x`, applying `f` to `x` after forcing `x` to WHNF.
+pub fn eval_strict_app(state: &mut State) -> EvalResult {
+    state.mk_strict_ap()
+}
+
+/// Evaluates its argument to normal form.
+pub fn eval_force(state: &mut State) -> EvalResult {
+    state.deep_eval()
+}
+
+/// Like `seq`, but forces the first argument to normal form.
+pub fn eval_deepseq(state: &mut State) -> EvalResult {
+    state.deep_eval()?;
+    state.stack_pop();
+    Ok(())
+}
+
+/// Raises an `EvalError::Error` with the forced message.
+pub fn eval_error(state: &mut State) -> EvalResult {
+    let msg = state.eval_string()?;
+    Err(EvalError::Error(msg))
+}
+
+pub fn eval_undefined(_state: &mut State) -> EvalResult {
+    Err(EvalError::Error("Prelude.undefined".to_string()))
+}
+
+/// `Debug.Trace.trace`, printing the message to stderr before
+/// leaving the value on the stack to be evaluated in its place. Without
+/// `std` there is no stderr, the message is only forced.
+pub fn eval_trace(state: &mut State) -> EvalResult {
+    let msg = state.eval_string()?;
+    #[cfg(feature = "std")]
+    eprint_line(&msg);
+    #[cfg(not(feature = "std"))]
+    drop(msg);
+    Ok(())
+}
+
+/// Forces only the condition, leaving the selected branch
+/// unevaluated on the stack.
+pub fn eval_if(state: &mut State) -> EvalResult {
+    state.eval()?;
+    let cond = state.stack_pop();
+    let then_branch = state.stack_pop();
+    let else_branch = state.stack_pop();
+
+    if let Node::Bool(cond) = cond {
+        state.stack_push(if cond { then_branch } else { else_branch });
+        Ok(())
+    } else {
+        Err(EvalError::type_mismatch("if", "Bool for condition", &cond))
+    }
+}
+
+pub fn eval_to_integer(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Int(i) => state.push_integer(BigInt::from(i)),
+        node @ Node::Integer(_) => state.stack_push(node),
+        node => return Err(EvalError::type_mismatch("toInteger", "Int", &node))
+    }
+    Ok(())
+}
+
+/// Converts to `Int`, wrapping around on overflow like Haskell's `fromInteger`.
+pub fn eval_from_integer(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Integer(i) => state.push_int(i.to_i64_wrapping()),
+        node @ Node::Int(_) => state.stack_push(node),
+        node => return Err(EvalError::type_mismatch("fromInteger", "Integer", &node))
+    }
+    Ok(())
+}
+
+/// Builds the normalized fraction of two `Integer`s (or `Int`s).
+pub fn eval_ratio(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let den = state.stack_pop();
+    let num = state.stack_pop();
+    let num = expect_integral("(%)", &num)?;
+    let den = expect_integral("(%)", &den)?;
+
+    match Rational::new(num, den) {
+        Some(ratio) => state.push_ratio(ratio),
+        None => return Err(ArithException::RatioZeroDenominator.into())
+    }
+    Ok(())
+}
+
+pub fn eval_numerator(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Ratio(r) => state.push_integer(r.numerator().clone()),
+        node => return Err(EvalError::type_mismatch("numerator", "Rational", &node))
+    }
+    Ok(())
+}
+
+pub fn eval_denominator(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Ratio(r) => state.push_integer(r.denominator().clone()),
+        node => return Err(EvalError::type_mismatch("denominator", "Rational", &node))
+    }
+    Ok(())
+}
+
+fn expect_integral(context: &'static str, node: &Node) -> EvalResult<BigInt> {
+    match node {
+        Node::Int(i) => Ok(BigInt::from(*i)),
+        Node::Integer(i) => Ok(i.as_ref().clone()),
+        node => Err(EvalError::type_mismatch(context, "integral number", node))
+    }
+}
+
+pub fn eval_pack(state: &mut State) -> EvalResult {
+    let text = state.eval_string()?;
+    state.push_text(text);
+    Ok(())
+}
+
+/// Lazily unpacks the text, one character per forced cell.
+pub fn eval_unpack(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.push_int(0);
+    state.stack_swap();
+    eval_unpack_from(state)
+}
+
+/// Unpacks the text from the given byte offset.
+fn eval_unpack_from(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let offset_node = state.stack_pop();
+    let text_node = state.stack_pop();
+
+    let (text, offset) = match (text_node, offset_node) {
+        (Node::Text(text), Node::Int(offset)) => (text, offset as usize),
+        (Node::Text(_), node) => return Err(EvalError::type_mismatch("unpack", "Int offset", &node)),
+        (node, _) => return Err(EvalError::type_mismatch("unpack", "Text", &node))
+    };
+
+    match text[offset..].chars().next() {
+        None => state.push_nil(),
+        Some(c) => {
+            state.push_int((offset + c.len_utf8()) as i64);
+            state.stack_push(Node::Text(text));
+            state.push_fn(FN_UNPACK_FROM);
+            state.mk_ap();
+            state.mk_ap();
+            state.push_char(c);
+            state.mk_cons();
+        }
+    }
+    Ok(())
+}
+
+pub fn eval_text_append(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let vr = state.stack_pop();
+    let vl = state.stack_pop();
+    match (vl, vr) {
+        (Node::Text(vl), Node::Text(vr)) => {
+            let mut text = vl.as_ref().clone();
+            text.push_str(&vr);
+            state.push_text(text);
+        },
+        (Node::Text(_), node) | (node, _) => return Err(EvalError::type_mismatch("append", "Text", &node))
+    }
+    Ok(())
+}
+
+/// The length in characters.
+pub fn eval_text_length(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Text(text) => state.push_int(text.chars().count() as i64),
+        node => return Err(EvalError::type_mismatch("length", "Text", &node))
+    }
+    Ok(())
+}
+
+/// The character at the given character index.
+pub fn eval_text_index(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    state.eval()?;
+
+    let index = state.stack_pop();
+    let text = state.stack_pop();
+    match (text, index) {
+        (Node::Text(text), Node::Int(index)) => {
+            match usize::try_from(index).ok().and_then(|index| text.chars().nth(index)) {
+                Some(c) => state.push_char(c),
+                None => return Err(EvalError::Error(format!("index: index {} out of bounds", index)))
+            }
+        },
+        (Node::Text(_), node) => return Err(EvalError::type_mismatch("index", "Int", &node)),
+        (node, _) => return Err(EvalError::type_mismatch("index", "Text", &node))
+    }
+    Ok(())
+}
+
+pub fn eval_ord(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Char(c) => state.push_int(c as i64),
+        node => return Err(EvalError::type_mismatch("ord", "Char", &node))
+    }
+    Ok(())
+}
+
+pub fn eval_chr(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Int(i) => match u32::try_from(i).ok().and_then(char::from_u32) {
+            Some(c) => state.push_char(c),
+            None => return Err(EvalError::Error(format!("chr: invalid code point: {}", i)))
+        },
+        node => return Err(EvalError::type_mismatch("chr", "Int", &node))
+    }
+    Ok(())
+}
+
+pub fn eval_nil(state: &mut State) -> EvalResult {
+    state.push_nil();
+    Ok(())
+}
+
+/// Builds the cell without forcing either the head or the tail,
+/// so infinite lists can be constructed.
+pub fn eval_cons(state: &mut State) -> EvalResult {
+    state.mk_cons();
+    Ok(())
+}
+
+pub fn eval_head(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Cons(head, _) => state.stack_push(*head),
+        Node::Nil => return Err(EvalError::Error("head: empty list".to_string())),
+        node => return Err(EvalError::type_mismatch("head", "list", &node))
+    }
+    Ok(())
+}
+
+pub fn eval_tail(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Cons(_, tail) => state.stack_push(*tail),
+        Node::Nil => return Err(EvalError::Error("tail: empty list".to_string())),
+        node => return Err(EvalError::type_mismatch("tail", "list", &node))
+    }
+    Ok(())
+}
+
+/// Like `cons`, the tuple builders leave their components unevaluated.
+pub fn eval_pair(state: &mut State) -> EvalResult {
+    state.mk_tuple(2);
+    Ok(())
+}
+
+pub fn eval_tuple3(state: &mut State) -> EvalResult {
+    state.mk_tuple(3);
+    Ok(())
+}
+
+pub fn eval_tuple4(state: &mut State) -> EvalResult {
+    state.mk_tuple(4);
+    Ok(())
+}
+
+pub fn eval_fst(state: &mut State) -> EvalResult {
+    select_component(state, 0)
+}
+
+pub fn eval_snd(state: &mut State) -> EvalResult {
+    select_component(state, 1)
+}
+
+/// Forces the tuple on top of the stack and replaces it with
+/// its (unevaluated) component at `index`.
+pub fn select_component(state: &mut State, index: usize) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Tuple(fields) if index < fields.len() => state.stack_push(fields[index]),
+        node => return Err(EvalError::type_mismatch("select", "tuple with enough components", &node))
+    }
+    Ok(())
+}
+
+/// A thunk for `value` that prints its name when forced, to follow the
+/// order thunks are forced in.
+#[cfg(feature = "std")]
+pub struct TracedThunk {
     name: String,
     value: Node
 }
 
+#[cfg(feature = "std")]
+impl TracedThunk {
+    pub fn new(name: String, value: Node) -> Self {
+        TracedThunk { name, value }
+    }
+}
+
+#[cfg(feature = "std")]
 impl ThunkEval for TracedThunk {
     fn eval_thunk(&self) -> Node {
         println!("Evaling: {}", self.name);
-        self.value.clone()
-    }
-}
-
-// impl TracedThunk {
-//     pub fn new(name: String, value: Node) -> Node {
-//         thunk(Box::new(TracedThunk { name, value }))
-//     }
-// }
-
-// macro_rules! bin_thunk {
-//     ($thunk_name:ident, $eval_fn:ident, $fn_name:ident) => {
-//         struct $thunk_name {
-//             nl: Node,
-//             nr: Node
-//         }
-
-//         impl ThunkEval for $thunk_name {
-//             fn eval_thunk(&self) -> Node {
-//                 $eval_fn(self.nl.clone(), self.nr.clone())
-//             }
-//         }
-
-//         pub fn $fn_name(nl: Node, nr: Node) -> Node {
-//             thunk(Box::new($thunk_name { nl, nr }))
-//         }
-//     }
-// }
-
-// bin_thunk!(AddThunk, eval_add, add);
-// bin_thunk!(SubThunk, eval_sub, sub);
-// bin_thunk!(DivThunk, eval_div, div);
-// bin_thunk!(MulThunk, eval_mul, mul);
+        self.value
+    }
+
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        self.value.mark_refs(worklist);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+    use crate::closure::thunk_fn;
+    use crate::testing::*;
+    use super::*;
+
+    /// Code for a thunk that records when it is dropped.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl ThunkEval for DropFlag {
+        fn eval_thunk(&self) -> Node {
+            Node::Int(1)
+        }
+    }
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, AtomicOrdering::SeqCst);
+        }
+    }
+
+    /// Pushes `x + 1`.
+    fn push_succ(state: &mut State, x: Node) {
+        state.push_int(1);
+        state.push(x);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+    }
+
+    #[test]
+    fn thunk_is_forced_once() {
+        let mut state = State::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        state.push_thunk_eval(thunk_fn(move || {
+            counted.fetch_add(1, AtomicOrdering::SeqCst);
+            Node::Int(41)
+        }));
+        let thunk = *state.stack_peek();
+        push_succ(&mut state, thunk);
+        push_succ(&mut state, thunk);
+        // left on the stack, where they are rooted while the other is forced
+        let second = *state.stack_peek();
+        let first = *state.stack_peek_nth(1);
+        assert!(matches!(state.try_eval(first), Ok(Node::Int(42))));
+        assert!(matches!(state.try_eval(second), Ok(Node::Int(42))));
+        assert_eq!(calls.load(AtomicOrdering::SeqCst), 1);
+        assert_eq!(state.stats().thunks_updated, 1);
+    }
+
+    #[test]
+    fn comparisons_force_both_sides() {
+        let mut state = State::new();
+        let cases = [(FN_EQ, "True"), (FN_NE, "False"), (FN_LT, "False"),
+                     (FN_LE, "True"), (FN_GT, "False"), (FN_GE, "True")];
+        for (fn_def, expected) in cases {
+            // 2 `op` (1 + 1)
+            push_succ(&mut state, Node::Int(1));
+            state.mk_thunk();
+            state.push_int(2);
+            state.push_fn(fn_def);
+            assert!(state.apply(2).is_ok());
+            let result = state.stack_pop();
+            assert_eq!(result.to_string(), expected, "{}", fn_def.name);
+        }
+        assert_eq!(call(&mut state, FN_LT, &["'a'", "'b'"]).ok().as_deref(), Some("True"));
+        assert_eq!(call(&mut state, FN_GE, &["1.5", "2.5"]).ok().as_deref(), Some("False"));
+    }
+
+    #[test]
+    fn comparing_different_types_is_an_error() {
+        let mut state = State::new();
+        let result = call(&mut state, FN_EQ, &["1", "'a'"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::TypeMismatch { .. })));
+    }
+
+    /// Pushes `if cond then then_branch else else_branch` and evaluates it.
+    fn eval_if_with(state: &mut State, cond: Node, then_branch: Node, else_branch: Node) -> EvalResult<Node> {
+        state.push(else_branch);
+        state.push(then_branch);
+        state.push(cond);
+        state.push_fn(FN_IF);
+        state.apply(3)?;
+        Ok(state.stack_pop())
+    }
+
+    #[test]
+    fn if_evaluates_only_the_branch_taken() {
+        let mut state = State::new();
+        let undefined = Node::FnDef(FN_UNDEFINED);
+        // 0 < 1
+        state.push_int(1);
+        state.push_int(0);
+        state.push_fn(FN_LT);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let cond = state.stack_pop();
+        assert!(matches!(eval_if_with(&mut state, cond, Node::Int(10), undefined), Ok(Node::Int(10))));
+        assert!(matches!(eval_if_with(&mut state, Node::Bool(false), undefined, Node::Int(20)), Ok(Node::Int(20))));
+        assert!(eval_if_with(&mut state, Node::Bool(false), Node::Int(10), undefined).is_err());
+    }
+
+    #[test]
+    fn if_needs_a_bool() {
+        let mut state = State::new();
+        let result = eval_if_with(&mut state, Node::Int(1), Node::Int(10), Node::Int(20));
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn infinite_lists_are_built_lazily() {
+        let mut state = State::new();
+        // ones = 1 : ones
+        state.mk_recursive(|state, ones| {
+            state.push(ones);
+            state.push_int(1);
+            state.push_fn(FN_CONS);
+            state.mk_ap();
+            state.mk_ap();
+        });
+        // head (tail (tail ones))
+        state.push_fn(FN_TAIL);
+        state.mk_ap();
+        state.push_fn(FN_TAIL);
+        state.mk_ap();
+        state.push_fn(FN_HEAD);
+        state.mk_ap();
+        let third = state.stack_pop();
+        assert!(matches!(state.try_eval(third), Ok(Node::Int(1))));
+    }
+
+    #[test]
+    fn head_and_tail_of_lists() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_HEAD, &["[1, 2, 3]"]).ok().as_deref(), Some("1"));
+        assert_eq!(call(&mut state, FN_TAIL, &["[1, 2, 3]"]).ok().as_deref(), Some("[2,3]"));
+        assert_eq!(call(&mut state, FN_CONS, &["0", "[1]"]).ok().as_deref(), Some("[0,1]"));
+        let result = call(&mut state, FN_HEAD, &["[]"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_))));
+        let result = call(&mut state, FN_TAIL, &["[]"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_))));
+    }
+
+    /// `Leaf`, tag 0, gives 0.
+    fn alt_leaf(state: &mut State) -> EvalResult {
+        state.push_int(0);
+        Ok(())
+    }
+
+    /// `Node l r`, tag 1, gives `l - r`.
+    fn alt_node(state: &mut State) -> EvalResult {
+        state.push_fn(FN_SUB);
+        state.apply(2)
+    }
+
+    #[test]
+    fn case_dispatches_on_the_tag() {
+        let mut state = State::new();
+        let alts: [StateFn; 2] = [alt_leaf, alt_node];
+        state.mk_data(0, 0);
+        assert!(state.case(&alts).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(0)));
+
+        // Node 3 (1 + 1), the fields given to the alternative unevaluated
+        push_succ(&mut state, Node::Int(1));
+        state.mk_thunk();
+        state.push_int(3);
+        state.mk_data(1, 2);
+        assert!(state.case(&alts).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(1)));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn case_without_the_alternative_fails() {
+        let mut state = State::new();
+        state.push_int(1);
+        state.mk_data(2, 1);
+        let result = state.case(&[alt_leaf, alt_node]);
+        assert!(matches!(result, Err(EvalError::PatternMatchFail(_))));
+    }
+
+    #[test]
+    fn projections_leave_the_other_component_alone() {
+        let mut state = State::new();
+        // fst (pair 1 undefined), snd (pair undefined 2)
+        state.push_fn(FN_UNDEFINED);
+        state.push_int(1);
+        state.push_fn(FN_PAIR);
+        state.apply(2).expect("a pair");
+        state.push_fn(FN_FST);
+        assert!(state.apply(1).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(1)));
+
+        state.push_int(2);
+        state.push_fn(FN_UNDEFINED);
+        state.push_fn(FN_PAIR);
+        state.apply(2).expect("a pair");
+        state.push_fn(FN_SND);
+        assert!(state.apply(1).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(2)));
+    }
+
+    #[test]
+    fn tuples_are_built_in_order() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_TUPLE3, &["1", "'b'", "True"]).ok().as_deref(), Some("(1,'b',True)"));
+        assert_eq!(call(&mut state, FN_SND, &["(1, 2, 3)"]).ok().as_deref(), Some("2"));
+        assert!(call(&mut state, FN_SND, &["(1)"]).is_err());
+    }
+
+    #[test]
+    fn ord_and_chr_convert_code_points() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_ORD, &["'A'"]).ok().as_deref(), Some("65"));
+        assert_eq!(call(&mut state, FN_CHR, &["955"]).ok().as_deref(), Some("'λ'"));
+        for invalid in ["-1", "55296", "1114112"] {
+            let result = call(&mut state, FN_CHR, &[invalid]);
+            assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_))), "chr {}", invalid);
+        }
+    }
+
+    #[test]
+    fn double_arithmetic() {
+        let mut state = State::new();
+        let cases = [(FN_ADD, "1.5", "2.25", "3.75"), (FN_SUB, "1.5", "2.25", "-0.75"),
+                     (FN_MUL, "1.5", "2.0", "3.0"), (FN_FDIV, "1.0", "4.0", "0.25"),
+                     (FN_FDIV, "1.0", "0.0", "Infinity")];
+        for (fn_def, l, r, expected) in cases {
+            assert_eq!(call(&mut state, fn_def, &[l, r]).ok().as_deref(), Some(expected), "{} {} {}", fn_def.name, l, r);
+        }
+    }
+
+    #[test]
+    fn mixing_int_and_double_is_a_type_mismatch() {
+        let mut state = State::new();
+        for (l, r) in [("1", "2.0"), ("2.0", "1")] {
+            let result = call(&mut state, FN_ADD, &[l, r]);
+            assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::TypeMismatch { .. })));
+        }
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn integers_are_unbounded() {
+        let mut state = State::new();
+        let big = "123456789012345678901234567890";
+        assert_eq!(call(&mut state, FN_MUL, &[big, big]).ok().as_deref(),
+                   Some("15241578753238836750495351562536198787501905199875019052100"));
+        assert_eq!(call(&mut state, FN_SUB, &[big, big]).ok().as_deref(), Some("0"));
+        // toInteger maxBound + toInteger 1
+        state.push_int(1);
+        state.push_fn(FN_TO_INTEGER);
+        state.mk_ap();
+        state.push_int(i64::MAX);
+        state.push_fn(FN_TO_INTEGER);
+        state.mk_ap();
+        state.push_fn(FN_ADD);
+        assert!(state.apply(2).is_ok());
+        let sum = state.stack_pop();
+        assert_eq!(sum.to_string(), "9223372036854775808");
+    }
+
+    #[test]
+    fn from_integer_wraps_around() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_FROM_INTEGER, &["18446744073709551617"]).ok().as_deref(), Some("1"));
+        assert_eq!(call(&mut state, FN_FROM_INTEGER, &["-9223372036854775809"]).ok().as_deref(),
+                   Some("9223372036854775807"));
+    }
+
+    #[test]
+    fn ratios_through_the_builtins() {
+        let mut state = State::new();
+        // numerator (6 % 8), denominator (6 % 8)
+        for (fn_def, expected) in [(FN_NUMERATOR, "3"), (FN_DENOMINATOR, "4")] {
+            state.push_int(8);
+            state.push_int(6);
+            state.push_fn(FN_RATIO);
+            state.mk_ap();
+            state.mk_ap();
+            state.push_fn(fn_def);
+            assert!(state.apply(1).is_ok());
+            assert_eq!(state.stack_pop().to_string(), expected);
+        }
+        let result = call(&mut state, FN_RATIO, &["1", "0"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause),
+                         Err(EvalError::Arith(ArithException::RatioZeroDenominator))));
+    }
+
+    #[test]
+    fn string_literals_are_char_lists() {
+        let mut state = State::new();
+        state.push_str("h\u{e9}\"");
+        assert!(matches!(state.stack_peek(), Node::Cons(_, _)));
+        // length, by walking the spine
+        let mut len = 0;
+        let mut rest = *state.stack_peek();
+        while let Node::Cons(head, tail) = rest {
+            assert!(matches!(*head, Node::Char(_)));
+            len += 1;
+            rest = *tail;
+        }
+        assert_eq!(len, 3);
+        assert_eq!(state.eval_string().ok().as_deref(), Some("h\u{e9}\""));
+
+        state.push_str("");
+        assert!(matches!(state.stack_peek(), Node::Nil));
+        assert_eq!(state.eval_string().ok().as_deref(), Some(""));
+    }
+
+    #[test]
+    fn text_is_indexed_by_character() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_PACK, &["\"h\u{e9}llo\""]).ok().as_deref(), Some("\"h\u{e9}llo\""));
+        // left on the stack, where it is rooted
+        state.push_text("h\u{e9}llo".to_string());
+        let text = *state.stack_peek();
+        for (fn_def, args, expected) in [(FN_TEXT_LENGTH, vec![], "5"), (FN_TEXT_INDEX, vec![Node::Int(1)], "'\u{e9}'"),
+                                         (FN_TEXT_APPEND, vec![text], "\"h\u{e9}lloh\u{e9}llo\""),
+                                         (FN_UNPACK, vec![], "\"h\u{e9}llo\"")] {
+            for arg in args.iter().rev() {
+                state.push(*arg);
+            }
+            state.push(text);
+            state.push_fn(fn_def);
+            assert!(state.apply(args.len() + 1).is_ok());
+            let result = state.stack_pop();
+            assert_eq!(state.render(result).ok().as_deref(), Some(expected), "{}", fn_def.name);
+        }
+        state.push_int(5);
+        state.push(text);
+        state.push_fn(FN_TEXT_INDEX);
+        let result = state.apply(2);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_))));
+    }
+
+    #[test]
+    fn unpacking_is_lazy() {
+        let mut state = State::new();
+        state.push_text("abc".to_string());
+        state.push_fn(FN_UNPACK);
+        assert!(state.apply(1).is_ok());
+        // only the first cell is built, the rest is still to be unpacked
+        match state.stack_pop() {
+            Node::Cons(head, tail) => {
+                assert!(matches!(*head, Node::Char('a')));
+                assert!(!tail.is_whnf());
+            },
+            node => panic!("not a list: {}", node.kind())
+        }
+    }
+
+    #[test]
+    fn division_rounds_as_haskell_does() {
+        let mut state = State::new();
+        // (l, r, div, mod, quot, rem)
+        let cases = [("7", "2", "3", "1", "3", "1"), ("-7", "2", "-4", "1", "-3", "-1"),
+                     ("7", "-2", "-4", "-1", "-3", "1"), ("-7", "-2", "3", "-1", "3", "-1")];
+        for (l, r, div, modulo, quot, rem) in cases {
+            for (fn_def, expected) in [(FN_DIV, div), (FN_MOD, modulo), (FN_QUOT, quot), (FN_REM, rem)] {
+                assert_eq!(call(&mut state, fn_def, &[l, r]).ok().as_deref(), Some(expected), "{} {} {}", fn_def.name, l, r);
+            }
+        }
+        assert_eq!(call(&mut state, FN_DIV_MOD, &["-7", "2"]).ok().as_deref(), Some("(-4,1)"));
+        assert_eq!(call(&mut state, FN_QUOT_REM, &["-7", "2"]).ok().as_deref(), Some("(-3,-1)"));
+        let big = "-100000000000000000000";
+        assert_eq!(call(&mut state, FN_DIV_MOD, &[big, "30000000000000000000"]).ok().as_deref(),
+                   Some("(-4,20000000000000000000)"));
+    }
+
+    #[test]
+    fn dividing_by_zero_is_an_arith_exception() {
+        let mut state = State::new();
+        fn is_divide_by_zero<T>(result: &EvalResult<T>) -> bool {
+            matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Arith(ArithException::DivideByZero)))
+        }
+        for fn_def in [FN_DIV, FN_MOD, FN_QUOT, FN_REM, FN_DIV_MOD, FN_QUOT_REM] {
+            assert!(is_divide_by_zero(&call(&mut state, fn_def, &["7", "0"])), "{} 7 0", fn_def.name);
+            state.push_integer(BigInt::from(0));
+            state.push_integer(BigInt::from(7));
+            state.push_fn(fn_def);
+            assert!(is_divide_by_zero(&state.apply(2)), "{} 7 0 on Integers", fn_def.name);
+            assert_eq!(state.stack_size(), 0);
+        }
+        // the state is usable afterwards
+        assert_eq!(call(&mut state, FN_DIV, &["7", "2"]).ok().as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn overflow_follows_the_policy() {
+        let max = i64::MAX.to_string();
+        let min = i64::MIN.to_string();
+        let cases = [(FN_ADD, [max.as_str(), "1"]), (FN_SUB, [min.as_str(), "1"]), (FN_MUL, [max.as_str(), "2"]),
+                     (FN_DIV, [min.as_str(), "-1"])];
+        let policies = [(OverflowPolicy::Wrapping, [min.as_str(), max.as_str(), "-2", min.as_str()]),
+                        (OverflowPolicy::Saturating, [max.as_str(), min.as_str(), max.as_str(), max.as_str()])];
+        for (policy, expected) in policies {
+            let mut state = State::new();
+            state.set_overflow_policy(policy);
+            for ((fn_def, args), expected) in cases.iter().zip(expected) {
+                assert_eq!(call(&mut state, *fn_def, args).ok().as_deref(), Some(expected), "{} {:?}", fn_def.name, args);
+            }
+        }
+
+        let mut state = State::new();
+        state.set_overflow_policy(OverflowPolicy::Checked);
+        for (fn_def, args) in cases.iter() {
+            let result = call(&mut state, *fn_def, args);
+            assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Arith(ArithException::Overflow))),
+                    "{} {:?}", fn_def.name, args);
+        }
+    }
+
+    #[test]
+    fn unary_numeric_builtins() {
+        let mut state = State::new();
+        let cases = [(FN_NEGATE, "5", "-5"), (FN_NEGATE, "-2.5", "2.5"), (FN_ABS, "-5", "5"), (FN_ABS, "-0.5", "0.5"),
+                     (FN_SIGNUM, "-5", "-1"), (FN_SIGNUM, "0", "0"), (FN_SIGNUM, "2.5", "1.0"),
+                     (FN_NEGATE, "100000000000000000000", "-100000000000000000000")];
+        for (fn_def, arg, expected) in cases {
+            assert_eq!(call(&mut state, fn_def, &[arg]).ok().as_deref(), Some(expected), "{} {}", fn_def.name, arg);
+        }
+        let min = i64::MIN.to_string();
+        state.set_overflow_policy(OverflowPolicy::Checked);
+        for fn_def in [FN_NEGATE, FN_ABS] {
+            let result = call(&mut state, fn_def, &[&min]);
+            assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Arith(ArithException::Overflow))));
+        }
+        state.set_overflow_policy(OverflowPolicy::Wrapping);
+        assert_eq!(call(&mut state, FN_NEGATE, &[&min]).ok(), Some(min));
+    }
+
+    #[test]
+    fn pow_gcd_lcm_min_max() {
+        let mut state = State::new();
+        let cases = [(FN_POW, "2", "10", "1024"), (FN_POW, "7", "0", "1"), (FN_POW, "1.5", "2", "2.25"),
+                     (FN_GCD, "12", "-18", "6"), (FN_GCD, "0", "0", "0"), (FN_LCM, "4", "6", "12"),
+                     (FN_LCM, "0", "6", "0"), (FN_MIN, "3", "-1", "-1"), (FN_MAX, "'a'", "'b'", "'b'"),
+                     (FN_MAX, "2.5", "1.5", "2.5")];
+        for (fn_def, l, r, expected) in cases {
+            assert_eq!(call(&mut state, fn_def, &[l, r]).ok().as_deref(), Some(expected), "{} {} {}", fn_def.name, l, r);
+        }
+        let result = call(&mut state, FN_POW, &["2", "-1"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_))));
+
+        // toInteger 2 ^ 70, which overflows an Int
+        state.push_int(70);
+        state.push_int(2);
+        state.push_fn(FN_TO_INTEGER);
+        state.mk_ap();
+        state.push_fn(FN_POW);
+        assert!(state.apply(2).is_ok());
+        assert_eq!(state.stack_pop().to_string(), "1180591620717411303424");
+        state.set_overflow_policy(OverflowPolicy::Checked);
+        let result = call(&mut state, FN_POW, &["2", "70"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Arith(ArithException::Overflow))));
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        let mut state = State::new();
+        let cases = [(FN_BIT_AND, &["12", "10"][..], "8"), (FN_BIT_OR, &["12", "10"], "14"), (FN_XOR, &["12", "10"], "6"),
+                     (FN_COMPLEMENT, &["0"], "-1"), (FN_SHIFT_L, &["1", "62"], "4611686018427387904"),
+                     (FN_SHIFT_L, &["1", "64"], "0"), (FN_SHIFT_R, &["-16", "2"], "-4"), (FN_SHIFT_R, &["-16", "70"], "-1"),
+                     (FN_TEST_BIT, &["5", "2"], "True"), (FN_TEST_BIT, &["5", "64"], "False"),
+                     (FN_POP_COUNT, &["-1"], "64")];
+        for (fn_def, args, expected) in cases {
+            assert_eq!(call(&mut state, fn_def, args).ok().as_deref(), Some(expected), "{} {:?}", fn_def.name, args);
+        }
+        let result = call(&mut state, FN_SHIFT_L, &["1", "-1"]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Arith(ArithException::Overflow))));
+    }
+
+    #[test]
+    fn seq_forces_its_first_argument_to_whnf() {
+        let mut state = State::new();
+        let forced = Arc::new(AtomicBool::new(false));
+        let flag = forced.clone();
+        state.push_int(2);
+        state.push_thunk_eval(thunk_fn(move || {
+            flag.store(true, AtomicOrdering::SeqCst);
+            Node::Int(1)
+        }));
+        state.push_fn(FN_SEQ);
+        assert!(state.apply(2).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(2)));
+        assert!(forced.load(AtomicOrdering::SeqCst));
+
+        let result = call(&mut state, FN_SEQ, &["(1, 2)", "3"]);
+        assert_eq!(result.ok().as_deref(), Some("3"));
+        state.push_int(3);
+        state.push_fn(FN_UNDEFINED);
+        state.push_fn(FN_SEQ);
+        assert!(state.apply(2).is_err());
+    }
+
+    /// Pushes `strict_app (cons 1) x`, or `cons 1 x` if not `strict`.
+    fn push_cons_one(state: &mut State, x: Node, strict: bool) {
+        state.push(x);
+        state.push_int(1);
+        state.push_fn(FN_CONS);
+        state.mk_ap();
+        if strict {
+            state.push_fn(FN_STRICT_APP);
+            state.mk_ap();
+        }
+        state.mk_ap();
+    }
+
+    #[test]
+    fn strict_application_forces_the_argument() {
+        let mut state = State::new();
+        let undefined = Node::FnDef(FN_UNDEFINED);
+        push_cons_one(&mut state, undefined, true);
+        let strict = state.stack_pop();
+        assert!(state.try_eval(strict).is_err());
+        push_cons_one(&mut state, undefined, false);
+        let lazy = state.stack_pop();
+        assert!(matches!(state.try_eval(lazy), Ok(Node::Cons(_, _))));
+
+        push_cons_one(&mut state, Node::Nil, true);
+        let strict = state.stack_pop();
+        assert_eq!(state.render(strict).ok().as_deref(), Some("[1]"));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn deepseq_forces_the_whole_value() {
+        let mut state = State::new();
+        // deepseq (1, undefined) 2 fails where seq doesn't
+        for (fn_def, fails) in [(FN_DEEPSEQ, true), (FN_SEQ, false)] {
+            state.push_int(2);
+            state.push_fn(FN_UNDEFINED);
+            state.push_int(1);
+            state.mk_tuple_in_order(2);
+            state.push_fn(fn_def);
+            state.mk_ap();
+            state.mk_ap();
+            let node = state.stack_pop();
+            assert_eq!(state.try_eval(node).is_err(), fails, "{}", fn_def.name);
+        }
+    }
+
+    #[test]
+    fn force_evaluates_every_element() {
+        let mut state = State::new();
+        // a list of 1000 suspended additions
+        state.push_nil();
+        for i in 0..1000 {
+            push_succ(&mut state, Node::Int(i));
+            state.mk_thunk();
+            state.mk_cons();
+        }
+        state.push_fn(FN_FORCE);
+        assert!(state.apply(1).is_ok());
+        let mut rest = state.stack_pop();
+        let mut len = 0;
+        while let Node::Cons(head, tail) = rest {
+            // built last to first
+            assert!(matches!(head.short_circuit(), Node::Int(n) if n == 1000 - len));
+            len += 1;
+            rest = tail.short_circuit();
+        }
+        assert!(matches!(rest, Node::Nil));
+        assert_eq!(len, 1000);
+    }
+
+    #[test]
+    fn error_carries_the_forced_message() {
+        let mut state = State::new();
+        let result = call(&mut state, FN_ERROR, &["\"no such key\""]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(msg)) if msg == "no such key"));
+        assert!(result.unwrap_err().to_string().contains("no such key"));
+        let result = call(&mut state, FN_UNDEFINED, &[]);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(msg)) if msg == "Prelude.undefined"));
+    }
+
+    #[test]
+    fn failures_are_returned_and_leave_the_state_usable() {
+        let mut state = State::new();
+        state.push_int(7);
+        // 1 + (2 + 'c'), failing below where it started
+        state.push_char('c');
+        state.push_int(2);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        let node = state.stack_pop();
+        let err = state.try_eval(node).expect_err("a type mismatch");
+        assert!(matches!(err.cause(), EvalError::TypeMismatch { context: "(+)", .. }));
+        assert!(err.to_string().starts_with("(+): expecting Int for right operand"));
+        assert_eq!(state.stack_size(), 1);
+        assert!(matches!(state.stack_peek(), Node::Int(7)));
+        assert_eq!(call(&mut state, FN_ADD, &["1", "2"]).ok().as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn thunks_forced_while_forcing_another_are_shared() {
+        let mut state = State::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        state.push_thunk_eval(thunk_fn(move || {
+            counted.fetch_add(1, AtomicOrdering::SeqCst);
+            Node::Int(21)
+        }));
+        let b = *state.stack_peek();
+        // a = b + b
+        state.push(b);
+        state.push(b);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let a = *state.stack_peek();
+        // c = a, entering a and then b while c is being forced
+        state.push_thunk_eval(thunk_fn(move || a));
+        let c = *state.stack_peek();
+        assert!(matches!(state.try_eval(c), Ok(Node::Int(42))));
+        assert!(matches!(state.try_eval(a), Ok(Node::Int(42))));
+        assert!(matches!(state.try_eval(b), Ok(Node::Int(21))));
+        assert_eq!(calls.load(AtomicOrdering::SeqCst), 1);
+        assert_eq!(state.stats().thunks_updated, 3);
+    }
+
+    #[test]
+    fn deep_thunk_chains_need_no_native_stack() {
+        // foldl (+) 0 [1..n], built as a chain of n suspended additions and
+        // forced on a thread with little stack
+        let evaluate = || {
+            let mut state = State::new();
+            state.push_int(0);
+            for i in 1..=2000 {
+                state.push_int(i);
+                state.stack_swap();
+                state.push_fn(FN_ADD);
+                state.mk_ap();
+                state.mk_ap();
+                state.mk_thunk();
+            }
+            let sum = *state.stack_peek();
+            matches!(state.try_eval(sum), Ok(Node::Int(2_001_000)))
+        };
+        let thread = std::thread::Builder::new().stack_size(32 * 1024).spawn(evaluate);
+        assert!(thread.expect("a thread").join().expect("no overflow"));
+    }
+
+    static FN_TWICE: FnDef = FnDef {
+        name: "twice",
+        arity: 2,
+        strict: 0,
+        fn_ref: eval_twice
+    };
+
+    /// `twice f x = f (f x)`
+    fn eval_twice(state: &mut State) -> EvalResult {
+        let f = state.stack_pop();
+        state.push(f);
+        state.mk_ap();
+        state.push(f);
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// Pushes `add 1`.
+    fn push_add_one(state: &mut State) {
+        state.push_int(1);
+        state.mk_pap(FN_ADD, 1);
+    }
+
+    #[test]
+    fn application_spines_are_unwound() {
+        let mut state = State::new();
+        // twice (add 1) 5
+        state.push_int(5);
+        push_add_one(&mut state);
+        state.push_fn(FN_TWICE);
+        state.mk_ap();
+        state.mk_ap();
+        let node = state.stack_pop();
+        assert!(matches!(state.try_eval(node), Ok(Node::Int(7))));
+
+        // twice twice (add 1) 0, applying twice to more arguments than it takes
+        state.push_int(0);
+        push_add_one(&mut state);
+        state.push_fn(FN_TWICE);
+        state.push_fn(FN_TWICE);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_ap();
+        let node = state.stack_pop();
+        assert!(matches!(state.try_eval(node), Ok(Node::Int(4))));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn partial_applications_are_values() {
+        let mut state = State::new();
+        // sub 10, applied to 3 and to 4
+        state.push_int(10);
+        state.mk_pap(FN_SUB, 1);
+        let sub_ten = *state.stack_peek();
+        assert!(sub_ten.is_whnf());
+        assert!(matches!(state.try_eval(sub_ten), Ok(Node::Pap(_, _))));
+        for (x, expected) in [(3, 7), (4, 6)] {
+            state.push_int(x);
+            state.push(sub_ten);
+            state.mk_ap();
+            let node = state.stack_pop();
+            assert!(matches!(state.try_eval(node), Ok(Node::Int(n)) if n == expected));
+        }
+
+        // tuple3 1, then 2, then 3, a partial application made of another
+        state.push_int(1);
+        state.push_fn(FN_TUPLE3);
+        state.mk_ap();
+        let node = state.stack_pop();
+        let one = state.try_eval(node).expect("a partial application");
+        assert!(matches!(one, Node::Pap(_, args) if args.len() == 1));
+        state.push_int(3);
+        state.push_int(2);
+        state.push(one);
+        state.mk_ap();
+        let node = state.stack_pop();
+        let two = state.try_eval(node).expect("a partial application");
+        assert!(matches!(two, Node::Pap(_, args) if args.len() == 2));
+        state.push(two);
+        state.mk_ap();
+        let node = state.stack_pop();
+        assert_eq!(state.render(node).ok().as_deref(), Some("(1,2,3)"));
+    }
+
+    #[test]
+    fn apply_handles_every_saturation() {
+        let mut state = State::new();
+        // exact: add 1 2
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        assert!(state.apply(2).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(3)));
+
+        // under: tuple3 1 2
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_TUPLE3);
+        assert!(state.apply(2).is_ok());
+        assert!(matches!(state.stack_peek(), Node::Pap(_, args) if args.len() == 2));
+        // and the rest to the partial application
+        state.push_int(3);
+        state.stack_swap();
+        assert!(state.apply(1).is_ok());
+        let tuple = state.stack_pop();
+        assert_eq!(tuple.to_string(), "(1,2,3)");
+
+        // over: twice twice (add 1) 0
+        state.push_int(0);
+        push_add_one(&mut state);
+        state.push_fn(FN_TWICE);
+        state.push_fn(FN_TWICE);
+        assert!(state.apply(3).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(4)));
+
+        // none at all evaluates to WHNF
+        push_succ(&mut state, Node::Int(1));
+        assert!(state.apply(0).is_ok());
+        assert!(matches!(state.stack_pop(), Node::Int(2)));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn applying_a_value_is_a_type_mismatch() {
+        let mut state = State::new();
+        state.push_int(2);
+        state.push_int(1);
+        let result = state.apply(1);
+        assert!(matches!(result, Err(EvalError::TypeMismatch { context: "apply", .. })));
+    }
+
+    #[test]
+    fn letrec_ties_the_knot() {
+        let mut state = State::new();
+        // xs = 0 : ys; ys = 1 : xs
+        state.mk_letrec(2, |state, refs| {
+            for (head, tail) in [(1, refs[0]), (0, refs[1])] {
+                state.push(tail);
+                state.push_int(head);
+                state.push_fn(FN_CONS);
+                state.mk_ap();
+                state.mk_ap();
+            }
+        });
+        let xs = *state.stack_peek();
+        let allocated = state.stats().thunks_allocated;
+        // walking round the cycle allocates nothing new
+        let mut rest = xs;
+        for i in 0..100 {
+            match state.try_eval(rest) {
+                Ok(Node::Cons(head, tail)) => {
+                    assert!(matches!(head.short_circuit(), Node::Int(n) if n == i % 2));
+                    rest = *tail;
+                },
+                _ => panic!("not a cons cell")
+            }
+        }
+        assert_eq!(state.stats().thunks_allocated, allocated);
+        assert_eq!(state.stack_size(), 2);
+    }
+
+    #[test]
+    fn updated_thunks_point_straight_at_the_value() {
+        let mut state = State::new();
+        // a = b, b = c, c = 2 + 3
+        state.push_int(3);
+        state.push_int(2);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let c = *state.stack_peek();
+        state.push(c);
+        state.mk_thunk();
+        let b = *state.stack_peek();
+        state.push(b);
+        state.mk_thunk();
+        let a = *state.stack_peek();
+        assert!(matches!(state.try_eval(a), Ok(Node::Int(5))));
+        for thunk in [a, b, c] {
+            match thunk {
+                Node::ThunkRef(thunk) => assert!(matches!(thunk.state, ThunkState::EThunk(Node::Int(5)))),
+                _ => unreachable!()
+            }
+        }
+        assert!(matches!(a.short_circuit(), Node::Int(5)));
+
+        let target = state.alloc(Node::Int(7));
+        let indirection = state.alloc(Node::NodeRef(target));
+        assert!(matches!(Node::NodeRef(indirection).short_circuit(), Node::Int(7)));
+    }
+
+    #[test]
+    fn labels_show_in_debug_output_and_loops() {
+        let mut state = State::new();
+        push_succ(&mut state, Node::Int(2));
+        state.mk_thunk();
+        state.label_thunk("total");
+        let total = *state.stack_peek();
+        let Node::ThunkRef(thunk) = total else { unreachable!() };
+        assert_eq!(format!("{:?}", thunk.as_ref()), "#[total: UNEVALED]");
+        assert!(state.try_eval(total).is_ok());
+        assert!(format!("{:?}", thunk.as_ref()).starts_with("#[total: "));
+        assert!(!format!("{:?}", thunk.as_ref()).contains("UNEVALED"));
+
+        // x = x + 1, labelled
+        state.mk_recursive(push_succ);
+        state.label_thunk("x");
+        let x = *state.stack_peek();
+        let err = state.try_eval(x).expect_err("a loop");
+        assert!(matches!(err.cause(), EvalError::NonTermination(Some("x"))));
+        assert!(err.to_string().contains("<<loop>> in x"));
+    }
+
+    /// Pushes `outer = inner + 1` for `inner = div 1 0`, each thunk labelled
+    /// and located if given one.
+    fn push_failing_sum(state: &mut State, inner: (&'static str, Option<SrcLoc>), outer: (&'static str, Option<SrcLoc>)) {
+        state.push_int(1);
+        state.push_int(0);
+        state.push_int(1);
+        state.push_fn(FN_DIV);
+        state.mk_ap();
+        state.mk_ap();
+        push_located_thunk(state, inner);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        push_located_thunk(state, outer);
+    }
+
+    fn push_located_thunk(state: &mut State, (label, loc): (&'static str, Option<SrcLoc>)) {
+        state.mk_thunk();
+        state.label_thunk(label);
+        if let Some(loc) = loc {
+            state.locate_thunk(loc);
+        }
+    }
+
+    #[test]
+    fn errors_report_the_innermost_location() {
+        let mut state = State::new();
+        let inner = SrcLoc { file: "Inner.hs", line: 2, col: 5 };
+        let outer = SrcLoc { file: "Main.hs", line: 1, col: 1 };
+        push_failing_sum(&mut state, ("inner", Some(inner)), ("outer", Some(outer)));
+        let node = *state.stack_peek();
+        let err = state.try_eval(node).expect_err("division by zero");
+        assert_eq!(err.location(), Some(inner));
+        assert!(err.to_string().starts_with("Inner.hs:2:5: divide by zero"));
+
+        push_failing_sum(&mut state, ("inner", None), ("outer", Some(outer)));
+        let node = *state.stack_peek();
+        let err = state.try_eval(node).expect_err("division by zero");
+        assert_eq!(err.location(), Some(outer));
+    }
+
+    #[test]
+    fn errors_list_the_frames_they_propagated_out_of() {
+        let mut state = State::new();
+        push_failing_sum(&mut state, ("compute", None), ("main", None));
+        let node = *state.stack_peek();
+        let err = state.try_eval(node).expect_err("division by zero");
+        assert_eq!(err.call_stack(), &["div", "compute", "add", "main"]);
+        assert_eq!(err.to_string(), "divide by zero\nCallStack:\n  div\n  compute\n  add\n  main");
+    }
+
+    #[test]
+    fn trace_forces_its_message_and_gives_the_value() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_TRACE, &["\"here\"", "42"]).ok().as_deref(), Some("42"));
+
+        state.push_int(42);
+        push_value(&mut state, "\"boom\"");
+        state.push_fn(FN_ERROR);
+        state.mk_ap();
+        state.push_fn(FN_TRACE);
+        let err = state.apply(2).expect_err("the message fails");
+        assert!(matches!(err.cause(), EvalError::Error(msg) if msg == "boom"));
+    }
+
+    #[test]
+    fn small_integers_are_shared() {
+        let mut state = State::new();
+        let addr = |node: &Node| match node {
+            Node::Integer(integer) => integer.addr(),
+            _ => panic!("an Integer")
+        };
+        state.push_integer(BigInt::from(5));
+        state.push_integer(BigInt::from(5));
+        assert_eq!(addr(state.stack_peek()), addr(state.stack_peek_nth(1)));
+        state.push_integer(BigInt::from(2000));
+        state.push_integer(BigInt::from(2000));
+        assert_ne!(addr(state.stack_peek()), addr(state.stack_peek_nth(1)));
+
+        // results in the range are shared too, and outlive collections
+        let five = addr(state.stack_peek_nth(2));
+        state.stack_pop();
+        state.stack_pop();
+        state.stack_pop();
+        state.stack_pop();
+        state.heap_census();
+        state.push_integer(BigInt::from(2));
+        state.push_integer(BigInt::from(3));
+        state.push_fn(FN_ADD);
+        state.apply(2).expect("a sum");
+        assert_eq!(addr(state.stack_peek()), five);
+        assert_eq!(state.render(*state.stack_peek()).ok().as_deref(), Some("5"));
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// `x - y`, counting its calls.
+    fn counted_sub(args: &[Node]) -> Node {
+        CALLS.fetch_add(1, AtomicOrdering::SeqCst);
+        match args {
+            [Node::Int(x), Node::Int(y)] => Node::Int(x - y),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn call_thunk_is_updated_once() {
+        let mut state = State::new();
+        state.push_int(3);
+        state.push_int(10);
+        state.push_thunk_call(counted_sub, 2);
+        assert_eq!(state.stack_size(), 1);
+        let thunk = *state.stack_peek();
+        push_succ(&mut state, thunk);
+        push_succ(&mut state, thunk);
+        // left on the stack, where they are rooted while the other is forced
+        let second = *state.stack_peek();
+        let first = *state.stack_peek_nth(1);
+        assert!(matches!(state.try_eval(first), Ok(Node::Int(8))));
+        assert!(matches!(state.try_eval(second), Ok(Node::Int(8))));
+        assert!(matches!(state.try_eval(thunk), Ok(Node::Int(7))));
+        assert_eq!(CALLS.load(AtomicOrdering::SeqCst), 1);
+        assert_eq!(state.stats().thunks_updated, 1);
+    }
+
+    #[test]
+    fn thunk_depending_on_itself_is_a_loop() {
+        let mut state = State::new();
+        // x = x + 1
+        state.mk_recursive(push_succ);
+        let x = state.stack_pop();
+        let is_loop = |result: EvalResult<Node>| {
+            matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::NonTermination(_)))
+        };
+        assert!(is_loop(state.try_eval(x)));
+        // left as it was, so forcing it again fails the same way
+        assert!(is_loop(state.try_eval(x)));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn forced_code_is_dropped_with_the_update() {
+        let mut state = State::new();
+        let dropped = Arc::new(AtomicBool::new(false));
+        state.push_thunk_eval(Box::new(DropFlag(dropped.clone())));
+        let thunk = *state.stack_peek();
+        assert!(matches!(state.try_eval(thunk), Ok(Node::Int(1))));
+        // the thunk is still rooted, but no longer holds its code
+        assert!(dropped.load(AtomicOrdering::SeqCst));
+    }
+
+    #[test]
+    fn captures_are_dropped_with_the_update() {
+        let mut state = State::new();
+        let captured = Rc::new(vec![1, 2, 3]);
+        let held = captured.clone();
+        state.push_thunk_eval(thunk_fn(move || Node::Int(held.iter().sum())));
+        assert_eq!(Rc::strong_count(&captured), 2);
+        let thunk = *state.stack_peek();
+        assert!(matches!(state.try_eval(thunk), Ok(Node::Int(6))));
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn unforced_code_lives_as_long_as_its_thunk() {
+        let mut state = State::new();
+        let dropped = Arc::new(AtomicBool::new(false));
+        state.push_thunk_eval(Box::new(DropFlag(dropped.clone())));
+        state.collect(Vec::new());
+        assert!(!dropped.load(AtomicOrdering::SeqCst));
+
+        state.stack_pop();
+        let freed = state.stats().objects_freed;
+        state.collect(Vec::new());
+        assert!(dropped.load(AtomicOrdering::SeqCst));
+        assert!(state.stats().objects_freed > freed);
+    }
+
+    #[test]
+    fn deep_eq_compares_structurally() {
+        let mut state = State::new();
+        let cases = [("[1, 2, 3]", "[1, 2, 3]", true), ("[1, 2, 3]", "[1, 2]", false),
+                     ("(1, 'a')", "(1, 'b')", false), ("\"ab\"", "\"ab\"", true)];
+        for (l, r, equal) in cases {
+            push_value(&mut state, r);
+            push_value(&mut state, l);
+            assert_eq!(state.deep_eq().ok(), Some(equal), "{} == {}", l, r);
+            assert_eq!(state.stack_size(), 0);
+        }
+    }
+
+    #[test]
+    fn deep_eq_cleans_up_after_errors() {
+        let mut state = State::new();
+        push_value(&mut state, "[1, 'c']");
+        push_value(&mut state, "[1, 2]");
+        assert!(state.deep_eq().is_err());
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn deep_cmp_orders_lexicographically() {
+        let mut state = State::new();
+        let cases = [("[1, 2]", "[1, 2, 3]", Ordering::Less), ("[1, 3]", "[1, 2, 3]", Ordering::Greater),
+                     ("(1, 'a')", "(1, 'a')", Ordering::Equal), ("'a'", "'b'", Ordering::Less)];
+        for (l, r, ordering) in cases {
+            push_value(&mut state, r);
+            push_value(&mut state, l);
+            assert_eq!(state.deep_cmp().ok(), Some(ordering), "compare {} {}", l, r);
+            assert_eq!(state.stack_size(), 0);
+        }
+    }
+
+    #[test]
+    fn deep_cmp_cleans_up_after_errors() {
+        let mut state = State::new();
+        push_value(&mut state, "[1, 'c']");
+        push_value(&mut state, "[1, 2]");
+        assert!(state.deep_cmp().is_err());
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn fuel_suspends_and_resumes() {
+        let mut state = State::new();
+        // a chain of 100 thunks adding one each
+        state.push_int(0);
+        for _ in 0..100 {
+            state.push_int(1);
+            state.stack_swap();
+            state.push_fn(FN_ADD);
+            state.mk_ap();
+            state.mk_ap();
+            state.mk_thunk();
+        }
+        let node = *state.stack_peek();
+        let mut suspensions = 0;
+        let mut step = state.eval_with_fuel(node, 10);
+        let value = loop {
+            match step {
+                Ok(StepResult::Done(value)) => break value,
+                Ok(StepResult::Suspended(suspension)) => {
+                    suspensions += 1;
+                    step = state.resume_with_fuel(suspension, 10);
+                },
+                Err(err) => panic!("{}", err)
+            }
+        };
+        assert!(matches!(value, Node::Int(100)));
+        assert!(suspensions > 1);
+        assert_eq!(state.stack_size(), 1);
+    }
+
+    #[test]
+    fn display_shows_what_is_not_evaluated_yet() {
+        let mut state = State::new();
+        // [2 + 1, 4]
+        state.push_nil();
+        state.push_int(4);
+        state.mk_cons();
+        push_succ(&mut state, Node::Int(2));
+        state.mk_thunk();
+        state.mk_cons();
+        assert_eq!(state.stack_peek().to_string(), "[<thunk>,4]");
+        assert_eq!(state.render(*state.stack_peek()).ok().as_deref(), Some("[3,4]"));
+        assert_eq!(state.stack_peek().to_string(), "[3,4]");
+
+        // 1 : (2 + 1), its tail not a list until forced
+        push_succ(&mut state, Node::Int(2));
+        state.mk_thunk();
+        state.push_int(1);
+        state.mk_cons();
+        assert_eq!(state.stack_peek().to_string(), "[1,..]");
+        assert_eq!(Node::FnDef(FN_ADD).to_string(), "<function>");
+    }
+
+    #[test]
+    fn render_forces_nested_values() {
+        let mut state = State::new();
+        push_succ(&mut state, Node::Int(0));
+        state.mk_thunk();
+        state.mk_just();
+        state.mk_just();
+        push_value(&mut state, r#""a\"b\n""#);
+        state.mk_tuple_in_order(2);
+        assert_eq!(state.render(*state.stack_peek()).ok().as_deref(), Some(r#"(Pack{1,1} (Pack{1,1} 1),"a\"b\n")"#));
+
+        // a failure leaves the stack as it was
+        state.push_int(0);
+        state.push_int(1);
+        state.push_fn(FN_DIV);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.mk_just();
+        let err = state.render(*state.stack_peek()).expect_err("division by zero");
+        assert!(matches!(err.cause(), EvalError::Arith(ArithException::DivideByZero)));
+        assert_eq!(state.stack_size(), 2);
+    }
+}
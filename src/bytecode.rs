@@ -0,0 +1,508 @@
+use std::convert::TryFrom;
+use std::io;
+use crate::builtins::*;
+
+// A portable form for compiled programs: rather than Rust calling the
+// state's operations, each supercombinator is a list of instructions for
+// them, run by `run_bytecode` over the same heap and stack. Functions take
+// their arguments on top of the stack, the first on top, and replace them
+// with their result, as builtins do.
+//
+// A `.hbc` file is, little-endian throughout:
+//
+//     "HBC" version:u8 count:u32 function*
+//     function = name:str arity:u32 strict:u32 length:u32 instr*
+//     instr    = opcode:u8 operands
+//     str      = length:u32 utf8
+//
+// the operands of each instruction being those of its variant in order,
+// integers as `i64`, doubles as `f64`, chars, counts and jump targets as
+// `u32` and `Case` as a count followed by that many targets. Loading a
+// program registers its functions as globals, and, as with compiled
+// modules, keeps them for as long as the process runs.
+
+const MAGIC: &[u8] = b"HBC";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    PushInt(i64),
+    PushDouble(f64),
+    PushChar(char),
+    PushBool(bool),
+    PushNil,
+    PushUnit,
+    PushStr(String),
+    /// Pushes the global or, if there is none, the builtin by that name.
+    PushGlobal(String),
+    /// Pushes the node `n` below the top again, e.g. an argument.
+    Push(usize),
+    Pop(usize),
+    /// Drops the `n` nodes below the top, e.g. the arguments under the result.
+    Slide(usize),
+    Swap,
+    MkAp,
+    MkThunk,
+    MkCons,
+    MkData(u32, usize),
+    MkTuple(usize),
+    /// Partially applies the function by that name to the `n` nodes on top.
+    MkPap(String, usize),
+    /// Pushes an empty thunk for a recursive binding, see `State::push_hole`.
+    Alloc,
+    /// Fills the empty thunk `n` below the top with the expression on it.
+    Update(usize),
+    Eval,
+    /// Splits the constructor on top as `State::case_split` does, jumping to
+    /// the alternative for its tag.
+    Case(Vec<usize>),
+    Jump(usize),
+    /// Ends the function, as running off the end of its code does.
+    Return
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub strict: usize,
+    pub code: Vec<Instr>
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub functions: Vec<Function>
+}
+
+impl Instr {
+    fn opcode(&self) -> u8 {
+        match self {
+            Instr::PushInt(_) => 0,
+            Instr::PushDouble(_) => 1,
+            Instr::PushChar(_) => 2,
+            Instr::PushBool(_) => 3,
+            Instr::PushNil => 4,
+            Instr::PushUnit => 5,
+            Instr::PushStr(_) => 6,
+            Instr::PushGlobal(_) => 7,
+            Instr::Push(_) => 8,
+            Instr::Pop(_) => 9,
+            Instr::Slide(_) => 10,
+            Instr::Swap => 11,
+            Instr::MkAp => 12,
+            Instr::MkThunk => 13,
+            Instr::MkCons => 14,
+            Instr::MkData(_, _) => 15,
+            Instr::MkTuple(_) => 16,
+            Instr::MkPap(_, _) => 17,
+            Instr::Alloc => 18,
+            Instr::Update(_) => 19,
+            Instr::Eval => 20,
+            Instr::Case(_) => 21,
+            Instr::Jump(_) => 22,
+            Instr::Return => 23
+        }
+    }
+
+    /// How many nodes of the frame the instruction takes or reaches.
+    fn pops(&self) -> usize {
+        match self {
+            Instr::Push(n) | Instr::Slide(n) => n.saturating_add(1),
+            Instr::Pop(n) | Instr::MkTuple(n) | Instr::MkData(_, n) | Instr::MkPap(_, n) => *n,
+            Instr::Update(n) => n.saturating_add(2),
+            Instr::Swap | Instr::MkAp | Instr::MkCons => 2,
+            Instr::MkThunk | Instr::Eval | Instr::Case(_) => 1,
+            Instr::PushInt(_) | Instr::PushDouble(_) | Instr::PushChar(_) | Instr::PushBool(_) | Instr::PushNil
+                | Instr::PushUnit | Instr::PushStr(_) | Instr::PushGlobal(_) | Instr::Alloc | Instr::Jump(_)
+                | Instr::Return => 0
+        }
+    }
+
+    /// Where the instruction may jump to.
+    fn targets(&self) -> &[usize] {
+        match self {
+            Instr::Case(alts) => alts,
+            Instr::Jump(target) => std::slice::from_ref(target),
+            _ => &[]
+        }
+    }
+}
+
+impl Program {
+    /// The program as the contents of a `.hbc` file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.0.push(VERSION);
+        writer.count(self.functions.len());
+        for function in self.functions.iter() {
+            writer.str(&function.name);
+            writer.count(function.arity);
+            writer.count(function.strict);
+            writer.count(function.code.len());
+            for instr in function.code.iter() {
+                writer.instr(instr);
+            }
+        }
+        writer.0
+    }
+
+    /// Reads the contents of a `.hbc` file, failing with `InvalidData` if
+    /// they are not a well-formed program.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Program> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a bytecode file"));
+        }
+        if reader.take(1)?[0] != VERSION {
+            return Err(invalid("unsupported bytecode version"));
+        }
+        let mut functions = Vec::new();
+        for _ in 0..reader.count()? {
+            let name = reader.str()?;
+            let arity = reader.count()?;
+            let strict = reader.count()?;
+            let length = reader.count()?;
+            if strict > arity {
+                return Err(invalid("more strict arguments than the function takes"));
+            }
+            let code = (0..length).map(|_| reader.instr()).collect::<io::Result<Vec<Instr>>>()?;
+            if code.iter().any(|instr| instr.targets().iter().any(|target| *target > length)) {
+                return Err(invalid("jump out of the function"));
+            }
+            functions.push(Function { name, arity, strict, code });
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(Program { functions })
+    }
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes the little-endian encoding `.hbc` files use, as snapshots do too.
+pub(crate) struct Writer(pub(crate) Vec<u8>);
+
+impl Writer {
+    pub(crate) fn count(&mut self, count: usize) {
+        self.0.extend_from_slice(&(count as u32).to_le_bytes());
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.count(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        self.0.push(instr.opcode());
+        match instr {
+            Instr::PushInt(int) => self.0.extend_from_slice(&int.to_le_bytes()),
+            Instr::PushDouble(double) => self.0.extend_from_slice(&double.to_le_bytes()),
+            Instr::PushChar(c) => self.count(*c as usize),
+            Instr::PushBool(b) => self.0.push(*b as u8),
+            Instr::PushStr(s) | Instr::PushGlobal(s) => self.str(s),
+            Instr::Push(n) | Instr::Pop(n) | Instr::Slide(n) | Instr::MkTuple(n) | Instr::Update(n) | Instr::Jump(n) => self.count(*n),
+            Instr::MkData(tag, arity) => {
+                self.count(*tag as usize);
+                self.count(*arity);
+            },
+            Instr::MkPap(name, arity) => {
+                self.str(name);
+                self.count(*arity);
+            },
+            Instr::Case(alts) => {
+                self.count(alts.len());
+                for alt in alts.iter() {
+                    self.count(*alt);
+                }
+            },
+            Instr::PushNil | Instr::PushUnit | Instr::Swap | Instr::MkAp | Instr::MkThunk | Instr::MkCons
+                | Instr::Alloc | Instr::Eval | Instr::Return => {}
+        }
+    }
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("unexpected end of file"));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(<[u8; 4]>::try_from(self.take(4)?).unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(<[u8; 8]>::try_from(self.take(8)?).unwrap()))
+    }
+
+    pub(crate) fn count(&mut self) -> io::Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    pub(crate) fn str(&mut self) -> io::Result<String> {
+        let length = self.count()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string not UTF-8"))
+    }
+
+    fn instr(&mut self) -> io::Result<Instr> {
+        let instr = match self.take(1)?[0] {
+            0 => Instr::PushInt(self.u64()? as i64),
+            1 => Instr::PushDouble(f64::from_bits(self.u64()?)),
+            2 => Instr::PushChar(char::from_u32(self.u32()?).ok_or_else(|| invalid("invalid char"))?),
+            3 => Instr::PushBool(self.take(1)?[0] != 0),
+            4 => Instr::PushNil,
+            5 => Instr::PushUnit,
+            6 => Instr::PushStr(self.str()?),
+            7 => Instr::PushGlobal(self.str()?),
+            8 => Instr::Push(self.count()?),
+            9 => Instr::Pop(self.count()?),
+            10 => Instr::Slide(self.count()?),
+            11 => Instr::Swap,
+            12 => Instr::MkAp,
+            13 => Instr::MkThunk,
+            14 => Instr::MkCons,
+            15 => Instr::MkData(self.u32()?, self.count()?),
+            16 => Instr::MkTuple(self.count()?),
+            17 => Instr::MkPap(self.str()?, self.count()?),
+            18 => Instr::Alloc,
+            19 => Instr::Update(self.count()?),
+            20 => Instr::Eval,
+            21 => {
+                let count = self.count()?;
+                Instr::Case((0..count).map(|_| self.count()).collect::<io::Result<Vec<usize>>>()?)
+            },
+            22 => Instr::Jump(self.count()?),
+            23 => Instr::Return,
+            _ => return Err(invalid("unknown opcode"))
+        };
+        Ok(instr)
+    }
+}
+
+impl State {
+    /// Registers the functions of `program` as globals, replacing any of
+    /// the same names. CAFs evaluated so far are released, as the program
+    /// may have redefined them.
+    pub fn load_program(&mut self, program: Program) {
+        for function in program.functions {
+            let function: &'static Function = Box::leak(Box::new(function));
+            self.bytecode.insert(&function.name, function);
+            self.register_global(FnDef {
+                name: &function.name,
+                arity: function.arity,
+                strict: function.strict,
+                fn_ref: run_bytecode
+            });
+        }
+        self.globals.release_cafs();
+    }
+
+    /// Loads the `.hbc` file at `path`, see `load_program`.
+    pub fn load_bytecode(&mut self, path: &str) -> EvalResult {
+        let program = std::fs::read(path)
+            .and_then(|bytes| Program::from_bytes(&bytes))
+            .map_err(|err| IoException::new(path, "loadBytecode", &err))?;
+        self.load_program(program);
+        Ok(())
+    }
+
+    fn push_global_or_builtin(&mut self, name: &str) -> EvalResult {
+        match self.globals.lookup(name) {
+            Some(_) => self.push_global(name),
+            None => {
+                let fn_def = self.lookup_fn(name)?;
+                self.push_fn(fn_def);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Runs the code of the bytecode function being called. Its frame is its
+/// arguments and what it pushes: code reaching below them, or leaving
+/// anything but the result there, fails rather than corrupting the stack.
+fn run_bytecode(state: &mut State) -> EvalResult {
+    let function = *state.bytecode.get(state.callee)
+        .ok_or_else(|| EvalError::UnknownGlobal(state.callee.to_string()))?;
+    let base = state.stack_size().saturating_sub(function.arity);
+    let mut pc = 0;
+    while let Some(instr) = function.code.get(pc) {
+        pc += 1;
+        let needed = instr.pops();
+        if state.stack_size() - base < needed {
+            return Err(EvalError::Error(format!("bytecode function {} takes {} nodes but has {}",
+                                                function.name, needed, state.stack_size() - base)));
+        }
+        match instr {
+            Instr::PushInt(int) => state.push_int(*int),
+            Instr::PushDouble(double) => state.push_double(*double),
+            Instr::PushChar(c) => state.push_char(*c),
+            Instr::PushBool(b) => state.stack_push(Node::Bool(*b)),
+            Instr::PushNil => state.push_nil(),
+            Instr::PushUnit => state.push_unit(),
+            Instr::PushStr(s) => state.push_str(s),
+            Instr::PushGlobal(name) => state.push_global_or_builtin(name)?,
+            Instr::Push(n) => {
+                let node = *state.stack_peek_nth(*n);
+                state.stack_push(node);
+            },
+            Instr::Pop(n) => {
+                for _ in 0..*n {
+                    state.stack_pop();
+                }
+            },
+            Instr::Slide(n) => {
+                let top = state.stack_pop();
+                for _ in 0..*n {
+                    state.stack_pop();
+                }
+                state.stack_push(top);
+            },
+            Instr::Swap => state.stack_swap(),
+            Instr::MkAp => state.mk_ap(),
+            Instr::MkThunk => state.mk_thunk(),
+            Instr::MkCons => state.mk_cons(),
+            Instr::MkData(tag, arity) => state.mk_data(*tag, *arity),
+            Instr::MkTuple(arity) => state.mk_tuple(*arity),
+            Instr::MkPap(name, arity) => {
+                let fn_def = state.lookup_fn(name)?;
+                if *arity > fn_def.arity {
+                    return Err(EvalError::Error(format!("{} applied to {} arguments, it takes {}",
+                                                        name, arity, fn_def.arity)));
+                }
+                state.mk_pap(fn_def, *arity);
+            },
+            Instr::Alloc => state.push_hole(),
+            Instr::Update(n) => state.fill_hole(*n),
+            Instr::Eval => state.eval()?,
+            Instr::Case(alts) => {
+                let tag = state.case_split()?;
+                pc = *alts.get(tag as usize).ok_or_else(|| {
+                    EvalError::PatternMatchFail(format!("no alternative for constructor tag {}", tag))
+                })?;
+            },
+            Instr::Jump(target) => pc = *target,
+            Instr::Return => break
+        }
+    }
+    if state.stack_size() != base + 1 {
+        return Err(EvalError::Error(format!("bytecode function {} left {} nodes for its result",
+                                            function.name, state.stack_size() - base)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, arity: usize, code: Vec<Instr>) -> Function {
+        Function { name: name.to_string(), arity, strict: 0, code }
+    }
+
+    /// `inc x = x + 1`
+    fn inc() -> Function {
+        function("inc", 1, vec![Instr::PushInt(1), Instr::Push(1), Instr::PushGlobal("+".to_string()),
+                                Instr::MkAp, Instr::MkAp, Instr::Eval, Instr::Slide(1)])
+    }
+
+    /// Loads `function` and applies it to `args`, the first on top.
+    fn call(state: &mut State, function: Function, args: &[Node]) -> EvalResult<Node> {
+        let name = function.name.clone();
+        state.load_program(Program { functions: vec![function] });
+        let fn_def = state.lookup_fn(&name)?;
+        state.push_fn(fn_def);
+        for arg in args {
+            state.push(*arg);
+            state.stack_swap();
+            state.mk_ap();
+        }
+        let node = state.stack_pop();
+        state.try_eval(node)
+    }
+
+    fn is_error(result: EvalResult<Node>) -> bool {
+        matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Error(_)))
+    }
+
+    fn is_invalid(bytes: &[u8]) -> bool {
+        matches!(Program::from_bytes(bytes), Err(err) if err.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn program_round_trips_through_bytes() {
+        let code = vec![
+            Instr::PushInt(-7), Instr::PushDouble(0.5), Instr::PushChar('λ'), Instr::PushBool(true),
+            Instr::PushNil, Instr::PushUnit, Instr::PushStr("ab\n".to_string()),
+            Instr::PushGlobal("map".to_string()), Instr::Push(2), Instr::Pop(1), Instr::Slide(3), Instr::Swap,
+            Instr::MkAp, Instr::MkThunk, Instr::MkCons, Instr::MkData(4, 2), Instr::MkTuple(3),
+            Instr::MkPap("add".to_string(), 1), Instr::Alloc, Instr::Update(1), Instr::Eval,
+            Instr::Case(vec![23, 24]), Instr::Jump(24), Instr::Return
+        ];
+        let program = Program { functions: vec![Function { name: "f".to_string(), arity: 2, strict: 1, code }, inc()] };
+        assert_eq!(Program::from_bytes(&program.to_bytes()).ok(), Some(program));
+    }
+
+    #[test]
+    fn loaded_functions_run() {
+        let mut state = State::new();
+        assert!(matches!(call(&mut state, inc(), &[Node::Int(41)]), Ok(Node::Int(42))));
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn malformed_files_are_invalid_data() {
+        let bytes = Program { functions: vec![inc()] }.to_bytes();
+        assert!(is_invalid(b"HBX\x01\0\0\0\0"));
+        assert!(is_invalid(b"HBC\x02\0\0\0\0"));
+        assert!(is_invalid(&bytes[..bytes.len() - 1]));
+        assert!(is_invalid(&[&bytes[..], &[0]].concat()));
+
+        let mut opcode = Program { functions: vec![function("f", 0, vec![Instr::Return])] }.to_bytes();
+        *opcode.last_mut().unwrap() = 0xff;
+        assert!(is_invalid(&opcode));
+
+        let jump = Program { functions: vec![function("f", 0, vec![Instr::Jump(2)])] };
+        assert!(is_invalid(&jump.to_bytes()));
+        let strict = Program { functions: vec![Function { strict: 2, ..inc() }] };
+        assert!(is_invalid(&strict.to_bytes()));
+    }
+
+    #[test]
+    fn code_reaching_below_its_frame_fails() {
+        let mut state = State::new();
+        let cases = [vec![Instr::Pop(2)], vec![Instr::Push(1)], vec![Instr::Slide(1)], vec![Instr::Swap],
+                     vec![Instr::MkData(0, 2)], vec![Instr::MkTuple(3)], vec![Instr::Update(0)],
+                     vec![Instr::PushInt(1)], vec![Instr::Pop(1)]];
+        for code in cases {
+            let result = call(&mut state, function("f", 1, code.clone()), &[Node::Int(1)]);
+            assert!(is_error(result), "{:?}", code);
+            assert_eq!(state.stack_size(), 0);
+        }
+    }
+
+    #[test]
+    fn partial_applications_are_not_over_applied() {
+        let mut state = State::new();
+        let code = vec![Instr::PushInt(1), Instr::PushInt(2), Instr::MkPap("add".to_string(), 3)];
+        assert!(is_error(call(&mut state, function("f", 1, code), &[Node::Int(0)])));
+    }
+
+    #[test]
+    fn missing_code_is_an_unknown_global() {
+        let mut state = State::new();
+        let fn_def = FnDef { name: "nowhere", arity: 0, strict: 0, fn_ref: run_bytecode };
+        let result = state.try_eval(Node::FnDef(fn_def));
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::UnknownGlobal(_))));
+    }
+}
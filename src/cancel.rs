@@ -0,0 +1,172 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+use crate::builtins::*;
+
+/// How many reductions go by between checks for cancellation, so that
+/// looking at the clock and the flag costs next to nothing.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// A flag another thread can raise to stop an evaluation started with
+/// `State::eval_with_cancel`. Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// What stops the evaluation in progress early.
+#[derive(Clone, Default)]
+pub(crate) struct Cancel {
+    token: Option<CancelToken>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>
+}
+
+impl Cancel {
+    #[cfg(feature = "std")]
+    fn past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Without `std` there is no clock, and no deadlines.
+    #[cfg(not(feature = "std"))]
+    fn past_deadline(&self) -> bool {
+        false
+    }
+}
+
+impl State {
+    /// Evaluates `node` to WHNF like `try_eval`, but fails with
+    /// `EvalError::Cancelled` soon after `token` is cancelled. Thunks being
+    /// forced at the time are left to be evaluated again, so the state
+    /// stays usable. An IO action blocked waiting for input is not
+    /// interrupted, only pure reduction is checked.
+    pub fn eval_with_cancel(&mut self, node: Node, token: &CancelToken) -> EvalResult<Node> {
+        let mut cancel = self.cancel.clone();
+        cancel.token = Some(token.clone());
+        self.with_cancel(cancel, node)
+    }
+
+    /// Evaluates `node` to WHNF like `try_eval`, but fails with
+    /// `EvalError::Cancelled` once `timeout` has passed.
+    #[cfg(feature = "std")]
+    pub fn eval_with_timeout(&mut self, node: Node, timeout: Duration) -> EvalResult<Node> {
+        let deadline = Instant::now() + timeout;
+        let deadline = self.cancel.deadline.map_or(deadline, |outer| outer.min(deadline));
+        let cancel = Cancel { deadline: Some(deadline), ..self.cancel.clone() };
+        self.with_cancel(cancel, node)
+    }
+
+    fn with_cancel(&mut self, cancel: Cancel, node: Node) -> EvalResult<Node> {
+        let outer = core::mem::replace(&mut self.cancel, cancel);
+        let result = self.try_eval(node);
+        self.cancel = outer;
+        result
+    }
+
+    /// Fails if the evaluation has been cancelled or has run past its
+    /// deadline, looking every `CHECK_INTERVAL` reductions.
+    pub(crate) fn check_cancelled(&self) -> EvalResult {
+        if !self.stats.reductions.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+        let cancelled = self.cancel.token.as_ref().is_some_and(CancelToken::is_cancelled)
+            || self.cancel.past_deadline();
+        if cancelled {
+            return Err(EvalError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FN_SPIN: FnDef = FnDef {
+        name: "spin",
+        arity: 1,
+        strict: 1,
+        fn_ref: eval_spin
+    };
+
+    /// `spin n = spin (n + 1)`, reducing forever.
+    fn eval_spin(state: &mut State) -> EvalResult {
+        let Node::Int(n) = state.stack_pop() else {
+            unreachable!()
+        };
+        state.push_int(n.wrapping_add(1));
+        state.push_fn(FN_SPIN);
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// Pushes `spin 0` in a thunk.
+    fn push_spin(state: &mut State) -> Node {
+        state.push_int(0);
+        state.push_fn(FN_SPIN);
+        state.mk_ap();
+        state.mk_thunk();
+        *state.stack_peek()
+    }
+
+    #[test]
+    fn cancelling_stops_the_evaluation() {
+        let mut state = State::new();
+        let spin = push_spin(&mut state);
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let result = state.eval_with_cancel(spin, &token);
+        thread.join().expect("cancelled");
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Cancelled)));
+        assert!(token.is_cancelled());
+
+        // the state goes on, with the thunk left to be forced again
+        assert_eq!(state.stack_size(), 1);
+        assert!(matches!(spin, Node::ThunkRef(thunk) if matches!(thunk.state, ThunkState::Pending(_))));
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.apply(2).expect("a sum");
+        assert!(matches!(state.stack_pop(), Node::Int(3)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn timeouts_stop_the_evaluation() {
+        let mut state = State::new();
+        let spin = push_spin(&mut state);
+        let start = Instant::now();
+        let result = state.eval_with_timeout(spin, Duration::from_millis(20));
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // an evaluation that finishes in time is unaffected
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        let sum = *state.stack_peek();
+        assert!(matches!(state.eval_with_timeout(sum, Duration::from_secs(10)), Ok(Node::Int(3))));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::collections::HashMap;
+use core::fmt;
+use core::fmt::Formatter;
+use alloc::vec::Vec;
+use crate::gc::*;
+
+/// The live objects of one kind and label, e.g. thunks labelled `xs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CensusEntry {
+    pub kind: &'static str,
+    pub label: Option<&'static str>,
+    pub objects: usize,
+    pub bytes: usize
+}
+
+/// What is on the heap after a collection, grouped by kind of object and
+/// thunk label (like GHC's `-hT`), largest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapCensus {
+    /// The number of collections run before the census was taken.
+    pub collection: u64,
+    pub entries: Vec<CensusEntry>
+}
+
+impl HeapCensus {
+    /// Takes a census of `alloc`, which should just have been collected
+    /// so that only live objects are counted.
+    pub fn of(alloc: &GcAlloc) -> Self {
+        let mut groups: HashMap<(&'static str, Option<&'static str>), (usize, usize)> = HashMap::new();
+        for obj in alloc.objects() {
+            let group = groups.entry(obj.census_key()).or_insert((0, 0));
+            group.0 += 1;
+            group.1 += obj.heap_size();
+        }
+
+        let mut entries: Vec<CensusEntry> = groups.into_iter()
+            .map(|((kind, label), (objects, bytes))| CensusEntry { kind, label, objects, bytes })
+            .collect();
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.kind.cmp(b.kind)).then(a.label.cmp(&b.label)));
+
+        HeapCensus { collection: alloc.collections(), entries }
+    }
+
+    pub fn total_objects(&self) -> usize {
+        self.entries.iter().map(|entry| entry.objects).sum()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+}
+
+impl fmt::Display for HeapCensus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "heap census after {} collections: {} objects, {} bytes",
+                 self.collection, self.total_objects(), self.total_bytes())?;
+        for entry in self.entries.iter() {
+            write!(f, "{:>12} {:>8}  {}", entry.bytes, entry.objects, entry.kind)?;
+            if let Some(label) = entry.label {
+                write!(f, " {}", label)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::*;
+
+    fn labelled(census: &HeapCensus, label: &str) -> Option<usize> {
+        census.entries.iter()
+            .find(|entry| entry.kind == "thunk" && entry.label == Some(label))
+            .map(|entry| entry.objects)
+    }
+
+    #[test]
+    fn live_thunks_are_counted_by_label() {
+        let mut state = State::new();
+        for _ in 0..3 {
+            state.push_int(1);
+            state.mk_thunk();
+            state.label_thunk("xs");
+        }
+        let census = state.heap_census();
+        assert_eq!(labelled(&census, "xs"), Some(3));
+        assert!(census.entries.windows(2).all(|pair| pair[0].bytes >= pair[1].bytes));
+        assert_eq!(census.total_bytes(), census.entries.iter().map(|entry| entry.bytes).sum::<usize>());
+        assert!(census.to_string().contains(" thunk xs\n"));
+
+        for _ in 0..3 {
+            state.stack_pop();
+        }
+        assert_eq!(labelled(&state.heap_census(), "xs"), None);
+    }
+
+    #[test]
+    fn censuses_are_taken_every_interval() {
+        let mut state = State::new();
+        state.set_census_interval(Some(2));
+        for i in 0..8 {
+            state.push_int(i);
+            state.mk_thunk();
+        }
+        let censuses = state.take_censuses();
+        assert!(!censuses.is_empty());
+        assert!(censuses.iter().all(|census| census.collection % 2 == 0));
+        assert!(state.take_censuses().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(not(feature = "wasm"))]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::builtins::*;
+#[cfg(feature = "wasm")]
+use crate::wasm;
+
+/// When the monotonic clock started counting, on its first use.
+#[cfg(not(feature = "wasm"))]
+static MONOTONIC_START: OnceLock<Instant> = OnceLock::new();
+
+/// The CPU time used by the process so far, from `clock_gettime`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn cpu_time() -> Option<Duration> {
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: i64,
+        tv_nsec: i64
+    }
+
+    extern "C" {
+        fn clock_gettime(clock_id: i32, tp: *mut Timespec) -> i32;
+    }
+
+    #[cfg(target_os = "linux")]
+    const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
+    #[cfg(target_os = "macos")]
+    const CLOCK_PROCESS_CPUTIME_ID: i32 = 12;
+
+    let mut time = Timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { clock_gettime(CLOCK_PROCESS_CPUTIME_ID, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// Where the CPU time cannot be read, the time since the clock was first used.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn cpu_time() -> Option<Duration> {
+    Some(Duration::from_secs_f64(monotonic_time()))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn current_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or_else(|before| -before.duration().as_secs_f64(), |after| after.as_secs_f64())
+}
+
+#[cfg(not(feature = "wasm"))]
+fn monotonic_time() -> f64 {
+    MONOTONIC_START.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+#[cfg(feature = "wasm")]
+fn current_time() -> f64 {
+    wasm::now()
+}
+
+/// Since the clock was first used, like the standard library's.
+#[cfg(feature = "wasm")]
+fn monotonic_time() -> f64 {
+    static START: OnceLock<f64> = OnceLock::new();
+    wasm::monotonic_now() - START.get_or_init(wasm::monotonic_now)
+}
+
+pub static FN_GET_CURRENT_TIME: FnDef = FnDef {
+    name: "getCurrentTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_current_time
+};
+
+pub static FN_GET_MONOTONIC_TIME: FnDef = FnDef {
+    name: "getMonotonicTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_monotonic_time
+};
+
+pub static FN_GET_CPU_TIME: FnDef = FnDef {
+    name: "getCPUTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_cpu_time
+};
+
+static IO_GET_CURRENT_TIME: FnDef = FnDef {
+    name: "getCurrentTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_get_current_time
+};
+
+static IO_GET_MONOTONIC_TIME: FnDef = FnDef {
+    name: "getMonotonicTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_get_monotonic_time
+};
+
+static IO_GET_CPU_TIME: FnDef = FnDef {
+    name: "getCPUTime",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_get_cpu_time
+};
+
+pub fn eval_get_current_time(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_CURRENT_TIME);
+    Ok(())
+}
+
+pub fn eval_get_monotonic_time(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_MONOTONIC_TIME);
+    Ok(())
+}
+
+pub fn eval_get_cpu_time(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_CPU_TIME);
+    Ok(())
+}
+
+/// Seconds since the Unix epoch as a `Double`, like `getPOSIXTime`.
+fn perform_get_current_time(state: &mut State) -> EvalResult {
+    state.push_double(current_time());
+    Ok(())
+}
+
+/// Seconds as a `Double` from an arbitrary start, never going backwards,
+/// for timing parts of a program as `GHC.Clock` does.
+fn perform_get_monotonic_time(state: &mut State) -> EvalResult {
+    state.push_double(monotonic_time());
+    Ok(())
+}
+
+/// The CPU time used so far in picoseconds as an `Integer`, as `System.CPUTime` has it.
+fn perform_get_cpu_time(state: &mut State) -> EvalResult {
+    let time = cpu_time().unwrap_or_default();
+    let nanos = BigInt::from(time.as_secs() as i64 * 1_000_000_000 + time.subsec_nanos() as i64);
+    state.push_integer(&nanos * &BigInt::from(1000));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    fn seconds(state: &mut State, fn_def: FnDef) -> f64 {
+        run_action(state, fn_def, &[]).expect("a time").parse().expect("a Double")
+    }
+
+    #[test]
+    fn clocks_move_forward() {
+        let mut state = State::new();
+        // after 2020-01-01
+        assert!(seconds(&mut state, FN_GET_CURRENT_TIME) > 1.5e9);
+        let start = seconds(&mut state, FN_GET_MONOTONIC_TIME);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(seconds(&mut state, FN_GET_MONOTONIC_TIME) - start >= 0.002);
+
+        let cpu_time = run_action(&mut state, FN_GET_CPU_TIME, &[]).expect("a time");
+        assert!(cpu_time.ends_with("000"));
+        assert!(cpu_time.parse::<u128>().expect("picoseconds") > 0);
+    }
+}
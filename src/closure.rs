@@ -0,0 +1,171 @@
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::builders::IntoNode;
+use crate::gc::*;
+
+// Rust closures the program calls back, e.g. the thunk of a value the host
+// computes only if the program needs it. A closure is applied to a token,
+// an `IORef` standing for it, with `FN_CALL_CLOSURE`, and the state keeps
+// it by token until the token is collected: nothing can call it any more,
+// and it is dropped. A closure that needs no state can instead be the
+// computation of a thunk itself, see `thunk_fn`.
+
+/// Called with its token on top of the stack, on which it pushes its
+/// result. It is kept to be called again for as long as the token lives.
+pub type NativeClosure = Box<dyn FnMut(&mut State) -> EvalResult>;
+
+/// The closures registered, by token.
+#[derive(Default)]
+pub(crate) struct Closures(Vec<(Gc<Node>, NativeClosure)>);
+
+impl Closures {
+    /// Drops the closures whose tokens the collection in progress found
+    /// unreachable, everything else marked already.
+    pub(crate) fn prune(&mut self) {
+        self.0.retain(|(token, _)| token.is_marked());
+    }
+
+    fn take(&mut self, token: Gc<Node>) -> Option<NativeClosure> {
+        let index = self.0.iter().position(|(other, _)| other.addr() == token.addr())?;
+        Some(self.0.swap_remove(index).1)
+    }
+}
+
+impl State {
+    /// Pushes a new token for `closure`.
+    pub(crate) fn push_closure_token(&mut self, closure: NativeClosure) {
+        let token = self.alloc(Node::Nil);
+        self.closures.0.push((token, closure));
+        self.stack_push(Node::IORef(token));
+    }
+
+    /// Replaces the token on top of the stack with a thunk calling its
+    /// closure.
+    pub(crate) fn mk_closure_thunk(&mut self) {
+        self.push_fn(FN_CALL_CLOSURE);
+        self.mk_ap();
+        self.mk_thunk();
+    }
+
+    /// Pushes a thunk evaluated by calling `f`, which pushes the value, e.g.
+    /// `state.push_thunk_with(|state| { state.push_int(expensive()); Ok(()) })`.
+    pub fn push_thunk_with<F>(&mut self, f: F) where F: FnOnce(&mut State) -> EvalResult + 'static {
+        let mut f = Some(f);
+        self.push_closure_token(Box::new(move |state| {
+            // the thunk is updated with the value, so is only forced once
+            (f.take().expect("closure thunk forced twice"))(state)
+        }));
+        self.mk_closure_thunk();
+    }
+
+    /// Pushes a thunk for the value `f` returns, e.g.
+    /// `state.push_thunk_fn(|| expensive())`.
+    pub fn push_thunk_fn<F, T>(&mut self, f: F) where F: FnOnce() -> T + 'static, T: IntoNode {
+        self.push_thunk_with(move |state| {
+            state.push(f());
+            Ok(())
+        });
+    }
+}
+
+/// A closure giving the expression of a thunk, e.g.
+/// `state.push_thunk_eval(thunk_fn(|| Node::Int(expensive())))`. Nodes it
+/// captures aren't traced, so any into the heap have to be rooted elsewhere.
+impl<F> ThunkEval for F where F: Fn() -> Node + 'static {
+    fn eval_thunk(&self) -> Node {
+        self()
+    }
+}
+
+/// Boxes `f` as the computation of a thunk, for `State::push_thunk_eval`.
+pub fn thunk_fn<F>(f: F) -> Box<dyn ThunkEval> where F: Fn() -> Node + 'static {
+    Box::new(f)
+}
+
+static FN_CALL_CLOSURE: FnDef = FnDef {
+    name: "callClosure",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_call_closure
+};
+
+/// Calls the closure standing behind the token on top of the stack.
+fn eval_call_closure(state: &mut State) -> EvalResult {
+    let token = match *state.stack_peek() {
+        Node::IORef(token) => token,
+        node => return Err(EvalError::type_mismatch("callClosure", "closure", &node))
+    };
+    // taken out while it runs, as it may register closures of its own
+    let mut closure = match state.closures.take(token) {
+        Some(closure) => closure,
+        None => return Err(EvalError::Error("closure called while it runs".to_string()))
+    };
+    // the token stays on the stack, rooted, until the closure is back
+    let result = closure(state);
+    state.closures.0.push((token, closure));
+    result?;
+    state.stack_swap();
+    state.stack_pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use super::*;
+
+    #[test]
+    fn closure_thunks_run_once_when_forced() {
+        let mut state = State::new();
+        let runs = Rc::new(Cell::new(0));
+        let counted = runs.clone();
+        state.push_thunk_fn(move || {
+            counted.set(counted.get() + 1);
+            "value"
+        });
+        assert_eq!(runs.get(), 0);
+        let thunk = *state.stack_peek();
+        let value = state.try_eval(thunk).expect("a string");
+        assert_eq!(state.render(value).expect("a string"), "\"value\"");
+        let thunk = *state.stack_peek();
+        state.try_eval(thunk).expect("a string");
+        assert_eq!(runs.get(), 1);
+    }
+
+    #[test]
+    fn closures_are_dropped_with_their_tokens() {
+        let mut state = State::new();
+        let captured = Rc::new(());
+        let held = captured.clone();
+        state.push_thunk_with(move |state| {
+            let _held = &held;
+            state.push_int(1);
+            Ok(())
+        });
+        state.collect(Vec::new());
+        assert_eq!(Rc::strong_count(&captured), 2);
+        state.stack_pop();
+        state.collect(Vec::new());
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn failing_closures_fail_the_thunk() {
+        let mut state = State::new();
+        state.push_thunk_with(|_| Err(EvalError::Error("no value".to_string())));
+        let thunk = *state.stack_peek();
+        let err = state.try_eval(thunk).expect_err("no value");
+        assert!(matches!(err.cause(), EvalError::Error(message) if message == "no value"));
+    }
+
+    #[test]
+    fn thunk_fns_give_the_expression() {
+        let mut state = State::new();
+        state.push_thunk_eval(thunk_fn(|| Node::Int(7)));
+        let thunk = state.stack_pop();
+        assert!(matches!(state.try_eval(thunk), Ok(Node::Int(7))));
+    }
+}
@@ -0,0 +1,474 @@
+use crate::collections::VecDeque;
+use alloc::vec::Vec;
+use alloc::vec;
+use crate::builtins::*;
+use crate::gc::*;
+use crate::stm::TVar;
+
+// Threads are green threads, all run by the outermost `State::run_io` on
+// the one heap, which is not shared with other OS threads. Each has a frame
+// of IO continuations of its own; the running thread's is the driver's
+// frame, the others wait in the scheduler with theirs. Threads take turns
+// every `TIME_SLICE` IO steps and whenever the running one blocks on an
+// `MVar` or retries an STM transaction. The program ends with its main thread, whatever the others are
+// doing.
+
+/// How many IO steps a thread takes before the next one gets a turn.
+const TIME_SLICE: u32 = 1000;
+
+/// The `ThreadId` of the thread running `main`.
+const MAIN_THREAD: i64 = 0;
+
+/// A box that is either full or empty, `takeMVar` waiting for it to be
+/// full and `putMVar` for it to be empty.
+pub struct MVar {
+    value: Option<Node>
+}
+
+impl Trace for MVar {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        if let Some(value) = &self.value {
+            value.mark_refs(worklist);
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("MVar", None)
+    }
+}
+
+/// What a blocked thread waits for before trying its action again.
+pub(crate) enum Blocked {
+    Full(Gc<MVar>),
+    Empty(Gc<MVar>),
+    /// A change to any of the `TVar`s from the versions the transaction read.
+    Retry(Vec<(Gc<TVar>, u64)>)
+}
+
+impl Blocked {
+    fn is_over(&self) -> bool {
+        match self {
+            Blocked::Full(mvar) => mvar.value.is_some(),
+            Blocked::Empty(mvar) => mvar.value.is_none(),
+            Blocked::Retry(reads) => reads.iter().any(|(tvar, version)| tvar.version != *version)
+        }
+    }
+
+    /// The exception a thread blocked for good gets.
+    pub(crate) fn error(&self) -> EvalError {
+        match self {
+            Blocked::Full(_) | Blocked::Empty(_) => EvalError::BlockedIndefinitely,
+            Blocked::Retry(_) => EvalError::BlockedIndefinitelyOnStm
+        }
+    }
+
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        match self {
+            Blocked::Full(mvar) | Blocked::Empty(mvar) => mvar.mark_into(worklist),
+            Blocked::Retry(reads) => {
+                for (tvar, _) in reads.iter() {
+                    tvar.mark_into(worklist);
+                }
+            }
+        }
+    }
+}
+
+struct Thread {
+    id: i64,
+    frame: Vec<Node>,
+    blocked: Option<Blocked>
+}
+
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    /// Whether an IO driver is running threads, nested ones leave it to that one.
+    active: bool,
+    current: i64,
+    next_id: i64,
+    /// The threads waiting for their turn, in the order they get it.
+    waiting: VecDeque<Thread>,
+    /// Set by an MVar operation that cannot go ahead or a transaction that
+    /// retried, for the driver to block the running thread on and retry the
+    /// action later.
+    pub(crate) blocked: Option<Blocked>,
+    steps: u32
+}
+
+impl Scheduler {
+    pub(crate) fn mark_roots(&self, worklist: &mut Worklist) {
+        for thread in self.waiting.iter() {
+            for node in thread.frame.iter() {
+                node.mark_refs(worklist);
+            }
+            if let Some(blocked) = &thread.blocked {
+                blocked.mark_refs(worklist);
+            }
+        }
+        if let Some(blocked) = &self.blocked {
+            blocked.mark_refs(worklist);
+        }
+    }
+}
+
+impl State {
+    /// Makes the driver starting up the one running threads, unless one
+    /// already is, returning whether it is.
+    pub(crate) fn start_scheduling(&mut self) -> bool {
+        if self.scheduler.active {
+            return false;
+        }
+        let next_id = self.scheduler.next_id.max(MAIN_THREAD + 1);
+        self.scheduler = Scheduler { active: true, current: MAIN_THREAD, next_id, ..Scheduler::default() };
+        true
+    }
+
+    /// Drops the threads left once the main thread is done.
+    pub(crate) fn stop_scheduling(&mut self) {
+        let next_id = self.scheduler.next_id;
+        self.scheduler = Scheduler { next_id, ..Scheduler::default() };
+    }
+
+    pub(crate) fn on_main_thread(&self) -> bool {
+        self.scheduler.current == MAIN_THREAD
+    }
+
+    /// Counts an IO step of the running thread, and lets the next one have
+    /// its turn once its time slice is used up, the finalizers of the keys
+    /// that have died since the last turn queued as threads of their own.
+    pub(crate) fn tick_thread(&mut self, depth: usize) {
+        self.scheduler.steps += 1;
+        if self.scheduler.steps >= TIME_SLICE {
+            self.scheduler.steps = 0;
+            self.fork_finalizers();
+            if self.scheduler.waiting.iter().any(|thread| thread.blocked.as_ref().is_none_or(Blocked::is_over)) {
+                self.suspend_thread(depth, None);
+                self.resume_thread(depth);
+            }
+        }
+    }
+
+    /// Moves the running thread, its frame the one at `depth`, to the back of the queue.
+    pub(crate) fn suspend_thread(&mut self, depth: usize, blocked: Option<Blocked>) {
+        let frame = core::mem::take(&mut self.stacks[depth - 1]);
+        self.scheduler.waiting.push_back(Thread { id: self.scheduler.current, frame, blocked });
+    }
+
+    /// Gives the turn to the first thread that can go on, the running one
+    /// having been suspended or finished. If they are all blocked, the main
+    /// thread, or failing that the first, is woken up anyway, and the
+    /// `BlockedIndefinitelyOnMVar` or `BlockedIndefinitelyOnSTM` exception it
+    /// is to get in place of its action is returned.
+    pub(crate) fn resume_thread(&mut self, depth: usize) -> Option<EvalError> {
+        let waiting = &mut self.scheduler.waiting;
+        let (next, deadlocked) = match waiting.iter().position(|thread| thread.blocked.as_ref().is_none_or(Blocked::is_over)) {
+            Some(next) => (next, false),
+            None => (waiting.iter().position(|thread| thread.id == MAIN_THREAD).unwrap_or(0), true)
+        };
+        let thread = waiting.remove(next).expect("the main thread is running or waiting");
+        self.stacks[depth - 1] = thread.frame;
+        self.scheduler.current = thread.id;
+        self.scheduler.steps = 0;
+        if deadlocked {
+            thread.blocked.as_ref().map(Blocked::error)
+        } else {
+            None
+        }
+    }
+
+    /// Queues a new thread to perform the action on top of the stack, popping it.
+    pub(crate) fn fork_thread(&mut self) -> i64 {
+        let action = self.stack_pop();
+        let id = self.scheduler.next_id.max(MAIN_THREAD + 1);
+        self.scheduler.next_id = id + 1;
+        self.scheduler.waiting.push_back(Thread { id, frame: vec![action], blocked: None });
+        id
+    }
+
+    /// Forces the `MVar` on top of the stack, popping it.
+    fn pop_mvar(&mut self, context: &'static str) -> EvalResult<Gc<MVar>> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::MVar(mvar) => Ok(mvar),
+            node => Err(EvalError::type_mismatch(context, "MVar", &node))
+        }
+    }
+
+    fn push_mvar(&mut self, value: Option<Node>) {
+        let mvar = self.alloc(MVar { value });
+        self.stack_push(Node::MVar(mvar));
+    }
+}
+
+pub static FN_FORK_IO: FnDef = FnDef {
+    name: "forkIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_fork_io
+};
+
+pub static FN_MY_THREAD_ID: FnDef = FnDef {
+    name: "myThreadId",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_my_thread_id
+};
+
+pub static FN_YIELD: FnDef = FnDef {
+    name: "yield",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_yield
+};
+
+pub static FN_NEW_MVAR: FnDef = FnDef {
+    name: "newMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_new_mvar
+};
+
+pub static FN_NEW_EMPTY_MVAR: FnDef = FnDef {
+    name: "newEmptyMVar",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_new_empty_mvar
+};
+
+pub static FN_TAKE_MVAR: FnDef = FnDef {
+    name: "takeMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_take_mvar
+};
+
+pub static FN_PUT_MVAR: FnDef = FnDef {
+    name: "putMVar",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_put_mvar
+};
+
+pub static FN_READ_MVAR: FnDef = FnDef {
+    name: "readMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read_mvar
+};
+
+static IO_FORK_IO: FnDef = FnDef {
+    name: "forkIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_fork_io
+};
+
+static IO_MY_THREAD_ID: FnDef = FnDef {
+    name: "myThreadId",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_my_thread_id
+};
+
+static IO_YIELD: FnDef = FnDef {
+    name: "yield",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_yield
+};
+
+static IO_NEW_MVAR: FnDef = FnDef {
+    name: "newMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_new_mvar
+};
+
+static IO_NEW_EMPTY_MVAR: FnDef = FnDef {
+    name: "newEmptyMVar",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_new_empty_mvar
+};
+
+static IO_TAKE_MVAR: FnDef = FnDef {
+    name: "takeMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_take_mvar
+};
+
+static IO_PUT_MVAR: FnDef = FnDef {
+    name: "putMVar",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_put_mvar
+};
+
+static IO_READ_MVAR: FnDef = FnDef {
+    name: "readMVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_read_mvar
+};
+
+pub fn eval_fork_io(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_FORK_IO);
+    Ok(())
+}
+
+pub fn eval_my_thread_id(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MY_THREAD_ID);
+    Ok(())
+}
+
+pub fn eval_yield(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_YIELD);
+    Ok(())
+}
+
+pub fn eval_new_mvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_NEW_MVAR);
+    Ok(())
+}
+
+pub fn eval_new_empty_mvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_NEW_EMPTY_MVAR);
+    Ok(())
+}
+
+pub fn eval_take_mvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_TAKE_MVAR);
+    Ok(())
+}
+
+pub fn eval_put_mvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_PUT_MVAR);
+    Ok(())
+}
+
+pub fn eval_read_mvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_READ_MVAR);
+    Ok(())
+}
+
+/// The new thread's `ThreadId`, an `Int`.
+fn perform_fork_io(state: &mut State) -> EvalResult {
+    let id = state.fork_thread();
+    state.push_int(id);
+    Ok(())
+}
+
+fn perform_my_thread_id(state: &mut State) -> EvalResult {
+    let id = state.scheduler.current;
+    state.push_int(id);
+    Ok(())
+}
+
+/// Ends the running thread's time slice.
+fn perform_yield(state: &mut State) -> EvalResult {
+    state.scheduler.steps = TIME_SLICE;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_new_mvar(state: &mut State) -> EvalResult {
+    let value = state.stack_pop();
+    state.push_mvar(Some(value));
+    Ok(())
+}
+
+fn perform_new_empty_mvar(state: &mut State) -> EvalResult {
+    state.push_mvar(None);
+    Ok(())
+}
+
+fn perform_take_mvar(state: &mut State) -> EvalResult {
+    let mut mvar = state.pop_mvar("takeMVar")?;
+    match mvar.as_mut().value.take() {
+        Some(value) => state.stack_push(value),
+        None => state.scheduler.blocked = Some(Blocked::Full(mvar))
+    }
+    Ok(())
+}
+
+fn perform_put_mvar(state: &mut State) -> EvalResult {
+    let mut mvar = state.pop_mvar("putMVar")?;
+    if mvar.value.is_some() {
+        state.scheduler.blocked = Some(Blocked::Empty(mvar));
+        return Ok(());
+    }
+    mvar.as_mut().value = Some(state.stack_pop());
+    state.push_unit();
+    Ok(())
+}
+
+/// Waits for the `MVar` to be full like `takeMVar`, but leaves the value in it.
+fn perform_read_mvar(state: &mut State) -> EvalResult {
+    let mvar = state.pop_mvar("readMVar")?;
+    match mvar.value {
+        Some(value) => state.stack_push(value),
+        None => state.scheduler.blocked = Some(Blocked::Full(mvar))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::*;
+    use super::*;
+
+    static FN_FORK_AND_TAKE: FnDef = FnDef {
+        name: "forkAndTake",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_fork_and_take
+    };
+
+    /// `\m -> forkIO (putMVar m 42) >> takeMVar m`
+    fn eval_fork_and_take(state: &mut State) -> EvalResult {
+        let mvar = *state.stack_peek();
+        state.push_fn(FN_TAKE_MVAR);
+        state.mk_ap();
+        state.push_int(42);
+        state.push(mvar);
+        state.push_fn(FN_PUT_MVAR);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_FORK_IO);
+        state.mk_ap();
+        state.push_fn(FN_THEN_IO);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    #[test]
+    fn main_waits_for_a_forked_thread() {
+        let mut state = State::new();
+        // newEmptyMVar >>= forkAndTake
+        state.push_fn(FN_FORK_AND_TAKE);
+        state.push_fn(FN_NEW_EMPTY_MVAR);
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let result = state.run_io(main).and_then(|result| state.try_eval(result));
+        assert!(matches!(result, Ok(Node::Int(42))));
+    }
+
+    #[test]
+    fn taking_an_mvar_nobody_fills_is_deadlock() {
+        let mut state = State::new();
+        // newEmptyMVar >>= takeMVar
+        state.push_fn(FN_TAKE_MVAR);
+        state.push_fn(FN_NEW_EMPTY_MVAR);
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let result = state.run_io(main);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::BlockedIndefinitely)));
+        assert_eq!(state.stack_size(), 0);
+    }
+}
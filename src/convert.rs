@@ -0,0 +1,255 @@
+use core::convert::TryFrom;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::maybe::{TAG_NOTHING, TAG_JUST};
+use crate::ratio::Rational;
+
+// Conversions of Haskell values to Rust ones, for the host to read results
+// with. The nodes have to be in normal form already, as converting them
+// can't evaluate them; `State::extract` forces a node fully first.
+//
+// Nodes aren't `PartialEq`, as comparing them takes evaluating them, so
+// to compare results, e.g. in tests, a value is extracted as an `EvalEq`,
+// a copy out of the heap that compares as `deepEq` does:
+//
+//     assert_eq!(state.extract::<EvalEq>(result)?, EvalEq::List(vec![EvalEq::Int(1)]));
+
+impl TryFrom<Node> for i64 {
+    type Error = EvalError;
+
+    /// An `Int`, or an `Integer` small enough to be one.
+    fn try_from(node: Node) -> EvalResult<i64> {
+        match node.short_circuit() {
+            Node::Int(int) => Ok(int),
+            Node::Integer(integer) => integer.to_i64().ok_or_else(|| ArithException::Overflow.into()),
+            node => Err(EvalError::type_mismatch("i64", "Int", &node))
+        }
+    }
+}
+
+impl TryFrom<Node> for f64 {
+    type Error = EvalError;
+
+    fn try_from(node: Node) -> EvalResult<f64> {
+        match node.short_circuit() {
+            Node::Double(double) => Ok(double),
+            node => Err(EvalError::type_mismatch("f64", "Double", &node))
+        }
+    }
+}
+
+impl TryFrom<Node> for bool {
+    type Error = EvalError;
+
+    fn try_from(node: Node) -> EvalResult<bool> {
+        match node.short_circuit() {
+            Node::Bool(b) => Ok(b),
+            node => Err(EvalError::type_mismatch("bool", "Bool", &node))
+        }
+    }
+}
+
+impl TryFrom<Node> for String {
+    type Error = EvalError;
+
+    /// A `[Char]` list or a `Text`.
+    fn try_from(node: Node) -> EvalResult<String> {
+        if let Node::Text(text) = node.short_circuit() {
+            return Ok(text.to_string());
+        }
+        let mut s = String::new();
+        let mut rest = node.short_circuit();
+        while let Node::Cons(head, tail) = rest {
+            match head.short_circuit() {
+                Node::Char(c) => s.push(c),
+                head => return Err(EvalError::type_mismatch("String", "Char", &head))
+            }
+            rest = tail.short_circuit();
+        }
+        match rest {
+            Node::Nil => Ok(s),
+            rest => Err(EvalError::type_mismatch("String", "String", &rest))
+        }
+    }
+}
+
+impl<T: TryFrom<Node, Error = EvalError>> TryFrom<Node> for Vec<T> {
+    type Error = EvalError;
+
+    fn try_from(node: Node) -> EvalResult<Vec<T>> {
+        let mut elems = Vec::new();
+        let mut rest = node.short_circuit();
+        while let Node::Cons(head, tail) = rest {
+            elems.push(T::try_from(*head)?);
+            rest = tail.short_circuit();
+        }
+        match rest {
+            Node::Nil => Ok(elems),
+            rest => Err(EvalError::type_mismatch("Vec", "list", &rest))
+        }
+    }
+}
+
+impl<T: TryFrom<Node, Error = EvalError>> TryFrom<Node> for Option<T> {
+    type Error = EvalError;
+
+    /// A `Maybe`.
+    fn try_from(node: Node) -> EvalResult<Option<T>> {
+        match node.short_circuit() {
+            Node::Data { tag: TAG_NOTHING, fields } if fields.is_empty() => Ok(None),
+            Node::Data { tag: TAG_JUST, fields } if fields.len() == 1 => T::try_from(fields[0]).map(Some),
+            node => Err(EvalError::type_mismatch("Option", "Nothing or Just", &node))
+        }
+    }
+}
+
+/// A value in normal form, copied so that it stays as it is after the
+/// heap it came from is collected. Derived equality agrees with `deepEq`,
+/// down to `Double`s comparing as `f64`s do: `NaN` is unequal to itself,
+/// and `0.0` equal to `-0.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalEq {
+    Int(i64),
+    Integer(BigInt),
+    Ratio(Rational),
+    Double(f64),
+    Bool(bool),
+    Char(char),
+    Text(String),
+    List(Vec<EvalEq>),
+    Data(u32, Vec<EvalEq>),
+    Tuple(Vec<EvalEq>),
+    /// The bounds and the elements.
+    Array(i64, i64, Vec<EvalEq>),
+    /// The entries, in key order.
+    Map(Vec<(EvalEq, EvalEq)>),
+    Set(Vec<EvalEq>)
+}
+
+impl TryFrom<Node> for EvalEq {
+    type Error = EvalError;
+
+    /// Functions, IO actions and mutable cells aren't values to compare.
+    fn try_from(node: Node) -> EvalResult<EvalEq> {
+        let all = |nodes: &[Node]| nodes.iter().map(|node| EvalEq::try_from(*node)).collect::<EvalResult<Vec<_>>>();
+        match node.short_circuit() {
+            Node::Int(int) => Ok(EvalEq::Int(int)),
+            Node::Integer(integer) => Ok(EvalEq::Integer((*integer).clone())),
+            Node::Ratio(ratio) => Ok(EvalEq::Ratio((*ratio).clone())),
+            Node::Double(double) => Ok(EvalEq::Double(double)),
+            Node::Bool(b) => Ok(EvalEq::Bool(b)),
+            Node::Char(c) => Ok(EvalEq::Char(c)),
+            Node::Text(text) => Ok(EvalEq::Text(text.to_string())),
+            Node::Nil | Node::Cons(_, _) => {
+                let mut elems = Vec::new();
+                let mut rest = node.short_circuit();
+                while let Node::Cons(head, tail) = rest {
+                    elems.push(EvalEq::try_from(*head)?);
+                    rest = tail.short_circuit();
+                }
+                match rest {
+                    Node::Nil => Ok(EvalEq::List(elems)),
+                    rest => Err(EvalError::type_mismatch("EvalEq", "list", &rest))
+                }
+            },
+            Node::Data { tag, fields } => Ok(EvalEq::Data(tag, all(&fields)?)),
+            Node::Tuple(fields) => Ok(EvalEq::Tuple(all(&fields)?)),
+            Node::Array(array) => Ok(EvalEq::Array(array.lo, array.hi, all(&array.elems)?)),
+            Node::Map(tree) => {
+                let entries = tree.entries().into_iter()
+                    .map(|(key, value)| Ok((EvalEq::try_from(key)?, EvalEq::try_from(value)?)))
+                    .collect::<EvalResult<Vec<_>>>()?;
+                Ok(EvalEq::Map(entries))
+            },
+            Node::Set(tree) => {
+                let keys: Fields = tree.entries().into_iter().map(|(key, _)| key).collect();
+                Ok(EvalEq::Set(all(&keys)?))
+            },
+            node => Err(EvalError::type_mismatch("EvalEq", "value", &node))
+        }
+    }
+}
+
+impl State {
+    /// Evaluates `node` to normal form and converts it, e.g.
+    /// `state.extract::<Vec<i64>>(result)`.
+    pub fn extract<T: TryFrom<Node, Error = EvalError>>(&mut self, node: Node) -> EvalResult<T> {
+        let frame_size = self.stack_size();
+        self.stack_push(node);
+        if let Err(err) = self.deep_eval() {
+            self.stacks.last_mut().unwrap().truncate(frame_size);
+            return Err(err);
+        }
+        T::try_from(self.stack_pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::FN_MAP_FROM_LIST;
+    use crate::read::read;
+    use super::*;
+
+    fn extract(state: &mut State) -> EvalEq {
+        let node = state.stack_pop();
+        state.extract(node).expect("a value")
+    }
+
+    #[test]
+    fn lists_are_forced_and_compared_by_element() {
+        let mut state = State::new();
+        // [1 + 1, 3]
+        state.push(Node::Nil);
+        state.push_int(3);
+        state.mk_cons();
+        state.push_int(1);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.mk_cons();
+        let list = extract(&mut state);
+        assert_eq!(list, EvalEq::List(vec![EvalEq::Int(2), EvalEq::Int(3)]));
+        assert_ne!(list, EvalEq::List(vec![EvalEq::Int(2)]));
+    }
+
+    #[test]
+    fn data_compares_tag_and_fields() {
+        let mut state = State::new();
+        state.push_int(7);
+        state.mk_data(TAG_JUST, 1);
+        let just = extract(&mut state);
+        assert_eq!(just, EvalEq::Data(TAG_JUST, vec![EvalEq::Int(7)]));
+        assert_ne!(just, EvalEq::Data(TAG_NOTHING, vec![]));
+    }
+
+    #[test]
+    fn maps_compare_entries_in_key_order() {
+        let mut state = State::new();
+        state.push(read("[(2, 'b'), (1, 'a')]").expect("a list"));
+        state.push_fn(FN_MAP_FROM_LIST);
+        state.mk_ap();
+        assert_eq!(extract(&mut state), EvalEq::Map(vec![
+            (EvalEq::Int(1), EvalEq::Char('a')),
+            (EvalEq::Int(2), EvalEq::Char('b'))
+        ]));
+    }
+
+    #[test]
+    fn doubles_compare_as_deep_eq_does() {
+        let mut state = State::new();
+        for (l, r) in [(f64::NAN, f64::NAN), (0.0, -0.0), (1.5, 1.5), (1.5, 2.5)] {
+            state.push(Node::Double(r));
+            state.push(Node::Double(l));
+            let deep_equal = state.deep_eq().expect("comparable");
+            state.push(Node::Double(l));
+            let el = extract(&mut state);
+            state.push(Node::Double(r));
+            let er = extract(&mut state);
+            assert_eq!(el == er, deep_equal, "{} == {}", l, r);
+        }
+        assert_ne!(EvalEq::Double(f64::NAN), EvalEq::Double(f64::NAN));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::collections::HashSet;
+use core::fmt::Write;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::format;
+use crate::builtins::*;
+use crate::gc::*;
+
+// Graphviz output of the graph reachable from a node. Every heap object is
+// drawn once however many references it has, so sharing and cycles show up
+// as they are in the heap. Thunks are drawn as their own boxes, indirections
+// as dashed edges.
+
+impl Node {
+    /// Renders the graph reachable from the node in DOT format.
+    pub fn to_dot(self) -> String {
+        let mut dot = DotWriter { out: String::new(), seen: HashSet::new(), work: Vec::new() };
+        dot.out.push_str("digraph heap {\n    node [fontname=\"monospace\"];\n");
+        let root = dot.target("root".to_string(), self);
+        dot.work.push(root);
+        while let Some((id, target)) = dot.work.pop() {
+            dot.visit(id, target);
+        }
+        dot.out.push_str("}\n");
+        dot.out
+    }
+}
+
+/// Something to draw: a node where it lives, or a thunk.
+#[derive(Clone, Copy)]
+enum Target {
+    Node(Node),
+    Thunk(Gc<Thunk>)
+}
+
+struct DotWriter {
+    out: String,
+    seen: HashSet<String>,
+    work: Vec<(String, Target)>
+}
+
+impl DotWriter {
+    /// Resolves the node stored at the location `id` to what it is drawn as:
+    /// thunks are shared objects of their own rather than part of where they
+    /// are referred from.
+    fn target(&self, id: String, node: Node) -> (String, Target) {
+        match node {
+            Node::ThunkRef(thunk) => (format!("t{:x}", thunk.addr()), Target::Thunk(thunk)),
+            node => (id, Target::Node(node))
+        }
+    }
+
+    fn edge(&mut self, from: &str, label: &str, id: String, node: Node) {
+        let (to, target) = self.target(id, node);
+        let _ = writeln!(self.out, "    {} -> {} [label=\"{}\"];", from, to, label);
+        self.work.push((to, target));
+    }
+
+    fn gc_edge(&mut self, from: &str, label: &str, node: Gc<Node>) {
+        self.edge(from, label, format!("n{:x}", node.addr()), *node);
+    }
+
+    fn fields_edges(&mut self, from: &str, fields: Gc<Fields>) {
+        for (i, field) in fields.iter().enumerate() {
+            self.edge(from, &i.to_string(), format!("n{:x}_{}", fields.addr(), i), *field);
+        }
+    }
+
+    fn label(&mut self, id: &str, shape: &str, label: &str) {
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let _ = writeln!(self.out, "    {} [shape={}, label=\"{}\"];", id, shape, label);
+    }
+
+    fn visit(&mut self, id: String, target: Target) {
+        if !self.seen.insert(id.clone()) {
+            return;
+        }
+
+        let node = match target {
+            Target::Node(node) => node,
+            Target::Thunk(thunk) => {
+                let label = thunk.label.unwrap_or("thunk");
+                match thunk.state {
+                    ThunkState::Pending(expr) => {
+                        self.label(&id, "box", &format!("{}\nunevaluated", label));
+                        self.edge(&id, "expr", format!("{}_v", id), expr);
+                    },
+                    ThunkState::UThunk(_) => self.label(&id, "box", &format!("{}\nunevaluated code", label)),
+                    ThunkState::UCall(_, ref args) => {
+                        self.label(&id, "box", &format!("{}\nunevaluated call", label));
+                        for (i, arg) in args.iter().enumerate() {
+                            self.edge(&id, &i.to_string(), format!("{}_{}", id, i), *arg);
+                        }
+                    },
+                    ThunkState::Blackhole => self.label(&id, "box", &format!("{}\nblackhole", label)),
+                    ThunkState::EThunk(value) => {
+                        self.label(&id, "box", &format!("{}\nevaluated", label));
+                        self.edge(&id, "value", format!("{}_v", id), value);
+                    }
+                }
+                return;
+            }
+        };
+
+        match node {
+            Node::App(fun, arg) => {
+                self.label(&id, "circle", "@");
+                self.gc_edge(&id, "fun", fun);
+                self.gc_edge(&id, "arg", arg);
+            },
+            Node::Cons(head, tail) => {
+                self.label(&id, "circle", ":");
+                self.gc_edge(&id, "head", head);
+                self.gc_edge(&id, "tail", tail);
+            },
+            Node::Data { tag, fields } => {
+                self.label(&id, "ellipse", &format!("Pack{{{},{}}}", tag, fields.len()));
+                self.fields_edges(&id, fields);
+            },
+            Node::Tuple(fields) => {
+                self.label(&id, "ellipse", &format!("({})", ",".repeat(fields.len().saturating_sub(1))));
+                self.fields_edges(&id, fields);
+            },
+            Node::Pap(fn_def, args) => {
+                self.label(&id, "ellipse", &format!("{} {}/{}", fn_def.name, args.len(), fn_def.arity));
+                self.fields_edges(&id, args);
+            },
+            Node::FnDef(fn_def) => self.label(&id, "ellipse", fn_def.name),
+            Node::NodeRef(target) => {
+                self.label(&id, "point", "");
+                let to = format!("n{:x}", target.addr());
+                let (to, target) = self.target(to, *target);
+                let _ = writeln!(self.out, "    {} -> {} [style=dashed];", id, to);
+                self.work.push((to, target));
+            },
+            Node::IO(action) => match *action {
+                IoAction::Return(value) => {
+                    self.label(&id, "hexagon", "return");
+                    self.edge(&id, "value", format!("n{:x}_v", action.addr()), value);
+                },
+                IoAction::Bind(m, k) => {
+                    self.label(&id, "hexagon", ">>=");
+                    self.edge(&id, "action", format!("n{:x}_m", action.addr()), m);
+                    self.edge(&id, "cont", format!("n{:x}_k", action.addr()), k);
+                },
+                IoAction::Prim(fn_def, args) => {
+                    self.label(&id, "hexagon", fn_def.name);
+                    self.fields_edges(&id, args);
+                },
+                IoAction::Catch(m, handler) => {
+                    self.label(&id, "hexagon", "catch");
+                    self.edge(&id, "action", format!("n{:x}_m", action.addr()), m);
+                    self.edge(&id, "handler", format!("n{:x}_h", action.addr()), handler);
+                }
+            },
+            Node::IORef(cell) => {
+                self.label(&id, "house", "IORef");
+                self.gc_edge(&id, "contents", cell);
+            },
+            Node::IOArray(array) | Node::Array(array) => {
+                let kind = if matches!(node, Node::IOArray(_)) { "IOArray" } else { "array" };
+                self.label(&id, "house", &format!("{} ({},{})", kind, array.lo, array.hi));
+                for (i, elem) in array.elems.iter().enumerate() {
+                    self.edge(&id, &i.to_string(), format!("n{:x}_{}", array.addr(), i), *elem);
+                }
+            },
+            Node::ThunkRef(_) => unreachable!("Thunks are drawn as their own targets"),
+            value => self.label(&id, "plaintext", &value.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_thunks_are_drawn_once() {
+        let mut state = State::new();
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.label_thunk("shared");
+        state.push(*state.stack_peek());
+        state.mk_tuple(2);
+        let dot = state.stack_peek().to_dot();
+        assert!(dot.starts_with("digraph heap {\n"));
+        assert_eq!(dot.matches("shared\\nunevaluated").count(), 1);
+        assert_eq!(dot.matches("root -> t").count(), 2);
+        assert!(dot.contains("[shape=ellipse, label=\"(,)\"]"));
+    }
+
+    #[test]
+    fn cycles_are_followed_once() {
+        let mut state = State::new();
+        state.mk_recursive(|state, ones| {
+            state.push(ones);
+            state.push_int(1);
+            state.mk_cons();
+        });
+        state.label_thunk("ones");
+        let node = *state.stack_peek();
+        let Node::ThunkRef(thunk) = node else {
+            panic!("a thunk");
+        };
+        let dot = node.to_dot();
+        let id = format!("t{:x}", thunk.addr());
+        assert_eq!(dot.matches(&format!("    {} [shape=box", id)).count(), 1);
+        assert!(dot.contains(&format!(" -> {} [label=\"tail\"]", id)));
+        assert!(dot.contains("[shape=plaintext, label=\"1\"]"));
+    }
+}
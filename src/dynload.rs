@@ -0,0 +1,107 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io;
+use crate::builtins::*;
+use crate::ffi::HL_OK;
+
+// With the `dynload` feature the runtime can load a compiled module, a
+// shared object linking against the C ABI in `src/ffi.rs`, instead of
+// running the `prog.rs` it was built with: `runtime module.so args...`
+// runs the module's `main`, so after an edit only the module has to be
+// compiled again. A module exports
+//
+//     int32_t hl_module_init(hl_state *state);
+//
+// which registers its supercombinators with `hl_register_global`,
+// replacing any of the same names loaded before, and returns `HL_OK`.
+// The definitions registered point into the module, so it is never
+// unloaded.
+
+/// The symbol a module is initialized by.
+const MODULE_INIT: &[u8] = b"hl_module_init\0";
+
+const RTLD_NOW: c_int = 2;
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+type ModuleInit = unsafe extern "C" fn(state: *mut State) -> i32;
+
+/// Why the last `dlopen` or `dlsym` failed.
+fn dl_failure(path: &str) -> EvalError {
+    let message = unsafe {
+        let err = dlerror();
+        if err.is_null() {
+            "unknown error".to_string()
+        } else {
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        }
+    };
+    IoException::new(path, "loadModule", &io::Error::other(message)).into()
+}
+
+impl State {
+    /// Loads the compiled module at `path`, registering its definitions as
+    /// globals. CAFs evaluated so far are released, as the module may have
+    /// redefined them.
+    pub fn load_module(&mut self, path: &str) -> EvalResult {
+        let invalid = || IoException::new(path, "loadModule", &io::ErrorKind::InvalidInput.into());
+        let c_path = CString::new(path).map_err(|_| invalid())?;
+        let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            return Err(dl_failure(path));
+        }
+        let init = unsafe { dlsym(handle, MODULE_INIT.as_ptr() as *const c_char) };
+        if init.is_null() {
+            return Err(dl_failure(path));
+        }
+
+        let init: ModuleInit = unsafe { std::mem::transmute::<*mut c_void, ModuleInit>(init) };
+        self.ffi.error = None;
+        if unsafe { init(self) } != HL_OK {
+            let err = self.ffi.error.take();
+            return Err(err.unwrap_or_else(|| EvalError::Error(format!("{}: hl_module_init failed", path))));
+        }
+        self.globals.release_cafs();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+
+    fn load_failure(path: &str) -> IoException {
+        let mut state = State::new();
+        match state.load_module(path) {
+            Err(EvalError::Io(exception)) => exception,
+            Err(err) => panic!("an IO exception, not {}", err),
+            Ok(()) => panic!("{} loaded", path)
+        }
+    }
+
+    #[test]
+    fn missing_modules_fail_to_load() {
+        let path = temp_path("missing.so");
+        let exception = load_failure(&path);
+        assert_eq!(exception.location, path);
+        assert_eq!(exception.operation, "loadModule");
+        assert!(exception.description.contains("missing.so"), "{}", exception.description);
+    }
+
+    #[test]
+    fn paths_with_nuls_are_invalid() {
+        let exception = load_failure("module\0.so");
+        assert_eq!(exception.kind, io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn libraries_without_an_init_are_not_modules() {
+        let exception = load_failure("libc.so.6");
+        assert!(exception.description.contains("hl_module_init"), "{}", exception.description);
+    }
+}
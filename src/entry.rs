@@ -0,0 +1,186 @@
+use crate::builtins::*;
+use crate::handle::{print_line, eprint_line};
+
+// What every program's `fn main()` does, generated by `haskelite_main!`:
+// evaluate the program's `main`, perform it if it is an IO action and
+// print it otherwise, report an uncaught exception and exit with the code
+// it calls for. With `HASKELITE_STATS` set the runtime's counters are
+// printed to stderr once the program is done, e.g. to compare two
+// versions of it.
+
+/// Set to print `RuntimeStats` when the program ends.
+pub const STATS_VAR: &str = "HASKELITE_STATS";
+
+/// Runs the program whose `main` `setup` gives, having configured the
+/// state as it likes, exiting with the program's code if it fails.
+pub fn run<F: FnOnce(&mut State) -> EvalResult<Node>>(setup: F) {
+    let mut state = State::new();
+    let code = run_main(&mut state, setup);
+    if std::env::var_os(STATS_VAR).is_some() {
+        eprint_line(&state.stats().to_string());
+    }
+    drop(state);
+    if code != 0 {
+        exit(code);
+    }
+}
+
+/// Evaluates and performs the program's `main`, giving the exit code.
+pub fn run_main<F: FnOnce(&mut State) -> EvalResult<Node>>(state: &mut State, setup: F) -> i32 {
+    // a program whose `main` is an IO action is run for its effects
+    let result = setup(state).and_then(|main| state.try_eval(main)).and_then(|result| match result {
+        Node::IO(_) => state.run_io(result).map(|_| None),
+        value => state.render(value).map(Some)
+    });
+    let code = match result {
+        Ok(Some(shown)) => {
+            print_line(&shown);
+            0
+        },
+        Ok(None) => 0,
+        Err(err) => {
+            let err = state.uncaught(err);
+            err.exit_code().unwrap_or_else(|| {
+                eprint_line(&format!("runtime: {}", err));
+                1
+            })
+        }
+    };
+    // `exit` skips flushing what the program wrote so far
+    #[cfg(not(feature = "wasm"))]
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    code
+}
+
+/// Loads the program at `path`, bytecode from a `.hbc` file and a compiled
+/// module otherwise, if the runtime was built to load both.
+pub fn load(state: &mut State, path: &str) -> EvalResult {
+    if cfg!(feature = "bytecode") && (path.ends_with(".hbc") || cfg!(not(feature = "dynload"))) {
+        load_bytecode(state, path)
+    } else {
+        load_module(state, path)
+    }
+}
+
+#[cfg(feature = "bytecode")]
+fn load_bytecode(state: &mut State, path: &str) -> EvalResult {
+    state.load_bytecode(path)
+}
+
+#[cfg(not(feature = "bytecode"))]
+fn load_bytecode(_state: &mut State, _path: &str) -> EvalResult {
+    Err(EvalError::Error("can't load bytecode, the runtime was built without the `bytecode` feature".to_string()))
+}
+
+#[cfg(feature = "dynload")]
+fn load_module(state: &mut State, path: &str) -> EvalResult {
+    state.load_module(path)
+}
+
+#[cfg(not(feature = "dynload"))]
+fn load_module(_state: &mut State, _path: &str) -> EvalResult {
+    Err(EvalError::Error("can't load a compiled module, the runtime was built without the `dynload` feature".to_string()))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn exit(code: i32) -> ! {
+    std::process::exit(code)
+}
+
+#[cfg(feature = "wasm")]
+fn exit(code: i32) -> ! {
+    crate::wasm::exit(code)
+}
+
+/// Generates `fn main()` for a program, e.g. `haskelite_main!(FN_PROG);`
+/// for the compiled supercombinator `main`. A setup function configuring
+/// the state first can be given too, `haskelite_main!(FN_PROG, setup)`, or
+/// one giving `main` itself, `haskelite_main!(with setup)`.
+#[macro_export]
+macro_rules! haskelite_main {
+    (with $setup:expr) => {
+        fn main() {
+            $crate::entry::run($setup)
+        }
+    };
+    ($main:expr) => {
+        fn main() {
+            $crate::entry::run(|_state| Ok($crate::builtins::Node::FnDef($main)))
+        }
+    };
+    ($main:expr, $setup:expr) => {
+        fn main() {
+            $crate::entry::run(|state| {
+                ($setup)(state)?;
+                Ok($crate::builtins::Node::FnDef($main))
+            })
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::FN_RETURN_IO;
+    use crate::env::{FN_EXIT_WITH, TAG_EXIT_FAILURE};
+
+    #[test]
+    fn io_programs_are_run_for_their_effects() {
+        let mut state = State::new();
+        let code = run_main(&mut state, |state| {
+            state.push_int(1);
+            state.push_fn(FN_RETURN_IO);
+            state.mk_ap();
+            Ok(state.stack_pop())
+        });
+        assert_eq!(code, 0);
+    }
+
+    #[test]
+    fn programs_exit_with_the_code_they_call_for() {
+        let mut state = State::new();
+        let code = run_main(&mut state, |state| {
+            state.push_int(3);
+            state.mk_data(TAG_EXIT_FAILURE, 1);
+            state.push_fn(FN_EXIT_WITH);
+            state.mk_ap();
+            Ok(state.stack_pop())
+        });
+        assert_eq!(code, 3);
+    }
+
+    #[test]
+    fn failures_exit_with_1() {
+        let mut state = State::new();
+        let code = run_main(&mut state, |_| Err(EvalError::Error("no main".to_string())));
+        assert_eq!(code, 1);
+    }
+
+    #[cfg(not(any(feature = "bytecode", feature = "dynload")))]
+    #[test]
+    fn nothing_loads_without_a_loader() {
+        let mut state = State::new();
+        for path in ["prog.hbc", "prog.so"] {
+            let err = load(&mut state, path).expect_err("no loader");
+            assert!(err.to_string().contains("built without"), "{}", err);
+        }
+    }
+
+    #[cfg(all(feature = "bytecode", not(feature = "dynload")))]
+    #[test]
+    fn everything_loads_as_bytecode_without_dynload() {
+        let mut state = State::new();
+        let err = load(&mut state, "missing.so").expect_err("no such file");
+        assert!(matches!(err.cause(), EvalError::Io(_)), "{}", err);
+    }
+
+    #[cfg(all(feature = "bytecode", feature = "dynload"))]
+    #[test]
+    fn modules_load_by_their_extension() {
+        let mut state = State::new();
+        let err = load(&mut state, "missing.so").expect_err("no such module");
+        assert!(matches!(err.cause(), EvalError::Io(exception) if exception.operation == "loadModule"), "{}", err);
+        let err = load(&mut state, "missing.hbc").expect_err("no such file");
+        assert!(matches!(err.cause(), EvalError::Io(exception) if exception.operation != "loadModule"), "{}", err);
+    }
+}
@@ -0,0 +1,347 @@
+use std::io;
+use std::path::Path;
+use crate::builtins::*;
+
+impl State {
+    /// Sets the command line the program sees, `argv[0]` first,
+    /// instead of the one the process was started with.
+    pub fn set_args(&mut self, argv: Vec<String>) {
+        self.argv = Some(argv);
+    }
+
+    fn argv(&mut self) -> &[String] {
+        self.argv.get_or_insert_with(|| std::env::args().collect())
+    }
+
+    /// Pushes `strs` as a list of strings, each unpacked only once it is forced.
+    fn push_str_list(&mut self, strs: &[String]) {
+        self.push_nil();
+        for s in strs.iter().rev() {
+            self.push_text(s.clone());
+            self.push_fn(FN_UNPACK);
+            self.mk_ap();
+            self.mk_thunk();
+            self.mk_cons();
+        }
+    }
+}
+
+pub static FN_GET_ARGS: FnDef = FnDef {
+    name: "getArgs",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_args
+};
+
+pub static FN_GET_PROG_NAME: FnDef = FnDef {
+    name: "getProgName",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_prog_name
+};
+
+static IO_GET_ARGS: FnDef = FnDef {
+    name: "getArgs",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_get_args
+};
+
+static IO_GET_PROG_NAME: FnDef = FnDef {
+    name: "getProgName",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_get_prog_name
+};
+
+pub fn eval_get_args(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_ARGS);
+    Ok(())
+}
+
+pub fn eval_get_prog_name(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_PROG_NAME);
+    Ok(())
+}
+
+/// The arguments after the program name.
+fn perform_get_args(state: &mut State) -> EvalResult {
+    let args = state.argv().get(1..).unwrap_or(&[]).to_vec();
+    state.push_str_list(&args);
+    Ok(())
+}
+
+/// The file name the program was invoked as, without its directory.
+fn perform_get_prog_name(state: &mut State) -> EvalResult {
+    let argv0 = state.argv().first().cloned().unwrap_or_default();
+    let name = Path::new(&argv0).file_name()
+        .map_or(argv0.clone(), |name| name.to_string_lossy().into_owned());
+    state.push_str(&name);
+    Ok(())
+}
+
+pub static FN_GET_ENV: FnDef = FnDef {
+    name: "getEnv",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_get_env
+};
+
+pub static FN_LOOKUP_ENV: FnDef = FnDef {
+    name: "lookupEnv",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_lookup_env
+};
+
+pub static FN_SET_ENV: FnDef = FnDef {
+    name: "setEnv",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_set_env
+};
+
+static IO_GET_ENV: FnDef = FnDef {
+    name: "getEnv",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_get_env
+};
+
+static IO_LOOKUP_ENV: FnDef = FnDef {
+    name: "lookupEnv",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_lookup_env
+};
+
+static IO_SET_ENV: FnDef = FnDef {
+    name: "setEnv",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_set_env
+};
+
+pub fn eval_get_env(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_GET_ENV);
+    Ok(())
+}
+
+pub fn eval_lookup_env(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_LOOKUP_ENV);
+    Ok(())
+}
+
+pub fn eval_set_env(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_SET_ENV);
+    Ok(())
+}
+
+/// The value of a variable, `None` if it is unset. A value that is not
+/// valid Unicode is converted lossily rather than reported as missing.
+fn var(name: &str) -> Option<String> {
+    std::env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+}
+
+/// Fails with `isDoesNotExistError` if the variable is unset.
+fn perform_get_env(state: &mut State) -> EvalResult {
+    let name = state.eval_string()?;
+    match var(&name) {
+        Some(value) => state.push_str(&value),
+        None => return Err(IoException {
+            location: name,
+            operation: "getEnv".into(),
+            kind: io::ErrorKind::NotFound,
+            description: "does not exist (no environment variable)".to_string()
+        }.into())
+    }
+    Ok(())
+}
+
+fn perform_lookup_env(state: &mut State) -> EvalResult {
+    let name = state.eval_string()?;
+    match var(&name) {
+        Some(value) => {
+            state.push_str(&value);
+            state.mk_just();
+        },
+        None => state.push_nothing()
+    }
+    Ok(())
+}
+
+/// Setting a variable to the empty string unsets it, as in
+/// `System.Environment`. Names that cannot be set fail with an
+/// `IOException` instead of panicking.
+fn perform_set_env(state: &mut State) -> EvalResult {
+    let name = state.eval_string()?;
+    let value = state.eval_string()?;
+    if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+        return Err(IoException {
+            location: name,
+            operation: "setEnv".into(),
+            kind: io::ErrorKind::InvalidInput,
+            description: "invalid argument".to_string()
+        }.into());
+    }
+    set_var(name, &value)?;
+    state.push_unit();
+    Ok(())
+}
+
+/// Sets the variable, or unsets it if `value` is empty.
+#[cfg(not(feature = "wasm"))]
+fn set_var(name: String, value: &str) -> EvalResult {
+    if value.is_empty() {
+        std::env::remove_var(&name);
+    } else {
+        std::env::set_var(&name, value);
+    }
+    Ok(())
+}
+
+/// A page has no environment to change, the standard library panicking
+/// if asked to.
+#[cfg(feature = "wasm")]
+fn set_var(name: String, _value: &str) -> EvalResult {
+    Err(IoException {
+        location: name,
+        operation: "setEnv".into(),
+        kind: io::ErrorKind::Unsupported,
+        description: "unsupported operation".to_string()
+    }.into())
+}
+
+// `data ExitCode = ExitSuccess | ExitFailure Int`
+pub const TAG_EXIT_SUCCESS: u32 = 0;
+pub const TAG_EXIT_FAILURE: u32 = 1;
+
+pub static FN_EXIT_WITH: FnDef = FnDef {
+    name: "exitWith",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_exit_with
+};
+
+pub static FN_EXIT_SUCCESS: FnDef = FnDef {
+    name: "exitSuccess",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_exit_success
+};
+
+pub static FN_EXIT_FAILURE: FnDef = FnDef {
+    name: "exitFailure",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_exit_failure
+};
+
+static IO_EXIT_WITH: FnDef = FnDef {
+    name: "exitWith",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_exit_with
+};
+
+pub fn eval_exit_with(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_EXIT_WITH);
+    Ok(())
+}
+
+/// `exitWith ExitSuccess`
+pub fn eval_exit_success(state: &mut State) -> EvalResult {
+    state.mk_data(TAG_EXIT_SUCCESS, 0);
+    eval_exit_with(state)
+}
+
+/// `exitWith (ExitFailure 1)`
+pub fn eval_exit_failure(state: &mut State) -> EvalResult {
+    state.push_int(1);
+    state.mk_data(TAG_EXIT_FAILURE, 1);
+    eval_exit_with(state)
+}
+
+/// Raises `EvalError::Exit`, which `run_io` passes on like any other error
+/// for the host to exit with. `ExitFailure 0` is rejected, as in GHC.
+fn perform_exit_with(state: &mut State) -> EvalResult {
+    state.eval()?;
+    let code = match state.stack_pop() {
+        Node::Data { tag: TAG_EXIT_SUCCESS, .. } => 0,
+        Node::Data { tag: TAG_EXIT_FAILURE, fields } if fields.len() == 1 => {
+            state.stack_push(fields[0]);
+            state.eval()?;
+            match state.stack_pop() {
+                Node::Int(0) => return Err(IoException {
+                    location: "ExitFailure 0".to_string(),
+                    operation: "exitWith".into(),
+                    kind: io::ErrorKind::InvalidInput,
+                    description: "invalid argument".to_string()
+                }.into()),
+                Node::Int(code) => code as i32,
+                node => return Err(EvalError::type_mismatch("exitWith", "Int exit code", &node))
+            }
+        },
+        node => return Err(EvalError::type_mismatch("exitWith", "ExitCode", &node))
+    };
+    Err(EvalError::Exit(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    #[test]
+    fn the_command_line_can_be_set() {
+        let mut state = State::new();
+        state.set_args(vec!["/usr/bin/prog".to_string(), "a".to_string(), "b c".to_string()]);
+        assert_eq!(run_action(&mut state, FN_GET_ARGS, &[]).ok().as_deref(), Some("[\"a\",\"b c\"]"));
+        assert_eq!(run_action(&mut state, FN_GET_PROG_NAME, &[]).ok().as_deref(), Some("\"prog\""));
+
+        state.set_args(Vec::new());
+        assert_eq!(run_action(&mut state, FN_GET_ARGS, &[]).ok().as_deref(), Some("[]"));
+        assert_eq!(run_action(&mut state, FN_GET_PROG_NAME, &[]).ok().as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn variables_are_looked_up_set_and_unset() {
+        let mut state = State::new();
+        let name = "\"HASKELITE_TEST_ENV\"";
+        assert_eq!(run_action(&mut state, FN_SET_ENV, &[name, "\"on\""]).ok().as_deref(), Some("()"));
+        assert_eq!(run_action(&mut state, FN_GET_ENV, &[name]).ok().as_deref(), Some("\"on\""));
+        assert_eq!(run_action(&mut state, FN_LOOKUP_ENV, &[name]).ok().as_deref(), Some("Pack{1,1} \"on\""));
+
+        run_action(&mut state, FN_SET_ENV, &[name, "\"\""]).expect("unset");
+        assert_eq!(run_action(&mut state, FN_LOOKUP_ENV, &[name]).ok().as_deref(), Some("Pack{0,0}"));
+        let err = run_action(&mut state, FN_GET_ENV, &[name]).expect_err("unset");
+        assert!(matches!(err.cause(), EvalError::Io(exception) if exception.kind == io::ErrorKind::NotFound));
+
+        let err = run_action(&mut state, FN_SET_ENV, &["\"A=B\"", "\"on\""]).expect_err("an invalid name");
+        assert!(matches!(err.cause(), EvalError::Io(exception) if exception.kind == io::ErrorKind::InvalidInput));
+    }
+
+    /// Runs `exitWith (ExitFailure code)`.
+    fn exit_failure(state: &mut State, code: i64) -> EvalError {
+        state.push_int(code);
+        state.mk_data(TAG_EXIT_FAILURE, 1);
+        state.push_fn(FN_EXIT_WITH);
+        state.mk_ap();
+        let main = state.stack_pop();
+        state.run_io(main).expect_err("an exit")
+    }
+
+    #[test]
+    fn exiting_is_an_error_carrying_the_code() {
+        let mut state = State::new();
+        let exit_code = |result: EvalResult<String>| result.expect_err("an exit").exit_code();
+        assert_eq!(exit_code(run_action(&mut state, FN_EXIT_SUCCESS, &[])), Some(0));
+        assert_eq!(exit_code(run_action(&mut state, FN_EXIT_FAILURE, &[])), Some(1));
+        assert_eq!(exit_failure(&mut state, 3).exit_code(), Some(3));
+        assert_eq!(state.stack_size(), 0);
+
+        let err = exit_failure(&mut state, 0);
+        assert_eq!(err.exit_code(), None);
+        assert!(matches!(err.cause(), EvalError::Io(exception) if exception.kind == io::ErrorKind::InvalidInput));
+    }
+}
@@ -0,0 +1,243 @@
+use core::fmt;
+use core::fmt::Formatter;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io;
+use crate::builtins::Node;
+
+/// A failure raised while evaluating the program graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// A builtin was handed a value of the wrong kind.
+    TypeMismatch {
+        context: &'static str,
+        expected: &'static str,
+        found: &'static str
+    },
+    /// An arithmetic builtin failed, e.g. on division by zero.
+    Arith(ArithException),
+    /// No alternative of a case expression matched the scrutinee.
+    PatternMatchFail(String),
+    /// An `error` call (Haskell's `ErrorCall`), raised either by the program
+    /// or by a partial builtin such as `head`.
+    Error(String),
+    /// A thunk, labelled if it has one, was entered while it was already
+    /// being forced, i.e. its value depends on itself (Haskell's `NonTermination`).
+    NonTermination(Option<&'static str>),
+    /// Evaluation ran out of fuel somewhere it could not be suspended.
+    FuelExhausted,
+    /// The host cancelled the evaluation, or its time ran out.
+    Cancelled,
+    /// The heap grew past the limit set with `State::set_max_heap`
+    /// (Haskell's `HeapOverflow`).
+    HeapOverflow,
+    /// No global definition is registered under the name.
+    UnknownGlobal(String),
+    /// An IO action failed, e.g. writing to a closed pipe.
+    #[cfg(feature = "std")]
+    Io(IoException),
+    /// An exception thrown by the program with `throw` or `throwIO`. The
+    /// exception value itself is kept by the state while it propagates.
+    Thrown,
+    /// Every thread is blocked on an `MVar` that none of the others will
+    /// fill or empty (Haskell's `BlockedIndefinitelyOnMVar`).
+    BlockedIndefinitely,
+    /// Every thread is blocked in an STM transaction that retried, waiting
+    /// for `TVar`s none of the others will write (Haskell's
+    /// `BlockedIndefinitelyOnSTM`).
+    BlockedIndefinitelyOnStm,
+    /// `retry` abandoning the STM transaction it was performed in, for
+    /// `atomically` to run it again once the `TVar`s it read change.
+    Retry,
+    /// The program asked to exit with the status, raised by `exitWith`
+    /// so that evaluation is unwound properly on the way out.
+    Exit(i32),
+    /// An error together with where it was raised: the source location of
+    /// the innermost thunk being forced that has one, and the functions and
+    /// labelled thunks it propagated out of, innermost first.
+    Context {
+        error: Box<EvalError>,
+        loc: Option<SrcLoc>,
+        call_stack: Vec<&'static str>
+    }
+}
+
+pub type EvalResult<T = ()> = Result<T, EvalError>;
+
+/// A position in the source program, attached to thunks by the compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SrcLoc {
+    pub file: &'static str,
+    pub line: u32,
+    pub col: u32
+}
+
+/// Mirrors Haskell's `IOException`: what failed, on what, and why.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoException {
+    /// The handle or file the operation was on, e.g. `<stdout>`.
+    pub location: String,
+    /// The builtin that failed, e.g. `hGetLine`.
+    pub operation: Cow<'static, str>,
+    pub kind: io::ErrorKind,
+    pub description: String
+}
+
+#[cfg(feature = "std")]
+impl IoException {
+    pub fn new(location: &str, operation: &'static str, err: &io::Error) -> Self {
+        IoException {
+            location: location.to_string(),
+            operation: operation.into(),
+            kind: err.kind(),
+            description: err.to_string()
+        }
+    }
+}
+
+/// Mirrors Haskell's `Control.Exception.ArithException`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithException {
+    Overflow,
+    DivideByZero,
+    RatioZeroDenominator
+}
+
+impl EvalError {
+    pub fn type_mismatch(context: &'static str, expected: &'static str, found: &Node) -> Self {
+        EvalError::TypeMismatch { context, expected, found: found.kind() }
+    }
+
+    /// The error itself, without the context it was raised in.
+    pub fn cause(&self) -> &EvalError {
+        match self {
+            EvalError::Context { error, .. } => error,
+            error => error
+        }
+    }
+
+    /// The status to exit with, if the error is a request to exit.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.cause() {
+            EvalError::Exit(code) => Some(*code),
+            _ => None
+        }
+    }
+
+    pub fn location(&self) -> Option<SrcLoc> {
+        match self {
+            EvalError::Context { loc, .. } => *loc,
+            _ => None
+        }
+    }
+
+    pub fn call_stack(&self) -> &[&'static str] {
+        match self {
+            EvalError::Context { call_stack, .. } => call_stack,
+            _ => &[]
+        }
+    }
+
+    /// Attributes the error to `loc`, unless it was raised somewhere more specific.
+    pub fn at(self, loc: SrcLoc) -> Self {
+        let (error, old_loc, call_stack) = self.into_context();
+        EvalError::Context { error, loc: old_loc.or(Some(loc)), call_stack }
+    }
+
+    /// Records that the error propagated out of `frame`.
+    pub fn called_from(self, frame: &'static str) -> Self {
+        let (error, loc, mut call_stack) = self.into_context();
+        call_stack.push(frame);
+        EvalError::Context { error, loc, call_stack }
+    }
+
+    fn into_context(self) -> (Box<EvalError>, Option<SrcLoc>, Vec<&'static str>) {
+        match self {
+            EvalError::Context { error, loc, call_stack } => (error, loc, call_stack),
+            error => (Box::new(error), None, Vec::new())
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::TypeMismatch { context, expected, found } => {
+                write!(f, "{}: expecting {}, found {}", context, expected, found)
+            },
+            EvalError::Arith(exception) => write!(f, "{}", exception),
+            EvalError::PatternMatchFail(msg) => write!(f, "Non-exhaustive patterns: {}", msg),
+            EvalError::Error(msg) => write!(f, "{}", msg),
+            EvalError::NonTermination(None) => write!(f, "<<loop>>"),
+            EvalError::NonTermination(Some(label)) => write!(f, "<<loop>> in {}", label),
+            EvalError::FuelExhausted => write!(f, "evaluation ran out of fuel"),
+            EvalError::Cancelled => write!(f, "evaluation cancelled"),
+            EvalError::HeapOverflow => write!(f, "heap overflow"),
+            EvalError::UnknownGlobal(name) => write!(f, "unknown global: {}", name),
+            #[cfg(feature = "std")]
+            EvalError::Io(exception) => write!(f, "{}", exception),
+            EvalError::Thrown => write!(f, "uncaught exception"),
+            EvalError::BlockedIndefinitely => write!(f, "thread blocked indefinitely in an MVar operation"),
+            EvalError::BlockedIndefinitelyOnStm => write!(f, "thread blocked indefinitely in an STM transaction"),
+            EvalError::Retry => write!(f, "retry performed outside of atomically"),
+            EvalError::Exit(0) => write!(f, "ExitSuccess"),
+            EvalError::Exit(code) => write!(f, "ExitFailure {}", code),
+            EvalError::Context { error, loc, call_stack } => {
+                if let Some(loc) = loc {
+                    write!(f, "{}: ", loc)?;
+                }
+                write!(f, "{}", error)?;
+                if !call_stack.is_empty() {
+                    write!(f, "\nCallStack:")?;
+                    for frame in call_stack {
+                        write!(f, "\n  {}", frame)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for SrcLoc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for IoException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.location, self.operation, self.description)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<IoException> for EvalError {
+    fn from(exception: IoException) -> Self {
+        EvalError::Io(exception)
+    }
+}
+
+impl From<ArithException> for EvalError {
+    fn from(exception: ArithException) -> Self {
+        EvalError::Arith(exception)
+    }
+}
+
+impl fmt::Display for ArithException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithException::Overflow => write!(f, "arithmetic overflow"),
+            ArithException::DivideByZero => write!(f, "divide by zero"),
+            ArithException::RatioZeroDenominator => write!(f, "Ratio has zero denominator")
+        }
+    }
+}
+
+impl core::error::Error for EvalError {}
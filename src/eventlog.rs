@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use crate::builtins::*;
+use crate::tracer::Tracer;
+
+// Event types, numbered as in GHC's `rts/include/rts/EventLogFormat.h`
+// so that its tools recognise them.
+
+const EVENT_GC_START: u16 = 9;
+const EVENT_GC_END: u16 = 10;
+const EVENT_USER_MSG: u16 = 19;
+
+/// The size of an event type whose events carry their own size.
+const VARIABLE_SIZE: i16 = -1;
+
+const EVENT_TYPES: &[(u16, i16, &str)] = &[
+    (EVENT_GC_START, 0, "Starting GC"),
+    (EVENT_GC_END, 0, "Finished GC"),
+    (EVENT_USER_MSG, VARIABLE_SIZE, "User message")
+];
+
+/// A tracer writing what it observes as a binary event log in GHC's
+/// eventlog format, readable with `ghc-events` and similar tools.
+/// Collections are logged as GC events and everything else as user
+/// messages, all timestamped in nanoseconds since the log was created.
+///
+/// Tracer callbacks can't fail, so the first write error stops the log
+/// and is kept to be returned by `finish`.
+pub struct EventLog<W: Write> {
+    out: Option<W>,
+    start: Instant,
+    error: Option<io::Error>
+}
+
+impl EventLog<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        EventLog::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> EventLog<W> {
+    /// Starts a log written to `out`, beginning with the header
+    /// describing the event types used.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(b"hdrb")?;
+        out.write_all(b"hetb")?;
+        for &(num, size, desc) in EVENT_TYPES {
+            out.write_all(b"etb\0")?;
+            out.write_all(&num.to_be_bytes())?;
+            out.write_all(&size.to_be_bytes())?;
+            out.write_all(&(desc.len() as u32).to_be_bytes())?;
+            out.write_all(desc.as_bytes())?;
+            out.write_all(&0u32.to_be_bytes())?;
+            out.write_all(b"ete\0")?;
+        }
+        out.write_all(b"hete")?;
+        out.write_all(b"hdre")?;
+        out.write_all(b"datb")?;
+
+        Ok(EventLog { out: Some(out), start: Instant::now(), error: None })
+    }
+
+    /// Ends the log and hands back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end()?;
+        Ok(self.out.take().unwrap())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if let Some(out) = &mut self.out {
+            out.write_all(&0xffffu16.to_be_bytes())?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, num: u16, payload: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let timestamp = self.start.elapsed().as_nanos() as u64;
+        if let Some(out) = &mut self.out {
+            let result = out.write_all(&num.to_be_bytes())
+                .and_then(|_| out.write_all(&timestamp.to_be_bytes()))
+                .and_then(|_| out.write_all(payload));
+            if let Err(err) = result {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn message(&mut self, msg: &str) {
+        let msg = &msg.as_bytes()[..msg.len().min(u16::MAX as usize)];
+        let mut payload = Vec::with_capacity(2 + msg.len());
+        payload.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        payload.extend_from_slice(msg);
+        self.event(EVENT_USER_MSG, &payload);
+    }
+}
+
+impl<W: Write> Tracer for EventLog<W> {
+    fn on_thunk_enter(&mut self, thunk: &Thunk, _expr: &Node) {
+        self.message(&format!("enter {}", thunk.label.unwrap_or("thunk")));
+    }
+
+    fn on_thunk_update(&mut self, thunk: &Thunk) {
+        self.message(&format!("update {}", thunk.label.unwrap_or("thunk")));
+    }
+
+    fn on_builtin_call(&mut self, fn_def: &FnDef, _args: &[Node]) {
+        self.message(&format!("call {}", fn_def.name));
+    }
+
+    fn on_gc_start(&mut self) {
+        self.event(EVENT_GC_START, &[]);
+    }
+
+    fn on_gc_end(&mut self, _live: usize) {
+        self.event(EVENT_GC_END, &[]);
+    }
+}
+
+impl<W: Write> Drop for EventLog<W> {
+    /// A log dropped without `finish`, e.g. along with the state it was
+    /// installed on, still gets its end marker if it can.
+    fn drop(&mut self) {
+        let _ = self.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails every write after the first `room` bytes.
+    struct Full {
+        room: usize
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.room {
+                return Err(io::Error::other("no room"));
+            }
+            self.room -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events_follow_the_header() {
+        let mut log = EventLog::new(Vec::new()).expect("a header");
+        log.on_gc_start();
+        log.on_builtin_call(&FN_ADD, &[]);
+        log.on_gc_end(0);
+        let out = log.finish().expect("a log");
+        assert!(out.starts_with(b"hdrbhetbetb\0"));
+        let data = out.windows(4).position(|w| w == b"datb").expect("a data section") + 4;
+        let events = &out[data..];
+
+        assert_eq!(&events[..2], &EVENT_GC_START.to_be_bytes());
+        let msg = &events[10..];
+        assert_eq!(&msg[..2], &EVENT_USER_MSG.to_be_bytes());
+        assert_eq!(&msg[10..12], &8u16.to_be_bytes());
+        assert_eq!(&msg[12..20], b"call add");
+        assert_eq!(&msg[20..22], &EVENT_GC_END.to_be_bytes());
+        assert_eq!(&msg[30..], &0xffffu16.to_be_bytes());
+    }
+
+    #[test]
+    fn the_first_write_error_is_kept_for_finish() {
+        let log = EventLog::new(Vec::new()).expect("a header");
+        let header = log.out.as_ref().unwrap().len();
+        drop(log);
+        let mut log = EventLog::new(Full { room: header + 4 }).expect("a header");
+        log.on_gc_start();
+        log.on_gc_end(0);
+        let err = log.finish().err().expect("a write error");
+        assert_eq!(err.to_string(), "no room");
+    }
+}
@@ -0,0 +1,728 @@
+#[cfg(feature = "std")]
+use core::convert::TryFrom;
+use alloc::string::ToString;
+#[cfg(feature = "std")]
+use std::io;
+use crate::builtins::*;
+use crate::action::*;
+use crate::show::eval_show;
+
+// Exceptions are `SomeException` values, constructor nodes tagged with the
+// kind of exception. The program's own exceptions are wrapped with
+// `toException` and thrown with `throw` or `throwIO`; the errors the
+// runtime raises itself are made into values of the other kinds once a
+// `catch` handler is given them. Out of pure code they propagate like any
+// error, leaving the thunks being forced as they were, so forcing them
+// again throws again. Only IO code can catch them.
+//
+// The handler is given every exception, the compiler dispatching on its
+// kind by matching on the tag or with `fromException` and rethrowing the
+// ones it does not handle with `throwIO`.
+
+/// `SomeException e` for an exception of the program's, its field `e`.
+pub const TAG_USER_EXCEPTION: u32 = 0;
+/// `ErrorCall`, raised by `error`, its field the message.
+pub const TAG_ERROR_CALL: u32 = 1;
+/// `ArithException`, its field the `Int` code from `arith_code`.
+pub const TAG_ARITH_EXCEPTION: u32 = 2;
+/// `PatternMatchFail`, its field the message.
+pub const TAG_PATTERN_MATCH_FAIL: u32 = 3;
+/// `IOException`, its fields the message and then the location, operation,
+/// kind (see `io_kind_code`) and description it is made of.
+pub const TAG_IO_EXCEPTION: u32 = 4;
+/// `ExitCode`, raised by `exitWith`, its field the `Int` status.
+pub const TAG_EXIT_CODE: u32 = 5;
+/// `NonTermination`, with no fields.
+pub const TAG_NON_TERMINATION: u32 = 6;
+/// `BlockedIndefinitelyOnMVar`, with no fields.
+pub const TAG_BLOCKED_INDEFINITELY: u32 = 7;
+/// `BlockedIndefinitelyOnSTM`, with no fields.
+pub const TAG_BLOCKED_INDEFINITELY_ON_STM: u32 = 8;
+/// `HeapOverflow`, with no fields.
+pub const TAG_HEAP_OVERFLOW: u32 = 9;
+
+// `data Either a b = Left a | Right b`, the result of `try`.
+
+pub const TAG_LEFT: u32 = 0;
+pub const TAG_RIGHT: u32 = 1;
+
+fn arith_code(exception: ArithException) -> i64 {
+    match exception {
+        ArithException::Overflow => 0,
+        ArithException::DivideByZero => 1,
+        ArithException::RatioZeroDenominator => 2
+    }
+}
+
+/// The kinds of IO error an `IOException` tells apart, by their codes.
+/// Any other kind is `Other`'s once made into an exception value.
+#[cfg(feature = "std")]
+const IO_KINDS: [io::ErrorKind; 19] = [
+    io::ErrorKind::Other,
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::WriteZero,
+    io::ErrorKind::OutOfMemory,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::NotConnected,
+    io::ErrorKind::AddrInUse
+];
+
+#[cfg(feature = "std")]
+fn io_kind_code(kind: io::ErrorKind) -> i64 {
+    IO_KINDS.iter().position(|other| *other == kind).unwrap_or(0) as i64
+}
+
+#[cfg(feature = "std")]
+fn io_kind(code: i64) -> Option<io::ErrorKind> {
+    usize::try_from(code).ok().and_then(|code| IO_KINDS.get(code).copied())
+}
+
+fn arith_exception(code: i64) -> Option<ArithException> {
+    match code {
+        0 => Some(ArithException::Overflow),
+        1 => Some(ArithException::DivideByZero),
+        2 => Some(ArithException::RatioZeroDenominator),
+        _ => None
+    }
+}
+
+/// Whether a handler can be given the error. Type mismatches, unknown
+/// globals and running out of fuel are faults of the runtime or the
+/// compiler rather than exceptions of the program, `retry` is for
+/// `atomically` to handle, and cancelling is the host's doing.
+pub(crate) fn is_catchable(err: &EvalError) -> bool {
+    matches!(err.cause(),
+        EvalError::Thrown
+        | EvalError::Error(_)
+        | EvalError::Arith(_)
+        | EvalError::PatternMatchFail(_)
+        | EvalError::Exit(_)
+        | EvalError::NonTermination(_)
+        | EvalError::BlockedIndefinitely
+        | EvalError::BlockedIndefinitelyOnStm
+        | EvalError::HeapOverflow)
+        || is_io_error(err)
+}
+
+#[cfg(feature = "std")]
+fn is_io_error(err: &EvalError) -> bool {
+    matches!(err.cause(), EvalError::Io(_))
+}
+
+/// Without `std` nothing fails with an `IOException`.
+#[cfg(not(feature = "std"))]
+fn is_io_error(_err: &EvalError) -> bool {
+    false
+}
+
+impl State {
+    /// Pushes the `SomeException` the catchable error `err` stands for,
+    /// taking it from the state if the program threw it.
+    pub(crate) fn push_exception(&mut self, err: EvalError) {
+        match err.cause() {
+            EvalError::Thrown => {
+                let thrown = self.thrown.take().expect("a thrown exception is kept until caught");
+                self.stack_push(thrown);
+            },
+            EvalError::Error(msg) => {
+                self.push_str(msg);
+                self.mk_data(TAG_ERROR_CALL, 1);
+            },
+            EvalError::Arith(exception) => {
+                self.push_int(arith_code(*exception));
+                self.mk_data(TAG_ARITH_EXCEPTION, 1);
+            },
+            EvalError::PatternMatchFail(msg) => {
+                self.push_str(msg);
+                self.mk_data(TAG_PATTERN_MATCH_FAIL, 1);
+            },
+            #[cfg(feature = "std")]
+            EvalError::Io(exception) => {
+                self.push_str(&exception.description);
+                self.push_int(io_kind_code(exception.kind));
+                self.push_str(&exception.operation);
+                self.push_str(&exception.location);
+                self.push_str(&exception.to_string());
+                self.mk_data(TAG_IO_EXCEPTION, 5);
+            },
+            EvalError::Exit(code) => {
+                self.push_int(*code as i64);
+                self.mk_data(TAG_EXIT_CODE, 1);
+            },
+            EvalError::NonTermination(_) => self.mk_data(TAG_NON_TERMINATION, 0),
+            EvalError::BlockedIndefinitely => self.mk_data(TAG_BLOCKED_INDEFINITELY, 0),
+            EvalError::BlockedIndefinitelyOnStm => self.mk_data(TAG_BLOCKED_INDEFINITELY_ON_STM, 0),
+            EvalError::HeapOverflow => self.mk_data(TAG_HEAP_OVERFLOW, 0),
+            err => unreachable!("uncatchable error {:?}", err)
+        }
+    }
+
+    /// Forces the `SomeException` on top of the stack, popping it, and gives
+    /// the runtime error it stands for. For the program's own exceptions it
+    /// gives `None` and pushes the value wrapped instead.
+    fn pop_exception(&mut self) -> EvalResult<Option<EvalError>> {
+        self.eval()?;
+        let node = self.stack_pop();
+        let (tag, fields) = match node {
+            Node::Data { tag, fields } => (tag, fields),
+            _ => return Err(EvalError::type_mismatch("SomeException", "exception", &node))
+        };
+        match tag {
+            TAG_NON_TERMINATION => return Ok(Some(EvalError::NonTermination(None))),
+            TAG_BLOCKED_INDEFINITELY => return Ok(Some(EvalError::BlockedIndefinitely)),
+            TAG_BLOCKED_INDEFINITELY_ON_STM => return Ok(Some(EvalError::BlockedIndefinitelyOnStm)),
+            TAG_HEAP_OVERFLOW => return Ok(Some(EvalError::HeapOverflow)),
+            #[cfg(feature = "std")]
+            TAG_IO_EXCEPTION if fields.len() == 5 => return self.pop_io_exception(&fields).map(Some),
+            _ => {}
+        }
+        if fields.len() != 1 {
+            return Err(EvalError::type_mismatch("SomeException", "exception", &node));
+        }
+        self.stack_push(fields[0]);
+        let err = match tag {
+            TAG_USER_EXCEPTION => return Ok(None),
+            TAG_ERROR_CALL => EvalError::Error(self.eval_string()?),
+            TAG_PATTERN_MATCH_FAIL => EvalError::PatternMatchFail(self.eval_string()?),
+            TAG_ARITH_EXCEPTION | TAG_EXIT_CODE => {
+                self.eval()?;
+                let code = match self.stack_pop() {
+                    Node::Int(code) => code,
+                    node => return Err(EvalError::type_mismatch("SomeException", "Int", &node))
+                };
+                match (tag, arith_exception(code)) {
+                    (TAG_EXIT_CODE, _) => EvalError::Exit(code as i32),
+                    (_, Some(exception)) => EvalError::Arith(exception),
+                    (_, None) => return Err(EvalError::type_mismatch("SomeException", "ArithException", &node))
+                }
+            },
+            _ => return Err(EvalError::type_mismatch("SomeException", "exception", &node))
+        };
+        Ok(Some(err))
+    }
+
+    /// The error an `IOException`'s fields, after its message, stand for.
+    #[cfg(feature = "std")]
+    fn pop_io_exception(&mut self, fields: &Fields) -> EvalResult<EvalError> {
+        let frame_size = self.stack_size();
+        // the fields stay on the stack, rooted, while the strings are forced
+        for field in fields[1..].iter().rev() {
+            self.stack_push(*field);
+        }
+        let exception = self.pop_io_fields();
+        self.stacks.last_mut().unwrap().truncate(frame_size);
+        Ok(EvalError::Io(exception?))
+    }
+
+    #[cfg(feature = "std")]
+    fn pop_io_fields(&mut self) -> EvalResult<IoException> {
+        let location = self.eval_string()?;
+        let operation = self.eval_string()?;
+        self.eval()?;
+        let node = self.stack_pop();
+        let kind = match node {
+            Node::Int(code) => io_kind(code),
+            _ => None
+        };
+        let kind = kind.ok_or_else(|| EvalError::type_mismatch("SomeException", "IOException kind", &node))?;
+        let description = self.eval_string()?;
+        Ok(IoException { location, operation: operation.into(), kind, description })
+    }
+
+    /// The error to report for `err` escaping the program: for an exception
+    /// it threw, the error the exception stands for, or its shown value.
+    pub fn uncaught(&mut self, err: EvalError) -> EvalError {
+        if *err.cause() != EvalError::Thrown || self.thrown.is_none() {
+            return err;
+        }
+        self.push_exception(err);
+        match self.pop_exception() {
+            Ok(Some(err)) => err,
+            Ok(None) => match eval_show(self).and_then(|_| self.eval_string()) {
+                Ok(shown) => EvalError::Error(shown),
+                Err(err) => err
+            },
+            Err(err) => err
+        }
+    }
+}
+
+pub static FN_TO_EXCEPTION: FnDef = FnDef {
+    name: "toException",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_to_exception
+};
+
+pub static FN_FROM_EXCEPTION: FnDef = FnDef {
+    name: "fromException",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_from_exception
+};
+
+pub static FN_DISPLAY_EXCEPTION: FnDef = FnDef {
+    name: "displayException",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_display_exception
+};
+
+pub static FN_THROW: FnDef = FnDef {
+    name: "throw",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_throw
+};
+
+pub static FN_THROW_IO: FnDef = FnDef {
+    name: "throwIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_throw_io
+};
+
+pub static FN_CATCH: FnDef = FnDef {
+    name: "catch",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_catch
+};
+
+pub static FN_HANDLE: FnDef = FnDef {
+    name: "handle",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_handle
+};
+
+pub static FN_TRY: FnDef = FnDef {
+    name: "try",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_try
+};
+
+pub static FN_EVALUATE: FnDef = FnDef {
+    name: "evaluate",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_evaluate
+};
+
+pub static FN_FINALLY: FnDef = FnDef {
+    name: "finally",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_finally
+};
+
+pub static FN_BRACKET: FnDef = FnDef {
+    name: "bracket",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_bracket
+};
+
+static IO_THROW: FnDef = FnDef {
+    name: "throwIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_throw
+};
+
+static IO_EVALUATE: FnDef = FnDef {
+    name: "evaluate",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_evaluate
+};
+
+/// `return . Right`, the continuation of `try`'s action.
+static FN_TRY_RIGHT: FnDef = FnDef {
+    name: "try",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_try_right
+};
+
+/// `return . Left`, the handler of `try`.
+static FN_TRY_LEFT: FnDef = FnDef {
+    name: "try",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_try_left
+};
+
+/// `\fin e -> fin >> throwIO e`, the handler of `finally`.
+static FN_FINALLY_HANDLER: FnDef = FnDef {
+    name: "finally",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_finally_handler
+};
+
+/// `\fin x -> fin >> return x`, the continuation of `finally`'s action.
+static FN_FINALLY_CONT: FnDef = FnDef {
+    name: "finally",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_finally_cont
+};
+
+/// `\acquire release use r -> use r `finally` release r`, the continuation
+/// of `bracket`'s acquire, which it keeps until then.
+static FN_BRACKET_CONT: FnDef = FnDef {
+    name: "bracket",
+    arity: 4,
+    strict: 0,
+    fn_ref: eval_bracket_cont
+};
+
+pub fn eval_to_exception(state: &mut State) -> EvalResult {
+    state.mk_data(TAG_USER_EXCEPTION, 1);
+    Ok(())
+}
+
+/// The program's own exception, or `Nothing` for those of the runtime.
+pub fn eval_from_exception(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Data { tag: TAG_USER_EXCEPTION, fields } if fields.len() == 1 => {
+            state.stack_push(fields[0]);
+            state.mk_just();
+        },
+        Node::Data { .. } => state.push_nothing(),
+        node => return Err(EvalError::type_mismatch("fromException", "exception", &node))
+    }
+    Ok(())
+}
+
+/// The message for the exception, as it is reported if it is not caught.
+pub fn eval_display_exception(state: &mut State) -> EvalResult {
+    match state.pop_exception()? {
+        Some(err) => state.push_str(&err.to_string()),
+        None => eval_show(state)?
+    }
+    Ok(())
+}
+
+/// Throws the `SomeException` on top of the stack.
+pub fn eval_throw(state: &mut State) -> EvalResult {
+    state.thrown = Some(state.stack_pop());
+    Err(EvalError::Thrown)
+}
+
+pub fn eval_throw_io(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_THROW);
+    Ok(())
+}
+
+pub fn eval_catch(state: &mut State) -> EvalResult {
+    let m = state.stack_pop();
+    let handler = state.stack_pop();
+    state.push_io(IoAction::Catch(m, handler));
+    Ok(())
+}
+
+/// `flip catch`
+pub fn eval_handle(state: &mut State) -> EvalResult {
+    state.stack_swap();
+    eval_catch(state)
+}
+
+/// `catch (m >>= return . Right) (return . Left)`
+pub fn eval_try(state: &mut State) -> EvalResult {
+    state.push_fn(FN_TRY_RIGHT);
+    state.stack_swap();
+    eval_bind_io(state)?;
+    state.push_fn(FN_TRY_LEFT);
+    state.stack_swap();
+    eval_catch(state)
+}
+
+fn eval_try_right(state: &mut State) -> EvalResult {
+    state.mk_data(TAG_RIGHT, 1);
+    eval_return_io(state)
+}
+
+fn eval_try_left(state: &mut State) -> EvalResult {
+    state.mk_data(TAG_LEFT, 1);
+    eval_return_io(state)
+}
+
+/// Forces the value to WHNF when the action is performed, so that any
+/// exception it throws is thrown then, where it can be caught.
+pub fn eval_evaluate(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_EVALUATE);
+    Ok(())
+}
+
+fn perform_evaluate(state: &mut State) -> EvalResult {
+    state.eval()
+}
+
+/// `(m `catch` \e -> fin >> throwIO e) >>= \x -> fin >> return x`, so `fin`
+/// is performed once whether `m` throws or not, and outside the catch.
+pub fn eval_finally(state: &mut State) -> EvalResult {
+    let fin = *state.stack_peek_nth(1);
+    state.stack_push(fin);
+    state.mk_pap(FN_FINALLY_HANDLER, 1);
+    state.stack_swap();
+    eval_catch(state)?;
+    state.stack_swap();
+    state.mk_pap(FN_FINALLY_CONT, 1);
+    state.stack_swap();
+    eval_bind_io(state)
+}
+
+fn eval_finally_handler(state: &mut State) -> EvalResult {
+    state.stack_swap();
+    eval_throw_io(state)?;
+    state.stack_swap();
+    eval_then_io(state)
+}
+
+fn eval_finally_cont(state: &mut State) -> EvalResult {
+    state.stack_swap();
+    eval_return_io(state)?;
+    state.stack_swap();
+    eval_then_io(state)
+}
+
+/// `acquire >>= \r -> use r `finally` release r`, releasing the resource
+/// whether using it throws or not.
+pub fn eval_bracket(state: &mut State) -> EvalResult {
+    state.mk_pap(FN_BRACKET_CONT, 3);
+    let acquire = match state.stack_peek() {
+        Node::Pap(_, args) => args[0],
+        _ => unreachable!()
+    };
+    state.stack_push(acquire);
+    eval_bind_io(state)
+}
+
+fn eval_bracket_cont(state: &mut State) -> EvalResult {
+    state.stack_pop();
+    // release r
+    let r = *state.stack_peek_nth(2);
+    state.stack_push(r);
+    state.stack_swap();
+    state.mk_ap();
+    // use r
+    state.stack_swap();
+    let r = *state.stack_peek_nth(2);
+    state.stack_push(r);
+    state.stack_swap();
+    state.mk_ap();
+    eval_finally(state)?;
+    // drop the argument the applications were made of
+    state.stack_swap();
+    state.stack_pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ioref::*;
+    use super::*;
+
+    /// Runs the action and forces the result, expected to be a constructor.
+    fn run_to_data(state: &mut State, action: Node) -> (u32, Vec<Node>) {
+        let result = state.run_io(action).and_then(|result| state.try_eval(result));
+        match result {
+            Ok(Node::Data { tag, fields }) => (tag, fields.to_vec()),
+            Ok(node) => panic!("not a constructor: {}", node.kind()),
+            Err(err) => panic!("{}", err)
+        }
+    }
+
+    /// Pushes `catch action return`, the handler returning the exception.
+    fn push_catch_returning(state: &mut State) {
+        state.push_fn(FN_RETURN_IO);
+        state.stack_swap();
+        state.push_fn(FN_CATCH);
+        state.mk_ap();
+        state.mk_ap();
+    }
+
+    /// Pushes `throwIO (toException n)`.
+    fn push_throw_io(state: &mut State, n: i64) {
+        state.push_int(n);
+        state.push_fn(FN_TO_EXCEPTION);
+        state.mk_ap();
+        state.push_fn(FN_THROW_IO);
+        state.mk_ap();
+    }
+
+    static FN_FINALLY_WRITES: FnDef = FnDef {
+        name: "finallyWrites",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_finally_writes
+    };
+
+    static FN_READ_AFTER: FnDef = FnDef {
+        name: "readAfter",
+        arity: 2,
+        strict: 0,
+        fn_ref: eval_read_after
+    };
+
+    /// `\r -> (throwIO (toException 1) `finally` writeIORef r 7) `catch` readAfter r`
+    fn eval_finally_writes(state: &mut State) -> EvalResult {
+        let r = *state.stack_peek();
+        state.mk_pap(FN_READ_AFTER, 1);
+        state.push_int(7);
+        state.push(r);
+        state.push_fn(FN_WRITE_IOREF);
+        state.mk_ap();
+        state.mk_ap();
+        push_throw_io(state, 1);
+        state.push_fn(FN_FINALLY);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_CATCH);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// `\r _ -> readIORef r`
+    fn eval_read_after(state: &mut State) -> EvalResult {
+        state.stack_swap();
+        state.stack_pop();
+        eval_read_ioref(state)
+    }
+
+    #[test]
+    fn catch_hands_the_thrown_exception_to_the_handler() {
+        let mut state = State::new();
+        // catch (throwIO (toException 3)) return
+        push_throw_io(&mut state, 3);
+        push_catch_returning(&mut state);
+        let action = state.stack_pop();
+        let (tag, fields) = run_to_data(&mut state, action);
+        assert_eq!(tag, TAG_USER_EXCEPTION);
+        assert!(matches!(state.try_eval(fields[0]), Ok(Node::Int(3))));
+    }
+
+    #[test]
+    fn catch_makes_runtime_errors_exceptions() {
+        let mut state = State::new();
+        // catch (evaluate (div 1 0)) return
+        state.push_int(0);
+        state.push_int(1);
+        state.push_fn(FN_DIV);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_EVALUATE);
+        state.mk_ap();
+        push_catch_returning(&mut state);
+        let action = state.stack_pop();
+        let (tag, fields) = run_to_data(&mut state, action);
+        assert_eq!(tag, TAG_ARITH_EXCEPTION);
+        let code = arith_code(ArithException::DivideByZero);
+        assert!(matches!(state.try_eval(fields[0]), Ok(Node::Int(n)) if n == code));
+    }
+
+    #[test]
+    fn error_calls_round_trip_through_catch() {
+        let mut state = State::new();
+        // catch (evaluate (error "boom")) return
+        state.push_str("boom");
+        state.push_fn(FN_ERROR);
+        state.mk_ap();
+        state.push_fn(FN_EVALUATE);
+        state.mk_ap();
+        // left below, rooted, for the second catch
+        let action = *state.stack_peek();
+        state.push(action);
+        push_catch_returning(&mut state);
+        let caught = state.stack_pop();
+        let (tag, fields) = run_to_data(&mut state, caught);
+        assert_eq!(tag, TAG_ERROR_CALL);
+        state.push(fields[0]);
+        assert_eq!(state.eval_string().ok().as_deref(), Some("boom"));
+
+        // catch (evaluate (error "boom")) throwIO, reported as it was
+        state.push_fn(FN_THROW_IO);
+        state.stack_swap();
+        state.push_fn(FN_CATCH);
+        state.mk_ap();
+        state.mk_ap();
+        let rethrown = state.stack_pop();
+        let err = state.run_io(rethrown).expect_err("rethrown");
+        assert!(matches!(state.uncaught(err).cause(), EvalError::Error(msg) if msg == "boom"));
+    }
+
+    #[test]
+    fn try_returns_either() {
+        let mut state = State::new();
+        // try (return 5)
+        state.push_int(5);
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        state.push_fn(FN_TRY);
+        state.mk_ap();
+        let action = state.stack_pop();
+        let (tag, fields) = run_to_data(&mut state, action);
+        assert_eq!(tag, TAG_RIGHT);
+        assert!(matches!(state.try_eval(fields[0]), Ok(Node::Int(5))));
+
+        // try (throwIO (toException 1))
+        push_throw_io(&mut state, 1);
+        state.push_fn(FN_TRY);
+        state.mk_ap();
+        let action = state.stack_pop();
+        let (tag, _) = run_to_data(&mut state, action);
+        assert_eq!(tag, TAG_LEFT);
+    }
+
+    #[test]
+    fn finally_runs_when_the_action_throws() {
+        let mut state = State::new();
+        // newIORef 0 >>= finallyWrites
+        state.push_fn(FN_FINALLY_WRITES);
+        state.push_int(0);
+        state.push_fn(FN_NEW_IOREF);
+        state.mk_ap();
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let result = state.run_io(main).and_then(|result| state.try_eval(result));
+        assert!(matches!(result, Ok(Node::Int(7))));
+    }
+
+    #[test]
+    fn pure_throw_propagates_and_leaves_the_stack() {
+        let mut state = State::new();
+        // throw (toException 1)
+        state.push_int(1);
+        state.push_fn(FN_TO_EXCEPTION);
+        state.mk_ap();
+        state.push_fn(FN_THROW);
+        state.mk_ap();
+        state.mk_thunk();
+        let thunk = state.stack_pop();
+        let result = state.try_eval(thunk);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Thrown)));
+        assert_eq!(state.stack_size(), 0);
+        // the thunk is left unevaluated, to throw again
+        let result = state.try_eval(thunk);
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::Thrown)));
+    }
+}
@@ -0,0 +1,443 @@
+// What every entry point requires of its caller is stated once, below.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::collections::HashMap;
+use core::ffi::{c_char, CStr};
+use alloc::ffi::CString;
+use core::ptr;
+use core::slice;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::format;
+use crate::builtins::*;
+use crate::registry::builtin;
+
+// The runtime's entry points for compiled code that isn't Rust, declared
+// for C in `include/haskelite.h`, so the compiler can emit object code
+// that links against the runtime instead of generating `prog.rs`. They
+// are the operations generated Rust uses, on an opaque `hl_state`, the
+// stack discipline the same: arguments are pushed last first, and
+// builders pop what they are built from, the first on top.
+//
+// Calls that can fail return `HL_OK` or `HL_ERROR`, the error kept by
+// the state: a native function given one returns `HL_ERROR` in turn to
+// propagate it, exceptions the program threw included, and the host can
+// read its message with `hl_last_error`. Pointers passed in must be valid,
+// and strings UTF-8 with their length in bytes.
+
+pub const HL_OK: i32 = 0;
+pub const HL_ERROR: i32 = 1;
+
+/// A function compiled to native code, taking the state it is run on.
+pub type NativeFn = unsafe extern "C" fn(state: *mut State) -> i32;
+
+/// `hl_fn_def`, the definition of a native function, living as long as
+/// the program. Its name is stored inline at the end, and is the name of
+/// the `FnDef` made from it.
+#[repr(C)]
+pub struct NativeFnDef {
+    fn_ptr: NativeFn,
+    arity: usize,
+    strict: usize,
+    /// NUL-terminated.
+    name: [c_char; 0]
+}
+
+/// What the state keeps for native code.
+#[derive(Default)]
+pub(crate) struct Ffi {
+    /// Why the last call that failed did.
+    pub(crate) error: Option<EvalError>,
+    /// The error's message for `hl_last_error`, once asked for.
+    message: Option<CString>,
+    /// The definitions `FnDef`s were made from, by the address of the name
+    /// they carry, which is the callee's when `call_native` runs.
+    defs: HashMap<usize, *const NativeFnDef>
+}
+
+/// The `FnDef` of every native function, entering the definition's.
+fn call_native(state: &mut State) -> EvalResult {
+    let name = state.callee;
+    let Some(&def) = state.ffi.defs.get(&(name.as_ptr() as usize)) else {
+        return Err(EvalError::UnknownGlobal(name.to_string()));
+    };
+    state.ffi.error = None;
+    match unsafe { ((*def).fn_ptr)(state) } {
+        HL_OK => Ok(()),
+        _ => Err(state.ffi.error.take().unwrap_or_else(|| EvalError::Error(format!("{} failed", name))))
+    }
+}
+
+/// The `FnDef` of `def`, which `state` can call from then on.
+unsafe fn fn_def(state: &mut State, def: *const NativeFnDef) -> Option<FnDef> {
+    // the name is past the end of the struct as Rust sees it, so is only
+    // reached through the lone pointer to the definition
+    let name = CStr::from_ptr(ptr::addr_of!((*def).name) as *const c_char).to_str().ok()?;
+    state.ffi.defs.insert(name.as_ptr() as usize, def);
+    Some(FnDef { name, arity: (*def).arity, strict: (*def).strict, fn_ref: call_native })
+}
+
+unsafe fn str_arg<'a>(ptr: *const u8, len: usize) -> Option<&'a str> {
+    core::str::from_utf8(slice::from_raw_parts(ptr, len)).ok()
+}
+
+impl State {
+    /// `HL_OK` for `Ok`, otherwise `HL_ERROR`, keeping the error.
+    fn status(&mut self, result: EvalResult) -> i32 {
+        match result {
+            Ok(()) => HL_OK,
+            Err(err) => {
+                self.ffi.error = Some(err);
+                self.ffi.message = None;
+                HL_ERROR
+            }
+        }
+    }
+
+    fn invalid_argument(&mut self, context: &str) -> i32 {
+        self.status(Err(EvalError::Error(format!("{}: invalid argument", context))))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hl_state_new() -> *mut State {
+    Box::into_raw(Box::new(State::new()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_state_free(state: *mut State) {
+    drop(Box::from_raw(state));
+}
+
+/// The message of the error the last failed call kept, NUL-terminated and
+/// valid until the next call, or null if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn hl_last_error(state: *mut State) -> *const c_char {
+    let ffi = &mut (*state).ffi;
+    let Some(err) = &ffi.error else {
+        return core::ptr::null();
+    };
+    let message = err.to_string().replace('\0', " ");
+    ffi.message.insert(CString::new(message).expect("NULs are replaced")).as_ptr()
+}
+
+/// Raises an `error` call with the message, for a native function to
+/// return `HL_ERROR` with.
+#[no_mangle]
+pub unsafe extern "C" fn hl_fail(state: *mut State, msg: *const u8, len: usize) -> i32 {
+    let msg = String::from_utf8_lossy(slice::from_raw_parts(msg, len)).into_owned();
+    (*state).status(Err(EvalError::Error(msg)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_int(state: *mut State, value: i64) {
+    (*state).push_int(value);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_double(state: *mut State, value: f64) {
+    (*state).push_double(value);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_char(state: *mut State, code_point: u32) -> i32 {
+    let state = &mut *state;
+    match char::from_u32(code_point) {
+        Some(c) => {
+            state.push_char(c);
+            HL_OK
+        },
+        None => state.invalid_argument("hl_push_char")
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_bool(state: *mut State, value: bool) {
+    (*state).stack_push(Node::Bool(value));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_nil(state: *mut State) {
+    (*state).push_nil();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_unit(state: *mut State) {
+    (*state).push_unit();
+}
+
+/// Pushes the string as a `[Char]` list.
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_str(state: *mut State, ptr: *const u8, len: usize) -> i32 {
+    let state = &mut *state;
+    match str_arg(ptr, len) {
+        Some(s) => {
+            state.push_str(s);
+            HL_OK
+        },
+        None => state.invalid_argument("hl_push_str")
+    }
+}
+
+/// Pushes a native function, whose definition must live as long as the program.
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_fn(state: *mut State, def: *const NativeFnDef) -> i32 {
+    let state = &mut *state;
+    match fn_def(state, def) {
+        Some(fn_def) => {
+            state.push_fn(fn_def);
+            HL_OK
+        },
+        None => state.invalid_argument("hl_push_fn")
+    }
+}
+
+/// The builtin named `name`, to push with `hl_push_builtin`, or null if
+/// there is none.
+#[no_mangle]
+pub unsafe extern "C" fn hl_builtin(name: *const u8, len: usize) -> *const FnDef {
+    str_arg(name, len).and_then(builtin).map_or(core::ptr::null(), |fn_def| fn_def as *const FnDef)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_builtin(state: *mut State, builtin: *const FnDef) {
+    (*state).push_fn(*builtin);
+}
+
+/// Registers a native function as a global, under its name.
+#[no_mangle]
+pub unsafe extern "C" fn hl_register_global(state: *mut State, def: *const NativeFnDef) -> i32 {
+    let state = &mut *state;
+    match fn_def(state, def) {
+        Some(fn_def) => {
+            state.register_global(fn_def);
+            HL_OK
+        },
+        None => state.invalid_argument("hl_register_global")
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_push_global(state: *mut State, name: *const u8, len: usize) -> i32 {
+    let state = &mut *state;
+    match str_arg(name, len) {
+        Some(name) => {
+            let result = state.push_global(name);
+            state.status(result)
+        },
+        None => state.invalid_argument("hl_push_global")
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_ap(state: *mut State) {
+    (*state).mk_ap();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_thunk(state: *mut State) {
+    (*state).mk_thunk();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_cons(state: *mut State) {
+    (*state).mk_cons();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_data(state: *mut State, tag: u32, arity: usize) {
+    (*state).mk_data(tag, arity);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_tuple(state: *mut State, arity: usize) {
+    (*state).mk_tuple(arity);
+}
+
+/// Pops `arity` arguments and pushes the native function partially applied to them.
+#[no_mangle]
+pub unsafe extern "C" fn hl_mk_pap(state: *mut State, def: *const NativeFnDef, arity: usize) -> i32 {
+    let state = &mut *state;
+    match fn_def(state, def) {
+        Some(fn_def) => {
+            state.mk_pap(fn_def, arity);
+            HL_OK
+        },
+        None => state.invalid_argument("hl_mk_pap")
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_stack_pop(state: *mut State) {
+    (*state).stack_pop();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_stack_swap(state: *mut State) {
+    (*state).stack_swap();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_stack_size(state: *mut State) -> usize {
+    (*state).stack_size()
+}
+
+/// Pushes a copy of the node `n` below the top, 0 for the top.
+#[no_mangle]
+pub unsafe extern "C" fn hl_stack_dup(state: *mut State, n: usize) {
+    let state = &mut *state;
+    let node = *state.stack_peek_nth(n);
+    state.stack_push(node);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_stack_bring_up(state: *mut State, n: usize) {
+    (*state).stack_bring_up(n);
+}
+
+/// Reduces the node on top of the stack to WHNF in place.
+#[no_mangle]
+pub unsafe extern "C" fn hl_eval(state: *mut State) -> i32 {
+    let state = &mut *state;
+    let result = state.eval();
+    state.status(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_deep_eval(state: *mut State) -> i32 {
+    let state = &mut *state;
+    let result = state.deep_eval();
+    state.status(result)
+}
+
+/// Forces the node on top of the stack and replaces it with its fields,
+/// the first on top, giving its constructor's tag.
+#[no_mangle]
+pub unsafe extern "C" fn hl_case_split(state: *mut State, tag: *mut u32) -> i32 {
+    let state = &mut *state;
+    let result = state.case_split().map(|split| *tag = split);
+    state.status(result)
+}
+
+/// Performs the IO action on top of the stack, replacing it with its result.
+#[no_mangle]
+pub unsafe extern "C" fn hl_run_io(state: *mut State) -> i32 {
+    let state = &mut *state;
+    let action = state.stack_pop();
+    let result = state.run_io(action).map(|value| state.stack_push(value));
+    state.status(result)
+}
+
+/// Forces the node on top of the stack, leaving it there, and writes what
+/// `read` makes of it to `out`.
+unsafe fn peek<T>(state: *mut State, out: *mut T, read: impl FnOnce(Node) -> EvalResult<T>) -> i32 {
+    let state = &mut *state;
+    let result = state.eval().and_then(|_| read(*state.stack_peek())).map(|value| *out = value);
+    state.status(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_peek_int(state: *mut State, value: *mut i64) -> i32 {
+    peek(state, value, |node| match node {
+        Node::Int(int) => Ok(int),
+        node => Err(EvalError::type_mismatch("hl_peek_int", "Int", &node))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_peek_double(state: *mut State, value: *mut f64) -> i32 {
+    peek(state, value, |node| match node {
+        Node::Double(double) => Ok(double),
+        node => Err(EvalError::type_mismatch("hl_peek_double", "Double", &node))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_peek_char(state: *mut State, code_point: *mut u32) -> i32 {
+    peek(state, code_point, |node| match node {
+        Node::Char(c) => Ok(c as u32),
+        node => Err(EvalError::type_mismatch("hl_peek_char", "Char", &node))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hl_peek_bool(state: *mut State, value: *mut bool) -> i32 {
+    peek(state, value, |node| match node {
+        Node::Bool(b) => Ok(b),
+        node => Err(EvalError::type_mismatch("hl_peek_bool", "Bool", &node))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A definition as C lays one out, its name following it.
+    #[repr(C)]
+    struct Def<const N: usize> {
+        def: NativeFnDef,
+        name: [u8; N]
+    }
+
+    impl<const N: usize> Def<N> {
+        fn as_ptr(&self) -> *const NativeFnDef {
+            (self as *const Def<N>).cast()
+        }
+    }
+
+    /// `double x = x * 2`
+    unsafe extern "C" fn double(state: *mut State) -> i32 {
+        let mut x = 0;
+        if hl_peek_int(state, &mut x) != HL_OK {
+            return HL_ERROR;
+        }
+        hl_stack_pop(state);
+        hl_push_int(state, x * 2);
+        HL_OK
+    }
+
+    unsafe extern "C" fn failing(state: *mut State) -> i32 {
+        let msg = "no luck";
+        hl_fail(state, msg.as_ptr(), msg.len())
+    }
+
+    static DOUBLE: Def<7> = Def { def: NativeFnDef { fn_ptr: double, arity: 1, strict: 1, name: [] }, name: *b"double\0" };
+    static FAILING: Def<8> = Def { def: NativeFnDef { fn_ptr: failing, arity: 0, strict: 0, name: [] }, name: *b"failing\0" };
+
+    #[test]
+    fn native_functions_are_called_through_their_definitions() {
+        unsafe {
+            let state = hl_state_new();
+            assert_eq!(hl_register_global(state, DOUBLE.as_ptr()), HL_OK);
+            hl_push_int(state, 21);
+            let name = "double";
+            assert_eq!(hl_push_global(state, name.as_ptr(), name.len()), HL_OK);
+            hl_mk_ap(state);
+            let mut value = 0;
+            assert_eq!(hl_peek_int(state, &mut value), HL_OK);
+            assert_eq!(value, 42);
+            hl_stack_pop(state);
+
+            hl_push_int(state, 5);
+            assert_eq!(hl_mk_pap(state, DOUBLE.as_ptr(), 1), HL_OK);
+            assert_eq!(hl_peek_int(state, &mut value), HL_OK);
+            assert_eq!(value, 10);
+            hl_stack_pop(state);
+            assert_eq!(hl_stack_size(state), 0);
+            hl_state_free(state);
+        }
+    }
+
+    #[test]
+    fn native_errors_are_kept_for_the_host() {
+        unsafe {
+            let state = hl_state_new();
+            assert!(hl_last_error(state).is_null());
+            assert_eq!(hl_push_fn(state, FAILING.as_ptr()), HL_OK);
+            assert_eq!(hl_eval(state), HL_ERROR);
+            let message = CStr::from_ptr(hl_last_error(state)).to_str().unwrap();
+            assert!(message.contains("no luck"), "{}", message);
+            hl_state_free(state);
+        }
+    }
+}
@@ -1,19 +1,79 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use crate::builtins::*;
-use std::fmt;
-use std::fmt::Formatter;
-use std::fmt::Debug;
-use std::ops::Deref;
-use std::mem;
+use core::fmt;
+use core::fmt::Formatter;
+use core::fmt::Debug;
+use core::ops::Deref;
+use core::mem;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+/// Implemented by every value that can live on the GC heap.
+pub trait Trace {
+    /// The function to 'mark' any contained GC references.
+    /// Sets the `marked` bit on the GC references,
+    /// as well as add them to the `worklist`.
+    fn mark_refs(&self, worklist: &mut Worklist);
+
+    /// How the object is grouped in a heap census:
+    /// its kind, and its label if it has one.
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("other", None)
+    }
+
+    /// The bytes the object takes up, counting memory it owns.
+    fn heap_size(&self) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+/// How many thunks a block of the thunk pool has room for.
+const THUNK_BLOCK_SIZE: usize = 1024;
 
 pub struct GcAlloc {
-    nodes: Vec<*mut GcObj<Node>>
+    objs: Vec<*mut GcObj<dyn Trace>>,
+    /// Objects that are never swept, see `alloc_pinned`.
+    pinned: Vec<*mut GcObj<dyn Trace>>,
+    /// Whether thunks are allocated from `thunk_pool` rather than boxed one
+    /// by one like every other object.
+    pool_thunks: bool,
+    thunk_pool: ThunkPool,
+    collections: u64,
+    freed: u64,
+    peak_live: usize,
+    /// The bytes taken up by the objects on the heap, as of the last sweep
+    /// and the allocations since.
+    live_bytes: usize
+}
+
+/// Thunks carved out of blocks of `THUNK_BLOCK_SIZE` slots, their
+/// slots reused once they are swept. Blocks stay put, so a `Gc<Thunk>`
+/// into one stays valid, and are only freed along with the allocator,
+/// pooling or not by then.
+#[derive(Default)]
+struct ThunkPool {
+    blocks: Vec<Box<[MaybeUninit<GcObj<Thunk>>]>>,
+    free: Vec<*mut GcObj<Thunk>>
+}
+
+impl ThunkPool {
+    fn slot(&mut self) -> *mut GcObj<Thunk> {
+        if self.free.is_empty() {
+            let mut block: Box<[MaybeUninit<GcObj<Thunk>>]> =
+                (0..THUNK_BLOCK_SIZE).map(|_| MaybeUninit::uninit()).collect();
+            self.free.extend(block.iter_mut().rev().map(|slot| slot.as_mut_ptr()));
+            self.blocks.push(block);
+        }
+        self.free.pop().unwrap()
+    }
 }
 
-pub struct Gc<T> {
+pub struct Gc<T: ?Sized> {
     ptr: *mut GcObj<T>
 }
 
-impl<T> Gc<T> {
+impl<T: ?Sized> Gc<T> {
     pub fn is_marked(&self) -> bool {
         unsafe { 
             (*self.ptr).marked
@@ -25,10 +85,32 @@ impl<T> Gc<T> {
             (*self.ptr).marked = true;
         }
     }
+
+    /// The address of the object, identifying it while it is alive.
+    pub fn addr(&self) -> usize {
+        self.ptr as *const u8 as usize
+    }
 }
 
-pub struct GcObj<T> {
+impl<T: Trace + 'static> Gc<T> {
+    /// Marks the reference and queues it on the `worklist`, 
+    /// unless it was already marked.
+    pub fn mark_into(mut self, worklist: &mut Worklist) {
+        if !self.is_marked() {
+            self.mark();
+            worklist.push(self.erase());
+        }
+    }
+
+    pub(crate) fn erase(self) -> Gc<dyn Trace> {
+        Gc { ptr: self.ptr as *mut GcObj<dyn Trace> }
+    }
+}
+
+pub struct GcObj<T: ?Sized> {
     marked: bool,
+    /// Whether the object is in a slot of the thunk pool rather than a box of its own.
+    pooled: bool,
     value: T
 }
 
@@ -38,15 +120,15 @@ impl<T: Debug> fmt::Debug for Gc<T> {
     }
 }
 
-impl<T> Copy for Gc<T> {}
+impl<T: ?Sized> Copy for Gc<T> {}
 
-impl<T> Clone for Gc<T> {
+impl<T: ?Sized> Clone for Gc<T> {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<T> Deref for Gc<T> {
+impl<T: ?Sized> Deref for Gc<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -60,62 +142,285 @@ impl fmt::Display for Gc<Node> {
     }
 }
 
-impl<T> AsRef<T> for Gc<T> {
+impl<T: ?Sized> AsRef<T> for Gc<T> {
     fn as_ref(&self) -> &T {
         unsafe { &(*self.ptr).value }
     }
 }
 
-impl<T> AsMut<T> for Gc<T> {
+impl<T: ?Sized> AsMut<T> for Gc<T> {
     fn as_mut(&mut self) -> &mut T {
         unsafe { &mut (*self.ptr).value }
     }
 }
 
-pub type Worklist = Vec<Gc<Node>>;
+pub type Worklist = Vec<Gc<dyn Trace>>;
+
+impl Default for GcAlloc {
+    fn default() -> Self {
+        GcAlloc::new()
+    }
+}
 
 impl GcAlloc {
     pub fn new() -> Self {
-        GcAlloc { nodes: Vec::new() }
+        GcAlloc {
+            objs: Vec::new(),
+            pinned: Vec::new(),
+            pool_thunks: false,
+            thunk_pool: ThunkPool::default(),
+            collections: 0,
+            freed: 0,
+            peak_live: 0,
+            live_bytes: 0
+        }
     }
 
-    pub fn alloc_node(&mut self, item: Node) -> Gc<Node> {
-        // println!("Allocating: {:?}", &item);
-        let node_ref = Box::into_raw(Box::new(GcObj { marked: false, value: item }));
-        self.nodes.push(node_ref);
-        Gc { ptr: node_ref }
+    /// Whether thunks are allocated from a pool of blocks from now on,
+    /// which takes the system allocator out of allocation-heavy programs.
+    /// Thunks already allocated stay where they are.
+    pub fn set_pool_thunks(&mut self, pool: bool) {
+        self.pool_thunks = pool;
+    }
+
+    pub fn alloc<T: Trace + 'static>(&mut self, item: T) -> Gc<T> {
+        let obj_ref = Box::into_raw(Box::new(GcObj { marked: false, pooled: false, value: item }));
+        self.track(obj_ref);
+        Gc { ptr: obj_ref }
+    }
+
+    /// Allocates an object that lives as long as the allocator, for values
+    /// shared from a cache. It is born marked, so collections pass it by
+    /// without tracing it: it must not refer to anything on the heap.
+    pub fn alloc_pinned<T: Trace + 'static>(&mut self, item: T) -> Gc<T> {
+        let obj_ref = Box::into_raw(Box::new(GcObj { marked: true, pooled: false, value: item }));
+        self.pinned.push(obj_ref);
+        Gc { ptr: obj_ref }
+    }
+
+    /// Allocates a thunk, from the pool if pooling them.
+    pub fn alloc_thunk(&mut self, thunk: Thunk) -> Gc<Thunk> {
+        if !self.pool_thunks {
+            return self.alloc(thunk);
+        }
+        let obj_ref = self.thunk_pool.slot();
+        unsafe {
+            obj_ref.write(GcObj { marked: false, pooled: true, value: thunk });
+        }
+        self.track(obj_ref);
+        Gc { ptr: obj_ref }
+    }
+
+    fn track(&mut self, obj_ref: *mut GcObj<dyn Trace>) {
+        self.live_bytes += unsafe { (*obj_ref).value.heap_size() };
+        self.objs.push(obj_ref);
+        self.peak_live = self.peak_live.max(self.objs.len());
+    }
+
+    /// Frees an object that is no longer referenced, giving its slot back
+    /// to the pool if it has one.
+    unsafe fn free(&mut self, gc_ref: *mut GcObj<dyn Trace>) {
+        if !(*gc_ref).pooled {
+            let _ = Box::from_raw(gc_ref);
+            return;
+        }
+        ptr::drop_in_place(gc_ref);
+        self.thunk_pool.free.push(gc_ref as *mut GcObj<Thunk>);
     }
 
     pub fn collect(&mut self, mut worklist: Worklist) {
         Self::mark(&mut worklist);
         self.sweep();
+        self.collections += 1;
     }
 
-    fn mark(worklist: &mut Worklist) {
-        while let Some(node) = worklist.pop() {
-            node.mark_refs(worklist);
+    /// Marks everything reachable from what is on the `worklist`, emptying it.
+    pub fn mark(worklist: &mut Worklist) {
+        while let Some(obj) = worklist.pop() {
+            obj.mark_refs(worklist);
         }
     }
 
     pub fn sweep(&mut self) {
         unsafe {
-            let mut new_nodes: Vec<*mut GcObj<Node>> = Vec::new();
-            while let Some(gc_ref) = self.nodes.pop() {
-                if (&*gc_ref).marked {
-                    (&mut *gc_ref).marked = false;
-                    new_nodes.push(gc_ref);
+            let mut new_objs: Vec<*mut GcObj<dyn Trace>> = Vec::new();
+            // objects can grow once allocated, so they are counted afresh
+            self.live_bytes = 0;
+            while let Some(gc_ref) = self.objs.pop() {
+                if (*gc_ref).marked {
+                    (*gc_ref).marked = false;
+                    self.live_bytes += (*gc_ref).value.heap_size();
+                    new_objs.push(gc_ref);
                 } else {
-                    println!("Sweeping");
-                    let _ = Box::from_raw(gc_ref); // let it get collected when exiting scope
+                    self.freed += 1;
+                    self.free(gc_ref);
                 }
             }
-            self.nodes = new_nodes;
+            self.objs = new_objs;
         }
     }
 
+    /// The objects on the heap, including garbage not yet swept.
+    pub fn objects(&self) -> impl Iterator<Item = &dyn Trace> + '_ {
+        self.objs.iter().map(|obj| unsafe { &(**obj).value })
+    }
+
+    pub fn live(&self) -> usize {
+        self.objs.len()
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes
+    }
+
+    pub fn peak_live(&self) -> usize {
+        self.peak_live
+    }
+
+    pub fn collections(&self) -> u64 {
+        self.collections
+    }
+
+    pub fn freed(&self) -> u64 {
+        self.freed
+    }
+
+    #[cfg(feature = "std")]
     pub fn dump(&self) {
         println!("\n--- Begin GC Stats ---\n");
-        println!("Objects: {} ({} bytes)", self.nodes.len(), self.nodes.len() * mem::size_of::<Node>());
+        println!("Objects: {} ({} bytes)", self.objs.len(), self.objs.len() * mem::size_of::<Node>());
         println!("\n--- End GC Stats ---\n");
     }
 }
+
+/// Frees whatever is left on the heap along with the state, reachable or
+/// not, cycles included. References into it must not be used afterwards.
+impl Drop for GcAlloc {
+    fn drop(&mut self) {
+        // the thunks in the pool go before the blocks they are in
+        while let Some(gc_ref) = self.objs.pop().or_else(|| self.pinned.pop()) {
+            unsafe {
+                self.free(gc_ref);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use super::*;
+
+    /// A heap object pointing at another, counting its drops.
+    struct Link {
+        next: Option<Gc<Link>>,
+        drops: Rc<Cell<usize>>
+    }
+
+    impl Trace for Link {
+        fn mark_refs(&self, worklist: &mut Worklist) {
+            if let Some(next) = self.next {
+                next.mark_into(worklist);
+            }
+        }
+    }
+
+    impl Drop for Link {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    /// Allocates two links pointing at each other.
+    fn alloc_cycle(alloc: &mut GcAlloc, drops: &Rc<Cell<usize>>) -> Gc<Link> {
+        let mut a = alloc.alloc(Link { next: None, drops: drops.clone() });
+        let b = alloc.alloc(Link { next: Some(a), drops: drops.clone() });
+        a.as_mut().next = Some(b);
+        a
+    }
+
+    #[test]
+    fn unreachable_cycles_are_freed() {
+        let mut alloc = GcAlloc::new();
+        let drops = Rc::new(Cell::new(0));
+        let root = alloc_cycle(&mut alloc, &drops);
+        alloc_cycle(&mut alloc, &drops);
+
+        let mut worklist = Vec::new();
+        root.mark_into(&mut worklist);
+        alloc.collect(worklist);
+        assert_eq!(drops.get(), 2);
+        assert_eq!(alloc.live(), 2);
+        assert!(root.next.is_some_and(|next| next.next.is_some()));
+
+        alloc.collect(Vec::new());
+        assert_eq!(drops.get(), 4);
+        assert_eq!(alloc.live(), 0);
+        assert_eq!(alloc.freed(), 4);
+        assert_eq!(alloc.collections(), 2);
+    }
+
+    #[test]
+    fn knot_tied_structures_are_reclaimed() {
+        let mut state = State::new();
+        let before = state.heap_census().total_objects();
+        // ones = 1 : ones
+        state.mk_recursive(|state, ones| {
+            state.push(ones);
+            state.push_int(1);
+            state.mk_cons();
+        });
+        let ones = *state.stack_peek();
+        state.try_eval(ones).expect("a cons");
+        assert!(state.heap_census().total_objects() > before);
+        state.stack_pop();
+        assert_eq!(state.heap_census().total_objects(), before);
+    }
+
+    fn thunk(value: i64) -> Thunk {
+        Thunk { label: None, loc: None, state: ThunkState::EThunk(Node::Int(value)) }
+    }
+
+    #[test]
+    fn pooled_thunks_reuse_freed_slots() {
+        let mut alloc = GcAlloc::new();
+        alloc.set_pool_thunks(true);
+        let first = alloc.alloc_thunk(thunk(1));
+        let second = alloc.alloc_thunk(thunk(2));
+        assert_eq!(second.addr() - first.addr(), mem::size_of::<GcObj<Thunk>>());
+
+        let mut worklist = Vec::new();
+        second.mark_into(&mut worklist);
+        alloc.collect(worklist);
+        let third = alloc.alloc_thunk(thunk(3));
+        assert_eq!(third.addr(), first.addr());
+        assert!(matches!(second.state, ThunkState::EThunk(Node::Int(2))));
+
+        for i in 0..THUNK_BLOCK_SIZE {
+            alloc.alloc_thunk(thunk(i as i64));
+        }
+        assert_eq!(alloc.thunk_pool.blocks.len(), 2);
+        alloc.collect(Vec::new());
+        assert_eq!(alloc.live(), 0);
+        assert_eq!(alloc.thunk_pool.free.len(), 2 * THUNK_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn evaluation_is_the_same_with_pooled_thunks() {
+        let mut state = State::new();
+        state.set_pool_thunks(true);
+        state.push_int(0);
+        for i in 1..=100 {
+            state.push_int(i);
+            state.stack_swap();
+            state.push_fn(FN_ADD);
+            state.mk_ap();
+            state.mk_ap();
+            state.mk_thunk();
+        }
+        let sum = *state.stack_peek();
+        assert!(matches!(state.try_eval(sum), Ok(Node::Int(5050))));
+        assert_eq!(state.stats().thunks_updated, 100);
+    }
+}
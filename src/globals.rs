@@ -0,0 +1,284 @@
+use crate::collections::HashMap;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::gc::*;
+use crate::registry;
+
+/// The top-level definitions of a program by name, so compiled code can
+/// refer to another definition without having it threaded through as an
+/// argument. Names are looked up when the reference is evaluated, so
+/// definitions may be registered in any order.
+#[derive(Default)]
+pub struct Globals {
+    defs: HashMap<&'static str, FnDef>,
+    /// The unqualified names of definitions of the modules loaded, see
+    /// `module`, by which they are found too.
+    aliases: HashMap<&'static str, &'static str>,
+    /// The names of the modules loaded.
+    modules: Vec<&'static str>,
+    /// The shared thunks of the CAFs referenced so far, kept alive
+    /// as GC roots until they are released.
+    cafs: HashMap<&'static str, Gc<Thunk>>,
+    /// How many times the definitions have changed.
+    generation: u64
+}
+
+/// The definitions of `Globals` without the CAFs evaluated on its state's
+/// heap, for another state to run the same program, e.g. on another thread.
+#[cfg(feature = "std")]
+#[derive(Clone, Default)]
+pub(crate) struct Definitions {
+    defs: HashMap<&'static str, FnDef>,
+    aliases: HashMap<&'static str, &'static str>,
+    modules: Vec<&'static str>
+}
+
+impl Globals {
+    pub fn new() -> Self {
+        Globals { defs: HashMap::new(), aliases: HashMap::new(), modules: Vec::new(), cafs: HashMap::new(), generation: 0 }
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn from_definitions(definitions: &Definitions) -> Self {
+        let Definitions { defs, aliases, modules } = definitions.clone();
+        Globals { defs, aliases, modules, ..Globals::new() }
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn definitions(&self) -> Definitions {
+        Definitions { defs: self.defs.clone(), aliases: self.aliases.clone(), modules: self.modules.clone() }
+    }
+
+    /// Changes every time a definition or module is added, removed or aliased.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Registers `fn_def` under its name, returning the definition it replaces.
+    pub fn register(&mut self, fn_def: FnDef) -> Option<FnDef> {
+        self.generation += 1;
+        self.defs.insert(fn_def.name, fn_def)
+    }
+
+    /// Removes the definition registered under `name`, returning it.
+    pub fn unregister(&mut self, name: &str) -> Option<FnDef> {
+        self.generation += 1;
+        self.defs.remove(name)
+    }
+
+    /// The definition registered under `name` or, failing that, the one
+    /// `name` is an alias of.
+    pub fn lookup(&self, name: &str) -> Option<FnDef> {
+        self.defs.get(name)
+            .or_else(|| self.aliases.get(name).and_then(|def| self.defs.get(def)))
+            .copied()
+    }
+
+    /// Makes `alias` find the definition registered under `name`.
+    pub fn alias(&mut self, alias: &'static str, name: &'static str) {
+        self.generation += 1;
+        self.aliases.insert(alias, name);
+    }
+
+    pub fn add_module(&mut self, name: &'static str) {
+        self.generation += 1;
+        self.modules.push(name);
+    }
+
+    pub fn has_module(&self, name: &str) -> bool {
+        self.modules.contains(&name)
+    }
+
+    pub fn modules(&self) -> &[&'static str] {
+        &self.modules
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.defs.keys().copied()
+    }
+
+    pub fn caf(&self, name: &str) -> Option<Gc<Thunk>> {
+        self.cafs.get(name).copied()
+    }
+
+    /// Forgets the cached value of the CAF `name`, so its memory can be
+    /// reclaimed once nothing else refers to it. It is evaluated again the
+    /// next time it is referenced.
+    pub fn release_caf(&mut self, name: &str) -> bool {
+        self.cafs.remove(name).is_some()
+    }
+
+    pub fn release_cafs(&mut self) {
+        self.cafs.clear();
+    }
+
+    pub fn mark_roots(&self, worklist: &mut Worklist) {
+        for thunk in self.cafs.values() {
+            thunk.mark_into(worklist);
+        }
+    }
+}
+
+impl State {
+    pub fn register_global(&mut self, fn_def: FnDef) {
+        self.globals.register(fn_def);
+    }
+
+    pub fn register_globals(&mut self, fn_defs: &[FnDef]) {
+        for fn_def in fn_defs {
+            self.globals.register(*fn_def);
+        }
+    }
+
+    /// The global by that name or, if there is none, the builtin, as a
+    /// symbolic reference to it is resolved.
+    pub fn lookup_fn(&self, name: &str) -> EvalResult<FnDef> {
+        self.globals.lookup(name)
+            .or_else(|| registry::builtin(name).copied())
+            .ok_or_else(|| EvalError::UnknownGlobal(name.to_string()))
+    }
+
+    /// The global `main`, that of a program loaded at run time.
+    pub fn global_main(&self) -> EvalResult<Node> {
+        match self.globals.lookup("main") {
+            Some(fn_def) => Ok(Node::FnDef(fn_def)),
+            None => Err(EvalError::UnknownGlobal("main".to_string()))
+        }
+    }
+
+    /// Pushes the global registered under `name`, shared as a CAF if it
+    /// takes no arguments.
+    pub fn push_global(&mut self, name: &str) -> EvalResult {
+        match self.globals.lookup(name) {
+            Some(fn_def) if fn_def.arity == 0 => {
+                self.push_caf(fn_def);
+                Ok(())
+            },
+            Some(fn_def) => {
+                self.push_fn(fn_def);
+                Ok(())
+            },
+            None => Err(EvalError::UnknownGlobal(name.to_string()))
+        }
+    }
+
+    /// Pushes the constant applicative form `fn_def`, a global taking no
+    /// arguments, as a thunk shared by the whole program. It is evaluated
+    /// at most once until it is released.
+    pub fn push_caf(&mut self, fn_def: FnDef) {
+        if let Some(thunk) = self.globals.caf(fn_def.name) {
+            self.stack_push(Node::ThunkRef(thunk));
+            return;
+        }
+
+        self.push_fn(fn_def);
+        self.mk_thunk();
+        self.label_thunk(fn_def.name);
+        if let Node::ThunkRef(thunk) = *self.stack_peek() {
+            self.globals.cafs.insert(fn_def.name, thunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    static EVALUATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    static FN_EXPENSIVE: FnDef = FnDef {
+        name: "expensive",
+        arity: 0,
+        strict: 0,
+        fn_ref: eval_expensive
+    };
+
+    fn eval_expensive(state: &mut State) -> EvalResult {
+        EVALUATIONS.fetch_add(1, Ordering::SeqCst);
+        state.push_int(42);
+        Ok(())
+    }
+
+    static FN_ANSWER: FnDef = FnDef {
+        name: "answer",
+        arity: 0,
+        strict: 0,
+        fn_ref: eval_answer
+    };
+
+    static FN_DOUBLE: FnDef = FnDef {
+        name: "double",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_double
+    };
+
+    fn eval_answer(state: &mut State) -> EvalResult {
+        state.push_int(21);
+        state.push_global("double")?;
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// `double x = x + x`
+    fn eval_double(state: &mut State) -> EvalResult {
+        let x = *state.stack_peek();
+        state.push(x);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    #[test]
+    fn definitions_are_found_by_name() {
+        let mut globals = Globals::new();
+        assert!(globals.register(FN_DOUBLE).is_none());
+        let generation = globals.generation();
+        assert!(globals.register(FN_DOUBLE).is_some());
+        assert!(globals.generation() > generation);
+        assert_eq!(globals.lookup("double").map(|fn_def| fn_def.arity), Some(1));
+        globals.alias("twice", "double");
+        assert_eq!(globals.lookup("twice").map(|fn_def| fn_def.name), Some("double"));
+        assert!(globals.unregister("double").is_some());
+        assert!(globals.lookup("double").is_none());
+        assert!(globals.lookup("twice").is_none());
+    }
+
+    #[test]
+    fn globals_refer_to_each_other_by_name() {
+        let mut state = State::new();
+        // double is looked up when answer is evaluated, not before
+        state.register_global(FN_ANSWER);
+        state.push_global("answer").expect("a global");
+        state.register_global(FN_DOUBLE);
+        let answer = state.stack_pop();
+        assert!(matches!(state.try_eval(answer), Ok(Node::Int(42))));
+
+        let result = state.push_global("missing");
+        assert!(matches!(result, Err(EvalError::UnknownGlobal(name)) if name == "missing"));
+        assert_eq!(state.lookup_fn("+").map(|fn_def| fn_def.name).ok(), Some("add"));
+    }
+
+    #[test]
+    fn cafs_are_evaluated_once_until_released() {
+        let mut state = State::new();
+        state.register_global(FN_EXPENSIVE);
+        for _ in 0..2 {
+            state.push_global("expensive").expect("a global");
+            let caf = state.stack_pop();
+            state.collect(Vec::new());
+            assert!(matches!(state.try_eval(caf), Ok(Node::Int(42))));
+        }
+        assert_eq!(EVALUATIONS.load(Ordering::SeqCst), 1);
+
+        assert!(state.globals.release_caf("expensive"));
+        assert!(state.globals.caf("expensive").is_none());
+        state.push_global("expensive").expect("a global");
+        let caf = state.stack_pop();
+        assert!(matches!(state.try_eval(caf), Ok(Node::Int(42))));
+        assert_eq!(EVALUATIONS.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,910 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use crate::builtins::*;
+use crate::gc::*;
+#[cfg(feature = "wasm")]
+use crate::wasm;
+
+// `data IOMode = ReadMode | WriteMode | AppendMode | ReadWriteMode`
+pub const TAG_READ_MODE: u32 = 0;
+pub const TAG_WRITE_MODE: u32 = 1;
+pub const TAG_APPEND_MODE: u32 = 2;
+pub const TAG_READ_WRITE_MODE: u32 = 3;
+
+// `data BufferMode = NoBuffering | LineBuffering | BlockBuffering (Maybe Int)`
+pub const TAG_NO_BUFFERING: u32 = 0;
+pub const TAG_LINE_BUFFERING: u32 = 1;
+pub const TAG_BLOCK_BUFFERING: u32 = 2;
+
+/// The size of a block when `BlockBuffering` does not give one.
+const DEFAULT_BLOCK_SIZE: usize = 8192;
+
+/// When output written to a handle is passed on to the stream,
+/// Haskell's `BufferMode`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferMode {
+    /// Straight away
+    Unbuffered,
+    /// At the end of every line
+    Line,
+    /// Once a block of the given size, or a default one, has been written
+    Block(Option<usize>)
+}
+
+enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File)
+}
+
+/// An open file or standard stream, referred to by `Node::Handle`.
+///
+/// Handles buffer input and output themselves, so reading and writing
+/// through them keeps its order whatever the buffer mode. A file is closed
+/// by `hClose`, once it is read to the end by `hGetContents`, or when its
+/// handle is collected, flushing what is left of its output.
+pub struct Handle {
+    /// The path the file was opened at or the stream, e.g. `<stdout>`,
+    /// shown in errors.
+    pub name: String,
+    /// `None` once the handle is closed.
+    stream: Option<Stream>,
+    readable: bool,
+    writable: bool,
+    buffering: BufferMode,
+    /// Read ahead of what has been asked for, from `input_pos` on.
+    input: Vec<u8>,
+    input_pos: usize,
+    /// Written but not passed on to the stream yet.
+    output: Vec<u8>
+}
+
+impl Handle {
+    fn new(name: &str, stream: Stream, readable: bool, writable: bool, buffering: BufferMode) -> Self {
+        Handle {
+            name: name.to_string(),
+            stream: Some(stream),
+            readable,
+            writable,
+            buffering,
+            input: Vec::new(),
+            input_pos: 0,
+            output: Vec::new()
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Handle::new("<stdin>", Stream::Stdin, true, false, BufferMode::Line)
+    }
+
+    /// Line buffered on a terminal and block buffered otherwise, as in GHC.
+    pub fn stdout() -> Self {
+        let buffering = if stdout_is_terminal() {
+            BufferMode::Line
+        } else {
+            BufferMode::Block(None)
+        };
+        Handle::new("<stdout>", Stream::Stdout, false, true, buffering)
+    }
+
+    pub fn stderr() -> Self {
+        Handle::new("<stderr>", Stream::Stderr, false, true, BufferMode::Unbuffered)
+    }
+
+    /// Opens the file at `path` in the mode of an `IOMode` tag.
+    pub fn open(path: &str, mode: u32) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        let (readable, writable) = match mode {
+            TAG_READ_MODE => (true, false),
+            TAG_WRITE_MODE => (false, true),
+            TAG_APPEND_MODE => (false, true),
+            _ => (true, true)
+        };
+        options.read(readable).write(writable);
+        match mode {
+            TAG_WRITE_MODE => options.create(true).truncate(true),
+            TAG_APPEND_MODE => options.create(true).append(true),
+            TAG_READ_WRITE_MODE => options.create(true),
+            _ => &mut options
+        };
+        let file = options.open(path)?;
+        Ok(Handle::new(path, Stream::File(file), readable, writable, BufferMode::Block(None)))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        matches!(self.stream, Some(Stream::Stdin))
+    }
+
+    pub fn buffering(&self) -> BufferMode {
+        self.buffering
+    }
+
+    /// Changes the buffer mode, first flushing what was buffered under the old one.
+    pub fn set_buffering(&mut self, buffering: BufferMode) -> io::Result<()> {
+        self.check_open()?;
+        self.flush()?;
+        self.buffering = buffering;
+        Ok(())
+    }
+
+    /// Flushes the handle and closes its stream. Closing a closed handle does nothing.
+    pub fn close(&mut self) -> io::Result<()> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        let flushed = self.flush();
+        self.stream = None;
+        self.input = Vec::new();
+        self.input_pos = 0;
+        flushed
+    }
+
+    /// Reads the next line, newline included, or `None` at the end of the stream.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.check_open()?;
+        if !self.readable {
+            return Err(illegal_operation("handle is not open for reading"));
+        }
+        loop {
+            if let Some(i) = self.input[self.input_pos..].iter().position(|&b| b == b'\n') {
+                let end = self.input_pos + i + 1;
+                return self.take_input(end).map(Some);
+            }
+            if self.fill_input()? == 0 {
+                if self.input_pos == self.input.len() {
+                    return Ok(None);
+                }
+                let end = self.input.len();
+                return self.take_input(end).map(Some);
+            }
+        }
+    }
+
+    fn take_input(&mut self, end: usize) -> io::Result<String> {
+        let line = std::str::from_utf8(&self.input[self.input_pos..end])
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid byte sequence"))?
+            .to_string();
+        self.input_pos = end;
+        Ok(line)
+    }
+
+    /// Reads another chunk of the stream into the input buffer.
+    fn fill_input(&mut self) -> io::Result<usize> {
+        self.input.drain(..self.input_pos);
+        self.input_pos = 0;
+        let mut chunk = [0; DEFAULT_BLOCK_SIZE];
+        let read = match &mut self.stream {
+            #[cfg(not(feature = "wasm"))]
+            Some(Stream::Stdin) => io::stdin().read(&mut chunk)?,
+            #[cfg(feature = "wasm")]
+            Some(Stream::Stdin) => wasm::read_stdin(&mut chunk)?,
+            Some(Stream::File(file)) => file.read(&mut chunk)?,
+            _ => 0
+        };
+        self.input.extend_from_slice(&chunk[..read]);
+        Ok(read)
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        match self.stream {
+            Some(_) => Ok(()),
+            None => Err(illegal_operation("handle is closed"))
+        }
+    }
+}
+
+/// Writes a line of the runtime's own to the process's stdout, around the
+/// program's `stdout` handle, e.g. the value of a program that is not IO.
+#[cfg(not(feature = "wasm"))]
+pub(crate) fn print_line(line: &str) {
+    println!("{}", line);
+}
+
+/// Writes a line of the runtime's own to the process's stderr, around the
+/// program's `stderr` handle, e.g. a `trace` message.
+#[cfg(not(feature = "wasm"))]
+pub(crate) fn eprint_line(line: &str) {
+    eprintln!("{}", line);
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn print_line(line: &str) {
+    let _ = wasm::write_stdout(format!("{}\n", line).as_bytes());
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn eprint_line(line: &str) {
+    let _ = wasm::write_stderr(format!("{}\n", line).as_bytes());
+}
+
+#[cfg(not(feature = "wasm"))]
+fn stdout_is_terminal() -> bool {
+    use std::io::IsTerminal;
+    io::stdout().is_terminal()
+}
+
+/// The page's console is written to a line at a time.
+#[cfg(feature = "wasm")]
+fn stdout_is_terminal() -> bool {
+    true
+}
+
+fn illegal_operation(reason: &str) -> io::Error {
+    io::Error::other(format!("illegal operation ({})", reason))
+}
+
+impl Write for Handle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_open()?;
+        if !self.writable {
+            return Err(illegal_operation("handle is not open for writing"));
+        }
+        self.output.extend_from_slice(buf);
+        let flush = match self.buffering {
+            BufferMode::Unbuffered => true,
+            BufferMode::Line => buf.contains(&b'\n'),
+            BufferMode::Block(size) => self.output.len() >= size.unwrap_or(DEFAULT_BLOCK_SIZE)
+        };
+        if flush {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        match &mut self.stream {
+            #[cfg(not(feature = "wasm"))]
+            Some(Stream::Stdout) => {
+                let mut out = io::stdout();
+                out.write_all(&self.output)?;
+                out.flush()?;
+            },
+            #[cfg(not(feature = "wasm"))]
+            Some(Stream::Stderr) => io::stderr().write_all(&self.output)?,
+            #[cfg(feature = "wasm")]
+            Some(Stream::Stdout) => wasm::write_stdout(&self.output)?,
+            #[cfg(feature = "wasm")]
+            Some(Stream::Stderr) => wasm::write_stderr(&self.output)?,
+            Some(Stream::File(file)) => file.write_all(&self.output)?,
+            _ => {}
+        }
+        self.output.clear();
+        Ok(())
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Trace for Handle {
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("handle", None)
+    }
+}
+
+/// The handles of the standard streams, made on first use.
+pub struct StdHandles {
+    pub stdin: Gc<Handle>,
+    pub stdout: Gc<Handle>,
+    pub stderr: Gc<Handle>
+}
+
+impl State {
+    pub fn push_handle(&mut self, handle: Handle) {
+        let handle = self.alloc(handle);
+        self.stack_push(Node::Handle(handle));
+    }
+
+    pub fn std_handles(&mut self) -> &StdHandles {
+        if self.stdio.is_none() {
+            let stdin = self.alloc(Handle::stdin());
+            self.stack_push(Node::Handle(stdin));
+            let stdout = self.alloc(Handle::stdout());
+            self.stack_push(Node::Handle(stdout));
+            let stderr = self.alloc(Handle::stderr());
+            self.stack_pop();
+            self.stack_pop();
+            self.stdio = Some(StdHandles { stdin, stdout, stderr });
+        }
+        self.stdio.as_ref().unwrap()
+    }
+
+    /// Flushes stdout and stderr, as is done when the program exits.
+    pub fn flush_std_handles(&mut self) -> EvalResult {
+        if let Some(stdio) = &mut self.stdio {
+            for handle in [&mut stdio.stdout, &mut stdio.stderr] {
+                let name = handle.name.clone();
+                handle.as_mut().flush().map_err(|err| IoException::new(&name, "hFlush", &err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forces the handle on top of the stack, popping it.
+    fn pop_handle(&mut self, context: &'static str) -> EvalResult<Gc<Handle>> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::Handle(handle) => Ok(handle),
+            node => Err(EvalError::type_mismatch(context, "handle", &node))
+        }
+    }
+
+    /// Reads a line from `handle`, first flushing stdout if it is stdin so
+    /// that a prompt written so far shows before waiting on input.
+    pub fn read_handle_line(&mut self, mut handle: Gc<Handle>, operation: &'static str) -> EvalResult<Option<String>> {
+        if handle.is_stdin() {
+            let mut stdout = self.std_handles().stdout;
+            stdout.as_mut().flush().map_err(|err| IoException::new("<stdout>", "hFlush", &err))?;
+        }
+        let name = handle.name.clone();
+        Ok(handle.as_mut().read_line().map_err(|err| IoException::new(&name, operation, &err))?)
+    }
+
+    /// Writes the string on top of the stack to `handle` as it is forced.
+    /// The handle stays reachable from the stack while it is being written to.
+    pub fn write_handle(&mut self, mut handle: Gc<Handle>, operation: &'static str) -> EvalResult {
+        self.stack_push(Node::Handle(handle));
+        self.stack_swap();
+        let name = handle.name.clone();
+        self.write_string(handle.as_mut(), &name, operation)?;
+        self.stack_pop();
+        Ok(())
+    }
+
+    /// Pushes the lazily read rest of the handle on top of the stack.
+    pub fn mk_handle_contents(&mut self) {
+        self.push_fn(HANDLE_CONTENTS);
+        self.mk_ap();
+        self.mk_thunk();
+    }
+
+    /// Writes the string on top of the stack to the file at `path` as it is
+    /// forced, replacing the file or appending to it.
+    fn write_file(&mut self, path: &str, mode: u32, operation: &'static str) -> EvalResult {
+        let handle = Handle::open(path, mode).map_err(|err| IoException::new(path, "openFile", &err))?;
+        // the string stays on top, the handle only needs to outlive writing it
+        let mut handle = self.alloc(handle);
+        self.write_handle(handle, operation)?;
+        handle.as_mut().close().map_err(|err| IoException::new(path, operation, &err))?;
+        Ok(())
+    }
+}
+
+fn pop_buffer_mode(state: &mut State) -> EvalResult<BufferMode> {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Data { tag: TAG_NO_BUFFERING, .. } => Ok(BufferMode::Unbuffered),
+        Node::Data { tag: TAG_LINE_BUFFERING, .. } => Ok(BufferMode::Line),
+        Node::Data { tag: TAG_BLOCK_BUFFERING, fields } if fields.len() == 1 => {
+            state.stack_push(fields[0]);
+            if !state.pop_maybe()? {
+                return Ok(BufferMode::Block(None));
+            }
+            state.eval()?;
+            match state.stack_pop() {
+                Node::Int(size) if size > 0 => Ok(BufferMode::Block(Some(size as usize))),
+                node => Err(EvalError::type_mismatch("hSetBuffering", "positive Int block size", &node))
+            }
+        },
+        node => Err(EvalError::type_mismatch("hSetBuffering", "BufferMode", &node))
+    }
+}
+
+fn pop_io_mode(state: &mut State) -> EvalResult<u32> {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Data { tag, fields } if tag <= TAG_READ_WRITE_MODE && fields.is_empty() => Ok(tag),
+        node => Err(EvalError::type_mismatch("openFile", "IOMode", &node))
+    }
+}
+
+fn io_error<'a>(handle: &'a Handle, operation: &'static str) -> impl Fn(io::Error) -> EvalError + 'a {
+    move |err| IoException::new(&handle.name, operation, &err).into()
+}
+
+pub static FN_STDIN: FnDef = FnDef {
+    name: "stdin",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_stdin
+};
+
+pub static FN_STDOUT: FnDef = FnDef {
+    name: "stdout",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_stdout
+};
+
+pub static FN_STDERR: FnDef = FnDef {
+    name: "stderr",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_stderr
+};
+
+pub static FN_OPEN_FILE: FnDef = FnDef {
+    name: "openFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_open_file
+};
+
+pub static FN_H_CLOSE: FnDef = FnDef {
+    name: "hClose",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_h_close
+};
+
+pub static FN_H_FLUSH: FnDef = FnDef {
+    name: "hFlush",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_h_flush
+};
+
+pub static FN_H_SET_BUFFERING: FnDef = FnDef {
+    name: "hSetBuffering",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_h_set_buffering
+};
+
+pub static FN_H_GET_LINE: FnDef = FnDef {
+    name: "hGetLine",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_h_get_line
+};
+
+pub static FN_H_GET_CONTENTS: FnDef = FnDef {
+    name: "hGetContents",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_h_get_contents
+};
+
+pub static FN_H_PUT_STR: FnDef = FnDef {
+    name: "hPutStr",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_h_put_str
+};
+
+pub static FN_H_PUT_STR_LN: FnDef = FnDef {
+    name: "hPutStrLn",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_h_put_str_ln
+};
+
+pub static FN_READ_FILE: FnDef = FnDef {
+    name: "readFile",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read_file
+};
+
+pub static FN_WRITE_FILE: FnDef = FnDef {
+    name: "writeFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_write_file
+};
+
+pub static FN_APPEND_FILE: FnDef = FnDef {
+    name: "appendFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_append_file
+};
+
+static IO_OPEN_FILE: FnDef = FnDef {
+    name: "openFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_open_file
+};
+
+static IO_H_CLOSE: FnDef = FnDef {
+    name: "hClose",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_h_close
+};
+
+static IO_H_FLUSH: FnDef = FnDef {
+    name: "hFlush",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_h_flush
+};
+
+static IO_H_SET_BUFFERING: FnDef = FnDef {
+    name: "hSetBuffering",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_h_set_buffering
+};
+
+pub(crate) static IO_H_GET_LINE: FnDef = FnDef {
+    name: "hGetLine",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_h_get_line
+};
+
+pub(crate) static IO_H_GET_CONTENTS: FnDef = FnDef {
+    name: "hGetContents",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_h_get_contents
+};
+
+pub(crate) static IO_H_PUT_STR: FnDef = FnDef {
+    name: "hPutStr",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_h_put_str
+};
+
+pub(crate) static IO_H_PUT_STR_LN: FnDef = FnDef {
+    name: "hPutStrLn",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_h_put_str_ln
+};
+
+static IO_READ_FILE: FnDef = FnDef {
+    name: "readFile",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_read_file
+};
+
+static IO_WRITE_FILE: FnDef = FnDef {
+    name: "writeFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_write_file
+};
+
+static IO_APPEND_FILE: FnDef = FnDef {
+    name: "appendFile",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_append_file
+};
+
+/// The rest of what can be read from a handle, a line at a time as far as it is forced.
+static HANDLE_CONTENTS: FnDef = FnDef {
+    name: "hGetContents",
+    arity: 1,
+    strict: 0,
+    fn_ref: read_handle_contents
+};
+
+pub fn eval_stdin(state: &mut State) -> EvalResult {
+    let handle = state.std_handles().stdin;
+    state.stack_push(Node::Handle(handle));
+    Ok(())
+}
+
+pub fn eval_stdout(state: &mut State) -> EvalResult {
+    let handle = state.std_handles().stdout;
+    state.stack_push(Node::Handle(handle));
+    Ok(())
+}
+
+pub fn eval_stderr(state: &mut State) -> EvalResult {
+    let handle = state.std_handles().stderr;
+    state.stack_push(Node::Handle(handle));
+    Ok(())
+}
+
+pub fn eval_open_file(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_OPEN_FILE);
+    Ok(())
+}
+
+pub fn eval_h_close(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_CLOSE);
+    Ok(())
+}
+
+pub fn eval_h_flush(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_FLUSH);
+    Ok(())
+}
+
+pub fn eval_h_set_buffering(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_SET_BUFFERING);
+    Ok(())
+}
+
+pub fn eval_h_get_line(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_GET_LINE);
+    Ok(())
+}
+
+pub fn eval_h_get_contents(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_GET_CONTENTS);
+    Ok(())
+}
+
+pub fn eval_h_put_str(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_PUT_STR);
+    Ok(())
+}
+
+pub fn eval_h_put_str_ln(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_H_PUT_STR_LN);
+    Ok(())
+}
+
+pub fn eval_read_file(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_READ_FILE);
+    Ok(())
+}
+
+pub fn eval_write_file(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_WRITE_FILE);
+    Ok(())
+}
+
+pub fn eval_append_file(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_APPEND_FILE);
+    Ok(())
+}
+
+fn perform_open_file(state: &mut State) -> EvalResult {
+    let path = state.eval_string()?;
+    let mode = pop_io_mode(state)?;
+    let handle = Handle::open(&path, mode).map_err(|err| IoException::new(&path, "openFile", &err))?;
+    state.push_handle(handle);
+    Ok(())
+}
+
+fn perform_h_close(state: &mut State) -> EvalResult {
+    let mut handle = state.pop_handle("hClose")?;
+    handle.as_mut().close().map_err(io_error(&handle, "hClose"))?;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_h_flush(state: &mut State) -> EvalResult {
+    let mut handle = state.pop_handle("hFlush")?;
+    handle.as_mut().check_open().and_then(|_| handle.as_mut().flush()).map_err(io_error(&handle, "hFlush"))?;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_h_set_buffering(state: &mut State) -> EvalResult {
+    state.eval()?;
+    state.stack_swap();
+    let buffering = pop_buffer_mode(state)?;
+    let mut handle = state.pop_handle("hSetBuffering")?;
+    handle.as_mut().set_buffering(buffering).map_err(io_error(&handle, "hSetBuffering"))?;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_h_get_line(state: &mut State) -> EvalResult {
+    let handle = state.pop_handle("hGetLine")?;
+    let mut line = match state.read_handle_line(handle, "hGetLine")? {
+        Some(line) => line,
+        None => return Err(IoException {
+            location: handle.name.clone(),
+            operation: "hGetLine".into(),
+            kind: io::ErrorKind::UnexpectedEof,
+            description: "end of file".to_string()
+        }.into())
+    };
+    if line.ends_with('\n') {
+        line.pop();
+    }
+    state.push_str(&line);
+    Ok(())
+}
+
+fn perform_h_get_contents(state: &mut State) -> EvalResult {
+    state.mk_handle_contents();
+    Ok(())
+}
+
+fn perform_h_put_str(state: &mut State) -> EvalResult {
+    let handle = state.pop_handle("hPutStr")?;
+    state.write_handle(handle, "hPutStr")?;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_h_put_str_ln(state: &mut State) -> EvalResult {
+    let mut handle = state.pop_handle("hPutStr")?;
+    state.write_handle(handle, "hPutStr")?;
+    handle.as_mut().write_all(b"\n").map_err(io_error(&handle, "hPutStr"))?;
+    state.push_unit();
+    Ok(())
+}
+
+/// Opens the file straight away, so a missing file fails here rather than
+/// wherever the contents happen to be forced, but reads none of it yet.
+fn perform_read_file(state: &mut State) -> EvalResult {
+    let path = state.eval_string()?;
+    let handle = Handle::open(&path, TAG_READ_MODE).map_err(|err| IoException::new(&path, "openFile", &err))?;
+    state.push_handle(handle);
+    state.mk_handle_contents();
+    Ok(())
+}
+
+fn perform_write_file(state: &mut State) -> EvalResult {
+    let path = state.eval_string()?;
+    state.write_file(&path, TAG_WRITE_MODE, "writeFile")?;
+    state.push_unit();
+    Ok(())
+}
+
+fn perform_append_file(state: &mut State) -> EvalResult {
+    let path = state.eval_string()?;
+    state.write_file(&path, TAG_APPEND_MODE, "appendFile")?;
+    state.push_unit();
+    Ok(())
+}
+
+/// A line of the handle in front of a thunk reading the rest, or `[]` at
+/// the end, closing the handle. Each thunk is forced at most once, so the
+/// handle is read in the order the string is consumed.
+fn read_handle_contents(state: &mut State) -> EvalResult {
+    state.eval()?;
+    let mut handle = match *state.stack_peek() {
+        Node::Handle(handle) => handle,
+        node => return Err(EvalError::type_mismatch("hGetContents", "handle", &node))
+    };
+    match state.read_handle_line(handle, "hGetContents")? {
+        Some(line) => {
+            state.mk_handle_contents();
+            state.push_str_onto(&line);
+        },
+        None => {
+            state.stack_pop();
+            if !handle.is_stdin() {
+                handle.as_mut().close().map_err(io_error(&handle, "hGetContents"))?;
+            }
+            state.push_nil();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::*;
+    use crate::testing::*;
+    use super::*;
+
+    /// Pushes `fn_def path` applied to the string values given.
+    fn push_file_action(state: &mut State, fn_def: FnDef, path: &str, args: &[&str]) {
+        for arg in args.iter().rev() {
+            push_value(state, arg);
+        }
+        push_value(state, &format!("{:?}", path));
+        state.push_fn(fn_def);
+        for _ in 0..=args.len() {
+            state.mk_ap();
+        }
+    }
+
+    /// `m >> n`, for `n` on top of the stack and `m` below it.
+    fn mk_then(state: &mut State) {
+        state.stack_swap();
+        state.push_fn(FN_THEN_IO);
+        state.mk_ap();
+        state.mk_ap();
+    }
+
+    #[test]
+    fn files_are_written_appended_to_and_read() {
+        let path = temp_path("files");
+        let mut state = State::new();
+        push_file_action(&mut state, FN_WRITE_FILE, &path, &["\"ab\""]);
+        push_file_action(&mut state, FN_APPEND_FILE, &path, &["\"cd\\n\""]);
+        mk_then(&mut state);
+        push_file_action(&mut state, FN_READ_FILE, &path, &[]);
+        mk_then(&mut state);
+        let main = state.stack_pop();
+        let contents = state.run_io(main).and_then(|contents| state.render(contents));
+        assert_eq!(contents.ok().as_deref(), Some("\"abcd\\n\""));
+        assert_eq!(std::fs::read_to_string(&path).expect("the file"), "abcd\n");
+        std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn missing_files_are_io_exceptions() {
+        let path = temp_path("missing");
+        let mut state = State::new();
+        push_file_action(&mut state, FN_READ_FILE, &path, &[]);
+        let main = state.stack_pop();
+        let err = state.run_io(main).expect_err("no such file");
+        let EvalError::Io(exception) = err.cause() else {
+            panic!("an IO exception, not {}", err);
+        };
+        assert_eq!(exception.location, path);
+        assert_eq!(exception.operation, "openFile");
+        assert_eq!(exception.kind, io::ErrorKind::NotFound);
+        assert_eq!(state.stack_size(), 0);
+    }
+
+    #[test]
+    fn output_is_passed_on_as_the_buffer_mode_says() {
+        let path = temp_path("buffering");
+        let written = || std::fs::read_to_string(&path).expect("the file");
+        let mut handle = Handle::open(&path, TAG_WRITE_MODE).expect("an open file");
+        assert_eq!(handle.buffering(), BufferMode::Block(None));
+        handle.write_all(b"a\n").expect("written");
+        assert_eq!(written(), "");
+        handle.flush().expect("flushed");
+        assert_eq!(written(), "a\n");
+
+        handle.set_buffering(BufferMode::Line).expect("line buffered");
+        handle.write_all(b"b").expect("written");
+        assert_eq!(written(), "a\n");
+        handle.write_all(b"\n").expect("written");
+        assert_eq!(written(), "a\nb\n");
+
+        handle.set_buffering(BufferMode::Block(Some(2))).expect("block buffered");
+        handle.write_all(b"c").expect("written");
+        assert_eq!(written(), "a\nb\n");
+        handle.write_all(b"d").expect("written");
+        assert_eq!(written(), "a\nb\ncd");
+
+        handle.write_all(b"e").expect("written");
+        handle.close().expect("closed");
+        assert_eq!(written(), "a\nb\ncde");
+        assert!(handle.is_closed());
+        assert_eq!(handle.write(b"f").expect_err("closed").to_string(), "illegal operation (handle is closed)");
+        handle.close().expect("closing again does nothing");
+        std::fs::remove_file(&path).expect("removed");
+    }
+
+    #[test]
+    fn lines_are_read_with_their_newlines() {
+        let path = temp_path("lines");
+        std::fs::write(&path, "one\ntwo").expect("a file");
+        let mut handle = Handle::open(&path, TAG_READ_MODE).expect("an open file");
+        assert_eq!(handle.read_line().expect("a line").as_deref(), Some("one\n"));
+        assert_eq!(handle.read_line().expect("a line").as_deref(), Some("two"));
+        assert_eq!(handle.read_line().expect("the end"), None);
+        assert!(handle.write(b"x").is_err());
+
+        // openFile path ReadMode >>= hGetLine
+        let mut state = State::new();
+        state.push_fn(FN_H_GET_LINE);
+        state.mk_data(TAG_READ_MODE, 0);
+        push_value(&mut state, &format!("{:?}", path));
+        state.push_fn(FN_OPEN_FILE);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_BIND_IO);
+        state.mk_ap();
+        state.mk_ap();
+        let main = state.stack_pop();
+        let line = state.run_io(main).and_then(|line| state.render(line));
+        assert_eq!(line.ok().as_deref(), Some("\"one\""));
+        std::fs::remove_file(&path).expect("removed");
+    }
+}
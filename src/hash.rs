@@ -0,0 +1,248 @@
+use core::hash::Hasher;
+use crate::builtins::*;
+use crate::bigint::BigInt;
+
+// Hashing values in normal form, so that they can key the host's hash
+// maps. Values equal as `deepEq` has them hash the same, and the hash of
+// a value is the same from run to run and build to build: it is 64-bit
+// FNV-1a over an encoding of the value, in which each node is a byte for
+// its kind followed by what it holds, integers little-endian:
+//
+//     Int         0x01, the i64
+//     Integer     0x02, a byte 1 if negative or 0, the digit count as a u64
+//                 and the base 2^32 digits as u32s, least significant first
+//     Rational    0x03, the numerator and the denominator as Integers
+//     Double      0x04, the bits as a u64, -0.0 as 0.0 and every NaN as one
+//     Bool        0x05, a byte 1 or 0
+//     Char        0x06, the code point as a u32
+//     Text        0x07, the byte count as a u64 and the UTF-8 bytes
+//     []          0x08
+//     (:)         0x09, the head and then the tail
+//     constructor 0x0a, the tag as a u32, the field count as a u64 and the fields
+//     tuple       0x0b, the field count as a u64 and the fields
+//     array       0x0c, the bounds as i64s and the elements
+//     map         0x0d, the entry count as a u64 and each key and its value
+//     set         0x0e, the key count as a u64 and the keys
+//
+// Functions, IO actions and mutable cells have no structure to hash and
+// are an error, as they are to compare.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub(crate) struct Fnv(u64);
+
+/// For hashing what the encoding doesn't cover, such as the identities
+/// of stable names, which needn't be the same from run to run.
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Fnv {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.bytes(&[byte]);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    fn integer(&mut self, integer: &BigInt) {
+        let digits = integer.digits();
+        self.byte(integer.is_negative() as u8);
+        self.len(digits.len());
+        for digit in digits {
+            self.u32(digit);
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> EvalResult {
+        let mut node = node.short_circuit();
+        // down the spine of a list in a loop, so a long one doesn't overflow
+        while let Node::Cons(head, tail) = node {
+            self.byte(0x09);
+            self.node(&head)?;
+            node = tail.short_circuit();
+        }
+        match node {
+            Node::Int(i) => {
+                self.byte(0x01);
+                self.u64(i as u64);
+            },
+            Node::Integer(integer) => {
+                self.byte(0x02);
+                self.integer(&integer);
+            },
+            Node::Ratio(ratio) => {
+                self.byte(0x03);
+                self.integer(ratio.numerator());
+                self.integer(ratio.denominator());
+            },
+            Node::Double(x) => {
+                self.byte(0x04);
+                let bits = if x == 0.0 { 0 } else if x.is_nan() { f64::NAN.to_bits() } else { x.to_bits() };
+                self.u64(bits);
+            },
+            Node::Bool(b) => {
+                self.byte(0x05);
+                self.byte(b as u8);
+            },
+            Node::Char(c) => {
+                self.byte(0x06);
+                self.u32(c as u32);
+            },
+            Node::Text(text) => {
+                self.byte(0x07);
+                self.len(text.len());
+                self.bytes(text.as_bytes());
+            },
+            Node::Nil => self.byte(0x08),
+            Node::Data { tag, fields } => {
+                self.byte(0x0a);
+                self.u32(tag);
+                self.fields(&fields)?;
+            },
+            Node::Tuple(fields) => {
+                self.byte(0x0b);
+                self.fields(&fields)?;
+            },
+            Node::Array(array) => {
+                self.byte(0x0c);
+                self.u64(array.lo as u64);
+                self.u64(array.hi as u64);
+                for elem in &array.elems {
+                    self.node(elem)?;
+                }
+            },
+            Node::Map(tree) => {
+                let entries = tree.entries();
+                self.byte(0x0d);
+                self.len(entries.len());
+                for (key, value) in &entries {
+                    self.node(key)?;
+                    self.node(value)?;
+                }
+            },
+            Node::Set(tree) => {
+                let entries = tree.entries();
+                self.byte(0x0e);
+                self.len(entries.len());
+                for (key, _) in &entries {
+                    self.node(key)?;
+                }
+            },
+            node => return Err(EvalError::type_mismatch("hash", "hashable value", &node))
+        }
+        Ok(())
+    }
+
+    fn fields(&mut self, fields: &Fields) -> EvalResult {
+        self.len(fields.len());
+        for field in fields {
+            self.node(field)?;
+        }
+        Ok(())
+    }
+}
+
+/// The hash of a value in normal form, see above.
+pub fn hash_value(node: &Node) -> EvalResult<u64> {
+    let mut fnv = Fnv::default();
+    fnv.node(node)?;
+    Ok(fnv.0)
+}
+
+impl State {
+    /// Evaluates `node` to normal form and hashes it, see `hash_value`.
+    pub fn hash_node(&mut self, node: Node) -> EvalResult<u64> {
+        let frame_size = self.stack_size();
+        self.stack_push(node);
+        match self.deep_eval() {
+            Ok(()) => hash_value(&self.stack_pop()),
+            Err(err) => {
+                self.stacks.last_mut().unwrap().truncate(frame_size);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::push_value;
+
+    fn hash(s: &str) -> u64 {
+        let mut state = State::new();
+        push_value(&mut state, s);
+        let node = state.stack_pop();
+        state.hash_node(node).expect("a hash")
+    }
+
+    #[test]
+    fn hashes_are_the_same_from_build_to_build() {
+        assert_eq!(hash("1"), 0x7194_f3e5_9ae4_7dcd);
+        assert_eq!(hash("[]"), 0xaf63_c54c_8601_c577);
+        assert_eq!(hash("[1]"), 0xa2d2_3e4a_da75_f982);
+        assert_eq!(hash("Just 'a'"), 0x204a_0e15_11a9_d420);
+    }
+
+    #[test]
+    fn values_hash_by_their_structure() {
+        assert_eq!(hash("(1, [True])"), hash("( 1 , [ True ] )"));
+        assert_ne!(hash("(1, 2)"), hash("(2, 1)"));
+        assert_ne!(hash("[1, 2]"), hash("(1, 2)"));
+        assert_ne!(hash("Left 1"), hash("Right 1"));
+        assert_eq!(hash("0.0"), hash("-0.0"));
+        let nan = |bits: u64| hash_value(&Node::Double(f64::from_bits(bits))).expect("a hash");
+        assert_eq!(nan(f64::NAN.to_bits()), nan(0xfff8_0000_0000_0001));
+    }
+
+    #[test]
+    fn values_are_forced_before_hashing() {
+        let mut state = State::new();
+        // [2 + 1]
+        state.push_nil();
+        state.push_int(1);
+        state.push_int(2);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.mk_cons();
+        let list = state.stack_pop();
+        assert_eq!(state.hash_node(list).expect("a hash"), hash("[3]"));
+
+        state.push_int(1);
+        let err = state.hash_node(Node::FnDef(FN_ADD)).expect_err("a function");
+        assert!(matches!(err, EvalError::TypeMismatch { .. }), "{}", err);
+        assert_eq!(state.stack_size(), 1);
+    }
+}
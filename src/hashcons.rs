@@ -0,0 +1,148 @@
+use crate::collections::HashMap;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::gc::*;
+use crate::stable::Identity;
+
+// With hash-consing on, the fields of constructors and the heads and tails
+// of list cells are looked up by the identities of what they hold before
+// being allocated, and an object already holding the same is shared
+// instead. Only evaluated nodes are: an unevaluated one has an identity but
+// no structure to be the same as. Built bottom up, identical values then
+// come out as one. Ints and characters are immediate and shared as they
+// are, it is the cells and constructors holding them that are saved.
+//
+// The table doesn't keep what it holds alive: entries for objects a
+// collection finds unreachable are dropped before they are swept.
+
+/// Constructors with more fields than this are allocated as they are, so
+/// are unlikely to be repeated to be worth looking up.
+const MAX_SHARED_ARITY: usize = 4;
+
+#[derive(Default)]
+pub(crate) struct HashCons {
+    fields: HashMap<Vec<Identity>, Gc<Fields>>,
+    nodes: HashMap<Identity, Gc<Node>>
+}
+
+/// The identity of `node` if it can be shared, i.e. it is evaluated.
+fn key(node: &Node) -> Option<Identity> {
+    node.short_circuit().is_whnf().then(|| Identity::of(*node))
+}
+
+impl HashCons {
+    /// Allocates `fields`, or gives an object already holding the same,
+    /// and whether it did the latter.
+    pub(crate) fn share_fields(&mut self, alloc: &mut GcAlloc, fields: Fields) -> (Gc<Fields>, bool) {
+        let key = match fields.len() <= MAX_SHARED_ARITY {
+            true => fields.iter().map(key).collect::<Option<Vec<_>>>(),
+            false => None
+        };
+        let Some(key) = key else {
+            return (alloc.alloc(fields), false);
+        };
+        if let Some(shared) = self.fields.get(&key) {
+            return (*shared, true);
+        }
+        let fields = alloc.alloc(fields);
+        self.fields.insert(key, fields);
+        (fields, false)
+    }
+
+    /// Allocates `node`, or gives an object already holding the same, and
+    /// whether it did the latter.
+    pub(crate) fn share_node(&mut self, alloc: &mut GcAlloc, node: Node) -> (Gc<Node>, bool) {
+        let Some(key) = key(&node) else {
+            return (alloc.alloc(node), false);
+        };
+        if let Some(shared) = self.nodes.get(&key) {
+            return (*shared, true);
+        }
+        let node = alloc.alloc(node);
+        self.nodes.insert(key, node);
+        (node, false)
+    }
+
+    /// Drops the entries for the objects the collection in progress has
+    /// not marked, once everything reachable is.
+    pub(crate) fn prune(&mut self) {
+        self.fields.retain(|_, fields| fields.is_marked());
+        self.nodes.retain(|_, node| node.is_marked());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::maybe::TAG_JUST;
+
+    /// Pushes the list `[1, 2]`, built from the end.
+    fn push_list(state: &mut State) {
+        state.push_nil();
+        state.push_int(2);
+        state.mk_cons();
+        state.push_int(1);
+        state.mk_cons();
+    }
+
+    fn push_sum(state: &mut State) {
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+    }
+
+    #[test]
+    fn identical_values_are_shared() {
+        let mut state = State::new();
+        state.set_hash_consing(true);
+        push_list(&mut state);
+        push_list(&mut state);
+        assert!(state.stack_peek().ptr_eq(state.stack_peek_nth(1)));
+        state.push_int(1);
+        state.mk_just();
+        state.push_int(1);
+        state.mk_just();
+        assert!(state.stack_peek().ptr_eq(state.stack_peek_nth(1)));
+        // the head and tail of both list cells, and the fields of the second Just
+        assert_eq!(state.stats().objects_shared, 5);
+    }
+
+    #[test]
+    fn only_evaluated_values_are_shared() {
+        let mut state = State::new();
+        state.set_hash_consing(true);
+        push_sum(&mut state);
+        state.push(*state.stack_peek());
+        state.mk_just();
+        state.push(*state.stack_peek_nth(1));
+        state.mk_just();
+        assert!(!state.stack_peek().ptr_eq(state.stack_peek_nth(1)));
+        assert_eq!(state.stats().objects_shared, 0);
+    }
+
+    #[test]
+    fn values_are_not_shared_without_hash_consing() {
+        let mut state = State::new();
+        push_list(&mut state);
+        push_list(&mut state);
+        assert!(!state.stack_peek().ptr_eq(state.stack_peek_nth(1)));
+        assert_eq!(state.stats().objects_shared, 0);
+    }
+
+    #[test]
+    fn collected_values_are_not_shared() {
+        let mut state = State::new();
+        state.set_hash_consing(true);
+        state.push_int(1);
+        state.mk_just();
+        state.stack_pop();
+        state.collect(Vec::new());
+        state.push_int(1);
+        state.mk_just();
+        assert!(matches!(state.stack_peek(), Node::Data { tag: TAG_JUST, .. }));
+        assert_eq!(state.stats().objects_shared, 0);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::builtins::*;
+
+// The heap can't refuse an allocation, the nodes being built have nowhere
+// to go, so going over the limit is noted by the collection that finds it
+// and `HeapOverflow` raised at the next call, where the program can catch
+// it like any exception. The handler is given some grace to recover in:
+// the heap may grow another `HEAP_OVERFLOW_GRACE` bytes before it is raised
+// again, or for good once it is back under the limit.
+
+/// How far past its limit the heap may grow while the program is handling
+/// the `HeapOverflow` it was given.
+const HEAP_OVERFLOW_GRACE: usize = 1 << 20;
+
+#[derive(Clone, Copy)]
+pub(crate) struct HeapLimit {
+    max: usize,
+    /// What the heap has to grow past for `HeapOverflow` to be raised.
+    threshold: usize,
+    /// Whether it has, and the exception is yet to be raised.
+    overflowed: bool
+}
+
+impl HeapLimit {
+    /// Notes the size of the heap once a collection is over.
+    pub(crate) fn observe(&mut self, live_bytes: usize) {
+        if live_bytes <= self.max {
+            self.threshold = self.max;
+        } else if live_bytes > self.threshold {
+            self.overflowed = true;
+        }
+    }
+}
+
+impl State {
+    /// Limits the heap to about `max_bytes`, as counted by `Trace::heap_size`,
+    /// or lifts the limit if `None`. A program whose heap grows past it is
+    /// given a `HeapOverflow` exception.
+    pub fn set_max_heap(&mut self, max_bytes: Option<usize>) {
+        self.heap_limit = max_bytes.map(|max| HeapLimit { max, threshold: max, overflowed: false });
+    }
+
+    /// Fails with `EvalError::HeapOverflow` if the heap has grown past its limit.
+    pub(crate) fn check_heap(&mut self) -> EvalResult {
+        match &mut self.heap_limit {
+            Some(limit) if limit.overflowed => {
+                limit.overflowed = false;
+                limit.threshold = limit.max + HEAP_OVERFLOW_GRACE;
+                Err(EvalError::HeapOverflow)
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes a list of `len` Ints, all of it allocated.
+    fn push_list(state: &mut State, len: i64) {
+        state.push_nil();
+        for i in 0..len {
+            state.push_int(i);
+            state.mk_cons();
+        }
+    }
+
+    /// Evaluates `1 + 2`, leaving the stack as it was.
+    fn sum(state: &mut State) -> EvalResult<String> {
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        let expr = state.stack_pop();
+        let result = state.try_eval(expr)?;
+        state.render(result)
+    }
+
+    #[test]
+    fn the_heap_overflows_at_the_next_call() {
+        let mut state = State::new();
+        state.set_max_heap(Some(1024));
+        assert_eq!(sum(&mut state).expect("a small heap"), "3");
+
+        push_list(&mut state, 1000);
+        let err = sum(&mut state).expect_err("a heap overflow");
+        assert!(matches!(err.cause(), EvalError::HeapOverflow));
+        // the handler is given some grace
+        assert_eq!(sum(&mut state).expect("within the grace"), "3");
+
+        state.stack_pop();
+        state.collect(Vec::new());
+        assert_eq!(sum(&mut state).expect("back under the limit"), "3");
+        push_list(&mut state, 1000);
+        let err = sum(&mut state).expect_err("a heap overflow");
+        assert!(matches!(err.cause(), EvalError::HeapOverflow));
+    }
+
+    #[test]
+    fn lifting_the_limit_lets_the_heap_grow() {
+        let mut state = State::new();
+        state.set_max_heap(Some(1024));
+        state.set_max_heap(None);
+        push_list(&mut state, 1000);
+        assert_eq!(sum(&mut state).expect("no limit"), "3");
+    }
+}
@@ -0,0 +1,126 @@
+use crate::builtins::{FnDef, Thunk};
+use crate::gc::Gc;
+
+// Reports reduction to the `tracing` crate with the `tracing` feature
+// enabled, and compiles to nothing otherwise. Thunks are identified by
+// their address so the events for one can be matched up.
+
+#[cfg(feature = "tracing")]
+pub use tracing::span::EnteredSpan as CallSpan;
+
+#[cfg(not(feature = "tracing"))]
+pub struct CallSpan;
+
+#[cfg(feature = "tracing")]
+pub fn thunk_allocated(thunk: &Gc<Thunk>) {
+    tracing::trace!(thunk = thunk.addr(), "thunk allocated");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub fn thunk_allocated(_thunk: &Gc<Thunk>) {}
+
+#[cfg(feature = "tracing")]
+pub fn thunk_enter(thunk: &Gc<Thunk>) {
+    tracing::trace!(thunk = thunk.addr(), label = thunk.label, "thunk entered");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub fn thunk_enter(_thunk: &Gc<Thunk>) {}
+
+#[cfg(feature = "tracing")]
+pub fn thunk_update(thunk: &Gc<Thunk>) {
+    tracing::trace!(thunk = thunk.addr(), label = thunk.label, "thunk updated");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub fn thunk_update(_thunk: &Gc<Thunk>) {}
+
+/// Entered for the duration of a call to `fn_def`. Calls run on the native
+/// stack and nest, while thunks are forced by the unwinder without
+/// recursing and only get events inside the enclosing call.
+#[cfg(feature = "tracing")]
+pub fn call_span(fn_def: &FnDef) -> CallSpan {
+    tracing::trace_span!("call", function = fn_def.name, arity = fn_def.arity).entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub fn call_span(_fn_def: &FnDef) -> CallSpan {
+    CallSpan
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::format;
+    use std::string::String;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::vec::Vec;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use crate::builtins::*;
+
+    /// Records the spans and messages of the events it is given, in order.
+    #[derive(Default)]
+    struct Recorder {
+        log: Arc<Mutex<Vec<String>>>,
+        next_id: AtomicU64
+    }
+
+    struct Message<'a>(&'a mut String);
+
+    impl Visit for Message<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.log.lock().unwrap().push(format!("span {}", span.metadata().name()));
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut msg = String::new();
+            event.record(&mut Message(&mut msg));
+            self.log.lock().unwrap().push(msg);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn thunks_and_calls_are_reported() {
+        let recorder = Recorder::default();
+        let log = recorder.log.clone();
+        tracing::subscriber::with_default(recorder, || {
+            let mut state = State::new();
+            state.push_int(2);
+            state.push_int(1);
+            state.push_fn(FN_ADD);
+            state.mk_ap();
+            state.mk_ap();
+            state.mk_thunk();
+            let node = *state.stack_peek();
+            state.try_eval(node).expect("a sum");
+        });
+        assert_eq!(*log.lock().unwrap(), ["thunk allocated", "thunk entered", "span call", "thunk updated"]);
+    }
+}
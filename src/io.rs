@@ -0,0 +1,210 @@
+use std::io::Write;
+use crate::builtins::*;
+use crate::handle::*;
+use crate::show::FN_SHOW;
+
+// The standard streams' actions, `putStr`, `getLine` and the rest, in
+// terms of the handles' own; what performs them, and every other action,
+// is in `action.rs`.
+
+impl State {
+    /// Pushes `line` as a `[Char]` list ending in `tail`, which is popped.
+    pub(crate) fn push_str_onto(&mut self, line: &str) {
+        for c in line.chars().rev() {
+            self.push_char(c);
+            self.mk_cons();
+        }
+    }
+
+    /// Writes the string on top of the stack to `out` as it is forced, a
+    /// character at a time, popping it. Strings are `[Char]` lists or
+    /// packed `Text`; a list's cells can be collected once written.
+    pub fn write_string(&mut self, out: &mut impl Write, location: &str, operation: &'static str) -> EvalResult {
+        let mut buf = [0; 4];
+        loop {
+            self.eval()?;
+            let written = match self.stack_pop() {
+                Node::Nil => return Ok(()),
+                Node::Cons(head, tail) => {
+                    self.stack_push(*tail);
+                    self.stack_push(*head);
+                    self.eval()?;
+                    match self.stack_pop() {
+                        Node::Char(c) => out.write_all(c.encode_utf8(&mut buf).as_bytes()),
+                        node => return Err(EvalError::type_mismatch(operation, "Char", &node))
+                    }
+                },
+                Node::Text(text) => {
+                    // a packed string ends the list
+                    self.push_nil();
+                    out.write_all(text.as_bytes())
+                },
+                node => return Err(EvalError::type_mismatch(operation, "string", &node))
+            };
+            written.map_err(|err| IoException::new(location, operation, &err))?;
+        }
+    }
+}
+
+pub static FN_PUT_STR: FnDef = FnDef {
+    name: "putStr",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_put_str
+};
+
+pub static FN_PUT_STR_LN: FnDef = FnDef {
+    name: "putStrLn",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_put_str_ln
+};
+
+pub static FN_PRINT: FnDef = FnDef {
+    name: "print",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_print
+};
+
+pub static FN_GET_LINE: FnDef = FnDef {
+    name: "getLine",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_line
+};
+
+pub static FN_GET_CONTENTS: FnDef = FnDef {
+    name: "getContents",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_get_contents
+};
+
+pub static FN_INTERACT: FnDef = FnDef {
+    name: "interact",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_interact
+};
+
+static IO_INTERACT: FnDef = FnDef {
+    name: "interact",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_interact
+};
+
+/// `hPutStr stdout`
+pub fn eval_put_str(state: &mut State) -> EvalResult {
+    eval_stdout(state)?;
+    state.mk_io_prim(IO_H_PUT_STR);
+    Ok(())
+}
+
+/// `hPutStrLn stdout`
+pub fn eval_put_str_ln(state: &mut State) -> EvalResult {
+    eval_stdout(state)?;
+    state.mk_io_prim(IO_H_PUT_STR_LN);
+    Ok(())
+}
+
+/// `putStrLn . show`
+pub fn eval_print(state: &mut State) -> EvalResult {
+    state.push_fn(FN_SHOW);
+    state.mk_ap();
+    eval_put_str_ln(state)
+}
+
+/// `hGetLine stdin`
+pub fn eval_get_line(state: &mut State) -> EvalResult {
+    eval_stdin(state)?;
+    state.mk_io_prim(IO_H_GET_LINE);
+    Ok(())
+}
+
+/// `hGetContents stdin`
+pub fn eval_get_contents(state: &mut State) -> EvalResult {
+    eval_stdin(state)?;
+    state.mk_io_prim(IO_H_GET_CONTENTS);
+    Ok(())
+}
+
+pub fn eval_interact(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_INTERACT);
+    Ok(())
+}
+
+/// Writes `f` applied to the lazily read stdin as the output is forced,
+/// so input is read only as far as the output written needs it, and
+/// neither is kept around once it has been dealt with. Reading stdin
+/// flushes stdout first, so output interleaves with input as it should.
+fn perform_interact(state: &mut State) -> EvalResult {
+    eval_stdin(state)?;
+    state.mk_handle_contents();
+    state.stack_swap();
+    state.mk_ap();
+    let stdout = state.std_handles().stdout;
+    state.write_handle(stdout, "hPutStr")?;
+    state.push_unit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    #[test]
+    fn strings_are_written_as_they_are_forced() {
+        let mut state = State::new();
+        // "hel" ++ a packed "lo"
+        state.push_text("lo".to_string());
+        state.push_str_onto("hel");
+        let mut out = Vec::new();
+        state.write_string(&mut out, "<test>", "putStr").expect("written");
+        assert_eq!(out, b"hello");
+        assert_eq!(state.stack_size(), 0);
+
+        // "ab" ++ error "boom", written up to where it fails
+        push_value(&mut state, "\"boom\"");
+        state.push_fn(FN_ERROR);
+        state.mk_ap();
+        state.push_str_onto("ab");
+        let mut out = Vec::new();
+        let err = state.write_string(&mut out, "<test>", "putStr").expect_err("the tail fails");
+        assert!(matches!(err.cause(), EvalError::Error(msg) if msg == "boom"));
+        assert_eq!(out, b"ab");
+    }
+
+    #[test]
+    fn only_characters_are_written() {
+        let mut state = State::new();
+        state.push_nil();
+        state.push_int(1);
+        state.mk_cons();
+        let err = state.write_string(&mut Vec::new(), "<test>", "putStr").expect_err("not a string");
+        assert!(matches!(err.cause(), EvalError::TypeMismatch { context: "putStr", expected: "Char", .. }));
+    }
+
+    #[test]
+    fn contents_are_read_as_far_as_they_are_forced() {
+        let path = temp_path("contents");
+        std::fs::write(&path, "one\ntwo").expect("a file");
+        let mut state = State::new();
+        let handle = Handle::open(&path, TAG_READ_MODE).expect("an open file");
+        state.push_handle(handle);
+        let Node::Handle(handle) = *state.stack_peek() else {
+            unreachable!()
+        };
+        // the handle is kept on the stack under its contents
+        state.push(Node::Handle(handle));
+        state.mk_handle_contents();
+        let contents = *state.stack_peek();
+        assert!(matches!(state.try_eval(contents), Ok(Node::Cons(..))));
+        assert!(!handle.is_closed());
+        assert_eq!(state.render(contents).ok().as_deref(), Some("\"one\\ntwo\""));
+        assert!(handle.is_closed());
+        std::fs::remove_file(&path).expect("removed");
+    }
+}
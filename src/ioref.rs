@@ -0,0 +1,244 @@
+use crate::builtins::*;
+use crate::gc::*;
+
+// `IORef a` is a `Node::IORef`, a cell of its own on the heap that writes
+// overwrite in place, so every copy of the reference sees them.
+
+impl State {
+    /// Forces the `IORef` on top of the stack, leaving it there, and gives its cell.
+    fn peek_ioref(&mut self, context: &'static str) -> EvalResult<Gc<Node>> {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::IORef(cell) => Ok(cell),
+            node => Err(EvalError::type_mismatch(context, "IORef", &node))
+        }
+    }
+
+    /// Applies the function below the `IORef` on top of the stack to what it
+    /// holds and writes the result back, forced to WHNF first if `strict`.
+    fn modify_ioref(&mut self, context: &'static str, strict: bool) -> EvalResult {
+        let mut cell = self.peek_ioref(context)?;
+        self.stack_swap();
+        self.stack_push(*cell);
+        self.stack_swap();
+        self.mk_ap();
+        if strict {
+            self.eval()?;
+        } else {
+            self.mk_thunk();
+        }
+        *cell.as_mut() = self.stack_pop();
+        self.stack_pop();
+        self.push_unit();
+        Ok(())
+    }
+}
+
+pub static FN_NEW_IOREF: FnDef = FnDef {
+    name: "newIORef",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_new_ioref
+};
+
+pub static FN_READ_IOREF: FnDef = FnDef {
+    name: "readIORef",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read_ioref
+};
+
+pub static FN_WRITE_IOREF: FnDef = FnDef {
+    name: "writeIORef",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_write_ioref
+};
+
+pub static FN_MODIFY_IOREF: FnDef = FnDef {
+    name: "modifyIORef",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_modify_ioref
+};
+
+pub static FN_MODIFY_IOREF_STRICT: FnDef = FnDef {
+    name: "modifyIORef'",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_modify_ioref_strict
+};
+
+static IO_NEW_IOREF: FnDef = FnDef {
+    name: "newIORef",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_new_ioref
+};
+
+static IO_READ_IOREF: FnDef = FnDef {
+    name: "readIORef",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_read_ioref
+};
+
+static IO_WRITE_IOREF: FnDef = FnDef {
+    name: "writeIORef",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_write_ioref
+};
+
+static IO_MODIFY_IOREF: FnDef = FnDef {
+    name: "modifyIORef",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_modify_ioref
+};
+
+static IO_MODIFY_IOREF_STRICT: FnDef = FnDef {
+    name: "modifyIORef'",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_modify_ioref_strict
+};
+
+pub fn eval_new_ioref(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_NEW_IOREF);
+    Ok(())
+}
+
+pub fn eval_read_ioref(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_READ_IOREF);
+    Ok(())
+}
+
+pub fn eval_write_ioref(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_WRITE_IOREF);
+    Ok(())
+}
+
+pub fn eval_modify_ioref(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MODIFY_IOREF);
+    Ok(())
+}
+
+pub fn eval_modify_ioref_strict(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MODIFY_IOREF_STRICT);
+    Ok(())
+}
+
+/// The initial value is stored as it is, unevaluated.
+fn perform_new_ioref(state: &mut State) -> EvalResult {
+    let value = state.stack_pop();
+    let cell = state.alloc(value);
+    state.stack_push(Node::IORef(cell));
+    Ok(())
+}
+
+fn perform_read_ioref(state: &mut State) -> EvalResult {
+    let cell = state.peek_ioref("readIORef")?;
+    state.stack_pop();
+    state.stack_push(*cell);
+    Ok(())
+}
+
+fn perform_write_ioref(state: &mut State) -> EvalResult {
+    let mut cell = state.peek_ioref("writeIORef")?;
+    state.stack_pop();
+    *cell.as_mut() = state.stack_pop();
+    state.push_unit();
+    Ok(())
+}
+
+/// Leaves the new value a thunk, as `modifyIORef` does, so repeated
+/// modifications build up a chain of applications until it is read.
+fn perform_modify_ioref(state: &mut State) -> EvalResult {
+    state.modify_ioref("modifyIORef", false)
+}
+
+fn perform_modify_ioref_strict(state: &mut State) -> EvalResult {
+    state.modify_ioref("modifyIORef'", true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Performs the action on top of the stack, popping it.
+    fn perform(state: &mut State) -> EvalResult<Node> {
+        let action = state.stack_pop();
+        state.run_io(action)
+    }
+
+    /// Pushes `fn_def ref`, for the `IORef` `n` below the top, applied to
+    /// what is on top of the stack if `fn_def` takes a second argument.
+    fn push_ioref_action(state: &mut State, fn_def: FnDef, n: usize) {
+        state.push(*state.stack_peek_nth(n));
+        state.push_fn(fn_def);
+        state.mk_ap();
+        if fn_def.arity == 2 {
+            state.mk_ap();
+        }
+    }
+
+    fn read(state: &mut State) -> EvalResult<Node> {
+        push_ioref_action(state, FN_READ_IOREF, 0);
+        let value = perform(state)?;
+        state.try_eval(value)
+    }
+
+    #[test]
+    fn writes_are_seen_through_the_reference() {
+        let mut state = State::new();
+        state.push_int(1);
+        state.push_fn(FN_NEW_IOREF);
+        state.mk_ap();
+        let ioref = perform(&mut state).expect("an IORef");
+        state.push(ioref);
+        assert!(matches!(read(&mut state), Ok(Node::Int(1))));
+
+        state.push_int(10);
+        push_ioref_action(&mut state, FN_WRITE_IOREF, 1);
+        perform(&mut state).expect("written");
+        assert!(matches!(read(&mut state), Ok(Node::Int(10))));
+
+        state.push_int(1);
+        state.mk_pap(FN_ADD, 1);
+        push_ioref_action(&mut state, FN_MODIFY_IOREF_STRICT, 1);
+        perform(&mut state).expect("modified");
+        assert!(matches!(read(&mut state), Ok(Node::Int(11))));
+        assert_eq!(state.stack_size(), 1);
+    }
+
+    #[test]
+    fn only_the_strict_modify_forces_the_value() {
+        let mut state = State::new();
+        state.push_int(1);
+        state.push_fn(FN_NEW_IOREF);
+        state.mk_ap();
+        let ioref = perform(&mut state).expect("an IORef");
+        state.push(ioref);
+
+        // modifyIORef r (div 1), dividing by what it holds once it is read
+        state.push_int(0);
+        push_ioref_action(&mut state, FN_WRITE_IOREF, 1);
+        perform(&mut state).expect("written");
+        state.push_int(1);
+        state.mk_pap(FN_DIV, 1);
+        push_ioref_action(&mut state, FN_MODIFY_IOREF, 1);
+        perform(&mut state).expect("modified lazily");
+        let err = read(&mut state).expect_err("division by zero");
+        assert!(matches!(err.cause(), EvalError::Arith(ArithException::DivideByZero)));
+
+        state.push_int(0);
+        push_ioref_action(&mut state, FN_WRITE_IOREF, 1);
+        perform(&mut state).expect("written");
+        state.push_int(1);
+        state.mk_pap(FN_DIV, 1);
+        push_ioref_action(&mut state, FN_MODIFY_IOREF_STRICT, 1);
+        let err = perform(&mut state).expect_err("division by zero");
+        assert!(matches!(err.cause(), EvalError::Arith(ArithException::DivideByZero)));
+    }
+}
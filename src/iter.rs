@@ -0,0 +1,199 @@
+use alloc::boxed::Box;
+use crate::builtins::*;
+use crate::builders::IntoNode;
+
+// Streaming lists between the host and the program, one cell at a time,
+// so neither side has to hold a whole list, which may be infinite.
+//
+// A list pulled from a Rust iterator is a closure thunk, see `closure`,
+// taking the next item and leaving the rest a thunk of the same closure:
+// once the rest of the list is out of reach, the iterator is dropped.
+
+/// The elements of a list, its cells forced as they are reached and the
+/// elements left as they are. The rest of the list is kept on the stack
+/// until the iterator is dropped; an element is only rooted until the next
+/// one is forced, so one to be kept has to be converted or pushed first.
+pub struct ListIter<'a> {
+    state: &'a mut State,
+    /// The size of the stack below the rest of the list.
+    frame_size: usize,
+    done: bool
+}
+
+impl Node {
+    /// Iterates over the list `self`, e.g. `node.iter_list(&mut state).take(10)`.
+    pub fn iter_list(self, state: &mut State) -> ListIter<'_> {
+        let frame_size = state.stack_size();
+        state.stack_push(self);
+        ListIter { state, frame_size, done: false }
+    }
+}
+
+impl ListIter<'_> {
+    fn finish(&mut self) {
+        if !self.done {
+            self.state.stacks.last_mut().unwrap().truncate(self.frame_size);
+            self.done = true;
+        }
+    }
+}
+
+impl Iterator for ListIter<'_> {
+    type Item = EvalResult<Node>;
+
+    fn next(&mut self) -> Option<EvalResult<Node>> {
+        if self.done {
+            return None;
+        }
+        let cell = match self.state.eval() {
+            Ok(()) => self.state.stack_pop(),
+            Err(err) => {
+                self.finish();
+                return Some(Err(err));
+            }
+        };
+        match cell {
+            Node::Cons(head, tail) => {
+                self.state.stack_push(tail.short_circuit());
+                Some(Ok(head.short_circuit()))
+            },
+            Node::Nil => {
+                self.finish();
+                None
+            },
+            node => {
+                self.finish();
+                Some(Err(EvalError::type_mismatch("iter_list", "list", &node)))
+            }
+        }
+    }
+}
+
+impl Drop for ListIter<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl State {
+    /// Pushes the lazy list of the items of `iter`, each taken from it only
+    /// once the cell holding it is forced, e.g.
+    /// `state.push_iter(reader.lines().map(Result::unwrap))`.
+    pub fn push_iter<I>(&mut self, mut iter: I) where I: Iterator + 'static, I::Item: IntoNode {
+        self.push_closure_token(Box::new(move |state| {
+            let token = *state.stack_peek();
+            match iter.next() {
+                Some(item) => {
+                    state.push(item);
+                    // the rest is pulled by the same closure
+                    state.stack_push(token);
+                    state.mk_closure_thunk();
+                    state.stack_swap();
+                    state.mk_cons();
+                },
+                None => state.push_nil()
+            }
+            Ok(())
+        }));
+        self.mk_closure_thunk();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+    use super::*;
+    use crate::testing::push_value;
+
+    fn ints(items: impl Iterator<Item = EvalResult<Node>>) -> Vec<i64> {
+        items.map(|item| match item {
+            Ok(Node::Int(int)) => int,
+            item => panic!("not an Int: {:?}", item)
+        }).collect()
+    }
+
+    #[test]
+    fn lists_are_iterated_over_cell_by_cell() {
+        let mut state = State::new();
+        push_value(&mut state, "[1, 2, 3]");
+        let list = *state.stack_peek();
+        assert_eq!(ints(list.iter_list(&mut state)), [1, 2, 3]);
+        assert_eq!(ints(list.iter_list(&mut state).take(2)), [1, 2]);
+        assert_eq!(state.stack_size(), 1);
+    }
+
+    #[test]
+    fn failing_cells_end_the_iteration() {
+        let mut state = State::new();
+        // 1 : div 1 0
+        state.push_int(0);
+        state.push_int(1);
+        state.push_fn(FN_DIV);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.push_int(1);
+        state.mk_cons();
+        let list = *state.stack_peek();
+        let mut items = list.iter_list(&mut state);
+        assert!(matches!(items.next(), Some(Ok(Node::Int(1)))));
+        let err = items.next().expect("an error").expect_err("division by zero");
+        assert!(matches!(err.cause(), EvalError::Arith(ArithException::DivideByZero)));
+        assert!(items.next().is_none());
+        drop(items);
+        assert_eq!(state.stack_size(), 1);
+
+        let mut items = Node::Int(1).iter_list(&mut state);
+        assert!(matches!(items.next(), Some(Err(EvalError::TypeMismatch { .. }))));
+        assert!(items.next().is_none());
+    }
+
+    /// Counts the items taken from it, and whether it was dropped.
+    struct Counted {
+        taken: Rc<Cell<i64>>,
+        dropped: Rc<Cell<bool>>
+    }
+
+    impl Iterator for Counted {
+        type Item = i64;
+
+        fn next(&mut self) -> Option<i64> {
+            self.taken.set(self.taken.get() + 1);
+            Some(self.taken.get())
+        }
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    #[test]
+    fn iterators_are_pulled_as_the_list_is_forced() {
+        let mut state = State::new();
+        state.push_iter(vec!["a", "b"].into_iter());
+        let list = state.stack_pop();
+        assert_eq!(state.render(list).expect("a list"), "[\"a\",\"b\"]");
+
+        let taken = Rc::new(Cell::new(0));
+        let dropped = Rc::new(Cell::new(false));
+        state.push_iter(Counted { taken: taken.clone(), dropped: dropped.clone() });
+        assert_eq!(taken.get(), 0);
+        let list = *state.stack_peek();
+        assert_eq!(ints(list.iter_list(&mut state).take(3)), [1, 2, 3]);
+        assert_eq!(taken.get(), 3);
+        // collections short-circuit the evaluated thunk on the stack, freeing it
+        let list = *state.stack_peek();
+        assert_eq!(ints(list.iter_list(&mut state).take(2)), [1, 2]);
+        assert_eq!(taken.get(), 3);
+
+        state.collect(Vec::new());
+        assert!(!dropped.get());
+        state.stack_pop();
+        state.collect(Vec::new());
+        assert!(dropped.get());
+    }
+}
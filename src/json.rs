@@ -0,0 +1,170 @@
+use std::convert::TryFrom;
+use serde_json::{Map, Number, Value};
+use crate::builtins::*;
+
+// Values exchanged with the host as JSON:
+//
+//     Int, Integer, Double    number
+//     Bool                    boolean
+//     String, Text            string
+//     lists, tuples           array, `()` as the empty one
+//     other constructors      {"tag": n, "fields": [...]}
+//
+// an `Integer` too big for an `Int` as the nearest number, and back,
+// `null` becoming `()` and an object that isn't a constructor
+// a list of pairs of its keys and values. Only values already in normal
+// form convert to JSON, see `State::deep_eval`.
+
+impl Node {
+    pub fn to_json(self) -> EvalResult<Value> {
+        let node = self.short_circuit();
+        let value = match node {
+            Node::Int(int) => Value::from(int),
+            Node::Integer(integer) => match integer.to_i64() {
+                Some(int) => Value::from(int),
+                None => Value::from(integer.to_f64())
+            },
+            Node::Double(double) => match Number::from_f64(double) {
+                Some(number) => Value::Number(number),
+                None => return Err(EvalError::type_mismatch("toJSON", "finite Double", &node))
+            },
+            Node::Bool(b) => Value::Bool(b),
+            Node::Text(text) => Value::String(text.to_string()),
+            Node::Nil => Value::Array(Vec::new()),
+            Node::Cons(head, _) if matches!(head.short_circuit(), Node::Char(_)) => Value::String(String::try_from(node)?),
+            Node::Cons(head, tail) => {
+                let mut elems = vec![head.to_json()?];
+                let mut rest = tail.short_circuit();
+                while let Node::Cons(head, tail) = rest {
+                    elems.push(head.to_json()?);
+                    rest = tail.short_circuit();
+                }
+                match rest {
+                    Node::Nil => Value::Array(elems),
+                    rest => return Err(EvalError::type_mismatch("toJSON", "list", &rest))
+                }
+            },
+            Node::Tuple(fields) => Value::Array(fields.iter().map(|field| field.to_json()).collect::<EvalResult<_>>()?),
+            Node::Data { tag, fields } => {
+                let mut object = Map::new();
+                object.insert("tag".to_string(), Value::from(tag));
+                object.insert("fields".to_string(), Value::Array(fields.iter().map(|field| field.to_json()).collect::<EvalResult<_>>()?));
+                Value::Object(object)
+            },
+            node => return Err(EvalError::type_mismatch("toJSON", "value in normal form", &node))
+        };
+        Ok(value)
+    }
+
+    /// The node for `value`, unrooted as `State::try_eval`'s result is.
+    pub fn from_json(state: &mut State, value: &Value) -> Node {
+        state.push_json(value);
+        state.stack_pop()
+    }
+}
+
+impl State {
+    /// Pushes the node for `value`.
+    pub fn push_json(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.push_unit(),
+            Value::Bool(b) => self.stack_push(Node::Bool(*b)),
+            Value::Number(number) => match number.as_i64() {
+                Some(int) => self.push_int(int),
+                None => self.push_double(number.as_f64().unwrap_or(f64::NAN))
+            },
+            Value::String(s) => self.push_str(s),
+            Value::Array(elems) => {
+                self.push_nil();
+                for elem in elems.iter().rev() {
+                    self.push_json(elem);
+                    self.mk_cons();
+                }
+            },
+            Value::Object(object) => match constructor(object) {
+                Some((tag, fields)) => {
+                    for field in fields.iter().rev() {
+                        self.push_json(field);
+                    }
+                    self.mk_data(tag, fields.len());
+                },
+                None => {
+                    self.push_nil();
+                    for (key, value) in object.iter().rev() {
+                        self.push_json(value);
+                        self.push_str(key);
+                        self.mk_tuple(2);
+                        self.mk_cons();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The tag and fields of an object standing for a constructor.
+fn constructor(object: &Map<String, Value>) -> Option<(u32, &Vec<Value>)> {
+    if object.len() != 2 {
+        return None;
+    }
+    let tag = object.get("tag")?.as_u64().and_then(|tag| u32::try_from(tag).ok())?;
+    match object.get("fields")? {
+        Value::Array(fields) => Some((tag, fields)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::testing::push_value;
+
+    fn to_json(s: &str) -> EvalResult<Value> {
+        let mut state = State::new();
+        push_value(&mut state, s);
+        state.stack_peek().to_json()
+    }
+
+    fn from_json(value: Value) -> String {
+        let mut state = State::new();
+        state.push_json(&value);
+        let node = state.stack_pop();
+        state.render(node).expect("a value")
+    }
+
+    #[test]
+    fn values_convert_to_json() {
+        assert_eq!(to_json("[1, 2, 3]").unwrap(), json!([1, 2, 3]));
+        assert_eq!(to_json("(1, True, 2.5)").unwrap(), json!([1, true, 2.5]));
+        assert_eq!(to_json("\"hi\"").unwrap(), json!("hi"));
+        assert_eq!(to_json("()").unwrap(), json!([]));
+        assert_eq!(to_json("Just [Nothing]").unwrap(), json!({"tag": 1, "fields": [[{"tag": 0, "fields": []}]]}));
+    }
+
+    #[test]
+    fn only_values_in_normal_form_convert() {
+        let mut state = State::new();
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.mk_just();
+        let err = state.stack_peek().to_json().expect_err("a thunk");
+        assert!(matches!(err, EvalError::TypeMismatch { .. }), "{}", err);
+
+        state.deep_eval().expect("a sum");
+        assert_eq!(state.stack_peek().to_json().unwrap(), json!({"tag": 1, "fields": [3]}));
+        assert!(Node::Double(f64::NAN).to_json().is_err());
+    }
+
+    #[test]
+    fn json_converts_to_values() {
+        assert_eq!(from_json(json!([1, 2.5, true])), "[1,2.5,True]");
+        assert_eq!(from_json(json!({"tag": 1, "fields": [null]})), "Pack{1,1} ()");
+        assert_eq!(from_json(json!({"a": 1})), "[(\"a\",1)]");
+        assert_eq!(from_json(json!("hi")), "\"hi\"");
+    }
+}
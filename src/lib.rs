@@ -0,0 +1,87 @@
+// The runtime: what the generated `prog.rs` builds on, the host's API and
+// everything behind it. The `runtime` binary runs the program compiled in,
+// `haskelite-run` one loaded when it starts.
+//
+// Without the `std` feature it is `no_std`, with only `alloc`: programs
+// evaluate as they do with it, IO actions and threads included, but none
+// of the OS's is there, no handles, environment or clock, and sparks
+// aren't given to workers.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+/// `std`'s collections, or with only `alloc` `hashbrown`'s, which they are
+/// built on.
+pub(crate) mod collections {
+    #[cfg(feature = "std")]
+    pub use std::collections::{HashMap, HashSet};
+    #[cfg(not(feature = "std"))]
+    pub use hashbrown::{HashMap, HashSet};
+    pub use alloc::collections::VecDeque;
+}
+
+pub mod builtins;
+pub mod gc;
+pub mod error;
+pub mod bigint;
+pub mod ratio;
+pub mod ordering;
+pub mod globals;
+pub mod module;
+pub mod tracer;
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod eventlog;
+pub mod dot;
+pub mod census;
+pub mod stepper;
+pub mod instrument;
+pub mod action;
+#[cfg(feature = "std")]
+pub mod io;
+pub mod show;
+pub mod read;
+#[cfg(feature = "std")]
+pub mod handle;
+pub mod maybe;
+#[cfg(feature = "std")]
+pub mod env;
+pub mod random;
+#[cfg(feature = "std")]
+pub mod clock;
+pub mod exception;
+pub mod ioref;
+pub mod array;
+pub mod map;
+pub mod concurrent;
+pub mod stm;
+pub mod spark;
+pub mod cancel;
+pub mod weak;
+pub mod stable;
+pub mod hashcons;
+pub mod hash;
+pub mod heaplimit;
+pub mod registry;
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod bytecode;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod convert;
+pub mod builders;
+pub mod iter;
+pub mod closure;
+#[cfg(feature = "std")]
+pub mod entry;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "dynload")]
+pub mod dynload;
+#[cfg(test)]
+mod testing;
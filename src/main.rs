@@ -1,21 +1,33 @@
-mod prog;
-mod builtins;
-mod gc;
+// The program compiled in, run on the runtime, which `prog.rs` refers to
+// as if it were this crate.
 
-use prog::eval_prog;
-use builtins::State;
+// a generated program may define more than its `main` reaches
+#[allow(dead_code)]
+mod prog;
 
-use std::{rc::Rc, cell::RefCell};
-use std::fmt::Display;
+use runtime::*;
+use builtins::{Node, State};
 
 use crate::prog::*;
 
-fn main() {
-    let mut state = State::new();
-    state.push_fn(FN_PROG);
-    state.eval();
-    println!("{}", state.stack_pop());
-    // state.collect(Vec::new());
-    // state.gc_dump();
-    // state.gc_dump();
+haskelite_main!(with prog_main);
+
+/// The program's `main`, that of the program named on the command line if
+/// there is one, the program taking the rest of it as its own.
+#[cfg(any(feature = "dynload", feature = "bytecode"))]
+fn prog_main(state: &mut State) -> builtins::EvalResult<Node> {
+    let mut argv = std::env::args().skip(1);
+    match argv.next() {
+        Some(path) => {
+            state.set_args(std::iter::once(path.clone()).chain(argv).collect());
+            entry::load(state, &path)?;
+            state.global_main()
+        },
+        None => Ok(Node::FnDef(FN_PROG))
+    }
+}
+
+#[cfg(not(any(feature = "dynload", feature = "bytecode")))]
+fn prog_main(_state: &mut State) -> builtins::EvalResult<Node> {
+    Ok(Node::FnDef(FN_PROG))
 }
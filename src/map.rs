@@ -0,0 +1,691 @@
+use core::cmp::Ordering;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::gc::*;
+
+// `Map k v` and `Set k` are weight-balanced trees, as in the `containers`
+// package, built in Rust out of reference counted nodes so that versions
+// share what they have in common. Each version is one object on the heap,
+// marking every key and value the tree holds. Keys are kept in normal
+// form, so they are compared without evaluating anything; values are
+// kept as they are given, unevaluated. A set's elements are its keys.
+
+const DELTA: usize = 3;
+const RATIO: usize = 2;
+
+#[derive(Clone)]
+pub enum MapTree {
+    Tip,
+    Bin(Rc<Bin>)
+}
+
+pub struct Bin {
+    size: usize,
+    key: Node,
+    value: Node,
+    left: MapTree,
+    right: MapTree
+}
+
+/// Orders keys in normal form: numbers, characters and so on as `compare`
+/// does, constructors by tag and then fields, lists and tuples lexically.
+pub fn cmp_keys(kl: &Node, kr: &Node) -> EvalResult<Ordering> {
+    match (kl.short_circuit(), kr.short_circuit()) {
+        (Node::Nil, Node::Nil) => Ok(Ordering::Equal),
+        (Node::Nil, Node::Cons(_, _)) => Ok(Ordering::Less),
+        (Node::Cons(_, _), Node::Nil) => Ok(Ordering::Greater),
+        (Node::Cons(hl, tl), Node::Cons(hr, tr)) => match cmp_keys(&hl, &hr)? {
+            Ordering::Equal => cmp_keys(&tl, &tr),
+            ordering => Ok(ordering)
+        },
+        (Node::Data { tag: tl, fields: fl }, Node::Data { tag: tr, fields: fr }) => match tl.cmp(&tr) {
+            Ordering::Equal => cmp_fields(&fl, &fr),
+            ordering => Ok(ordering)
+        },
+        (Node::Tuple(fl), Node::Tuple(fr)) => cmp_fields(&fl, &fr),
+        // NaN keys sort last, as `compare` has them greater
+        (kl, kr) => Ok(partial_cmp_nodes("Map key", &kl, &kr)?.unwrap_or(Ordering::Greater))
+    }
+}
+
+fn cmp_fields(fl: &Fields, fr: &Fields) -> EvalResult<Ordering> {
+    for (l, r) in fl.iter().zip(fr.iter()) {
+        match cmp_keys(l, r)? {
+            Ordering::Equal => {},
+            ordering => return Ok(ordering)
+        }
+    }
+    Ok(fl.len().cmp(&fr.len()))
+}
+
+impl MapTree {
+    pub fn size(&self) -> usize {
+        match self {
+            MapTree::Tip => 0,
+            MapTree::Bin(bin) => bin.size
+        }
+    }
+
+    fn bin(key: Node, value: Node, left: MapTree, right: MapTree) -> MapTree {
+        let size = left.size() + right.size() + 1;
+        MapTree::Bin(Rc::new(Bin { size, key, value, left, right }))
+    }
+
+    /// Rebuilds a node whose subtrees have got out of balance by at most
+    /// one insertion or deletion.
+    fn balance(key: Node, value: Node, left: MapTree, right: MapTree) -> MapTree {
+        let (size_l, size_r) = (left.size(), right.size());
+        if size_l + size_r <= 1 {
+            MapTree::bin(key, value, left, right)
+        } else if size_r > DELTA * size_l {
+            MapTree::rotate_left(key, value, left, right)
+        } else if size_l > DELTA * size_r {
+            MapTree::rotate_right(key, value, left, right)
+        } else {
+            MapTree::bin(key, value, left, right)
+        }
+    }
+
+    fn rotate_left(key: Node, value: Node, left: MapTree, right: MapTree) -> MapTree {
+        let MapTree::Bin(r) = right else { unreachable!() };
+        if r.left.size() < RATIO * r.right.size() {
+            MapTree::bin(r.key, r.value, MapTree::bin(key, value, left, r.left.clone()), r.right.clone())
+        } else {
+            let MapTree::Bin(rl) = &r.left else { unreachable!() };
+            MapTree::bin(rl.key, rl.value,
+                MapTree::bin(key, value, left, rl.left.clone()),
+                MapTree::bin(r.key, r.value, rl.right.clone(), r.right.clone()))
+        }
+    }
+
+    fn rotate_right(key: Node, value: Node, left: MapTree, right: MapTree) -> MapTree {
+        let MapTree::Bin(l) = left else { unreachable!() };
+        if l.right.size() < RATIO * l.left.size() {
+            MapTree::bin(l.key, l.value, l.left.clone(), MapTree::bin(key, value, l.right.clone(), right))
+        } else {
+            let MapTree::Bin(lr) = &l.right else { unreachable!() };
+            MapTree::bin(lr.key, lr.value,
+                MapTree::bin(l.key, l.value, l.left.clone(), lr.left.clone()),
+                MapTree::bin(key, value, lr.right.clone(), right))
+        }
+    }
+
+    /// The value of `key`, which has to be in normal form.
+    pub fn lookup(&self, key: &Node) -> EvalResult<Option<Node>> {
+        let mut tree = self;
+        while let MapTree::Bin(bin) = tree {
+            tree = match cmp_keys(key, &bin.key)? {
+                Ordering::Less => &bin.left,
+                Ordering::Greater => &bin.right,
+                Ordering::Equal => return Ok(Some(bin.value))
+            };
+        }
+        Ok(None)
+    }
+
+    /// The tree with `key` mapped to `value`, replacing what it was mapped to.
+    pub fn insert(&self, key: Node, value: Node) -> EvalResult<MapTree> {
+        let MapTree::Bin(bin) = self else {
+            return Ok(MapTree::bin(key, value, MapTree::Tip, MapTree::Tip));
+        };
+        Ok(match cmp_keys(&key, &bin.key)? {
+            Ordering::Less => MapTree::balance(bin.key, bin.value, bin.left.insert(key, value)?, bin.right.clone()),
+            Ordering::Greater => MapTree::balance(bin.key, bin.value, bin.left.clone(), bin.right.insert(key, value)?),
+            Ordering::Equal => MapTree::bin(key, value, bin.left.clone(), bin.right.clone())
+        })
+    }
+
+    /// The tree without `key`, the same tree if it is not there.
+    pub fn delete(&self, key: &Node) -> EvalResult<MapTree> {
+        let MapTree::Bin(bin) = self else {
+            return Ok(MapTree::Tip);
+        };
+        Ok(match cmp_keys(key, &bin.key)? {
+            Ordering::Less => MapTree::balance(bin.key, bin.value, bin.left.delete(key)?, bin.right.clone()),
+            Ordering::Greater => MapTree::balance(bin.key, bin.value, bin.left.clone(), bin.right.delete(key)?),
+            Ordering::Equal => MapTree::glue(bin.left.clone(), bin.right.clone())
+        })
+    }
+
+    /// Joins the subtrees of a deleted node, balanced with each other, by
+    /// moving the nearest key of the bigger one up between them.
+    fn glue(left: MapTree, right: MapTree) -> MapTree {
+        match (&left, &right) {
+            (MapTree::Tip, _) => right,
+            (_, MapTree::Tip) => left,
+            _ if left.size() > right.size() => {
+                let (key, value, left) = left.delete_max();
+                MapTree::balance(key, value, left, right)
+            },
+            _ => {
+                let (key, value, right) = right.delete_min();
+                MapTree::balance(key, value, left, right)
+            }
+        }
+    }
+
+    fn delete_min(&self) -> (Node, Node, MapTree) {
+        let MapTree::Bin(bin) = self else { unreachable!() };
+        match bin.left {
+            MapTree::Tip => (bin.key, bin.value, bin.right.clone()),
+            _ => {
+                let (key, value, left) = bin.left.delete_min();
+                (key, value, MapTree::balance(bin.key, bin.value, left, bin.right.clone()))
+            }
+        }
+    }
+
+    fn delete_max(&self) -> (Node, Node, MapTree) {
+        let MapTree::Bin(bin) = self else { unreachable!() };
+        match bin.right {
+            MapTree::Tip => (bin.key, bin.value, bin.left.clone()),
+            _ => {
+                let (key, value, right) = bin.right.delete_max();
+                (key, value, MapTree::balance(bin.key, bin.value, bin.left.clone(), right))
+            }
+        }
+    }
+
+    /// The keys and values in ascending order of the keys.
+    pub fn entries(&self) -> Vec<(Node, Node)> {
+        fn walk(tree: &MapTree, entries: &mut Vec<(Node, Node)>) {
+            if let MapTree::Bin(bin) = tree {
+                walk(&bin.left, entries);
+                entries.push((bin.key, bin.value));
+                walk(&bin.right, entries);
+            }
+        }
+        let mut entries = Vec::with_capacity(self.size());
+        walk(self, &mut entries);
+        entries
+    }
+
+    /// The same tree with its values replaced, in order, by `values`.
+    pub fn with_values(&self, values: &mut impl Iterator<Item = Node>) -> MapTree {
+        match self {
+            MapTree::Tip => MapTree::Tip,
+            MapTree::Bin(bin) => {
+                let left = bin.left.with_values(values);
+                let value = values.next().unwrap();
+                let right = bin.right.with_values(values);
+                MapTree::Bin(Rc::new(Bin { size: bin.size, key: bin.key, value, left, right }))
+            }
+        }
+    }
+}
+
+impl Trace for MapTree {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        if let MapTree::Bin(bin) = self {
+            bin.key.mark_refs(worklist);
+            bin.value.mark_refs(worklist);
+            bin.left.mark_refs(worklist);
+            bin.right.mark_refs(worklist);
+        }
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("map", None)
+    }
+}
+
+impl State {
+    fn push_map(&mut self, tree: MapTree, set: bool) {
+        let tree = self.alloc(tree);
+        self.stack_push(if set { Node::Set(tree) } else { Node::Map(tree) });
+    }
+
+    /// Forces the map, or set, on top of the stack, leaving it there, and gives its tree.
+    fn peek_map(&mut self, context: &'static str, set: bool) -> EvalResult<Gc<MapTree>> {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::Map(tree) if !set => Ok(tree),
+            Node::Set(tree) if set => Ok(tree),
+            node => Err(EvalError::type_mismatch(context, if set { "Set" } else { "Map" }, &node))
+        }
+    }
+
+    /// Forces the key on top of the stack to normal form, popping it.
+    fn pop_key(&mut self) -> EvalResult<Node> {
+        self.deep_eval()?;
+        Ok(self.stack_pop())
+    }
+
+    /// Gives the tree of the map below the key on top of the stack, and the
+    /// key, popping both. Nothing is allocated until the tree is used.
+    fn pop_key_and_map(&mut self, context: &'static str, set: bool) -> EvalResult<(Node, Gc<MapTree>)> {
+        self.stack_swap();
+        self.peek_map(context, set)?;
+        self.stack_swap();
+        let key = self.pop_key()?;
+        let tree = self.peek_map(context, set)?;
+        self.stack_pop();
+        Ok((key, tree))
+    }
+
+    /// Builds a map, or set, from the list of pairs, or elements, on top of
+    /// the stack, popping it. Later keys replace earlier ones.
+    fn map_from_list(&mut self, set: bool) -> EvalResult {
+        // the map is added to on the stack, keeping what it holds rooted
+        self.push_map(MapTree::Tip, set);
+        let mut tree = match *self.stack_peek() {
+            Node::Map(tree) | Node::Set(tree) => tree,
+            _ => unreachable!()
+        };
+        self.stack_swap();
+        loop {
+            self.eval()?;
+            match self.stack_pop() {
+                Node::Nil => break,
+                Node::Cons(head, tail) => {
+                    self.stack_push(*tail);
+                    self.stack_push(*head);
+                },
+                node => return Err(EvalError::type_mismatch("fromList", "list", &node))
+            }
+            if !set {
+                self.eval()?;
+                match self.stack_pop() {
+                    Node::Tuple(pair) if pair.len() == 2 => {
+                        self.stack_push(pair[1]);
+                        self.stack_push(pair[0]);
+                    },
+                    node => return Err(EvalError::type_mismatch("Map.fromList", "pair", &node))
+                }
+            }
+            let key = self.pop_key()?;
+            let value = if set { Node::Nil } else { self.stack_pop() };
+            *tree.as_mut() = tree.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes the lazy right fold of `f` over the entries of the tree, `f`
+    /// and then `z` below it, pops them and leaves the map there is below.
+    fn map_foldr(&mut self, tree: Gc<MapTree>, with_key: bool) {
+        let f = *self.stack_peek_nth(1);
+        let z = *self.stack_peek_nth(2);
+        self.stack_push(z);
+        for (key, value) in tree.entries().into_iter().rev() {
+            if with_key {
+                self.stack_push(value);
+            }
+            self.stack_push(key);
+            self.stack_push(f);
+            self.mk_ap();
+            if with_key {
+                self.mk_ap();
+            }
+            self.mk_ap();
+            self.mk_thunk();
+        }
+        // drop the map, `f` and `z`
+        for _ in 0..3 {
+            self.stack_swap();
+            self.stack_pop();
+        }
+    }
+}
+
+pub static FN_MAP_EMPTY: FnDef = FnDef {
+    name: "Map.empty",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_map_empty
+};
+
+pub static FN_MAP_SINGLETON: FnDef = FnDef {
+    name: "Map.singleton",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_map_singleton
+};
+
+pub static FN_MAP_INSERT: FnDef = FnDef {
+    name: "Map.insert",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_map_insert
+};
+
+pub static FN_MAP_LOOKUP: FnDef = FnDef {
+    name: "Map.lookup",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_map_lookup
+};
+
+pub static FN_MAP_MEMBER: FnDef = FnDef {
+    name: "Map.member",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_map_member
+};
+
+pub static FN_MAP_DELETE: FnDef = FnDef {
+    name: "Map.delete",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_map_delete
+};
+
+pub static FN_MAP_SIZE: FnDef = FnDef {
+    name: "Map.size",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_map_size
+};
+
+pub static FN_MAP_FOLDR_WITH_KEY: FnDef = FnDef {
+    name: "Map.foldrWithKey",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_map_foldr_with_key
+};
+
+pub static FN_MAP_FROM_LIST: FnDef = FnDef {
+    name: "Map.fromList",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_map_from_list
+};
+
+pub static FN_MAP_TO_LIST: FnDef = FnDef {
+    name: "Map.toList",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_map_to_list
+};
+
+pub static FN_SET_EMPTY: FnDef = FnDef {
+    name: "Set.empty",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_set_empty
+};
+
+pub static FN_SET_INSERT: FnDef = FnDef {
+    name: "Set.insert",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_set_insert
+};
+
+pub static FN_SET_MEMBER: FnDef = FnDef {
+    name: "Set.member",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_set_member
+};
+
+pub static FN_SET_DELETE: FnDef = FnDef {
+    name: "Set.delete",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_set_delete
+};
+
+pub static FN_SET_SIZE: FnDef = FnDef {
+    name: "Set.size",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_set_size
+};
+
+pub static FN_SET_FOLDR: FnDef = FnDef {
+    name: "Set.foldr",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_set_foldr
+};
+
+pub static FN_SET_FROM_LIST: FnDef = FnDef {
+    name: "Set.fromList",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_set_from_list
+};
+
+pub static FN_SET_TO_LIST: FnDef = FnDef {
+    name: "Set.toList",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_set_to_list
+};
+
+pub fn eval_map_empty(state: &mut State) -> EvalResult {
+    state.push_map(MapTree::Tip, false);
+    Ok(())
+}
+
+pub fn eval_map_singleton(state: &mut State) -> EvalResult {
+    let key = state.pop_key()?;
+    let value = state.stack_pop();
+    state.push_map(MapTree::Tip.insert(key, value)?, false);
+    Ok(())
+}
+
+pub fn eval_map_insert(state: &mut State) -> EvalResult {
+    // the map first, with the key and value left rooted below it
+    state.stack_bring_up(2);
+    state.peek_map("Map.insert", false)?;
+    state.stack_swap();
+    let key = state.pop_key()?;
+    let tree = state.peek_map("Map.insert", false)?;
+    state.stack_pop();
+    let value = state.stack_pop();
+    state.push_map(tree.insert(key, value)?, false);
+    Ok(())
+}
+
+pub fn eval_map_lookup(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Map.lookup", false)?;
+    match tree.lookup(&key)? {
+        Some(value) => {
+            state.stack_push(value);
+            state.mk_just();
+        },
+        None => state.push_nothing()
+    }
+    Ok(())
+}
+
+pub fn eval_map_member(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Map.member", false)?;
+    state.stack_push(Node::Bool(tree.lookup(&key)?.is_some()));
+    Ok(())
+}
+
+pub fn eval_map_delete(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Map.delete", false)?;
+    state.push_map(tree.delete(&key)?, false);
+    Ok(())
+}
+
+pub fn eval_map_size(state: &mut State) -> EvalResult {
+    let tree = state.peek_map("Map.size", false)?;
+    state.stack_pop();
+    state.push_int(tree.size() as i64);
+    Ok(())
+}
+
+/// `f k1 v1 (f k2 v2 (... z))`, each application a thunk of its own.
+pub fn eval_map_foldr_with_key(state: &mut State) -> EvalResult {
+    state.stack_bring_up(2);
+    let tree = state.peek_map("Map.foldrWithKey", false)?;
+    state.map_foldr(tree, true);
+    Ok(())
+}
+
+pub fn eval_map_from_list(state: &mut State) -> EvalResult {
+    state.map_from_list(false)
+}
+
+/// The `(key, value)` pairs in ascending order of the keys.
+pub fn eval_map_to_list(state: &mut State) -> EvalResult {
+    let tree = state.peek_map("Map.toList", false)?;
+    state.push_nil();
+    for (key, value) in tree.entries().into_iter().rev() {
+        state.stack_push(value);
+        state.stack_push(key);
+        state.mk_tuple(2);
+        state.mk_cons();
+    }
+    state.stack_swap();
+    state.stack_pop();
+    Ok(())
+}
+
+pub fn eval_set_empty(state: &mut State) -> EvalResult {
+    state.push_map(MapTree::Tip, true);
+    Ok(())
+}
+
+pub fn eval_set_insert(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Set.insert", true)?;
+    state.push_map(tree.insert(key, Node::Nil)?, true);
+    Ok(())
+}
+
+pub fn eval_set_member(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Set.member", true)?;
+    state.stack_push(Node::Bool(tree.lookup(&key)?.is_some()));
+    Ok(())
+}
+
+pub fn eval_set_delete(state: &mut State) -> EvalResult {
+    let (key, tree) = state.pop_key_and_map("Set.delete", true)?;
+    state.push_map(tree.delete(&key)?, true);
+    Ok(())
+}
+
+pub fn eval_set_size(state: &mut State) -> EvalResult {
+    let tree = state.peek_map("Set.size", true)?;
+    state.stack_pop();
+    state.push_int(tree.size() as i64);
+    Ok(())
+}
+
+/// `f x1 (f x2 (... z))` over the elements in ascending order.
+pub fn eval_set_foldr(state: &mut State) -> EvalResult {
+    state.stack_bring_up(2);
+    let tree = state.peek_map("Set.foldr", true)?;
+    state.map_foldr(tree, false);
+    Ok(())
+}
+
+pub fn eval_set_from_list(state: &mut State) -> EvalResult {
+    state.map_from_list(true)
+}
+
+/// The elements in ascending order.
+pub fn eval_set_to_list(state: &mut State) -> EvalResult {
+    let tree = state.peek_map("Set.toList", true)?;
+    state.push_nil();
+    for (key, _) in tree.entries().into_iter().rev() {
+        state.stack_push(key);
+        state.mk_cons();
+    }
+    state.stack_swap();
+    state.stack_pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    /// Checks the tree is ordered, sized and weight-balanced, giving its depth.
+    fn check(tree: &MapTree) -> usize {
+        let MapTree::Bin(bin) = tree else {
+            return 0;
+        };
+        let (size_l, size_r) = (bin.left.size(), bin.right.size());
+        assert_eq!(bin.size, size_l + size_r + 1);
+        assert!(size_l + size_r <= 1 || (size_l <= DELTA * size_r && size_r <= DELTA * size_l));
+        if let MapTree::Bin(left) = &bin.left {
+            assert_eq!(cmp_keys(&left.key, &bin.key).ok(), Some(Ordering::Less));
+        }
+        if let MapTree::Bin(right) = &bin.right {
+            assert_eq!(cmp_keys(&right.key, &bin.key).ok(), Some(Ordering::Greater));
+        }
+        1 + check(&bin.left).max(check(&bin.right))
+    }
+
+    fn keys(tree: &MapTree) -> Vec<i64> {
+        tree.entries().into_iter().map(|(key, _)| match key {
+            Node::Int(key) => key,
+            _ => unreachable!()
+        }).collect()
+    }
+
+    #[test]
+    fn trees_stay_balanced() {
+        let mut tree = MapTree::Tip;
+        // ascending keys, the worst case for an unbalanced tree
+        for i in 0..1000 {
+            tree = tree.insert(Node::Int(i), Node::Nil).expect("inserted");
+            check(&tree);
+        }
+        assert_eq!(tree.size(), 1000);
+        assert!(check(&tree) <= 20);
+
+        let before = tree.clone();
+        for i in (0..1000).step_by(2) {
+            tree = tree.delete(&Node::Int(i)).expect("deleted");
+            check(&tree);
+        }
+        assert_eq!(keys(&tree), (1..1000).step_by(2).collect::<Vec<_>>());
+        // the old version is untouched
+        assert_eq!(before.size(), 1000);
+        assert!(matches!(before.lookup(&Node::Int(4)), Ok(Some(Node::Nil))));
+        assert!(matches!(tree.lookup(&Node::Int(4)), Ok(None)));
+        assert_eq!(tree.delete(&Node::Int(4)).expect("not there").size(), 500);
+    }
+
+    /// Renders `to_list (from_list xs)`.
+    fn round_trip(state: &mut State, from_list: FnDef, to_list: FnDef, xs: &str) -> EvalResult<String> {
+        push_value(state, xs);
+        state.push_fn(from_list);
+        state.mk_ap();
+        state.push_fn(to_list);
+        state.mk_ap();
+        let list = state.stack_pop();
+        state.render(list)
+    }
+
+    #[test]
+    fn lists_come_back_sorted_by_key() {
+        let mut state = State::new();
+        let pairs = "[(\"b\",1),(\"a\",2),(\"ab\",3),(\"b\",4)]";
+        assert_eq!(round_trip(&mut state, FN_MAP_FROM_LIST, FN_MAP_TO_LIST, pairs).ok().as_deref(),
+                   Some("[(\"a\",2),(\"ab\",3),(\"b\",4)]"));
+        assert_eq!(round_trip(&mut state, FN_SET_FROM_LIST, FN_SET_TO_LIST, "[3,1,2,1]").ok().as_deref(),
+                   Some("[1,2,3]"));
+        assert_eq!(round_trip(&mut state, FN_SET_FROM_LIST, FN_SET_TO_LIST, "[]").ok().as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn values_are_looked_up_by_key() {
+        let mut state = State::new();
+        push_value(&mut state, "[(1,'a'),(2,'b')]");
+        state.push_fn(FN_MAP_FROM_LIST);
+        state.mk_ap();
+        state.mk_thunk();
+        for (key, found) in [("2", "Pack{1,1} 'b'"), ("3", "Pack{0,0}")] {
+            state.push(*state.stack_peek());
+            push_value(&mut state, key);
+            state.push_fn(FN_MAP_LOOKUP);
+            state.mk_ap();
+            state.mk_ap();
+            let value = state.stack_pop();
+            assert_eq!(state.render(value).ok().as_deref(), Some(found));
+        }
+        state.push(*state.stack_peek());
+        state.push_fn(FN_MAP_SIZE);
+        state.apply(1).expect("a size");
+        assert!(matches!(state.stack_pop(), Node::Int(2)));
+    }
+}
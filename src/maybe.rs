@@ -0,0 +1,31 @@
+use crate::builtins::*;
+
+// `data Maybe a = Nothing | Just a`, represented as constructor nodes.
+
+pub const TAG_NOTHING: u32 = 0;
+pub const TAG_JUST: u32 = 1;
+
+impl State {
+    pub fn push_nothing(&mut self) {
+        self.mk_data(TAG_NOTHING, 0);
+    }
+
+    /// Wraps the node on top of the stack in `Just`.
+    pub fn mk_just(&mut self) {
+        self.mk_data(TAG_JUST, 1);
+    }
+
+    /// Forces the `Maybe` on top of the stack, popping it and pushing the
+    /// value of a `Just` in its place.
+    pub fn pop_maybe(&mut self) -> EvalResult<bool> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::Data { tag: TAG_NOTHING, fields } if fields.is_empty() => Ok(false),
+            Node::Data { tag: TAG_JUST, fields } if fields.len() == 1 => {
+                self.stack_push(fields[0]);
+                Ok(true)
+            },
+            node => Err(EvalError::type_mismatch("Maybe", "Nothing or Just", &node))
+        }
+    }
+}
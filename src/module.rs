@@ -0,0 +1,300 @@
+use crate::collections::{HashMap, HashSet};
+use core::fmt;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use crate::builtins::*;
+
+// Separately compiled modules, linked into one program. A module's
+// definitions are named qualified, `Data.Queue.push`, so those of two
+// modules never clash, CAFs included; linking checks that and that every
+// module imported is there, then orders the modules so each comes after
+// those it imports. Loading the result registers every definition under
+// its qualified name, and under its own name too wherever only one module
+// defines it, so `main` is found as ever, and runs each module's init in
+// order. A name defined by a module loaded later refers to its definition
+// from then on.
+
+/// A compiled module: its definitions, the modules it refers to and what
+/// it does once they are all loaded.
+#[derive(Clone)]
+pub struct Module {
+    pub name: &'static str,
+    pub imports: Vec<&'static str>,
+    /// Named `name.def`, as `Data.Queue.push` for `push` in `Data.Queue`.
+    pub defs: Vec<FnDef>,
+    /// Run once the program is loaded, after the inits of its imports.
+    pub init: Option<StateFn>
+}
+
+impl Module {
+    pub fn new(name: &'static str, defs: Vec<FnDef>) -> Self {
+        Module { name, imports: Vec::new(), defs, init: None }
+    }
+
+    pub fn with_imports(mut self, imports: &[&'static str]) -> Self {
+        self.imports.extend_from_slice(imports);
+        self
+    }
+
+    pub fn with_init(mut self, init: StateFn) -> Self {
+        self.init = Some(init);
+        self
+    }
+
+    /// The unqualified name of `fn_def`, if it is named as one of this
+    /// module's.
+    fn local_name(&self, fn_def: &FnDef) -> Option<&'static str> {
+        let name: &'static str = fn_def.name;
+        let local = name.strip_prefix(self.name)?.strip_prefix('.')?;
+        if local.is_empty() { None } else { Some(local) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// Two modules of the same name were linked, or one loaded already.
+    DuplicateModule(&'static str),
+    /// A module has two definitions of the name.
+    DuplicateDefinition(&'static str),
+    /// A module's definition isn't named as the module's.
+    Unqualified { module: &'static str, name: &'static str },
+    /// A module imports one that isn't linked.
+    MissingImport { module: &'static str, import: &'static str },
+    /// The modules import each other, the first importing the second and
+    /// so on round to the first.
+    ImportCycle(Vec<&'static str>)
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateModule(name) => write!(f, "module {} linked twice", name),
+            LinkError::DuplicateDefinition(name) => write!(f, "{} defined twice", name),
+            LinkError::Unqualified { module, name } =>
+                write!(f, "{} isn't a definition of module {}", name, module),
+            LinkError::MissingImport { module, import } =>
+                write!(f, "module {} imports {}, which isn't linked", module, import),
+            LinkError::ImportCycle(cycle) => write!(f, "modules import each other: {}", cycle.join(" -> "))
+        }
+    }
+}
+
+impl From<LinkError> for EvalError {
+    fn from(err: LinkError) -> EvalError {
+        EvalError::Error(format!("link: {}", err))
+    }
+}
+
+/// Modules linked together, ready to be loaded.
+pub struct Linked {
+    /// Each after those it imports.
+    modules: Vec<Module>,
+    /// The unqualified names defined by only one module, and their definitions'.
+    aliases: Vec<(&'static str, &'static str)>
+}
+
+impl Linked {
+    pub fn modules(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.modules.iter().map(|module| module.name)
+    }
+}
+
+/// Links `modules`, checking their definitions are as named and their
+/// imports there.
+pub fn link(modules: Vec<Module>) -> Result<Linked, LinkError> {
+    let mut linked = HashSet::new();
+    let mut defined = HashSet::new();
+    let mut locals: HashMap<&'static str, Option<&'static str>> = HashMap::new();
+    for module in &modules {
+        if !linked.insert(module.name) {
+            return Err(LinkError::DuplicateModule(module.name));
+        }
+        for fn_def in &module.defs {
+            let local = module.local_name(fn_def)
+                .ok_or(LinkError::Unqualified { module: module.name, name: fn_def.name })?;
+            if !defined.insert(fn_def.name) {
+                return Err(LinkError::DuplicateDefinition(fn_def.name));
+            }
+            // ambiguous once a second module defines it
+            locals.entry(local).and_modify(|def| *def = None).or_insert(Some(fn_def.name));
+        }
+    }
+    for module in &modules {
+        if let Some(&import) = module.imports.iter().find(|import| !linked.contains(*import)) {
+            return Err(LinkError::MissingImport { module: module.name, import });
+        }
+    }
+
+    let order = init_order(&modules)?;
+    let mut modules: Vec<Option<Module>> = modules.into_iter().map(Some).collect();
+    let modules = order.into_iter().map(|index| modules[index].take().unwrap()).collect();
+    let mut aliases: Vec<_> = locals.into_iter()
+        .filter_map(|(local, def)| def.map(|def| (local, def)))
+        .collect();
+    aliases.sort_unstable();
+    Ok(Linked { modules, aliases })
+}
+
+/// The indices of `modules`, each after those it imports.
+fn init_order(modules: &[Module]) -> Result<Vec<usize>, LinkError> {
+    let index: HashMap<&'static str, usize> = modules.iter().enumerate()
+        .map(|(i, module)| (module.name, i))
+        .collect();
+    // 0 unvisited, 1 on the path being visited, 2 ordered
+    let mut marks = vec![0u8; modules.len()];
+    let mut order = Vec::with_capacity(modules.len());
+    for root in 0..modules.len() {
+        if marks[root] != 0 {
+            continue;
+        }
+        // the path from `root`, each module with the next of its imports to visit
+        let mut path = vec![(root, 0)];
+        marks[root] = 1;
+        while let Some(&(i, next)) = path.last() {
+            let imports = &modules[i].imports;
+            if next == imports.len() {
+                marks[i] = 2;
+                order.push(i);
+                path.pop();
+                continue;
+            }
+            path.last_mut().unwrap().1 += 1;
+            let import = index[imports[next]];
+            match marks[import] {
+                0 => {
+                    marks[import] = 1;
+                    path.push((import, 0));
+                },
+                1 => {
+                    let start = path.iter().position(|&(j, _)| j == import).unwrap();
+                    let mut cycle: Vec<_> = path[start..].iter().map(|&(j, _)| modules[j].name).collect();
+                    cycle.push(modules[import].name);
+                    return Err(LinkError::ImportCycle(cycle));
+                },
+                _ => {}
+            }
+        }
+    }
+    Ok(order)
+}
+
+impl State {
+    /// Loads the modules of `linked`, registering their definitions and
+    /// running their inits. CAFs evaluated so far are released, as with
+    /// other programs loaded.
+    pub fn load_linked(&mut self, linked: Linked) -> EvalResult {
+        if let Some(module) = linked.modules.iter().find(|module| self.globals.has_module(module.name)) {
+            return Err(LinkError::DuplicateModule(module.name).into());
+        }
+        for module in &linked.modules {
+            self.globals.add_module(module.name);
+            for fn_def in &module.defs {
+                self.globals.register(*fn_def);
+            }
+        }
+        for &(local, def) in &linked.aliases {
+            self.globals.alias(local, def);
+        }
+        self.globals.release_cafs();
+        for module in &linked.modules {
+            if let Some(init) = module.init {
+                init(self)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Links `modules` and loads them, see `link`.
+    pub fn link_modules(&mut self, modules: Vec<Module>) -> EvalResult {
+        let linked = link(modules)?;
+        self.load_linked(linked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_unit(state: &mut State) -> EvalResult {
+        state.push_unit();
+        Ok(())
+    }
+
+    fn def(name: &'static str) -> FnDef {
+        FnDef { name, arity: 0, strict: 0, fn_ref: eval_unit }
+    }
+
+    fn init_queue(state: &mut State) -> EvalResult {
+        state.push_int(1);
+        Ok(())
+    }
+
+    fn init_main(state: &mut State) -> EvalResult {
+        state.push_int(2);
+        Ok(())
+    }
+
+    fn program() -> Vec<Module> {
+        vec![
+            Module::new("Main", vec![def("Main.main"), def("Main.size")])
+                .with_imports(&["Data.Queue", "Data.Stack"])
+                .with_init(init_main),
+            Module::new("Data.Stack", vec![def("Data.Stack.push"), def("Data.Stack.size")])
+                .with_imports(&["Data.Queue"]),
+            Module::new("Data.Queue", vec![def("Data.Queue.push"), def("Data.Queue.empty")])
+                .with_init(init_queue)
+        ]
+    }
+
+    fn link_error(modules: Vec<Module>) -> LinkError {
+        link(modules).err().expect("a link error")
+    }
+
+    #[test]
+    fn modules_come_after_those_they_import() {
+        let linked = link(program()).expect("linked");
+        assert_eq!(linked.modules().collect::<Vec<_>>(), ["Data.Queue", "Data.Stack", "Main"]);
+        assert_eq!(linked.aliases, [("empty", "Data.Queue.empty"), ("main", "Main.main")]);
+    }
+
+    #[test]
+    fn bad_programs_fail_to_link() {
+        let queue = || Module::new("Data.Queue", vec![def("Data.Queue.push")]);
+        assert_eq!(link_error(vec![queue(), queue()]), LinkError::DuplicateModule("Data.Queue"));
+        assert_eq!(link_error(vec![Module::new("Data.Queue", vec![def("Data.Queue.push"), def("Data.Queue.push")])]),
+                   LinkError::DuplicateDefinition("Data.Queue.push"));
+        assert_eq!(link_error(vec![Module::new("Data.Queue", vec![def("Data.Queued.push")])]),
+                   LinkError::Unqualified { module: "Data.Queue", name: "Data.Queued.push" });
+        assert_eq!(link_error(vec![Module::new("Data.Queue", vec![def("Data.Queue.")])]),
+                   LinkError::Unqualified { module: "Data.Queue", name: "Data.Queue." });
+        assert_eq!(link_error(vec![queue().with_imports(&["Data.List"])]),
+                   LinkError::MissingImport { module: "Data.Queue", import: "Data.List" });
+
+        let cycle = link_error(vec![
+            Module::new("A", Vec::new()).with_imports(&["B"]),
+            Module::new("B", Vec::new()).with_imports(&["C"]),
+            Module::new("C", Vec::new()).with_imports(&["B"])
+        ]);
+        assert_eq!(cycle, LinkError::ImportCycle(vec!["B", "C", "B"]));
+        assert_eq!(cycle.to_string(), "modules import each other: B -> C -> B");
+    }
+
+    #[test]
+    fn loading_registers_the_definitions_and_runs_the_inits() {
+        let mut state = State::new();
+        state.link_modules(program()).expect("loaded");
+        // the inits in order, the last on top
+        assert!(matches!(state.stack_pop(), Node::Int(2)));
+        assert!(matches!(state.stack_pop(), Node::Int(1)));
+
+        assert_eq!(state.globals.lookup("main").map(|fn_def| fn_def.name), Some("Main.main"));
+        assert_eq!(state.globals.lookup("Data.Stack.push").map(|fn_def| fn_def.name), Some("Data.Stack.push"));
+        // defined by more than one module, so only found qualified
+        assert!(state.globals.lookup("push").is_none());
+        assert!(state.globals.lookup("size").is_none());
+        assert!(state.global_main().is_ok());
+
+        let err = state.link_modules(vec![Module::new("Data.Queue", Vec::new())]).expect_err("loaded already");
+        assert_eq!(err.to_string(), "link: module Data.Queue linked twice");
+    }
+}
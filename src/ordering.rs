@@ -0,0 +1,89 @@
+use core::cmp::Ordering;
+use crate::builtins::*;
+
+// `data Ordering = LT | EQ | GT`, represented as nullary constructor nodes.
+
+pub const TAG_LT: u32 = 0;
+pub const TAG_EQ: u32 = 1;
+pub const TAG_GT: u32 = 2;
+
+pub fn to_tag(ordering: Ordering) -> u32 {
+    match ordering {
+        Ordering::Less => TAG_LT,
+        Ordering::Equal => TAG_EQ,
+        Ordering::Greater => TAG_GT
+    }
+}
+
+pub fn from_tag(tag: u32) -> Option<Ordering> {
+    match tag {
+        TAG_LT => Some(Ordering::Less),
+        TAG_EQ => Some(Ordering::Equal),
+        TAG_GT => Some(Ordering::Greater),
+        _ => None
+    }
+}
+
+impl State {
+    pub fn push_ordering(&mut self, ordering: Ordering) {
+        self.mk_data(to_tag(ordering), 0);
+    }
+
+    /// Forces the `Ordering` on top of the stack, popping it.
+    pub fn pop_ordering(&mut self) -> EvalResult<Ordering> {
+        self.eval()?;
+        let node = self.stack_pop();
+        match node {
+            Node::Data { tag, fields } if fields.is_empty() => {
+                from_tag(tag).ok_or_else(|| EvalError::type_mismatch("Ordering", "LT, EQ or GT", &node))
+            },
+            node => Err(EvalError::type_mismatch("Ordering", "LT, EQ or GT", &node))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    /// Applies `fn_def` to the values given and pops the `Ordering`.
+    fn compare(state: &mut State, fn_def: FnDef, l: &str, r: &str) -> EvalResult<Ordering> {
+        push_value(state, r);
+        push_value(state, l);
+        state.push_fn(fn_def);
+        state.apply(2)?;
+        state.pop_ordering()
+    }
+
+    #[test]
+    fn compare_gives_an_ordering() {
+        let mut state = State::new();
+        let cases = [("1", "2", Ordering::Less), ("'b'", "'b'", Ordering::Equal), ("2.5", "-1.0", Ordering::Greater),
+                     ("True", "False", Ordering::Greater)];
+        for (l, r, ordering) in cases {
+            assert_eq!(compare(&mut state, FN_COMPARE, l, r).ok(), Some(ordering), "compare {} {}", l, r);
+        }
+        assert!(compare(&mut state, FN_COMPARE, "1", "'a'").is_err());
+    }
+
+    #[test]
+    fn then_cmp_forces_the_second_only_on_eq() {
+        let mut state = State::new();
+        for (first, expected) in [(Ordering::Less, Some(Ordering::Less)), (Ordering::Equal, None)] {
+            state.push_fn(FN_UNDEFINED);
+            state.push_ordering(first);
+            state.push_fn(FN_THEN_CMP);
+            let result = state.apply(2).and_then(|()| state.pop_ordering());
+            assert_eq!(result.ok(), expected);
+        }
+    }
+
+    #[test]
+    fn orderings_round_trip_through_tags() {
+        for ordering in [Ordering::Less, Ordering::Equal, Ordering::Greater] {
+            assert_eq!(from_tag(to_tag(ordering)), Some(ordering));
+        }
+        assert_eq!(from_tag(3), None);
+    }
+}
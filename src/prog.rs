@@ -20,11 +20,12 @@ use crate::builtins::*;
 
 pub static FN_PROG: FnDef = FnDef {
     name: "prog",
-    arity: 2,
+    arity: 0,
+    strict: 0,
     fn_ref: eval_prog
 };
 
-pub fn eval_prog(state: &mut State) {
+pub fn eval_prog(state: &mut State) -> EvalResult {
     state.push_int(13);
 
     state.push_int(2);
@@ -48,5 +49,5 @@ pub fn eval_prog(state: &mut State) {
     state.mk_ap();
     state.mk_ap();
 
-    state.unwind();
+    Ok(())
 }
\ No newline at end of file
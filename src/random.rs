@@ -0,0 +1,438 @@
+#[cfg(all(feature = "std", not(feature = "wasm")))]
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::builtins::*;
+
+// `StdGen` is a SplitMix64 generator (as in the `random` package), pure and
+// splittable, represented as a constructor node with its seed and gamma in
+// two `Int` fields. The IO functions share one generator kept by the state.
+//
+// The functions generic over `Random` pick the type from their bounds:
+// `randomR` and `randomRs` generate `Int`s, `Double`s, `Char`s or `Bool`s
+// like the bounds given, and `random` and `randoms` only `Int`s.
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A SplitMix64 generator.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StdGen {
+    seed: u64,
+    gamma: u64
+}
+
+fn mix64(z: u64) -> u64 {
+    let z = (z ^ (z >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let z = (z ^ (z >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    z ^ (z >> 33)
+}
+
+fn mix64_variant13(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// An odd gamma with enough bit transitions to give a good sequence.
+fn mix_gamma(z: u64) -> u64 {
+    let z = mix64_variant13(z) | 1;
+    if (z ^ (z >> 1)).count_ones() >= 24 {
+        z
+    } else {
+        z ^ 0xaaaa_aaaa_aaaa_aaaa
+    }
+}
+
+impl StdGen {
+    /// `mkStdGen`, the same generator for the same seed.
+    pub fn new(seed: u64) -> Self {
+        StdGen { seed: mix64(seed), gamma: mix_gamma(seed.wrapping_add(GOLDEN_GAMMA)) }
+    }
+
+    /// A generator seeded from the clock, for the IO functions.
+    #[cfg(all(feature = "std", not(feature = "wasm")))]
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        StdGen::new(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    /// There is no process to tell one page from another, only the clock.
+    #[cfg(feature = "wasm")]
+    pub fn from_time() -> Self {
+        StdGen::new((crate::wasm::now() * 1e6) as u64)
+    }
+
+    /// Without `std` there is no clock either, every run gets the same
+    /// numbers.
+    #[cfg(not(feature = "std"))]
+    pub fn from_time() -> Self {
+        StdGen::new(0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.seed = self.seed.wrapping_add(self.gamma);
+        mix64(self.seed)
+    }
+
+    /// Two generators independent of each other and of the original.
+    pub fn split(self) -> (StdGen, StdGen) {
+        let seed = self.seed.wrapping_add(self.gamma);
+        let seed2 = seed.wrapping_add(self.gamma);
+        (StdGen { seed: seed2, gamma: self.gamma }, StdGen { seed: mix64(seed), gamma: mix_gamma(seed2) })
+    }
+
+    /// Uniform in `0..=max`, by rejecting the draws that would bias the remainder.
+    fn below_or_eq(&mut self, max: u64) -> u64 {
+        if max == u64::MAX {
+            return self.next_u64();
+        }
+        let range = max + 1;
+        let limit = u64::MAX - (u64::MAX - range + 1) % range;
+        loop {
+            let x = self.next_u64();
+            if x <= limit {
+                return x % range;
+            }
+        }
+    }
+
+    pub fn int_in(&mut self, lo: i64, hi: i64) -> i64 {
+        let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+        lo.wrapping_add(self.below_or_eq(hi.wrapping_sub(lo) as u64) as i64)
+    }
+
+    /// Uniform in `[lo, hi]` up to rounding.
+    pub fn double_in(&mut self, lo: f64, hi: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        lo + (hi - lo) * unit
+    }
+
+    /// Uniform over the scalar values in `[lo, hi]`, skipping surrogates.
+    pub fn char_in(&mut self, lo: char, hi: char) -> char {
+        let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+        loop {
+            if let Some(c) = char::from_u32(self.int_in(lo as i64, hi as i64) as u32) {
+                return c;
+            }
+        }
+    }
+}
+
+impl State {
+    pub fn push_std_gen(&mut self, gen: StdGen) {
+        self.push_int(gen.gamma as i64);
+        self.push_int(gen.seed as i64);
+        self.mk_data(0, 2);
+    }
+
+    /// Forces the `StdGen` on top of the stack, popping it.
+    pub fn pop_std_gen(&mut self) -> EvalResult<StdGen> {
+        self.eval()?;
+        let node = self.stack_pop();
+        match node {
+            Node::Data { tag: 0, fields } if fields.len() == 2 => match (fields[0], fields[1]) {
+                (Node::Int(seed), Node::Int(gamma)) => Ok(StdGen { seed: seed as u64, gamma: gamma as u64 }),
+                _ => Err(EvalError::type_mismatch("StdGen", "StdGen", &node))
+            },
+            _ => Err(EvalError::type_mismatch("StdGen", "StdGen", &node))
+        }
+    }
+
+    /// The generator shared by the IO functions, seeded on first use.
+    fn global_gen(&mut self) -> &mut StdGen {
+        self.std_gen.get_or_insert_with(StdGen::from_time)
+    }
+
+    /// Forces the `(lo, hi)` bounds on top of the stack and pushes a value
+    /// uniformly drawn between them from `gen`.
+    fn push_random_in(&mut self, gen: &mut StdGen, context: &'static str) -> EvalResult {
+        self.eval()?;
+        let bounds = match self.stack_pop() {
+            Node::Tuple(fields) if fields.len() == 2 => fields,
+            node => return Err(EvalError::type_mismatch(context, "pair of bounds", &node))
+        };
+        self.stack_push(bounds[1]);
+        self.stack_push(bounds[0]);
+        self.eval()?;
+        self.stack_swap();
+        self.eval()?;
+        let hi = self.stack_pop();
+        let lo = self.stack_pop();
+        let value = match (lo, hi) {
+            (Node::Int(lo), Node::Int(hi)) => Node::Int(gen.int_in(lo, hi)),
+            (Node::Double(lo), Node::Double(hi)) => Node::Double(gen.double_in(lo, hi)),
+            (Node::Char(lo), Node::Char(hi)) => Node::Char(gen.char_in(lo, hi)),
+            (Node::Bool(lo), Node::Bool(hi)) => Node::Bool(gen.int_in(lo as i64, hi as i64) != 0),
+            (lo, _) => return Err(EvalError::type_mismatch(context, "Int, Double, Char or Bool bounds", &lo))
+        };
+        self.stack_push(value);
+        Ok(())
+    }
+}
+
+pub static FN_MK_STD_GEN: FnDef = FnDef {
+    name: "mkStdGen",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_mk_std_gen
+};
+
+pub static FN_RANDOM: FnDef = FnDef {
+    name: "random",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_random
+};
+
+pub static FN_RANDOM_R: FnDef = FnDef {
+    name: "randomR",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_random_r
+};
+
+pub static FN_RANDOMS: FnDef = FnDef {
+    name: "randoms",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_randoms
+};
+
+pub static FN_RANDOM_RS: FnDef = FnDef {
+    name: "randomRs",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_random_rs
+};
+
+pub static FN_SPLIT: FnDef = FnDef {
+    name: "split",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_split
+};
+
+pub static FN_RANDOM_IO: FnDef = FnDef {
+    name: "randomIO",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_random_io
+};
+
+pub static FN_RANDOM_R_IO: FnDef = FnDef {
+    name: "randomRIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_random_r_io
+};
+
+pub static FN_NEW_STD_GEN: FnDef = FnDef {
+    name: "newStdGen",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_new_std_gen
+};
+
+static IO_RANDOM: FnDef = FnDef {
+    name: "randomIO",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_random_io
+};
+
+static IO_RANDOM_R: FnDef = FnDef {
+    name: "randomRIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_random_r_io
+};
+
+static IO_NEW_STD_GEN: FnDef = FnDef {
+    name: "newStdGen",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_new_std_gen
+};
+
+pub fn eval_mk_std_gen(state: &mut State) -> EvalResult {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Int(seed) => state.push_std_gen(StdGen::new(seed as u64)),
+        node => return Err(EvalError::type_mismatch("mkStdGen", "Int", &node))
+    }
+    Ok(())
+}
+
+/// An `Int` over its whole range and the generator after it.
+pub fn eval_random(state: &mut State) -> EvalResult {
+    let mut gen = state.pop_std_gen()?;
+    let value = gen.next_u64() as i64;
+    state.push_std_gen(gen);
+    state.push_int(value);
+    state.mk_tuple(2);
+    Ok(())
+}
+
+pub fn eval_random_r(state: &mut State) -> EvalResult {
+    state.stack_swap();
+    let mut gen = state.pop_std_gen()?;
+    state.push_random_in(&mut gen, "randomR")?;
+    state.push_std_gen(gen);
+    state.stack_swap();
+    state.mk_tuple(2);
+    Ok(())
+}
+
+/// An infinite list of `Int`s, each drawn once its cell is forced.
+pub fn eval_randoms(state: &mut State) -> EvalResult {
+    let mut gen = state.pop_std_gen()?;
+    let value = gen.next_u64() as i64;
+    state.push_std_gen(gen);
+    state.push_fn(FN_RANDOMS);
+    state.mk_ap();
+    state.mk_thunk();
+    state.push_int(value);
+    state.mk_cons();
+    Ok(())
+}
+
+/// An infinite list of values between the bounds, each drawn once its cell is forced.
+pub fn eval_random_rs(state: &mut State) -> EvalResult {
+    // the bounds are shared by the rest of the list
+    state.eval()?;
+    state.stack_swap();
+    let mut gen = state.pop_std_gen()?;
+    let bounds = *state.stack_peek();
+    state.stack_push(bounds);
+    state.push_random_in(&mut gen, "randomRs")?;
+    state.stack_swap();
+    state.push_std_gen(gen);
+    state.stack_swap();
+    state.push_fn(FN_RANDOM_RS);
+    state.mk_ap();
+    state.mk_ap();
+    state.mk_thunk();
+    state.stack_swap();
+    state.mk_cons();
+    Ok(())
+}
+
+pub fn eval_split(state: &mut State) -> EvalResult {
+    let (gen1, gen2) = state.pop_std_gen()?.split();
+    state.push_std_gen(gen2);
+    state.push_std_gen(gen1);
+    state.mk_tuple(2);
+    Ok(())
+}
+
+pub fn eval_random_io(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_RANDOM);
+    Ok(())
+}
+
+pub fn eval_random_r_io(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_RANDOM_R);
+    Ok(())
+}
+
+pub fn eval_new_std_gen(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_NEW_STD_GEN);
+    Ok(())
+}
+
+fn perform_random_io(state: &mut State) -> EvalResult {
+    let value = state.global_gen().next_u64() as i64;
+    state.push_int(value);
+    Ok(())
+}
+
+fn perform_random_r_io(state: &mut State) -> EvalResult {
+    let mut gen = *state.global_gen();
+    state.push_random_in(&mut gen, "randomRIO")?;
+    *state.global_gen() = gen;
+    Ok(())
+}
+
+/// Splits the shared generator, keeping one half and returning the other.
+fn perform_new_std_gen(state: &mut State) -> EvalResult {
+    let (kept, returned) = state.global_gen().split();
+    *state.global_gen() = kept;
+    state.push_std_gen(returned);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use super::*;
+
+    #[test]
+    fn generators_are_deterministic_and_split_apart() {
+        let draws = |mut gen: StdGen| [gen.next_u64(), gen.next_u64(), gen.next_u64()];
+        assert_eq!(draws(StdGen::new(42)), draws(StdGen::new(42)));
+        assert_ne!(draws(StdGen::new(42)), draws(StdGen::new(43)));
+
+        let (left, right) = StdGen::new(42).split();
+        assert_ne!(draws(left), draws(right));
+        assert_ne!(draws(left), draws(StdGen::new(42)));
+        assert_eq!(StdGen::new(42).split(), (left, right));
+    }
+
+    #[test]
+    fn values_stay_within_their_bounds() {
+        let mut gen = StdGen::new(7);
+        for _ in 0..1000 {
+            assert!((1..=6).contains(&gen.int_in(1, 6)));
+            assert!((-3..=-1).contains(&gen.int_in(-1, -3)));
+            assert!((0.5..=1.5).contains(&gen.double_in(0.5, 1.5)));
+            assert!(matches!(gen.char_in('\u{d7ff}', '\u{e000}'), '\u{d7ff}' | '\u{e000}'));
+        }
+        assert_eq!(gen.int_in(5, 5), 5);
+        gen.int_in(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn pure_generation_threads_the_generator() {
+        let mut state = State::new();
+        let draw = |state: &mut State| {
+            push_value(state, "(1,6)");
+            state.push_int(42);
+            state.push_fn(FN_MK_STD_GEN);
+            state.mk_ap();
+            state.stack_swap();
+            state.push_fn(FN_RANDOM_R);
+            state.mk_ap();
+            state.mk_ap();
+            let result = state.stack_pop();
+            state.render(result)
+        };
+        let first = draw(&mut state).expect("a value and a generator");
+        assert_eq!(draw(&mut state).ok(), Some(first.clone()));
+        let value: i64 = first[1..first.find(',').unwrap()].parse().expect("an Int");
+        assert!((1..=6).contains(&value));
+
+        // head (randomRs ('a', 'z') (mkStdGen 42))
+        push_value(&mut state, "('a','z')");
+        state.push_int(42);
+        state.push_fn(FN_MK_STD_GEN);
+        state.mk_ap();
+        state.stack_swap();
+        state.push_fn(FN_RANDOM_RS);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(FN_HEAD);
+        state.mk_ap();
+        let head = *state.stack_peek();
+        assert!(matches!(state.try_eval(head), Ok(Node::Char('a'..='z'))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_generation_uses_the_shared_generator() {
+        let mut state = State::new();
+        for _ in 0..10 {
+            let value = run_action(&mut state, FN_RANDOM_R_IO, &["(1,6)"]).expect("a value");
+            assert!((1..=6).contains(&value.parse::<i64>().expect("an Int")));
+        }
+        let err = run_action(&mut state, FN_RANDOM_R_IO, &["('a',1)"]).expect_err("mismatched bounds");
+        assert!(matches!(err.cause(), EvalError::TypeMismatch { context: "randomRIO", .. }));
+    }
+}
@@ -0,0 +1,149 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::{Add, Sub, Mul, Div, Neg};
+use crate::bigint::BigInt;
+
+/// An exact fraction, always kept in lowest terms
+/// with a positive denominator.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt
+}
+
+impl Rational {
+    /// Builds the normalized fraction `num / den`,
+    /// or `None` if the denominator is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Option<Self> {
+        if den.is_zero() {
+            return None;
+        }
+
+        let gcd = num.gcd(&den);
+        let (mut num, mut den) = (&num / &gcd, &den / &gcd);
+        if den.is_negative() {
+            num = -&num;
+            den = -&den;
+        }
+        Some(Rational { num, den })
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational { num: self.num.abs(), den: self.den.clone() }
+    }
+
+    pub fn signum(&self) -> Rational {
+        Rational { num: self.num.signum(), den: BigInt::from(1) }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num.to_f64() / self.den.to_f64()
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} % {}", self.num, self.den)
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // denominators are positive, so cross multiplying preserves order
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { num: -&self.num, den: self.den.clone() }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, rhs: &Rational) -> Rational {
+        let num = &(&self.num * &rhs.den) + &(&rhs.num * &self.den);
+        Rational::new(num, &self.den * &rhs.den).unwrap()
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, rhs: &Rational) -> Rational {
+        let num = &(&self.num * &rhs.den) - &(&rhs.num * &self.den);
+        Rational::new(num, &self.den * &rhs.den).unwrap()
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, rhs: &Rational) -> Rational {
+        Rational::new(&self.num * &rhs.num, &self.den * &rhs.den).unwrap()
+    }
+}
+
+impl Div for &Rational {
+    type Output = Rational;
+
+    fn div(self, rhs: &Rational) -> Rational {
+        Rational::new(&self.num * &rhs.den, &self.den * &rhs.num).expect("Ratio has zero denominator")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(num: i64, den: i64) -> Rational {
+        Rational::new(BigInt::from(num), BigInt::from(den)).expect("a nonzero denominator")
+    }
+
+    #[test]
+    fn fractions_are_kept_in_lowest_terms() {
+        let r = ratio(6, -4);
+        assert_eq!(r.numerator(), &BigInt::from(-3));
+        assert_eq!(r.denominator(), &BigInt::from(2));
+        assert_eq!(r.to_string(), "-3 % 2");
+        assert_eq!(ratio(0, -5), ratio(0, 1));
+        assert!(Rational::new(BigInt::from(1), BigInt::from(0)).is_none());
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        assert_eq!(&ratio(1, 2) + &ratio(1, 3), ratio(5, 6));
+        assert_eq!(&ratio(1, 2) - &ratio(5, 6), ratio(-1, 3));
+        assert_eq!(&ratio(2, 3) * &ratio(9, 4), ratio(3, 2));
+        assert_eq!(&ratio(2, 3) / &ratio(-4, 9), ratio(-3, 2));
+        assert_eq!(-&ratio(1, 7), ratio(-1, 7));
+        assert_eq!(ratio(-3, 4).abs(), ratio(3, 4));
+        assert_eq!(ratio(-3, 4).signum(), ratio(-1, 1));
+    }
+
+    #[test]
+    fn ordering_cross_multiplies() {
+        assert!(ratio(1, 3) < ratio(1, 2));
+        assert!(ratio(-1, 2) < ratio(-1, 3));
+        assert_eq!(ratio(2, 4).cmp(&ratio(1, 2)), Ordering::Equal);
+    }
+}
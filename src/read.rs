@@ -0,0 +1,450 @@
+use core::convert::TryFrom;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::format;
+use crate::builtins::*;
+use crate::builders::IntoNode;
+use crate::exception::{TAG_LEFT, TAG_RIGHT};
+use crate::maybe::{TAG_NOTHING, TAG_JUST};
+use crate::ordering::{TAG_LT, TAG_EQ, TAG_GT};
+use crate::show::ASCII_NAMES;
+
+// Parsing values from strings written as `show` writes them, for `read`
+// and `reads`. With no types to go by, what is read is decided by its
+// syntax: digits are an `Int`, or an `Integer` if too big for one, with a
+// fraction or an exponent a `Double`, and so on through characters,
+// strings, lists and tuples. Of constructors only those of the builtin
+// types are known: `True` and `False`, `Nothing` and `Just`, `Left` and
+// `Right`, and `LT`, `EQ` and `GT`.
+
+/// A value read, to be pushed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Integer(BigInt),
+    Double(f64),
+    Char(char),
+    Str(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Con(u32, Vec<Value>)
+}
+
+impl IntoNode for Value {
+    fn push_into(self, state: &mut State) {
+        match self {
+            Value::Int(int) => state.push_int(int),
+            Value::Integer(integer) => state.push_integer(integer),
+            Value::Double(double) => state.push_double(double),
+            Value::Char(c) => state.push_char(c),
+            Value::Str(s) => state.push_str(&s),
+            Value::Bool(b) => state.push(b),
+            Value::List(elems) => state.push(elems),
+            Value::Tuple(fields) => {
+                let arity = fields.len();
+                for field in fields {
+                    state.push(field);
+                }
+                state.mk_tuple_in_order(arity);
+            },
+            Value::Con(tag, fields) => {
+                let arity = fields.len();
+                for field in fields.into_iter().rev() {
+                    state.push(field);
+                }
+                state.mk_data(tag, arity);
+            }
+        }
+    }
+}
+
+/// Reads the value `s` holds and nothing else, but whitespace around it.
+pub fn read(s: &str) -> Option<Value> {
+    let (value, rest) = reads(s)?;
+    if rest.trim_start().is_empty() { Some(value) } else { None }
+}
+
+/// Reads the value at the start of `s`, giving it and what follows it.
+pub fn reads(s: &str) -> Option<(Value, &str)> {
+    let mut parser = Parser { s };
+    let value = parser.value()?;
+    Some((value, parser.s))
+}
+
+struct Parser<'a> {
+    /// What is left to read.
+    s: &'a str
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        self.s = self.s.trim_start();
+    }
+
+    /// Takes `token` after any whitespace, if it is next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        match self.s.strip_prefix(token) {
+            Some(rest) => {
+                self.s = rest;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s.chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.s = &self.s[c.len_utf8()..];
+        Some(c)
+    }
+
+    /// A value at the top level or in a list or tuple: a negative number,
+    /// a constructor applied to its fields or an atom.
+    fn value(&mut self) -> Option<Value> {
+        if self.eat("-") {
+            return match self.atom()? {
+                Value::Int(int) => Some(Value::Int(-int)),
+                // `i64::MIN` is too big an `Int` until negated
+                Value::Integer(integer) => {
+                    let integer = -&integer;
+                    Some(integer.to_i64().map_or(Value::Integer(integer), Value::Int))
+                },
+                Value::Double(double) => Some(Value::Double(-double)),
+                _ => None
+            };
+        }
+        self.skip_space();
+        if self.peek().is_some_and(|c| c.is_ascii_uppercase()) {
+            let name = self.ident();
+            let (tag, arity) = match name {
+                "Nothing" => (TAG_NOTHING, 0),
+                "Just" => (TAG_JUST, 1),
+                "Left" => (TAG_LEFT, 1),
+                "Right" => (TAG_RIGHT, 1),
+                _ => return self.nullary(name)
+            };
+            let fields = (0..arity).map(|_| self.atom()).collect::<Option<Vec<_>>>()?;
+            return Some(Value::Con(tag, fields));
+        }
+        self.atom()
+    }
+
+    /// A constructor taking no fields, the only kind an atom can be.
+    fn nullary(&mut self, name: &str) -> Option<Value> {
+        match name {
+            "True" => Some(Value::Bool(true)),
+            "False" => Some(Value::Bool(false)),
+            "Nothing" => Some(Value::Con(TAG_NOTHING, Vec::new())),
+            "LT" => Some(Value::Con(TAG_LT, Vec::new())),
+            "EQ" => Some(Value::Con(TAG_EQ, Vec::new())),
+            "GT" => Some(Value::Con(TAG_GT, Vec::new())),
+            _ => None
+        }
+    }
+
+    fn ident(&mut self) -> &'a str {
+        let end = self.s.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '\'')).unwrap_or(self.s.len());
+        let (ident, rest) = self.s.split_at(end);
+        self.s = rest;
+        ident
+    }
+
+    /// A value that needs no parentheses as a constructor's field.
+    fn atom(&mut self) -> Option<Value> {
+        self.skip_space();
+        match self.peek()? {
+            '0'..='9' => self.number(),
+            '\'' => {
+                self.next_char();
+                let c = match self.next_char()? {
+                    '\\' => self.escape()?,
+                    '\'' => return None,
+                    c => Some(c)
+                }?;
+                if self.next_char()? == '\'' { Some(Value::Char(c)) } else { None }
+            },
+            '"' => {
+                self.next_char();
+                let mut s = String::new();
+                loop {
+                    match self.next_char()? {
+                        '"' => return Some(Value::Str(s)),
+                        '\\' => s.extend(self.escape()?),
+                        c => s.push(c)
+                    }
+                }
+            },
+            '[' => {
+                self.next_char();
+                Some(Value::List(self.elems("]")?))
+            },
+            '(' => {
+                self.next_char();
+                let mut fields = self.elems(")")?;
+                if fields.len() == 1 { fields.pop() } else { Some(Value::Tuple(fields)) }
+            },
+            c if c.is_ascii_uppercase() => {
+                let name = self.ident();
+                self.nullary(name)
+            },
+            _ => None
+        }
+    }
+
+    /// Values separated by commas up to `close`, the opening bracket taken.
+    fn elems(&mut self, close: &str) -> Option<Vec<Value>> {
+        let mut elems = Vec::new();
+        if self.eat(close) {
+            return Some(elems);
+        }
+        loop {
+            elems.push(self.value()?);
+            if self.eat(close) {
+                return Some(elems);
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        for (prefix, radix) in [("0x", 16), ("0X", 16), ("0o", 8), ("0O", 8)] {
+            if let Some(rest) = self.s.strip_prefix(prefix) {
+                let end = rest.find(|c: char| !c.is_digit(radix)).unwrap_or(rest.len());
+                if end > 0 {
+                    self.s = &rest[end..];
+                    return Some(integral(&rest[..end], radix));
+                }
+            }
+        }
+
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let mut end = digits(self.s);
+        let mut fractional = false;
+        if let Some(frac) = self.s[end..].strip_prefix('.') {
+            if digits(frac) > 0 {
+                end += 1 + digits(frac);
+                fractional = true;
+            }
+        }
+        if let Some(exp) = self.s[end..].strip_prefix(['e', 'E']) {
+            let sign = if exp.starts_with(['+', '-']) { 1 } else { 0 };
+            if digits(&exp[sign..]) > 0 {
+                end += 1 + sign + digits(&exp[sign..]);
+                fractional = true;
+            }
+        }
+        let (number, rest) = self.s.split_at(end);
+        self.s = rest;
+        if fractional {
+            number.parse().ok().map(Value::Double)
+        } else {
+            Some(integral(number, 10))
+        }
+    }
+
+    /// The character of an escape in a literal, the backslash taken, or
+    /// `None` inside for one standing for none, `\&`.
+    fn escape(&mut self) -> Option<Option<char>> {
+        let c = match self.next_char()? {
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\x0b',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            '&' => return Some(None),
+            '^' => {
+                let c = self.next_char()?;
+                if !('@'..='_').contains(&c) {
+                    return None;
+                }
+                char::from(c as u8 - b'@')
+            },
+            'x' => return self.numeric_escape(16),
+            'o' => return self.numeric_escape(8),
+            c if c.is_ascii_digit() => return self.numeric_escape_from(c, 10),
+            c if c.is_ascii_uppercase() => {
+                let start = format!("{}{}", c, self.s);
+                // `SOH` before `SO`, the longest name that fits
+                let (code, name) = ASCII_NAMES.iter().enumerate()
+                    .chain(core::iter::once((0x7f, &"DEL")))
+                    .filter(|(_, name)| name.len() > 1 && start.starts_with(*name))
+                    .max_by_key(|(_, name)| name.len())?;
+                self.s = &self.s[name.len() - 1..];
+                char::from(code as u8)
+            },
+            _ => return None
+        };
+        Some(Some(c))
+    }
+
+    fn numeric_escape(&mut self, radix: u32) -> Option<Option<char>> {
+        let first = self.next_char()?;
+        self.numeric_escape_from(first, radix)
+    }
+
+    fn numeric_escape_from(&mut self, first: char, radix: u32) -> Option<Option<char>> {
+        let mut code = first.to_digit(radix)?;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(radix)) {
+            code = code.checked_mul(radix)?.checked_add(digit)?;
+            self.next_char();
+        }
+        char::from_u32(code).map(Some)
+    }
+}
+
+/// The integer of `digits` in `radix`, an `Int` if it fits.
+fn integral(digits: &str, radix: u32) -> Value {
+    if let Ok(int) = i64::from_str_radix(digits, radix) {
+        return Value::Int(int);
+    }
+    let radix_big = BigInt::from(radix as i64);
+    let integer = digits.chars().fold(BigInt::zero(), |acc, c| {
+        &(&acc * &radix_big) + &BigInt::from(c.to_digit(radix).unwrap() as i64)
+    });
+    Value::Integer(integer)
+}
+
+pub static FN_READ: FnDef = FnDef {
+    name: "read",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read
+};
+
+pub static FN_READS: FnDef = FnDef {
+    name: "reads",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_reads
+};
+
+/// Reads the value of a string, raising `Prelude.read: no parse` if it
+/// doesn't hold one.
+pub fn eval_read(state: &mut State) -> EvalResult {
+    state.deep_eval()?;
+    let s = String::try_from(state.stack_pop())?;
+    match read(&s) {
+        Some(value) => {
+            state.push(value);
+            Ok(())
+        },
+        None => Err(EvalError::Error("Prelude.read: no parse".to_string()))
+    }
+}
+
+/// The value at the start of a string and the rest of it, in a list as
+/// Haskell's `reads` gives them: of one pair, or empty if there is none.
+pub fn eval_reads(state: &mut State) -> EvalResult {
+    state.deep_eval()?;
+    let s = String::try_from(state.stack_pop())?;
+    match reads(&s) {
+        Some((value, rest)) => {
+            state.push_str(rest);
+            state.push(value);
+            state.mk_tuple(2);
+            state.mk_list(1);
+        },
+        None => state.push_nil()
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::call;
+
+    /// Reads what `value` shows as, after pushing it.
+    fn round_trip(value: Value) -> Option<Value> {
+        let mut state = State::new();
+        state.push(value);
+        read(&state.stack_pop().show())
+    }
+
+    #[test]
+    fn numbers_are_read_by_their_syntax() {
+        assert_eq!(read("42"), Some(Value::Int(42)));
+        assert_eq!(read(" -42 "), Some(Value::Int(-42)));
+        assert_eq!(read("0x2a"), Some(Value::Int(42)));
+        assert_eq!(read("0o52"), Some(Value::Int(42)));
+        assert_eq!(read("-9223372036854775808"), Some(Value::Int(i64::MIN)));
+        assert_eq!(read("9223372036854775808"), Some(Value::Integer(&BigInt::from(i64::MAX) + &BigInt::from(1))));
+        assert_eq!(read("2.5e-3"), Some(Value::Double(2.5e-3)));
+        assert_eq!(read("1e3"), Some(Value::Double(1000.0)));
+        assert_eq!(read("1."), None);
+        assert_eq!(read("--1"), None);
+    }
+
+    #[test]
+    fn constructors_take_atoms_as_fields() {
+        assert_eq!(read("Just (-1)"), Some(Value::Con(TAG_JUST, vec![Value::Int(-1)])));
+        assert_eq!(read("Just -1"), None);
+        assert_eq!(read("Just Nothing"), Some(Value::Con(TAG_JUST, vec![Value::Con(TAG_NOTHING, Vec::new())])));
+        assert_eq!(read("Just Just"), None);
+        assert_eq!(read("[Left 1, Right GT]"), Some(Value::List(vec![
+            Value::Con(TAG_LEFT, vec![Value::Int(1)]),
+            Value::Con(TAG_RIGHT, vec![Value::Con(TAG_GT, Vec::new())])
+        ])));
+        assert_eq!(read("((1), (True, 'x'), ())"), Some(Value::Tuple(vec![
+            Value::Int(1),
+            Value::Tuple(vec![Value::Bool(true), Value::Char('x')]),
+            Value::Tuple(Vec::new())
+        ])));
+        assert_eq!(read("Foo"), None);
+        assert_eq!(read("[1,]"), None);
+    }
+
+    #[test]
+    fn escapes_are_read_as_written() {
+        assert_eq!(read(r#""\^A\x41\o101\65\SOH\SO\&H\DEL\&""#), Some(Value::Str("\x01AAA\x01\x0eH\x7f".to_string())));
+        assert_eq!(read(r"'\''"), Some(Value::Char('\'')));
+        assert_eq!(read(r"'\1114112'"), None);
+        assert_eq!(read(r"'\&'"), None);
+        assert_eq!(read(r#""unterminated"#), None);
+    }
+
+    #[test]
+    fn shown_values_read_back_the_same() {
+        for x in [0.1, 1.0 / 3.0, -2.5e-3, 1.0e7, 1.5e22, 5.0e-324, f64::MAX, -0.0] {
+            let Some(Value::Double(read_back)) = round_trip(Value::Double(x)) else {
+                panic!("{} didn't read back", x);
+            };
+            assert_eq!(read_back.to_bits(), x.to_bits());
+        }
+
+        let s: String = (0..0x100).filter_map(char::from_u32).chain("\u{3bb}12\u{e}H".chars()).collect();
+        assert_eq!(round_trip(Value::Str(s.clone())), Some(Value::Str(s)));
+        for c in ['\'', '"', '\\', '\0', '\x7f', '\u{10ffff}'] {
+            assert_eq!(round_trip(Value::Char(c)), Some(Value::Char(c)));
+        }
+
+        let value = Value::List(vec![
+            Value::Tuple(vec![Value::Int(-1), Value::Double(-2.5)]),
+            Value::Tuple(vec![Value::Int(i64::MIN), Value::Double(f64::MIN_POSITIVE)])
+        ]);
+        assert_eq!(round_trip(value.clone()), Some(value));
+    }
+
+    #[test]
+    fn read_and_reads_take_strings() {
+        let mut state = State::new();
+        assert_eq!(call(&mut state, FN_READ, &[r#""[1, 2]""#]).unwrap(), "[1,2]");
+        let err = call(&mut state, FN_READ, &[r#""[1, 2] x""#]).expect_err("no parse");
+        assert_eq!(err.cause().to_string(), "Prelude.read: no parse");
+        assert_eq!(call(&mut state, FN_READS, &[r#""12 rest""#]).unwrap(), r#"[(12," rest")]"#);
+        assert_eq!(call(&mut state, FN_READS, &[r#""x""#]).unwrap(), "[]");
+    }
+}
@@ -0,0 +1,312 @@
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+#[cfg(feature = "std")]
+use crate::collections::HashMap;
+use crate::builtins::*;
+use crate::builtins;
+use crate::{action, show, read, exception, ioref, array, map, random, concurrent, stm, spark, weak, stable};
+#[cfg(feature = "std")]
+use crate::{io, handle, env, clock};
+
+// The builtins by name, for code that refers to them symbolically rather
+// than by Rust path: bytecode, compiled modules and native code, all
+// resolving the names they use when they are run. A builtin is found by
+// the name its `FnDef` carries or, for those whose Haskell name is an
+// operator or a constructor, by that too, e.g. `"+"` for `add`.
+
+/// Every builtin the registry knows.
+static BUILTINS: &[&FnDef] = &[
+    &builtins::FN_ADD,
+    &builtins::FN_SUB,
+    &builtins::FN_MUL,
+    &builtins::FN_FDIV,
+    &builtins::FN_DIV,
+    &builtins::FN_MOD,
+    &builtins::FN_QUOT,
+    &builtins::FN_REM,
+    &builtins::FN_DIV_MOD,
+    &builtins::FN_QUOT_REM,
+    &builtins::FN_NEGATE,
+    &builtins::FN_ABS,
+    &builtins::FN_SIGNUM,
+    &builtins::FN_POW,
+    &builtins::FN_GCD,
+    &builtins::FN_LCM,
+    &builtins::FN_MIN,
+    &builtins::FN_MAX,
+    &builtins::FN_BIT_AND,
+    &builtins::FN_BIT_OR,
+    &builtins::FN_XOR,
+    &builtins::FN_COMPLEMENT,
+    &builtins::FN_SHIFT_L,
+    &builtins::FN_SHIFT_R,
+    &builtins::FN_TEST_BIT,
+    &builtins::FN_POP_COUNT,
+    &builtins::FN_EQ,
+    &builtins::FN_NE,
+    &builtins::FN_LT,
+    &builtins::FN_LE,
+    &builtins::FN_GT,
+    &builtins::FN_GE,
+    &builtins::FN_DEEP_EQ,
+    &builtins::FN_COMPARE,
+    &builtins::FN_DEEP_COMPARE,
+    &builtins::FN_THEN_CMP,
+    &builtins::FN_DATA_TO_TAG,
+    &builtins::FN_SEQ,
+    &builtins::FN_STRICT_APP,
+    &builtins::FN_FORCE,
+    &builtins::FN_DEEPSEQ,
+    &builtins::FN_ERROR,
+    &builtins::FN_UNDEFINED,
+    &builtins::FN_TRACE,
+    &builtins::FN_IF,
+    &builtins::FN_NIL,
+    &builtins::FN_CONS,
+    &builtins::FN_HEAD,
+    &builtins::FN_TAIL,
+    &builtins::FN_PAIR,
+    &builtins::FN_TUPLE3,
+    &builtins::FN_TUPLE4,
+    &builtins::FN_FST,
+    &builtins::FN_SND,
+    &builtins::FN_TO_INTEGER,
+    &builtins::FN_FROM_INTEGER,
+    &builtins::FN_RATIO,
+    &builtins::FN_NUMERATOR,
+    &builtins::FN_DENOMINATOR,
+    &builtins::FN_PACK,
+    &builtins::FN_UNPACK,
+    &builtins::FN_TEXT_APPEND,
+    &builtins::FN_TEXT_LENGTH,
+    &builtins::FN_TEXT_INDEX,
+    &builtins::FN_ORD,
+    &builtins::FN_CHR,
+    &action::FN_RETURN_IO,
+    &action::FN_BIND_IO,
+    &action::FN_THEN_IO,
+    &show::FN_SHOW,
+    &show::FN_SHOWS_PREC,
+    &show::FN_SHOW_CON,
+    &read::FN_READ,
+    &read::FN_READS,
+    &exception::FN_TO_EXCEPTION,
+    &exception::FN_FROM_EXCEPTION,
+    &exception::FN_DISPLAY_EXCEPTION,
+    &exception::FN_THROW,
+    &exception::FN_THROW_IO,
+    &exception::FN_CATCH,
+    &exception::FN_HANDLE,
+    &exception::FN_TRY,
+    &exception::FN_EVALUATE,
+    &exception::FN_FINALLY,
+    &exception::FN_BRACKET,
+    &ioref::FN_NEW_IOREF,
+    &ioref::FN_READ_IOREF,
+    &ioref::FN_WRITE_IOREF,
+    &ioref::FN_MODIFY_IOREF,
+    &ioref::FN_MODIFY_IOREF_STRICT,
+    &array::FN_LIST_ARRAY,
+    &array::FN_INDEX,
+    &array::FN_BOUNDS,
+    &array::FN_ELEMS,
+    &array::FN_NEW_ARRAY,
+    &array::FN_READ_ARRAY,
+    &array::FN_WRITE_ARRAY,
+    &array::FN_GET_BOUNDS,
+    &map::FN_MAP_EMPTY,
+    &map::FN_MAP_SINGLETON,
+    &map::FN_MAP_INSERT,
+    &map::FN_MAP_LOOKUP,
+    &map::FN_MAP_MEMBER,
+    &map::FN_MAP_DELETE,
+    &map::FN_MAP_SIZE,
+    &map::FN_MAP_FOLDR_WITH_KEY,
+    &map::FN_MAP_FROM_LIST,
+    &map::FN_MAP_TO_LIST,
+    &map::FN_SET_EMPTY,
+    &map::FN_SET_INSERT,
+    &map::FN_SET_MEMBER,
+    &map::FN_SET_DELETE,
+    &map::FN_SET_SIZE,
+    &map::FN_SET_FOLDR,
+    &map::FN_SET_FROM_LIST,
+    &map::FN_SET_TO_LIST,
+    &random::FN_MK_STD_GEN,
+    &random::FN_RANDOM,
+    &random::FN_RANDOM_R,
+    &random::FN_RANDOMS,
+    &random::FN_RANDOM_RS,
+    &random::FN_SPLIT,
+    &random::FN_RANDOM_IO,
+    &random::FN_RANDOM_R_IO,
+    &random::FN_NEW_STD_GEN,
+    &concurrent::FN_FORK_IO,
+    &concurrent::FN_MY_THREAD_ID,
+    &concurrent::FN_YIELD,
+    &concurrent::FN_NEW_MVAR,
+    &concurrent::FN_NEW_EMPTY_MVAR,
+    &concurrent::FN_TAKE_MVAR,
+    &concurrent::FN_PUT_MVAR,
+    &concurrent::FN_READ_MVAR,
+    &stm::FN_NEW_TVAR,
+    &stm::FN_NEW_TVAR_IO,
+    &stm::FN_READ_TVAR,
+    &stm::FN_READ_TVAR_IO,
+    &stm::FN_WRITE_TVAR,
+    &stm::FN_ATOMICALLY,
+    &stm::FN_RETRY,
+    &stm::FN_OR_ELSE,
+    &spark::FN_PAR,
+    &spark::FN_PSEQ,
+    &weak::FN_MK_WEAK,
+    &weak::FN_MK_WEAK_PTR,
+    &weak::FN_DE_REF_WEAK,
+    &weak::FN_FINALIZE,
+    &stable::FN_REALLY_UNSAFE_PTR_EQUALITY,
+    &stable::FN_MAKE_STABLE_NAME,
+    &stable::FN_HASH_STABLE_NAME,
+    &stable::FN_EQ_STABLE_NAME,
+];
+
+/// The builtins that need the OS, the standard streams' first.
+#[cfg(feature = "std")]
+static STD_BUILTINS: &[&FnDef] = &[
+    &io::FN_PUT_STR,
+    &io::FN_PUT_STR_LN,
+    &io::FN_PRINT,
+    &io::FN_GET_LINE,
+    &io::FN_GET_CONTENTS,
+    &io::FN_INTERACT,
+    &handle::FN_STDIN,
+    &handle::FN_STDOUT,
+    &handle::FN_STDERR,
+    &handle::FN_OPEN_FILE,
+    &handle::FN_H_CLOSE,
+    &handle::FN_H_FLUSH,
+    &handle::FN_H_SET_BUFFERING,
+    &handle::FN_H_GET_LINE,
+    &handle::FN_H_GET_CONTENTS,
+    &handle::FN_H_PUT_STR,
+    &handle::FN_H_PUT_STR_LN,
+    &handle::FN_READ_FILE,
+    &handle::FN_WRITE_FILE,
+    &handle::FN_APPEND_FILE,
+    &env::FN_GET_ARGS,
+    &env::FN_GET_PROG_NAME,
+    &env::FN_GET_ENV,
+    &env::FN_LOOKUP_ENV,
+    &env::FN_SET_ENV,
+    &env::FN_EXIT_WITH,
+    &env::FN_EXIT_SUCCESS,
+    &env::FN_EXIT_FAILURE,
+    &clock::FN_GET_CURRENT_TIME,
+    &clock::FN_GET_MONOTONIC_TIME,
+    &clock::FN_GET_CPU_TIME,
+];
+
+/// The Haskell names of builtins named otherwise.
+static ALIASES: &[(&str, &FnDef)] = &[
+    ("+", &builtins::FN_ADD),
+    ("-", &builtins::FN_SUB),
+    ("*", &builtins::FN_MUL),
+    ("==", &builtins::FN_EQ),
+    ("/=", &builtins::FN_NE),
+    ("<", &builtins::FN_LT),
+    ("<=", &builtins::FN_LE),
+    (">", &builtins::FN_GT),
+    (">=", &builtins::FN_GE),
+    ("[]", &builtins::FN_NIL),
+    (":", &builtins::FN_CONS),
+    ("(,)", &builtins::FN_PAIR),
+    ("(,,)", &builtins::FN_TUPLE3),
+    ("(,,,)", &builtins::FN_TUPLE4),
+    ("Text.pack", &builtins::FN_PACK),
+    ("Text.unpack", &builtins::FN_UNPACK),
+    ("Text.append", &builtins::FN_TEXT_APPEND),
+    ("Text.length", &builtins::FN_TEXT_LENGTH),
+    ("Text.index", &builtins::FN_TEXT_INDEX),
+];
+
+/// Every builtin by its own name and then its aliases.
+fn by_name() -> impl Iterator<Item = (&'static str, &'static FnDef)> {
+    builtins().map(|fn_def| (fn_def.name, fn_def)).chain(ALIASES.iter().copied())
+}
+
+#[cfg(feature = "std")]
+fn index() -> &'static HashMap<&'static str, &'static FnDef> {
+    static INDEX: OnceLock<HashMap<&'static str, &'static FnDef>> = OnceLock::new();
+    INDEX.get_or_init(|| by_name().collect())
+}
+
+/// The builtin named `name`, if there is one.
+#[cfg(feature = "std")]
+pub fn builtin(name: &str) -> Option<&'static FnDef> {
+    index().get(name).copied()
+}
+
+/// Without `std` there is nowhere to keep an index, the builtins are
+/// searched.
+#[cfg(not(feature = "std"))]
+pub fn builtin(name: &str) -> Option<&'static FnDef> {
+    by_name().filter(|(other, _)| *other == name).last().map(|(_, fn_def)| fn_def)
+}
+
+/// The arity of the builtin named `name`, if there is one.
+pub fn arity(name: &str) -> Option<usize> {
+    builtin(name).map(|fn_def| fn_def.arity)
+}
+
+/// Every builtin under its own name, aliases aside.
+#[cfg(feature = "std")]
+pub fn builtins() -> impl Iterator<Item = &'static FnDef> {
+    BUILTINS.iter().chain(STD_BUILTINS).copied()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn builtins() -> impl Iterator<Item = &'static FnDef> {
+    BUILTINS.iter().copied()
+}
+
+/// Every name a builtin is found by, its own and its aliases.
+#[cfg(feature = "std")]
+pub fn names() -> impl Iterator<Item = &'static str> {
+    index().keys().copied()
+}
+
+/// Every name a builtin is found by, its own and its aliases, the same
+/// name as often as it is given.
+#[cfg(not(feature = "std"))]
+pub fn names() -> impl Iterator<Item = &'static str> {
+    by_name().map(|(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_are_found_by_name_and_alias() {
+        assert!(core::ptr::eq(builtin("add").unwrap(), &builtins::FN_ADD));
+        assert!(core::ptr::eq(builtin("+").unwrap(), &builtins::FN_ADD));
+        assert!(core::ptr::eq(builtin("(,,)").unwrap(), &builtins::FN_TUPLE3));
+        assert_eq!(arity(":"), Some(2));
+        assert!(builtin("no such builtin").is_none());
+        assert_eq!(arity("no such builtin"), None);
+    }
+
+    #[test]
+    fn every_builtin_is_found_by_its_own_name() {
+        for fn_def in builtins() {
+            assert!(core::ptr::eq(builtin(fn_def.name).unwrap(), fn_def), "{} is found as another", fn_def.name);
+        }
+        assert!(names().any(|name| name == "=="));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn builtins_needing_the_os_are_there_with_std() {
+        assert!(core::ptr::eq(builtin(io::FN_PUT_STR_LN.name).unwrap(), &io::FN_PUT_STR_LN));
+        assert_eq!(names().count(), builtins().count() + ALIASES.len());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::builtins::*;
+
+// The runtime's side of a REPL: the compiler turns each definition typed
+// at the prompt into a global and each expression into a node referring
+// to them, and the environment evaluates it against the definitions so
+// far. Which CAFs a changed definition affects isn't known, so changing
+// any releases them all, to be evaluated again against the new
+// definitions when next needed.
+
+/// An incremental environment of definitions on a state of its own, each
+/// `define` taking effect for the expressions evaluated after it.
+pub struct ReplEnv {
+    state: State,
+    /// The names defined so far, in the order they were first defined.
+    names: Vec<&'static str>
+}
+
+impl Default for ReplEnv {
+    fn default() -> Self {
+        ReplEnv::new()
+    }
+}
+
+impl ReplEnv {
+    pub fn new() -> Self {
+        ReplEnv::with_state(State::new())
+    }
+
+    /// An environment on a state configured already, with the globals it
+    /// has registered defined too.
+    pub fn with_state(state: State) -> Self {
+        let mut names: Vec<&'static str> = state.globals.names().collect();
+        names.sort_unstable();
+        ReplEnv { state, names }
+    }
+
+    pub fn state(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn into_state(self) -> State {
+        self.state
+    }
+
+    /// Adds `fn_def` under its name, returning the definition it replaces.
+    pub fn define(&mut self, fn_def: FnDef) -> Option<FnDef> {
+        let replaced = self.state.globals.register(fn_def);
+        if replaced.is_none() {
+            self.names.push(fn_def.name);
+        }
+        self.state.globals.release_cafs();
+        replaced
+    }
+
+    pub fn define_all(&mut self, fn_defs: &[FnDef]) {
+        for fn_def in fn_defs {
+            self.define(*fn_def);
+        }
+    }
+
+    /// Removes the definition of `name`, returning it.
+    pub fn undefine(&mut self, name: &str) -> Option<FnDef> {
+        let removed = self.state.globals.unregister(name)?;
+        self.names.retain(|other| *other != name);
+        self.state.globals.release_cafs();
+        Some(removed)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<FnDef> {
+        self.state.globals.lookup(name)
+    }
+
+    /// The names defined, in the order they were first defined.
+    pub fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    /// Loads the definitions of the program at `path`, see `entry::load`,
+    /// those it redefines replaced.
+    pub fn load(&mut self, path: &str) -> EvalResult {
+        crate::entry::load(&mut self.state, path)?;
+        let mut loaded: Vec<&'static str> = self.state.globals.names()
+            .filter(|name| !self.names.contains(name))
+            .collect();
+        loaded.sort_unstable();
+        self.names.extend(loaded);
+        Ok(())
+    }
+
+    /// Evaluates `expr` to normal form, performing it if it is an IO action,
+    /// as an expression typed at the prompt is. An exception it throws is
+    /// given as the error it stands for.
+    pub fn eval(&mut self, expr: Node) -> EvalResult<Node> {
+        let result = self.state.try_eval(expr).and_then(|value| match value {
+            Node::IO(_) => self.state.run_io(value),
+            value => Ok(value)
+        });
+        let result = result.and_then(|value| self.force(value));
+        result.map_err(|err| self.state.uncaught(err))
+    }
+
+    /// Evaluates the definition `name`.
+    pub fn eval_global(&mut self, name: &str) -> EvalResult<Node> {
+        self.state.push_global(name)?;
+        let expr = self.state.stack_pop();
+        self.eval(expr)
+    }
+
+    fn force(&mut self, value: Node) -> EvalResult<Node> {
+        let frame_size = self.state.stack_size();
+        self.state.stack_push(value);
+        match self.state.deep_eval() {
+            Ok(()) => Ok(self.state.stack_pop()),
+            Err(err) => {
+                self.state.stacks.last_mut().unwrap().truncate(frame_size);
+                Err(err)
+            }
+        }
+    }
+
+    /// Forgets every definition and the CAFs evaluated so far.
+    pub fn clear(&mut self) {
+        for name in self.names.drain(..) {
+            self.state.globals.unregister(name);
+        }
+        self.state.globals.release_cafs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::FN_RETURN_IO;
+
+    static FN_ANSWER: FnDef = FnDef {
+        name: "answer",
+        arity: 0,
+        strict: 0,
+        fn_ref: eval_answer
+    };
+
+    static FN_SCALE_ADD: FnDef = FnDef {
+        name: "scale",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_scale_add
+    };
+
+    static FN_SCALE_MUL: FnDef = FnDef {
+        name: "scale",
+        arity: 1,
+        strict: 0,
+        fn_ref: eval_scale_mul
+    };
+
+    /// `answer = scale 21`
+    fn eval_answer(state: &mut State) -> EvalResult {
+        state.push_int(21);
+        state.push_global("scale")?;
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// `scale x = x + x`
+    fn eval_scale_add(state: &mut State) -> EvalResult {
+        state.push(*state.stack_peek());
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    /// `scale x = x * 3`
+    fn eval_scale_mul(state: &mut State) -> EvalResult {
+        state.push_int(3);
+        state.stack_swap();
+        state.push_fn(FN_MUL);
+        state.mk_ap();
+        state.mk_ap();
+        Ok(())
+    }
+
+    #[test]
+    fn definitions_take_effect_for_later_expressions() {
+        let mut env = ReplEnv::new();
+        env.define_all(&[FN_SCALE_ADD, FN_ANSWER]);
+        assert_eq!(env.names(), ["scale", "answer"]);
+        assert!(matches!(env.eval_global("answer"), Ok(Node::Int(42))));
+
+        let replaced = env.define(FN_SCALE_MUL).expect("scale was defined");
+        assert_eq!(replaced.fn_ref as usize, FN_SCALE_ADD.fn_ref as usize);
+        assert_eq!(env.names(), ["scale", "answer"]);
+        // the CAF is evaluated again against the new definition
+        assert!(matches!(env.eval_global("answer"), Ok(Node::Int(63))));
+
+        assert!(env.undefine("scale").is_some());
+        assert_eq!(env.names(), ["answer"]);
+        let err = env.eval_global("answer").expect_err("scale is gone");
+        assert!(matches!(err.cause(), EvalError::UnknownGlobal(name) if name == "scale"));
+        env.clear();
+        assert!(env.names().is_empty());
+        assert!(env.lookup("answer").is_none());
+    }
+
+    #[test]
+    fn expressions_are_performed_and_forced() {
+        let mut env = ReplEnv::new();
+        let state = env.state();
+        // return [1 + 2]
+        state.push_nil();
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.mk_cons();
+        state.push_fn(FN_RETURN_IO);
+        state.mk_ap();
+        let expr = state.stack_pop();
+        let value = env.eval(expr).expect("a list");
+        let Node::Cons(head, _) = value else {
+            panic!("not a list");
+        };
+        assert!(matches!(head.short_circuit(), Node::Int(3)));
+    }
+}
@@ -0,0 +1,425 @@
+use core::convert::TryFrom;
+use core::fmt::Write;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::format;
+use crate::builtins::*;
+
+// Rendering values as Haskell's `show` does, for `show` and `print` and
+// for derived `Show` instances. Nodes carry no types, so only the builtin
+// types can be rendered by the node alone; a constructor of the program's
+// is rendered by its instance with `showCon`, given its name and fixity
+// and its fields shown at the precedence it calls for:
+//
+//     showsPrec d (C x y) = showCon d "C" (-1) [showsPrec 11 x, showsPrec 11 y]
+//     showsPrec d (x :+ y) = showCon d ":+" 6 [showsPrec 7 x, showsPrec 7 y]
+//
+// A constructor node rendered by itself is shown as `Pack{tag,arity}`.
+
+/// The precedence of function application, above every operator's.
+pub const APP_PREC: i64 = 10;
+
+impl Node {
+    /// Renders the value, in normal form, as `show` does.
+    pub fn show(&self) -> String {
+        self.show_prec(0)
+    }
+
+    /// Renders the value in a context of precedence `d`, see `shows_prec`.
+    pub fn show_prec(&self, d: i64) -> String {
+        let mut out = String::new();
+        self.shows_prec(d, &mut out);
+        out
+    }
+
+    /// Appends the value rendered in a context of precedence `d`, as
+    /// `showsPrec d` does: parenthesized if it binds less tightly.
+    pub fn shows_prec(&self, d: i64, out: &mut String) {
+        match self {
+            Node::Int(i) => show_signed(d, *i < 0, &i.to_string(), out),
+            Node::Integer(i) => show_signed(d, i.is_negative(), &i.to_string(), out),
+            Node::Ratio(r) => {
+                // infixl 7 %
+                show_paren(d > 7, out, |out| {
+                    show_signed(8, r.numerator().is_negative(), &r.numerator().to_string(), out);
+                    out.push_str(" % ");
+                    out.push_str(&r.denominator().to_string());
+                })
+            },
+            Node::Double(x) => show_signed(d, x.is_sign_negative() && !x.is_nan(), &show_double(*x), out),
+            Node::Bool(b) => out.push_str(if *b { "True" } else { "False" }),
+            Node::Char(c) => {
+                out.push('\'');
+                if *c == '\'' {
+                    out.push_str("\\'");
+                } else {
+                    show_lit_char(*c, None, out);
+                }
+                out.push('\'');
+            },
+            Node::Cons(head, _) if matches!(head.short_circuit(), Node::Char(_)) => {
+                let mut chars = Vec::new();
+                let mut rest = *self;
+                while let Node::Cons(head, tail) = rest {
+                    if let Node::Char(c) = head.short_circuit() {
+                        chars.push(c);
+                    }
+                    rest = tail.short_circuit();
+                }
+                show_string(chars.into_iter(), out);
+            },
+            Node::Text(text) => show_string(text.chars(), out),
+            Node::Nil => out.push_str("[]"),
+            Node::Cons(_, _) => {
+                out.push('[');
+                let mut rest = *self;
+                let mut first = true;
+                while let Node::Cons(head, tail) = rest {
+                    if !first {
+                        out.push(',');
+                    }
+                    first = false;
+                    head.short_circuit().shows_prec(0, out);
+                    rest = tail.short_circuit();
+                }
+                out.push(']');
+            },
+            Node::Tuple(fields) => {
+                out.push('(');
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    field.short_circuit().shows_prec(0, out);
+                }
+                out.push(')');
+            },
+            Node::Data { tag, fields } => {
+                let shown: Vec<String> = fields.iter()
+                    .map(|field| {
+                        let mut shown = String::new();
+                        field.short_circuit().shows_prec(APP_PREC + 1, &mut shown);
+                        shown
+                    })
+                    .collect();
+                show_con(d, &format!("Pack{{{},{}}}", tag, fields.len()), None, &shown, out);
+            },
+            Node::Array(array) => {
+                show_paren(d > APP_PREC, out, |out| {
+                    let _ = write!(out, "array ({},{}) [", array.lo, array.hi);
+                    for (i, elem) in array.elems.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        let _ = write!(out, "({},", array.lo + i as i64);
+                        elem.short_circuit().shows_prec(0, out);
+                        out.push(')');
+                    }
+                    out.push(']');
+                })
+            },
+            Node::Map(tree) => {
+                show_paren(d > APP_PREC, out, |out| {
+                    out.push_str("fromList [");
+                    for (i, (key, value)) in tree.entries().iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        out.push('(');
+                        key.short_circuit().shows_prec(0, out);
+                        out.push(',');
+                        value.short_circuit().shows_prec(0, out);
+                        out.push(')');
+                    }
+                    out.push(']');
+                })
+            },
+            Node::Set(tree) => {
+                show_paren(d > APP_PREC, out, |out| {
+                    out.push_str("fromList [");
+                    for (i, (key, _)) in tree.entries().iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        key.short_circuit().shows_prec(0, out);
+                    }
+                    out.push(']');
+                })
+            },
+            Node::ThunkRef(_) | Node::NodeRef(_) => self.short_circuit().shows_prec(d, out),
+            Node::FnDef(_) | Node::Pap(_, _) | Node::App(_, _) => out.push_str("<function>"),
+            node => {
+                let _ = write!(out, "{}", node);
+            }
+        }
+    }
+}
+
+/// A number, parenthesized if negative where a negation would bind too
+/// loosely, as `negate` is `infixl 6`.
+fn show_signed(d: i64, negative: bool, shown: &str, out: &mut String) {
+    show_paren(negative && d > 6, out, |out| out.push_str(shown));
+}
+
+fn show_paren<F: FnOnce(&mut String)>(paren: bool, out: &mut String, f: F) {
+    if paren {
+        out.push('(');
+    }
+    f(out);
+    if paren {
+        out.push(')');
+    }
+}
+
+/// Appends the constructor `name` applied to its fields shown already, in
+/// a context of precedence `d`: infix between its two fields if it has a
+/// `fixity`, its precedence, and prefix otherwise.
+pub fn show_con(d: i64, name: &str, fixity: Option<i64>, fields: &[String], out: &mut String) {
+    match (fixity, fields) {
+        (Some(prec), [left, right]) => show_paren(d > prec, out, |out| {
+            let _ = write!(out, "{} {} {}", left, name, right);
+        }),
+        _ => show_paren(d > APP_PREC && !fields.is_empty(), out, |out| {
+            out.push_str(name);
+            for field in fields {
+                out.push(' ');
+                out.push_str(field);
+            }
+        })
+    }
+}
+
+/// A `Double` as Haskell shows it: in positional notation from `0.1` up to
+/// `10^7` and in scientific notation otherwise, always with a fraction,
+/// with as few digits as tell it apart from every other `Double`.
+fn show_double(x: f64) -> String {
+    if x.is_nan() {
+        return "NaN".to_string();
+    }
+    if x.is_infinite() {
+        return if x < 0.0 { "-Infinity" } else { "Infinity" }.to_string();
+    }
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let x = x.abs();
+    if x == 0.0 {
+        return format!("{}0.0", sign);
+    }
+    // the shortest digits, and where the point goes after the first of them
+    let sci = format!("{:e}", x);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exp: i32 = exp.parse().unwrap();
+    if (0.1..1e7).contains(&x) {
+        let point = exp + 1;
+        if point <= 0 {
+            format!("{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+        } else {
+            let point = point as usize;
+            if digits.len() <= point {
+                format!("{}{}{}.0", sign, digits, "0".repeat(point - digits.len()))
+            } else {
+                format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+            }
+        }
+    } else {
+        let frac = if digits.len() > 1 { &digits[1..] } else { "0" };
+        format!("{}{}.{}e{}", sign, &digits[..1], frac, exp)
+    }
+}
+
+/// The names of the control characters, as they are escaped.
+pub(crate) const ASCII_NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "a", "b", "t", "n", "v", "f", "r", "SO", "SI",
+    "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC", "FS", "GS", "RS", "US"
+];
+
+/// Appends `c` as it is written in a literal, escaped if it has to be,
+/// given the character after it in a string so that an escape isn't read
+/// as running on into it.
+fn show_lit_char(c: char, next: Option<char>, out: &mut String) {
+    match c {
+        '\\' => out.push_str("\\\\"),
+        '\x7f' => out.push_str("\\DEL"),
+        c if (c as u32) > 0x7f => {
+            let _ = write!(out, "\\{}", c as u32);
+            if next.is_some_and(|next| next.is_ascii_digit()) {
+                out.push_str("\\&");
+            }
+        },
+        c if (c as u32) < 0x20 => {
+            out.push('\\');
+            out.push_str(ASCII_NAMES[c as usize]);
+            if c == '\x0e' && next == Some('H') {
+                out.push_str("\\&");
+            }
+        },
+        c => out.push(c)
+    }
+}
+
+fn show_string<I: Iterator<Item = char>>(chars: I, out: &mut String) {
+    out.push('"');
+    let mut chars = chars.peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            out.push_str("\\\"");
+        } else {
+            show_lit_char(c, chars.peek().copied(), out);
+        }
+    }
+    out.push('"');
+}
+
+pub static FN_SHOW: FnDef = FnDef {
+    name: "show",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_show
+};
+
+pub static FN_SHOWS_PREC: FnDef = FnDef {
+    name: "showsPrec",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_shows_prec
+};
+
+pub static FN_SHOW_CON: FnDef = FnDef {
+    name: "showCon",
+    arity: 4,
+    strict: 1,
+    fn_ref: eval_show_con
+};
+
+/// Renders a value, forced to normal form, as Haskell's `show` does.
+pub fn eval_show(state: &mut State) -> EvalResult {
+    state.deep_eval()?;
+    let shown = state.stack_pop().show();
+    state.push_str(&shown);
+    Ok(())
+}
+
+/// `showsPrec d x`, rendering `x`, forced to normal form, as a `String`.
+/// Unlike Haskell's it gives the string itself rather than a function
+/// prepending it.
+pub fn eval_shows_prec(state: &mut State) -> EvalResult {
+    let d = pop_prec(state, "showsPrec")?;
+    state.deep_eval()?;
+    let shown = state.stack_pop().show_prec(d);
+    state.push_str(&shown);
+    Ok(())
+}
+
+/// `showCon d name fixity fields`, a constructor applied to its fields
+/// shown already, for derived `Show` instances: prefix if `fixity` is
+/// negative, and otherwise infix with it as its precedence.
+pub fn eval_show_con(state: &mut State) -> EvalResult {
+    let d = pop_prec(state, "showCon")?;
+    state.deep_eval()?;
+    let name = String::try_from(state.stack_pop())?;
+    let fixity = pop_prec(state, "showCon")?;
+    state.deep_eval()?;
+    let fields = Vec::<String>::try_from(state.stack_pop())?;
+    let mut shown = String::new();
+    show_con(d, &name, if fixity < 0 { None } else { Some(fixity) }, &fields, &mut shown);
+    state.push_str(&shown);
+    Ok(())
+}
+
+fn pop_prec(state: &mut State, context: &'static str) -> EvalResult<i64> {
+    state.eval()?;
+    match state.stack_pop() {
+        Node::Int(d) => Ok(d),
+        node => Err(EvalError::type_mismatch(context, "Int", &node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::FN_MAP_FROM_LIST;
+    use crate::testing::{call, push_value};
+
+    fn show_prec(s: &str, d: i64) -> String {
+        let mut state = State::new();
+        push_value(&mut state, s);
+        state.stack_pop().show_prec(d)
+    }
+
+    fn show(s: &str) -> String {
+        show_prec(s, 0)
+    }
+
+    #[test]
+    fn doubles_are_shown_with_the_fewest_digits() {
+        let shown = |x: f64| Node::Double(x).show();
+        assert_eq!(shown(0.1), "0.1");
+        assert_eq!(shown(123.456), "123.456");
+        assert_eq!(shown(1.0 / 3.0), "0.3333333333333333");
+        assert_eq!(shown(1234567.0), "1234567.0");
+        assert_eq!(shown(1.0e7), "1.0e7");
+        assert_eq!(shown(0.01), "1.0e-2");
+        assert_eq!(shown(1.5e22), "1.5e22");
+        assert_eq!(shown(5.0e-324), "5.0e-324");
+        assert_eq!(shown(-0.0), "-0.0");
+        assert_eq!(shown(f64::NAN), "NaN");
+        assert_eq!(shown(f64::NEG_INFINITY), "-Infinity");
+    }
+
+    #[test]
+    fn characters_are_escaped() {
+        assert_eq!(show(r"'\''"), r"'\''");
+        assert_eq!(show(r#"'"'"#), r#"'"'"#);
+        assert_eq!(show(r"'\n'"), r"'\n'");
+        assert_eq!(show(r#""a\"b'""#), r#""a\"b'""#);
+        assert_eq!(show(r#""\1234\&5""#), r#""\1234\&5""#);
+        assert_eq!(show(r#""\SO\&H\SOH""#), r#""\SO\&H\SOH""#);
+        assert_eq!(show(r#""\DEL\\""#), r#""\DEL\\""#);
+        assert_eq!(show(r#""\1234\n""#), r#""\1234\n""#);
+    }
+
+    #[test]
+    fn negative_numbers_are_parenthesized_as_fields() {
+        assert_eq!(show("Just (-1)"), "Pack{1,1} (-1)");
+        assert_eq!(show("Just (-2.5)"), "Pack{1,1} (-2.5)");
+        assert_eq!(show("[-1, 2]"), "[-1,2]");
+        assert_eq!(show("(-1, -2)"), "(-1,-2)");
+        assert_eq!(show_prec("-1", 7), "(-1)");
+        assert_eq!(show_prec("-1", 6), "-1");
+        assert_eq!(show_prec("Just 1", 11), "(Pack{1,1} 1)");
+        assert_eq!(show_prec("Nothing", 11), "Pack{0,0}");
+
+        let mut state = State::new();
+        state.push_int(2);
+        state.push_int(-1);
+        state.push_fn(FN_RATIO);
+        state.apply(2).expect("a ratio");
+        let ratio = state.stack_pop();
+        assert_eq!(ratio.show(), "(-1) % 2");
+        assert_eq!(ratio.show_prec(8), "((-1) % 2)");
+    }
+
+    #[test]
+    fn containers_are_shown_as_built() {
+        let mut state = State::new();
+        push_value(&mut state, "[(2, 'b'), (1, 'a')]");
+        state.push_fn(FN_MAP_FROM_LIST);
+        state.apply(1).expect("a map");
+        state.deep_eval().expect("a map");
+        assert_eq!(state.stack_peek().show_prec(11), "(fromList [(1,'a'),(2,'b')])");
+    }
+
+    #[test]
+    fn constructors_are_shown_by_their_instances() {
+        let mut state = State::new();
+        let show_con = |state: &mut State, d: &str, name: &str, fixity: &str| {
+            call(state, FN_SHOW_CON, &[d, name, fixity, "[\"1\", \"(-2)\"]"]).expect("shown")
+        };
+        assert_eq!(show_con(&mut state, "6", "\":+\"", "6"), "\"1 :+ (-2)\"");
+        assert_eq!(show_con(&mut state, "7", "\":+\"", "6"), "\"(1 :+ (-2))\"");
+        assert_eq!(show_con(&mut state, "10", "\"C\"", "-1"), "\"C 1 (-2)\"");
+        assert_eq!(show_con(&mut state, "11", "\"C\"", "-1"), "\"(C 1 (-2))\"");
+        assert_eq!(call(&mut state, FN_SHOWS_PREC, &["11", "Just (-1)"]).expect("shown"), "\"(Pack{1,1} (-1))\"");
+        assert_eq!(call(&mut state, FN_SHOW, &[r#""\n""#]).expect("shown"), r#""\"\\n\"""#);
+    }
+}
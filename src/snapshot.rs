@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::io;
+use crate::builtins::*;
+use crate::gc::*;
+use crate::bytecode::{Reader, Writer, invalid};
+
+// A snapshot is a node graph as bytes, to be restored by a later run of
+// the program, e.g. a structure that takes long to compute. Each heap
+// object reachable from the node is written once, however many nodes
+// refer to it, so sharing and cycles are restored as they were.
+//
+// Evaluated thunks are written as their values, pending ones as the
+// expressions they stand for, unevaluated. Functions are written by name,
+// to be found again as globals or builtins; what only the run that made
+// it can have, IO actions, mutable variables, handles and so on, can't be
+// written at all. The same encoding copies graphs between the states of
+// one process, spark workers', with functions indexing a table that goes
+// along rather than written by name.
+//
+//     "HSN" version:u8 count:u32 object* node
+//
+// is the encoding, little-endian as `.hbc` files are, each object an
+// `ObjKind` byte followed by what it holds, and nodes referring to
+// objects by their position.
+
+const MAGIC: &[u8] = b"HSN";
+const VERSION: u8 = 1;
+
+/// The kinds of heap object a snapshot holds.
+#[derive(Clone, Copy)]
+enum ObjKind {
+    Node,
+    Fields,
+    Thunk,
+    Integer,
+    Ratio,
+    Text
+}
+
+impl ObjKind {
+    fn from_byte(byte: u8) -> io::Result<ObjKind> {
+        let kind = match byte {
+            0 => ObjKind::Node,
+            1 => ObjKind::Fields,
+            2 => ObjKind::Thunk,
+            3 => ObjKind::Integer,
+            4 => ObjKind::Ratio,
+            5 => ObjKind::Text,
+            _ => return Err(invalid("unknown object kind"))
+        };
+        Ok(kind)
+    }
+}
+
+/// A heap object waiting to be written.
+#[derive(Clone, Copy)]
+enum Obj {
+    Node(Gc<Node>),
+    Fields(Gc<Fields>),
+    Thunk(Gc<Thunk>),
+    Integer(Gc<BigInt>),
+    Ratio(Gc<Rational>),
+    Text(Gc<Text>)
+}
+
+struct Encoder<'a> {
+    state: &'a State,
+    writer: Writer,
+    ids: HashMap<(u8, usize), u32>,
+    queue: Vec<Obj>,
+    /// The functions written so far, by position, if they are kept as they
+    /// are rather than written by name.
+    fn_defs: Option<Vec<FnDef>>
+}
+
+impl Encoder<'_> {
+    /// The position of `obj`, queuing it to be written if it is new.
+    fn id(&mut self, kind: ObjKind, addr: usize, obj: Obj) -> u32 {
+        let next = self.ids.len() as u32;
+        let id = *self.ids.entry((kind as u8, addr)).or_insert(next);
+        if id == next {
+            self.queue.push(obj);
+        }
+        id
+    }
+
+    fn obj_ref(&mut self, kind: ObjKind, addr: usize, obj: Obj) {
+        let id = self.id(kind, addr, obj);
+        self.writer.count(id as usize);
+    }
+
+    fn node_ref(&mut self, node: Gc<Node>) {
+        self.obj_ref(ObjKind::Node, node.addr(), Obj::Node(node));
+    }
+
+    fn fields_ref(&mut self, fields: Gc<Fields>) {
+        self.obj_ref(ObjKind::Fields, fields.addr(), Obj::Fields(fields));
+    }
+
+    fn fn_def(&mut self, fn_def: &FnDef) -> io::Result<()> {
+        if let Some(fn_defs) = &mut self.fn_defs {
+            self.writer.count(fn_defs.len());
+            fn_defs.push(*fn_def);
+            return Ok(());
+        }
+        // written by name, it has to be the one the name is found as
+        match self.state.lookup_fn(fn_def.name) {
+            Ok(found) if found.fn_ref as usize == fn_def.fn_ref as usize => {
+                self.writer.str(fn_def.name);
+                Ok(())
+            },
+            _ => Err(unsupported(&format!("function {} is not a global or builtin", fn_def.name)))
+        }
+    }
+
+    fn node(&mut self, node: Node) -> io::Result<()> {
+        let node = node.short_circuit();
+        let tag = match node {
+            Node::Int(_) => 0,
+            Node::Double(_) => 1,
+            Node::Bool(_) => 2,
+            Node::Char(_) => 3,
+            Node::Nil => 4,
+            Node::FnDef(_) => 5,
+            Node::Pap(_, _) => 6,
+            Node::App(_, _) => 7,
+            Node::Cons(_, _) => 8,
+            Node::Data { .. } => 9,
+            Node::Tuple(_) => 10,
+            Node::Integer(_) => 11,
+            Node::Ratio(_) => 12,
+            Node::Text(_) => 13,
+            Node::ThunkRef(_) => 14,
+            node => return Err(unsupported(&format!("a {} can't be written", node.kind())))
+        };
+        self.writer.0.push(tag);
+        match node {
+            Node::Int(int) => self.writer.0.extend_from_slice(&int.to_le_bytes()),
+            Node::Double(double) => self.writer.0.extend_from_slice(&double.to_le_bytes()),
+            Node::Bool(b) => self.writer.0.push(b as u8),
+            Node::Char(c) => self.writer.count(c as usize),
+            Node::FnDef(fn_def) => self.fn_def(&fn_def)?,
+            Node::Pap(fn_def, fields) => {
+                self.fn_def(&fn_def)?;
+                self.fields_ref(fields);
+            },
+            Node::App(first, second) | Node::Cons(first, second) => {
+                self.node_ref(first);
+                self.node_ref(second);
+            },
+            Node::Data { tag, fields } => {
+                self.writer.count(tag as usize);
+                self.fields_ref(fields);
+            },
+            Node::Tuple(fields) => self.fields_ref(fields),
+            Node::Integer(integer) => self.obj_ref(ObjKind::Integer, integer.addr(), Obj::Integer(integer)),
+            Node::Ratio(ratio) => self.obj_ref(ObjKind::Ratio, ratio.addr(), Obj::Ratio(ratio)),
+            Node::Text(text) => self.obj_ref(ObjKind::Text, text.addr(), Obj::Text(text)),
+            Node::ThunkRef(thunk) => self.obj_ref(ObjKind::Thunk, thunk.addr(), Obj::Thunk(thunk)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn obj(&mut self, obj: Obj) -> io::Result<()> {
+        match obj {
+            Obj::Node(node) => {
+                self.writer.0.push(ObjKind::Node as u8);
+                self.node(*node)?;
+            },
+            Obj::Fields(fields) => {
+                self.writer.0.push(ObjKind::Fields as u8);
+                self.writer.count(fields.len());
+                for field in fields.iter() {
+                    self.node(*field)?;
+                }
+            },
+            Obj::Thunk(thunk) => {
+                self.writer.0.push(ObjKind::Thunk as u8);
+                match thunk.state {
+                    ThunkState::Pending(expr) => self.node(expr)?,
+                    ThunkState::EThunk(value) => self.node(value)?,
+                    ThunkState::UThunk(_) | ThunkState::UCall(_, _) => return Err(unsupported("a thunk of Rust code can't be written")),
+                    ThunkState::Blackhole => return Err(unsupported("a thunk being evaluated can't be written"))
+                }
+            },
+            Obj::Integer(integer) => {
+                self.writer.0.push(ObjKind::Integer as u8);
+                self.writer.str(&integer.to_string());
+            },
+            Obj::Ratio(ratio) => {
+                self.writer.0.push(ObjKind::Ratio as u8);
+                self.writer.str(&ratio.numerator().to_string());
+                self.writer.str(&ratio.denominator().to_string());
+            },
+            Obj::Text(text) => {
+                self.writer.0.push(ObjKind::Text as u8);
+                self.writer.str(&text);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// A node as read, referring to objects by position.
+enum RawNode {
+    Node(Node),
+    Pap(FnDef, u32),
+    App(u32, u32),
+    Cons(u32, u32),
+    Data(u32, u32),
+    Tuple(u32),
+    Obj(u32)
+}
+
+/// An object as read, before it is allocated.
+enum RawObj {
+    Node(RawNode),
+    Fields(Vec<RawNode>),
+    Thunk(RawNode),
+    Integer(BigInt),
+    Ratio(Rational),
+    Text(Text)
+}
+
+struct Decoder<'a, 'b> {
+    state: &'a State,
+    reader: Reader<'b>,
+    fn_defs: Option<&'b [FnDef]>
+}
+
+impl Decoder<'_, '_> {
+    fn id(&mut self) -> io::Result<u32> {
+        self.reader.u32()
+    }
+
+    fn integer(&mut self) -> io::Result<BigInt> {
+        self.reader.str()?.parse().map_err(|_| invalid("invalid Integer"))
+    }
+
+    fn node(&mut self) -> io::Result<RawNode> {
+        let node = match self.reader.take(1)?[0] {
+            0 => RawNode::Node(Node::Int(self.reader.u64()? as i64)),
+            1 => RawNode::Node(Node::Double(f64::from_bits(self.reader.u64()?))),
+            2 => RawNode::Node(Node::Bool(self.reader.take(1)?[0] != 0)),
+            3 => RawNode::Node(Node::Char(char::from_u32(self.reader.u32()?).ok_or_else(|| invalid("invalid char"))?)),
+            4 => RawNode::Node(Node::Nil),
+            5 => RawNode::Node(Node::FnDef(self.fn_def()?)),
+            6 => RawNode::Pap(self.fn_def()?, self.id()?),
+            7 => RawNode::App(self.id()?, self.id()?),
+            8 => RawNode::Cons(self.id()?, self.id()?),
+            9 => RawNode::Data(self.reader.u32()?, self.id()?),
+            10 => RawNode::Tuple(self.id()?),
+            11..=14 => RawNode::Obj(self.id()?),
+            _ => return Err(invalid("unknown node"))
+        };
+        Ok(node)
+    }
+
+    fn fn_def(&mut self) -> io::Result<FnDef> {
+        if let Some(fn_defs) = self.fn_defs {
+            let index = self.reader.count()?;
+            return fn_defs.get(index).copied().ok_or_else(|| invalid("function out of range"));
+        }
+        let name = self.reader.str()?;
+        self.state.lookup_fn(&name).map_err(|_| invalid(&format!("no function {}", name)))
+    }
+
+    fn obj(&mut self) -> io::Result<RawObj> {
+        let obj = match ObjKind::from_byte(self.reader.take(1)?[0])? {
+            ObjKind::Node => RawObj::Node(self.node()?),
+            ObjKind::Fields => {
+                let count = self.reader.count()?;
+                RawObj::Fields((0..count).map(|_| self.node()).collect::<io::Result<Vec<RawNode>>>()?)
+            },
+            ObjKind::Thunk => RawObj::Thunk(self.node()?),
+            ObjKind::Integer => RawObj::Integer(self.integer()?),
+            ObjKind::Ratio => {
+                let num = self.integer()?;
+                let den = self.integer()?;
+                RawObj::Ratio(Rational::new(num, den).ok_or_else(|| invalid("zero denominator"))?)
+            },
+            ObjKind::Text => RawObj::Text(self.reader.str()?)
+        };
+        Ok(obj)
+    }
+}
+
+/// The nodes standing for the objects restored, by position, each
+/// referring to its object whatever kind it is.
+struct Restored(Vec<Node>);
+
+impl Restored {
+    fn get(&self, id: u32) -> io::Result<Node> {
+        self.0.get(id as usize).copied().ok_or_else(|| invalid("object out of range"))
+    }
+
+    fn node(&self, id: u32) -> io::Result<Gc<Node>> {
+        match self.get(id)? {
+            Node::IORef(node) => Ok(node),
+            _ => Err(invalid("not a node"))
+        }
+    }
+
+    fn fields(&self, id: u32) -> io::Result<Gc<Fields>> {
+        match self.get(id)? {
+            Node::Tuple(fields) => Ok(fields),
+            _ => Err(invalid("not fields"))
+        }
+    }
+
+    fn resolve(&self, raw: &RawNode) -> io::Result<Node> {
+        let node = match *raw {
+            RawNode::Node(node) => node,
+            RawNode::Pap(fn_def, fields) => Node::Pap(fn_def, self.fields(fields)?),
+            RawNode::App(first, second) => Node::App(self.node(first)?, self.node(second)?),
+            RawNode::Cons(head, tail) => Node::Cons(self.node(head)?, self.node(tail)?),
+            RawNode::Data(tag, fields) => Node::Data { tag, fields: self.fields(fields)? },
+            RawNode::Tuple(fields) => Node::Tuple(self.fields(fields)?),
+            RawNode::Obj(id) => match self.get(id)? {
+                Node::IORef(_) | Node::Tuple(_) => return Err(invalid("not a value")),
+                node => node
+            }
+        };
+        Ok(node)
+    }
+}
+
+/// A node graph copied out of a state for another in the same process,
+/// e.g. on another thread: a snapshot, but with its functions kept as they
+/// are rather than written by name, so they needn't be globals.
+pub(crate) struct Graph {
+    bytes: Vec<u8>,
+    fn_defs: Vec<FnDef>
+}
+
+impl State {
+    /// Writes the node on top of the stack as a snapshot, leaving it there.
+    pub fn snapshot(&self) -> EvalResult<Vec<u8>> {
+        let (bytes, _) = self.encode(*self.stack_peek(), None, usize::MAX)
+            .map_err(|err| IoException::new("<snapshot>", "snapshot", &err))?;
+        Ok(bytes)
+    }
+
+    /// Pushes the node the snapshot `bytes` was made of, its functions
+    /// found by name among the globals and builtins.
+    pub fn restore(&mut self, bytes: &[u8]) -> EvalResult {
+        self.decode(bytes, None)
+    }
+
+    /// Copies the graph of `node`, failing if it has more than `limit` heap
+    /// objects or anything a snapshot can't hold.
+    pub(crate) fn copy_graph(&self, node: Node, limit: usize) -> io::Result<Graph> {
+        let (bytes, fn_defs) = self.encode(node, Some(Vec::new()), limit)?;
+        Ok(Graph { bytes, fn_defs: fn_defs.unwrap_or_default() })
+    }
+
+    /// Pushes the node `graph` was copied from.
+    pub(crate) fn restore_graph(&mut self, graph: &Graph) -> EvalResult {
+        self.decode(&graph.bytes, Some(&graph.fn_defs))
+    }
+
+    fn encode(&self, node: Node, fn_defs: Option<Vec<FnDef>>, limit: usize) -> io::Result<(Vec<u8>, Option<Vec<FnDef>>)> {
+        let mut encoder = Encoder { state: self, writer: Writer(Vec::new()), ids: HashMap::new(), queue: Vec::new(), fn_defs };
+        encoder.node(node)?;
+        let root = std::mem::replace(&mut encoder.writer, Writer(Vec::new()));
+        // each object is written whole before the next, writing one
+        // only queues those it refers to
+        let mut written = 0;
+        while let Some(obj) = encoder.queue.get(written).copied() {
+            if written == limit {
+                return Err(unsupported("too many objects"));
+            }
+            encoder.obj(obj)?;
+            written += 1;
+        }
+
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.0.push(VERSION);
+        writer.count(encoder.queue.len());
+        writer.0.extend_from_slice(&encoder.writer.0);
+        writer.0.extend_from_slice(&root.0);
+        Ok((writer.0, encoder.fn_defs))
+    }
+
+    fn decode(&mut self, bytes: &[u8], fn_defs: Option<&[FnDef]>) -> EvalResult {
+        let (objects, root) = self.read_snapshot(bytes, fn_defs).map_err(|err| IoException::new("<snapshot>", "restore", &err))?;
+
+        // every object is allocated first, left on the stack to keep it
+        // rooted, and only then filled in, as objects can refer to each other
+        // in cycles
+        let frame_size = self.stack_size();
+        for obj in objects.iter() {
+            match obj {
+                RawObj::Node(_) => {
+                    // an `IORef`, as the collector would follow a `NodeRef`
+                    let node = self.alloc(Node::Nil);
+                    self.stack_push(Node::IORef(node));
+                },
+                RawObj::Fields(_) => {
+                    let fields = self.alloc(Fields::new());
+                    self.stack_push(Node::Tuple(fields));
+                },
+                RawObj::Thunk(_) => self.push_hole(),
+                RawObj::Integer(integer) => self.push_integer(integer.clone()),
+                RawObj::Ratio(ratio) => self.push_ratio(ratio.clone()),
+                RawObj::Text(text) => self.push_text(text.clone())
+            }
+        }
+        let restored = Restored(self.stacks.last().unwrap()[frame_size..].to_vec());
+        let result = fill(&restored, &objects).and_then(|()| restored.resolve(&root));
+        self.stacks.last_mut().unwrap().truncate(frame_size);
+        let root = result.map_err(|err| IoException::new("<snapshot>", "restore", &err))?;
+        self.stack_push(root);
+        Ok(())
+    }
+
+    fn read_snapshot<'b>(&self, bytes: &'b [u8], fn_defs: Option<&'b [FnDef]>) -> io::Result<(Vec<RawObj>, RawNode)> {
+        let mut decoder = Decoder { state: self, reader: Reader(bytes), fn_defs };
+        if decoder.reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        if decoder.reader.take(1)?[0] != VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+        let count = decoder.reader.count()?;
+        let objects = (0..count).map(|_| decoder.obj()).collect::<io::Result<Vec<RawObj>>>()?;
+        let root = decoder.node()?;
+        if !decoder.reader.0.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        for obj in objects.iter() {
+            obj.nodes().iter().try_for_each(|raw| check_pap(&objects, raw))?;
+        }
+        check_pap(&objects, &root)?;
+        Ok((objects, root))
+    }
+}
+
+impl RawObj {
+    /// The nodes it holds.
+    fn nodes(&self) -> &[RawNode] {
+        match self {
+            RawObj::Node(raw) | RawObj::Thunk(raw) => std::slice::from_ref(raw),
+            RawObj::Fields(raw) => raw,
+            RawObj::Integer(_) | RawObj::Ratio(_) | RawObj::Text(_) => &[]
+        }
+    }
+}
+
+/// Fails if `raw` is a partial application holding more arguments than its
+/// function takes, which the evaluator has no way to apply.
+fn check_pap(objects: &[RawObj], raw: &RawNode) -> io::Result<()> {
+    if let RawNode::Pap(fn_def, id) = *raw {
+        if let Some(RawObj::Fields(args)) = objects.get(id as usize) {
+            if args.len() > fn_def.arity {
+                return Err(invalid(&format!("{} applied to {} arguments, it takes {}",
+                                            fn_def.name, args.len(), fn_def.arity)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fills in the objects allocated for a snapshot with what they hold.
+fn fill(restored: &Restored, objects: &[RawObj]) -> io::Result<()> {
+    for (obj, node) in objects.iter().zip(restored.0.iter()) {
+        match (obj, *node) {
+            (RawObj::Node(raw), Node::IORef(mut node)) => *node.as_mut() = restored.resolve(raw)?,
+            (RawObj::Fields(raw), Node::Tuple(mut fields)) => {
+                *fields.as_mut() = raw.iter().map(|field| restored.resolve(field)).collect::<io::Result<Fields>>()?;
+            },
+            (RawObj::Thunk(raw), Node::ThunkRef(mut thunk)) => {
+                thunk.as_mut().state = ThunkState::Pending(restored.resolve(raw)?);
+            },
+            // the rest are allocated with what they hold
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::read;
+
+    fn push_value(state: &mut State, s: &str) {
+        state.push(read(s).expect("a value"));
+    }
+
+    /// `add` applied to `args`, written by hand.
+    fn pap_snapshot(args: &[i64]) -> Vec<u8> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.0.push(VERSION);
+        writer.count(1);
+        writer.0.push(ObjKind::Fields as u8);
+        writer.count(args.len());
+        for arg in args {
+            writer.0.push(0);
+            writer.0.extend_from_slice(&arg.to_le_bytes());
+        }
+        writer.0.push(6);
+        writer.str("add");
+        writer.count(0);
+        writer.0
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut state = State::new();
+        for value in ["[1, -2, 3]", "(1.5, 'x', \"a\\nb\")", "Just [(True, 12345678901234567890)]"] {
+            push_value(&mut state, value);
+            let bytes = state.snapshot().expect("a snapshot");
+            let original = state.stack_pop();
+            let expected = state.render(original).expect("a value");
+
+            let mut restored = State::new();
+            restored.restore(&bytes).expect("restored");
+            let node = restored.stack_pop();
+            assert_eq!(restored.render(node).ok(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn pending_thunks_and_partial_applications_round_trip() {
+        let mut state = State::new();
+        // a thunk for `(add 2) 40`
+        state.push_int(40);
+        state.push_int(2);
+        state.mk_pap(FN_ADD, 1);
+        state.mk_ap();
+        state.mk_thunk();
+        let bytes = state.snapshot().expect("a snapshot");
+
+        let mut restored = State::new();
+        restored.restore(&bytes).expect("restored");
+        let node = restored.stack_pop();
+        assert!(matches!(node, Node::ThunkRef(_)));
+        assert!(matches!(restored.try_eval(node), Ok(Node::Int(42))));
+    }
+
+    #[test]
+    fn corrupt_snapshots_fail() {
+        let mut state = State::new();
+        state.restore(&pap_snapshot(&[1, 2])).expect("a saturated call");
+        state.stack_pop();
+        for bytes in [pap_snapshot(&[1, 2, 3]), b"HSX\x01".to_vec(), pap_snapshot(&[1])[..10].to_vec()] {
+            assert!(matches!(state.restore(&bytes), Err(EvalError::Io(_))));
+            assert_eq!(state.stack_size(), 0);
+        }
+    }
+}
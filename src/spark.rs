@@ -0,0 +1,203 @@
+use crate::collections::VecDeque;
+use crate::builtins::*;
+use crate::gc::*;
+
+#[cfg(feature = "std")]
+mod workers;
+
+// `par a b` is a hint that `a` will be needed: it sparks `a`, queueing
+// the thunk, and goes on with `b`. With worker threads, see
+// `State::set_spark_workers`, sparks are evaluated in parallel. The heap
+// belongs to the thread the state is on and a `Gc` can't be sent to
+// another, so a spark's graph is copied for a worker, which evaluates it
+// to WHNF on a heap of its own, and the value is copied back into the
+// thunk. Forcing a spark a worker is evaluating waits for it, and one no
+// worker has started yet is taken back and evaluated where it is forced.
+//
+// Sparks made with no workers, and those whose graphs can't be copied
+// (Rust code, IO, mutable variables) or are too big to be worth copying,
+// stay in the pool, converted on the state's thread only when the host
+// calls `State::run_sparks`, e.g. while it waits for input. Evaluating a
+// spark is only ever a head start: a thunk the program forces first
+// fizzles, and one that fails, on a worker or not, is left for the program
+// to fail on when it needs the value. What a worker's copy shares with the
+// rest of the graph is evaluated again if both need it. Without `std`
+// there are no threads, and no workers.
+
+/// Sparks made while the pool is full are dropped.
+const SPARK_POOL_SIZE: usize = 4096;
+
+#[derive(Default)]
+pub(crate) struct SparkPool {
+    sparks: VecDeque<Gc<Thunk>>,
+    #[cfg(feature = "std")]
+    pub(crate) workers: workers::WorkerPool
+}
+
+impl SparkPool {
+    /// Drops the sparks the program has forced since they were made, giving how many,
+    /// and marks the rest.
+    pub(crate) fn mark_roots(&mut self, worklist: &mut Worklist) -> u64 {
+        let before = self.sparks.len();
+        self.sparks.retain(|thunk| is_pending(thunk));
+        for thunk in self.sparks.iter() {
+            thunk.mark_into(worklist);
+        }
+        #[cfg(feature = "std")]
+        self.workers.mark_roots(worklist);
+        (before - self.sparks.len()) as u64
+    }
+
+    /// The sparks waiting to be converted, by the workers or here.
+    fn len(&self) -> usize {
+        self.sparks.len() + self.given()
+    }
+
+    #[cfg(feature = "std")]
+    fn given(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Without `std` no spark is given to workers.
+    #[cfg(not(feature = "std"))]
+    fn given(&self) -> usize {
+        0
+    }
+}
+
+fn is_pending(thunk: &Thunk) -> bool {
+    matches!(thunk.state, ThunkState::Pending(_) | ThunkState::UThunk(_) | ThunkState::UCall(_, _))
+}
+
+impl State {
+    /// Evaluates up to `limit` sparks, oldest first, returning how many were
+    /// converted. Failures are not reported: the thunk is left as it was,
+    /// to raise the error again when the program forces it. Those the
+    /// workers have evaluated meanwhile are taken first, and don't count.
+    pub fn run_sparks(&mut self, limit: usize) -> usize {
+        #[cfg(feature = "std")]
+        self.take_done_sparks();
+        let mut converted = 0;
+        while converted < limit {
+            let Some(thunk) = self.sparks.sparks.pop_front() else {
+                break;
+            };
+            if !is_pending(&thunk) {
+                self.stats.sparks_fizzled += 1;
+                continue;
+            }
+            let _ = self.try_eval(Node::ThunkRef(thunk));
+            self.stats.sparks_converted += 1;
+            converted += 1;
+        }
+        converted
+    }
+
+    /// The sparks waiting to be converted, by the workers or on this thread.
+    pub fn sparks_pending(&self) -> usize {
+        self.sparks.len()
+    }
+
+    fn spark(&mut self, node: Node) {
+        let Node::ThunkRef(thunk) = node else {
+            // already a value, there is nothing to evaluate
+            return;
+        };
+        self.stats.sparks_created += 1;
+        if !is_pending(&thunk) || self.sparks.len() >= SPARK_POOL_SIZE {
+            self.stats.sparks_fizzled += 1;
+            return;
+        }
+        #[cfg(feature = "std")]
+        if self.give_to_workers(thunk) {
+            return;
+        }
+        self.sparks.sparks.push_back(thunk);
+    }
+}
+
+pub static FN_PAR: FnDef = FnDef {
+    name: "par",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_par
+};
+
+pub static FN_PSEQ: FnDef = FnDef {
+    name: "pseq",
+    arity: 2,
+    strict: 1,
+    fn_ref: eval_pseq
+};
+
+/// Sparks `a` and gives `b`, evaluating neither here: see above.
+pub fn eval_par(state: &mut State) -> EvalResult {
+    let spark = state.stack_pop();
+    state.spark(spark);
+    Ok(())
+}
+
+/// `seq`, but promising the order too: `a` is forced before `b` is even
+/// looked at, where `seq` only promises both are.
+pub fn eval_pseq(state: &mut State) -> EvalResult {
+    state.stack_pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes the thunk `x op y`, giving it too.
+    fn push_thunk(state: &mut State, op: FnDef, x: i64, y: i64) -> Gc<Thunk> {
+        state.push_int(y);
+        state.push_int(x);
+        state.push_fn(op);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let Node::ThunkRef(thunk) = *state.stack_peek() else {
+            unreachable!()
+        };
+        thunk
+    }
+
+    #[test]
+    fn par_sparks_its_first_argument() {
+        let mut state = State::new();
+        state.push_int(7);
+        let thunk = push_thunk(&mut state, FN_ADD, 1, 2);
+        state.push_fn(FN_PAR);
+        state.apply(2).expect("the second argument");
+        assert!(matches!(state.stack_peek(), Node::Int(7)));
+        // kept rooted once it is out of the pool
+        state.push(Node::ThunkRef(thunk));
+        assert_eq!(state.sparks_pending(), 1);
+        assert!(is_pending(&thunk));
+
+        assert_eq!(state.run_sparks(10), 1);
+        assert!(matches!(thunk.state, ThunkState::EThunk(Node::Int(3))));
+        assert_eq!(state.sparks_pending(), 0);
+        assert_eq!(state.stats().sparks_converted, 1);
+    }
+
+    #[test]
+    fn sparks_forced_first_fizzle() {
+        let mut state = State::new();
+        let thunk = push_thunk(&mut state, FN_ADD, 1, 2);
+        state.spark(Node::ThunkRef(thunk));
+        state.try_eval(Node::ThunkRef(thunk)).expect("a sum");
+        assert_eq!(state.run_sparks(10), 0);
+        assert_eq!(state.stats().sparks_fizzled, 1);
+    }
+
+    #[test]
+    fn failed_sparks_are_left_to_fail_when_forced() {
+        let mut state = State::new();
+        let thunk = push_thunk(&mut state, FN_DIV, 1, 0);
+        state.spark(Node::ThunkRef(thunk));
+        assert_eq!(state.run_sparks(10), 1);
+        assert!(is_pending(&thunk));
+        assert!(state.try_eval(Node::ThunkRef(thunk)).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use crate::builtins::*;
+use crate::bytecode::Function;
+use crate::cancel::CancelToken;
+use crate::collections::{HashMap, VecDeque};
+use crate::gc::*;
+use crate::globals::{Definitions, Globals};
+use crate::snapshot::Graph;
+use super::{SPARK_POOL_SIZE, is_pending};
+
+// The worker threads sparks are given to, see `spark.rs`.
+
+/// Sparks and values of more heap objects than this aren't copied between
+/// threads, which could cost more than evaluating the spark where it is.
+const MAX_COPIED_OBJECTS: usize = 10_000;
+
+/// The sparks given to the workers, and the workers, if there are any.
+#[derive(Default)]
+pub(crate) struct WorkerPool {
+    /// The sparks given to the workers, by the address of their thunks.
+    jobs: HashMap<usize, (Gc<Thunk>, Arc<Job>)>,
+    running: Option<Workers>
+}
+
+impl WorkerPool {
+    pub(super) fn mark_roots(&self, worklist: &mut Worklist) {
+        for (thunk, _) in self.jobs.values() {
+            thunk.mark_into(worklist);
+        }
+    }
+
+    /// How many sparks are with the workers.
+    pub(super) fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Whether any spark is with the workers, so thunks being forced have
+    /// to be claimed from them first.
+    pub(crate) fn has_jobs(&self) -> bool {
+        !self.jobs.is_empty()
+    }
+}
+
+/// A spark given to the workers.
+struct Job {
+    progress: Mutex<Progress>,
+    /// Signalled once the job is done.
+    done: Condvar
+}
+
+enum Progress {
+    /// Waiting for a worker, with the spark's graph and the program to run
+    /// it with.
+    Queued(Graph, Arc<Program>),
+    Running,
+    /// Evaluated, to the graph of the value unless it failed.
+    Done(Option<Graph>),
+    /// Taken back to be evaluated where it was sparked, or its value taken.
+    Taken
+}
+
+/// What a worker's state needs to run the code its sparks refer to.
+struct Program {
+    generation: u64,
+    definitions: Definitions,
+    bytecode: HashMap<&'static str, &'static Function>
+}
+
+/// What the workers and the state share.
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled when a job is queued or the workers are to stop.
+    work: Condvar,
+    /// Cancels what the workers are evaluating when they are stopped.
+    cancel: CancelToken
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Arc<Job>>,
+    /// The jobs done since the state last looked, for it to take their values.
+    done: Vec<Arc<Job>>,
+    stop: bool
+}
+
+struct Workers {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+    /// The program as of the last spark given to the workers.
+    program: Option<Arc<Program>>
+}
+
+impl Workers {
+    fn start(count: usize) -> Workers {
+        let shared = Arc::new(Shared::default());
+        let threads = (0..count).map(|_| {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("spark worker".to_string())
+                .spawn(move || work(&shared))
+                .expect("a worker thread")
+        }).collect();
+        Workers { shared, threads, program: None }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().stop = true;
+        self.shared.cancel.cancel();
+        self.shared.work.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs jobs on a state of the worker's own until the workers are stopped.
+fn work(shared: &Shared) {
+    let mut state = State::new();
+    let mut generation = None;
+    loop {
+        let job = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.stop {
+                    return;
+                }
+                if let Some(job) = queue.jobs.pop_front() {
+                    break job;
+                }
+                queue = shared.work.wait(queue).unwrap();
+            }
+        };
+        let (graph, program) = {
+            let mut progress = job.progress.lock().unwrap();
+            match std::mem::replace(&mut *progress, Progress::Running) {
+                Progress::Queued(graph, program) => (graph, program),
+                // taken back before it was started
+                taken => {
+                    *progress = taken;
+                    continue;
+                }
+            }
+        };
+        if generation != Some(program.generation) {
+            state.globals = Globals::from_definitions(&program.definitions);
+            state.bytecode = program.bytecode.clone();
+            generation = Some(program.generation);
+        }
+        let value = panic::catch_unwind(AssertUnwindSafe(|| evaluate(&mut state, &graph, &shared.cancel)));
+        if value.is_err() {
+            // the state may have been left in the middle of anything
+            state = State::new();
+            generation = None;
+        }
+        *job.progress.lock().unwrap() = Progress::Done(value.unwrap_or(None));
+        job.done.notify_all();
+        shared.queue.lock().unwrap().done.push(job);
+    }
+}
+
+/// Evaluates the spark copied to `graph`, giving a copy of its value.
+fn evaluate(state: &mut State, graph: &Graph, cancel: &CancelToken) -> Option<Graph> {
+    state.restore_graph(graph).ok()?;
+    let spark = state.stack_pop();
+    let value = state.eval_with_cancel(spark, cancel).ok()?;
+    state.copy_graph(value, MAX_COPIED_OBJECTS).ok()
+}
+
+impl State {
+    /// Evaluates sparks on `workers` threads of their own from then on, or
+    /// on none if it is `0`, as by default. Changing it first waits for the
+    /// workers to finish what they are evaluating, and takes back whatever
+    /// they haven't started for `run_sparks`.
+    pub fn set_spark_workers(&mut self, workers: usize) {
+        drop(self.sparks.workers.running.take());
+        let jobs: Vec<_> = self.sparks.workers.jobs.values().map(|(thunk, _)| *thunk).collect();
+        for thunk in jobs {
+            self.stack_push(Node::ThunkRef(thunk));
+            self.claim_spark(thunk);
+            self.stack_pop();
+            if is_pending(&thunk) && self.sparks.sparks.len() < SPARK_POOL_SIZE {
+                self.sparks.sparks.push_back(thunk);
+            }
+        }
+        if workers > 0 {
+            self.sparks.workers.running = Some(Workers::start(workers));
+        }
+    }
+
+    /// Queues `thunk` for the workers, unless there are none or its graph
+    /// can't be copied.
+    pub(super) fn give_to_workers(&mut self, thunk: Gc<Thunk>) -> bool {
+        if self.sparks.workers.running.is_none() {
+            return false;
+        }
+        self.take_done_sparks();
+        if self.sparks.workers.jobs.contains_key(&thunk.addr()) {
+            return true;
+        }
+        let Ok(graph) = self.copy_graph(Node::ThunkRef(thunk), MAX_COPIED_OBJECTS) else {
+            return false;
+        };
+        let program = self.spark_program();
+        let Some(workers) = &self.sparks.workers.running else {
+            return false;
+        };
+        let job = Arc::new(Job { progress: Mutex::new(Progress::Queued(graph, program)), done: Condvar::new() });
+        workers.shared.queue.lock().unwrap().jobs.push_back(job.clone());
+        workers.shared.work.notify_one();
+        self.sparks.workers.jobs.insert(thunk.addr(), (thunk, job));
+        true
+    }
+
+    /// The program as it is now, for the workers.
+    fn spark_program(&mut self) -> Arc<Program> {
+        let generation = self.globals.generation();
+        let workers = self.sparks.workers.running.as_mut().expect("only sparks given to workers need it");
+        match &workers.program {
+            Some(program) if program.generation == generation => program.clone(),
+            _ => {
+                let program = Arc::new(Program {
+                    generation,
+                    definitions: self.globals.definitions(),
+                    bytecode: self.bytecode.clone()
+                });
+                workers.program = Some(program.clone());
+                program
+            }
+        }
+    }
+
+    /// Takes the values of the sparks the workers have evaluated.
+    pub(super) fn take_done_sparks(&mut self) {
+        let Some(workers) = &self.sparks.workers.running else {
+            return;
+        };
+        let done = std::mem::take(&mut workers.shared.queue.lock().unwrap().done);
+        for job in done {
+            let thunk = self.sparks.workers.jobs.values()
+                .find(|(_, other)| Arc::ptr_eq(other, &job))
+                .map(|(thunk, _)| *thunk);
+            if let Some(thunk) = thunk {
+                self.stack_push(Node::ThunkRef(thunk));
+                self.claim_spark(thunk);
+                self.stack_pop();
+            }
+        }
+    }
+
+    /// Takes `thunk`, which has to be rooted, back from the workers if it is
+    /// one of their sparks: its value if they have evaluated it, waiting for
+    /// them to if they have started, and otherwise leaving it to be
+    /// evaluated here.
+    pub(crate) fn claim_spark(&mut self, mut thunk: Gc<Thunk>) {
+        let Some((_, job)) = self.sparks.workers.jobs.remove(&thunk.addr()) else {
+            return;
+        };
+        let mut progress = job.progress.lock().unwrap();
+        while let Progress::Running = *progress {
+            progress = job.done.wait(progress).unwrap();
+        }
+        let Progress::Done(Some(graph)) = std::mem::replace(&mut *progress, Progress::Taken) else {
+            return;
+        };
+        drop(progress);
+        if self.restore_graph(&graph).is_err() {
+            return;
+        }
+        let value = self.stack_pop();
+        if is_pending(&thunk) {
+            thunk.as_mut().state = ThunkState::EThunk(value);
+            self.stats.thunks_updated += 1;
+            self.stats.sparks_converted += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::closure::thunk_fn;
+    use super::*;
+    use super::super::*;
+
+    static FN_NFIB: FnDef = FnDef {
+        name: "nfib",
+        arity: 1,
+        strict: 1,
+        fn_ref: eval_nfib
+    };
+
+    /// Pushes `f x` for the node `n` below the top.
+    fn push_ap(state: &mut State, f: FnDef, n: usize) {
+        let x = *state.stack_peek_nth(n);
+        state.push(x);
+        state.push_fn(f);
+        state.mk_ap();
+    }
+
+    /// `nfib n = if n < 2 then 1 else par x (pseq y (x + y + 1))`, with
+    /// `x = nfib (n - 1)` and `y = nfib (n - 2)`, only sparking `x` for
+    /// bigger `n`.
+    fn eval_nfib(state: &mut State) -> EvalResult {
+        let n = match state.stack_pop() {
+            Node::Int(n) => n,
+            node => return Err(EvalError::type_mismatch("nfib", "Int", &node))
+        };
+        if n < 2 {
+            state.push_int(1);
+            return Ok(());
+        }
+        for k in [2, 1] {
+            state.push_int(n - k);
+            state.push_fn(FN_NFIB);
+            state.mk_ap();
+            state.mk_thunk();
+        }
+        // [y, x]
+        state.push_int(1);
+        push_ap(state, FN_ADD, 2);
+        state.mk_ap();
+        // [y, x, y + 1]
+        push_ap(state, FN_ADD, 1);
+        state.mk_ap();
+        push_ap(state, FN_PSEQ, 2);
+        state.mk_ap();
+        if n > 10 {
+            push_ap(state, FN_PAR, 1);
+            state.mk_ap();
+        }
+        state.stack_bring_up(2);
+        state.stack_pop();
+        state.stack_bring_up(1);
+        state.stack_pop();
+        Ok(())
+    }
+
+    fn nfib(state: &mut State, n: i64) -> EvalResult<Node> {
+        state.push_int(n);
+        state.push_fn(FN_NFIB);
+        state.mk_ap();
+        let node = state.stack_pop();
+        state.try_eval(node)
+    }
+
+    #[test]
+    fn workers_give_the_same_values() {
+        let mut state = State::new();
+        let Ok(Node::Int(expected)) = nfib(&mut state, 16) else {
+            panic!("nfib failed");
+        };
+        state.set_spark_workers(4);
+        for _ in 0..3 {
+            assert!(matches!(nfib(&mut state, 16), Ok(Node::Int(n)) if n == expected));
+            assert_eq!(state.stack_size(), 0);
+        }
+    }
+
+    #[test]
+    fn workers_evaluate_sparks_in_the_background() {
+        let mut state = State::new();
+        state.set_spark_workers(1);
+        state.push_int(15);
+        state.push_fn(FN_NFIB);
+        state.mk_ap();
+        state.mk_thunk();
+        let Node::ThunkRef(thunk) = *state.stack_peek() else {
+            unreachable!()
+        };
+        state.spark(Node::ThunkRef(thunk));
+        assert_eq!(state.sparks_pending(), 1);
+
+        // nothing forces it, the worker's value is taken once it is done
+        let start = Instant::now();
+        while state.sparks_pending() > 0 {
+            assert!(start.elapsed() < Duration::from_secs(30), "the worker never finished");
+            thread::sleep(Duration::from_millis(1));
+            state.run_sparks(0);
+        }
+        assert!(matches!(thunk.state, ThunkState::EThunk(Node::Int(1973))));
+        assert_eq!(state.stats().sparks_converted, 1);
+    }
+
+    #[test]
+    fn sparks_that_cant_be_copied_stay_here() {
+        let mut state = State::new();
+        state.set_spark_workers(1);
+        state.push_thunk_eval(thunk_fn(|| Node::Int(7)));
+        let Node::ThunkRef(thunk) = *state.stack_peek() else {
+            unreachable!()
+        };
+        state.spark(Node::ThunkRef(thunk));
+        assert!(!state.sparks.workers.has_jobs());
+        assert_eq!(state.run_sparks(1), 1);
+        assert!(matches!(thunk.state, ThunkState::EThunk(Node::Int(7))));
+    }
+}
@@ -0,0 +1,268 @@
+use core::hash::{Hash, Hasher};
+use core::mem::Discriminant;
+use crate::builtins::*;
+use crate::gc::*;
+use crate::hash::Fnv;
+
+// Nodes are compared by identity, without being evaluated: two nodes are
+// the same if they are the same immediate value or refer to the same heap
+// objects, once updated thunks and indirections are followed through. A
+// thunk and the value it is updated with later are not the same, so
+// identity only ever tells that two values are shared, never that they are
+// not: `reallyUnsafePtrEquality` giving 0 means nothing.
+//
+// A stable name is the identity of a node at the time it was made. It holds
+// on to the node, so the addresses it is made of can't be reused by other
+// objects while it is alive, unlike in GHC, where stable names don't keep
+// what they name from being collected.
+
+/// What a node is the same as. The addresses of the objects it refers to,
+/// or its bits if it is immediate, with the constructor's tag, as nodes of
+/// different variants can refer to the same object.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Identity(Discriminant<Node>, u64, u64);
+
+impl Identity {
+    pub(crate) fn of(node: Node) -> Identity {
+        let node = node.short_circuit();
+        let (first, second) = match node {
+            Node::Int(int) => (int as u64, 0),
+            Node::Double(double) => (double.to_bits(), 0),
+            Node::Bool(b) => (b as u64, 0),
+            Node::Char(c) => (c as u64, 0),
+            Node::Nil => (0, 0),
+            Node::FnDef(fn_def) => (fn_def.fn_ref as usize as u64, 0),
+            Node::Pap(_, fields) | Node::Tuple(fields) => (fields.addr() as u64, 0),
+            Node::Data { tag, fields } => (fields.addr() as u64, tag as u64),
+            Node::App(first, second) | Node::Cons(first, second) => (first.addr() as u64, second.addr() as u64),
+            Node::Integer(integer) => (integer.addr() as u64, 0),
+            Node::Ratio(ratio) => (ratio.addr() as u64, 0),
+            Node::Text(text) => (text.addr() as u64, 0),
+            Node::ThunkRef(thunk) => (thunk.addr() as u64, 0),
+            Node::NodeRef(node) | Node::IORef(node) => (node.addr() as u64, 0),
+            Node::IO(action) => (action.addr() as u64, 0),
+            #[cfg(feature = "std")]
+            Node::Handle(handle) => (handle.addr() as u64, 0),
+            Node::IOArray(array) | Node::Array(array) => (array.addr() as u64, 0),
+            Node::Map(tree) | Node::Set(tree) => (tree.addr() as u64, 0),
+            Node::MVar(mvar) => (mvar.addr() as u64, 0),
+            Node::TVar(tvar) => (tvar.addr() as u64, 0),
+            Node::Weak(weak) => (weak.addr() as u64, 0),
+            Node::StableName(name) => (name.addr() as u64, 0)
+        };
+        Identity(core::mem::discriminant(&node), first, second)
+    }
+}
+
+pub struct StableName {
+    /// What is named, kept alive for as long as the name is.
+    node: Node,
+    identity: Identity
+}
+
+impl StableName {
+    /// The same for every stable name equal to this one, for as long as
+    /// they are alive.
+    pub fn hash(&self) -> i64 {
+        let mut hasher = Fnv::default();
+        self.identity.hash(&mut hasher);
+        hasher.finish() as i64
+    }
+}
+
+impl PartialEq for StableName {
+    fn eq(&self, other: &StableName) -> bool {
+        self.identity == other.identity
+    }
+}
+
+impl Trace for StableName {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        self.node.mark_refs(worklist);
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("StableName", None)
+    }
+}
+
+impl Node {
+    /// Whether the two nodes are the same, by identity rather than by
+    /// value. Neither is evaluated.
+    pub fn ptr_eq(&self, other: &Node) -> bool {
+        Identity::of(*self) == Identity::of(*other)
+    }
+}
+
+impl State {
+    /// Forces the stable name on top of the stack, popping it.
+    fn pop_stable_name(&mut self, context: &'static str) -> EvalResult<Gc<StableName>> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::StableName(name) => Ok(name),
+            node => Err(EvalError::type_mismatch(context, "StableName", &node))
+        }
+    }
+}
+
+pub static FN_REALLY_UNSAFE_PTR_EQUALITY: FnDef = FnDef {
+    name: "reallyUnsafePtrEquality",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_really_unsafe_ptr_equality
+};
+
+pub static FN_MAKE_STABLE_NAME: FnDef = FnDef {
+    name: "makeStableName",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_make_stable_name
+};
+
+pub static FN_HASH_STABLE_NAME: FnDef = FnDef {
+    name: "hashStableName",
+    arity: 1,
+    strict: 1,
+    fn_ref: eval_hash_stable_name
+};
+
+pub static FN_EQ_STABLE_NAME: FnDef = FnDef {
+    name: "eqStableName",
+    arity: 2,
+    strict: 2,
+    fn_ref: eval_eq_stable_name
+};
+
+static IO_MAKE_STABLE_NAME: FnDef = FnDef {
+    name: "makeStableName",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_make_stable_name
+};
+
+/// 1 if the arguments are the same, 0 if they are not or it can't tell,
+/// neither being evaluated.
+pub fn eval_really_unsafe_ptr_equality(state: &mut State) -> EvalResult {
+    let a = state.stack_pop();
+    let b = state.stack_pop();
+    state.push_int(a.ptr_eq(&b) as i64);
+    Ok(())
+}
+
+pub fn eval_make_stable_name(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MAKE_STABLE_NAME);
+    Ok(())
+}
+
+pub fn eval_hash_stable_name(state: &mut State) -> EvalResult {
+    let name = state.pop_stable_name("hashStableName")?;
+    state.push_int(name.hash());
+    Ok(())
+}
+
+pub fn eval_eq_stable_name(state: &mut State) -> EvalResult {
+    let a = state.pop_stable_name("eqStableName")?;
+    let b = state.pop_stable_name("eqStableName")?;
+    state.stack_push(Node::Bool(*a == *b));
+    Ok(())
+}
+
+/// Names the argument as it is, unevaluated: a thunk is named apart from
+/// the value it is updated with.
+fn perform_make_stable_name(state: &mut State) -> EvalResult {
+    let node = state.stack_pop().short_circuit();
+    let name = state.alloc(StableName { node, identity: Identity::of(node) });
+    state.stack_push(Node::StableName(name));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes the thunk `x + y`.
+    fn push_sum(state: &mut State, x: i64, y: i64) {
+        state.push_int(y);
+        state.push_int(x);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+    }
+
+    /// `reallyUnsafePtrEquality` of the nodes `a` and `b` below the top of the stack.
+    fn ptr_equality(state: &mut State, a: usize, b: usize) -> Node {
+        state.push(*state.stack_peek_nth(b));
+        state.push(*state.stack_peek_nth(a + 1));
+        state.push_fn(FN_REALLY_UNSAFE_PTR_EQUALITY);
+        state.apply(2).expect("an Int");
+        state.stack_pop()
+    }
+
+    /// Pushes a stable name for the node `n` below the top of the stack.
+    fn push_stable_name(state: &mut State, n: usize) {
+        state.push(*state.stack_peek_nth(n));
+        state.push_fn(FN_MAKE_STABLE_NAME);
+        state.mk_ap();
+        let action = state.stack_pop();
+        let name = state.run_io(action).expect("a stable name");
+        state.push(name);
+    }
+
+    fn apply_to_names(state: &mut State, fn_def: FnDef, a: usize, b: usize) -> Node {
+        state.push(*state.stack_peek_nth(b));
+        state.push(*state.stack_peek_nth(a + 1));
+        state.push_fn(fn_def);
+        state.apply(2).expect("a result");
+        state.stack_pop()
+    }
+
+    #[test]
+    fn shared_nodes_are_the_same() {
+        let mut state = State::new();
+        push_sum(&mut state, 1, 2);
+        push_sum(&mut state, 1, 2);
+        assert!(matches!(ptr_equality(&mut state, 0, 0), Node::Int(1)));
+        assert!(matches!(ptr_equality(&mut state, 0, 1), Node::Int(0)));
+        assert!(Node::Int(3).ptr_eq(&Node::Int(3)));
+
+        // an updated thunk is its value, but the value isn't known to be the thunk
+        let thunk = *state.stack_peek();
+        let value = state.try_eval(thunk).expect("a sum");
+        state.push(value);
+        assert!(matches!(ptr_equality(&mut state, 0, 1), Node::Int(1)));
+    }
+
+    #[test]
+    fn stable_names_name_the_node_as_it_was() {
+        let mut state = State::new();
+        push_sum(&mut state, 1, 2);
+        push_sum(&mut state, 1, 2);
+        push_stable_name(&mut state, 0);
+        push_stable_name(&mut state, 1);
+        push_stable_name(&mut state, 3);
+        // the names of the second thunk, twice, then of the first
+        assert!(matches!(apply_to_names(&mut state, FN_EQ_STABLE_NAME, 1, 2), Node::Bool(true)));
+        assert!(matches!(apply_to_names(&mut state, FN_EQ_STABLE_NAME, 0, 1), Node::Bool(false)));
+
+        let hash = |state: &mut State, n: usize| {
+            state.push(*state.stack_peek_nth(n));
+            state.push_fn(FN_HASH_STABLE_NAME);
+            state.apply(1).expect("a hash");
+            match state.stack_pop() {
+                Node::Int(hash) => hash,
+                node => panic!("not a hash: {:?}", node)
+            }
+        };
+        assert_eq!(hash(&mut state, 1), hash(&mut state, 2));
+
+        // naming the thunk again once it is evaluated names its value
+        let thunk = *state.stack_peek_nth(3);
+        state.try_eval(thunk).expect("a sum");
+        push_stable_name(&mut state, 3);
+        assert!(matches!(apply_to_names(&mut state, FN_EQ_STABLE_NAME, 0, 2), Node::Bool(false)));
+        state.push_int(3);
+        push_stable_name(&mut state, 0);
+        assert!(matches!(apply_to_names(&mut state, FN_EQ_STABLE_NAME, 0, 2), Node::Bool(true)));
+    }
+}
@@ -0,0 +1,68 @@
+use core::fmt;
+
+/// Counters describing the work the runtime has done so far,
+/// as returned by `State::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeStats {
+    /// Functions entered, builtin or compiled.
+    pub reductions: u64,
+    pub thunks_allocated: u64,
+    /// Thunks overwritten with their value after being forced.
+    pub thunks_updated: u64,
+    pub collections: u64,
+    /// Heap objects reclaimed by the collector.
+    pub objects_freed: u64,
+    /// The most objects (nodes, fields, numbers, text) live on the heap at once.
+    pub peak_live_objects: usize,
+    /// Thunks given to `par`.
+    pub sparks_created: u64,
+    /// Sparks evaluated ahead of the program needing them.
+    pub sparks_converted: u64,
+    /// Sparks dropped unevaluated, because the program forced them first
+    /// or the pool was full.
+    pub sparks_fizzled: u64,
+    /// Constructor fields and list cell heads and tails found already
+    /// allocated by hash-consing, rather than allocated again.
+    pub objects_shared: u64
+}
+
+/// One counter a line, as `HASKELITE_STATS` prints them.
+impl fmt::Display for RuntimeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>14} reductions", self.reductions)?;
+        writeln!(f, "{:>14} thunks allocated", self.thunks_allocated)?;
+        writeln!(f, "{:>14} thunks updated", self.thunks_updated)?;
+        writeln!(f, "{:>14} collections", self.collections)?;
+        writeln!(f, "{:>14} objects freed", self.objects_freed)?;
+        writeln!(f, "{:>14} peak live objects", self.peak_live_objects)?;
+        writeln!(f, "{:>14} sparks ({} converted, {} fizzled)", self.sparks_created, self.sparks_converted, self.sparks_fizzled)?;
+        write!(f, "{:>14} objects shared", self.objects_shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::*;
+
+    #[test]
+    fn counters_follow_evaluation() {
+        let mut state = State::new();
+        let before = state.stats();
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let node = *state.stack_peek();
+        state.try_eval(node).expect("a sum");
+        state.try_eval(node).expect("a sum");
+        let stats = state.stats();
+        assert_eq!(stats.thunks_allocated - before.thunks_allocated, 1);
+        assert_eq!(stats.thunks_updated - before.thunks_updated, 1);
+        assert_eq!(stats.reductions - before.reductions, 1);
+        assert!(stats.collections > before.collections);
+        assert!(stats.peak_live_objects >= 3);
+        assert!(stats.to_string().contains("1 thunks updated"));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::builtins::{Node, State, StepResult, Suspension, EvalResult};
+
+/// Evaluates an expression to WHNF one reduction at a time, stopping in
+/// front of each function entry so the graph can be inspected in between.
+///
+/// A step is one reduction of the unwinder. What a function evaluates on
+/// its own (as `case` does with its scrutinee) happens within its step,
+/// while forcing thunks and unwinding the spine happen between steps.
+pub struct Stepper<'a> {
+    state: &'a mut State,
+    suspension: Option<Suspension>,
+    result: Option<Node>,
+    steps: u64
+}
+
+impl<'a> Stepper<'a> {
+    /// Unwinds `node` up to its first redex, without reducing anything yet.
+    pub fn new(state: &'a mut State, node: Node) -> EvalResult<Self> {
+        let outcome = state.step_with_fuel(node, 0)?;
+        let mut stepper = Stepper { state, suspension: None, result: None, steps: 0 };
+        stepper.settle(outcome);
+        Ok(stepper)
+    }
+
+    /// Performs the next reduction and unwinds up to the redex after it.
+    /// Returns `false` without doing anything once evaluation is done.
+    ///
+    /// If the reduction fails the evaluation is dropped, as with
+    /// `State::try_eval`, and the stepper is done without a result.
+    pub fn step(&mut self) -> EvalResult<bool> {
+        let suspension = match self.suspension.take() {
+            Some(suspension) => suspension,
+            None => return Ok(false)
+        };
+        self.steps += 1;
+        let outcome = self.state.resume_steps(suspension, 1)?;
+        self.settle(outcome);
+        Ok(true)
+    }
+
+    /// Steps until evaluation is done, returning the value in WHNF.
+    pub fn run(&mut self) -> EvalResult<Option<Node>> {
+        while self.step()? {}
+        Ok(self.result)
+    }
+
+    fn settle(&mut self, outcome: StepResult) {
+        match outcome {
+            StepResult::Done(value) => self.result = Some(value),
+            StepResult::Suspended(suspension) => self.suspension = Some(suspension)
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.suspension.is_none()
+    }
+
+    /// The value in WHNF, once evaluation is done.
+    pub fn result(&self) -> Option<Node> {
+        self.result
+    }
+
+    /// The number of reductions performed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The state being evaluated in, e.g. to take a heap census between steps.
+    pub fn state(&self) -> &State {
+        self.state
+    }
+
+    /// The spine of the current frame, the function about to be entered
+    /// on top and the applications of it to its arguments below.
+    pub fn spine(&self) -> &[Node] {
+        match self.suspension {
+            Some(_) => self.state.stacks.last().unwrap(),
+            None => &[]
+        }
+    }
+
+    /// The expression the next step reduces: the application saturating
+    /// the function on top of the spine, or the function itself if it
+    /// takes no further arguments.
+    pub fn redex(&self) -> Option<Node> {
+        let spine = self.spine();
+        let head = *spine.last()?;
+        let needed = match head {
+            Node::FnDef(fn_def) => fn_def.arity,
+            Node::Pap(fn_def, args) => fn_def.arity - args.len(),
+            _ => return None
+        };
+        Some(spine[spine.len() - 1 - needed])
+    }
+}
+
+impl<'a> Drop for Stepper<'a> {
+    fn drop(&mut self) {
+        if let Some(suspension) = self.suspension.take() {
+            self.state.abandon(suspension);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use std::string::ToString;
+    use crate::builtins::*;
+    use super::*;
+
+    /// Pushes `f (add 1 2) 3`.
+    fn push_call(state: &mut State, fn_def: FnDef) {
+        state.push_int(3);
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.push_fn(fn_def);
+        state.mk_ap();
+        state.mk_ap();
+    }
+
+    #[test]
+    fn each_step_is_one_reduction() {
+        let mut state = State::new();
+        push_call(&mut state, FN_MUL);
+        let node = *state.stack_peek();
+        let mut stepper = Stepper::new(&mut state, node).expect("a redex");
+        let mut entered: Vec<&str> = Vec::new();
+        while stepper.redex().is_some() {
+            let Some(Node::FnDef(fn_def)) = stepper.spine().last() else {
+                panic!("a function on top of the spine");
+            };
+            entered.push(fn_def.name);
+            assert!(stepper.step().expect("a reduction"));
+        }
+        assert_eq!(entered, ["mul", "add"]);
+        assert!(stepper.is_done());
+        assert_eq!(stepper.steps(), 2);
+        assert_eq!(stepper.result().map(|value| value.to_string()), Some("9".to_string()));
+        assert!(!stepper.step().expect("nothing left"));
+    }
+
+    #[test]
+    fn a_failed_step_ends_the_evaluation() {
+        let mut state = State::new();
+        state.push_int(0);
+        state.push_int(1);
+        state.push_fn(FN_DIV);
+        state.mk_ap();
+        state.mk_ap();
+        let node = *state.stack_peek();
+        let depth = state.stack_size();
+        let mut stepper = Stepper::new(&mut state, node).expect("a redex");
+        assert!(stepper.step().is_err());
+        assert!(stepper.is_done());
+        assert!(stepper.result().is_none());
+        assert!(stepper.spine().is_empty());
+        drop(stepper);
+        assert_eq!(state.stack_size(), depth);
+    }
+}
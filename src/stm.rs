@@ -0,0 +1,480 @@
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::concurrent::Blocked;
+use crate::gc::*;
+
+// STM actions are IO actions that only read and write `TVar`s, performed by
+// `atomically` with a nested driver. Threads only take turns between the
+// steps of the outermost driver, so nothing else runs while a transaction
+// does and it is atomic as it is: all that has to be kept is a log of what
+// it read, to wait for once it retries, and of what it overwrote, to put
+// back when it is abandoned. A transaction that retries is rolled back and
+// its thread blocked until another commits a write to one of the `TVar`s
+// it read, when it is run again from the start.
+
+/// A variable read and written by transactions, its version counting the
+/// transactions that have committed writes to it.
+pub struct TVar {
+    value: Node,
+    pub(crate) version: u64
+}
+
+impl Trace for TVar {
+    fn mark_refs(&self, worklist: &mut Worklist) {
+        self.value.mark_refs(worklist);
+    }
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("TVar", None)
+    }
+}
+
+/// The log of the transaction `atomically` is performing.
+#[derive(Default)]
+pub(crate) struct Transaction {
+    /// The `TVar`s read, with their versions at the time.
+    reads: Vec<(Gc<TVar>, u64)>,
+    /// The `TVar`s written, with the values they held before, oldest first.
+    writes: Vec<(Gc<TVar>, Node)>
+}
+
+impl Transaction {
+    pub(crate) fn mark_roots(&self, worklist: &mut Worklist) {
+        for (tvar, _) in self.reads.iter() {
+            tvar.mark_into(worklist);
+        }
+        for (tvar, old) in self.writes.iter() {
+            tvar.mark_into(worklist);
+            old.mark_refs(worklist);
+        }
+    }
+
+    /// Puts back what the writes after the first `len` overwrote.
+    fn roll_back_to(&mut self, len: usize) {
+        for (mut tvar, old) in self.writes.drain(len..).rev() {
+            tvar.as_mut().value = old;
+        }
+    }
+
+    fn commit(&self) {
+        for (mut tvar, _) in self.writes.iter().copied() {
+            tvar.as_mut().version += 1;
+        }
+    }
+}
+
+impl State {
+    /// Forces the `TVar` on top of the stack, popping it.
+    fn pop_tvar(&mut self, context: &'static str) -> EvalResult<Gc<TVar>> {
+        self.eval()?;
+        match self.stack_pop() {
+            Node::TVar(tvar) => Ok(tvar),
+            node => Err(EvalError::type_mismatch(context, "TVar", &node))
+        }
+    }
+
+    fn push_tvar(&mut self) {
+        let value = self.stack_pop();
+        let tvar = self.alloc(TVar { value, version: 0 });
+        self.stack_push(Node::TVar(tvar));
+    }
+
+    fn read_tvar(&mut self, context: &'static str) -> EvalResult {
+        let tvar = self.pop_tvar(context)?;
+        if let Some(transaction) = &mut self.transaction {
+            transaction.reads.push((tvar, tvar.version));
+        }
+        self.stack_push(tvar.value);
+        Ok(())
+    }
+}
+
+pub static FN_NEW_TVAR: FnDef = FnDef {
+    name: "newTVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_new_tvar
+};
+
+pub static FN_NEW_TVAR_IO: FnDef = FnDef {
+    name: "newTVarIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_new_tvar
+};
+
+pub static FN_READ_TVAR: FnDef = FnDef {
+    name: "readTVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read_tvar
+};
+
+pub static FN_READ_TVAR_IO: FnDef = FnDef {
+    name: "readTVarIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_read_tvar_io
+};
+
+pub static FN_WRITE_TVAR: FnDef = FnDef {
+    name: "writeTVar",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_write_tvar
+};
+
+pub static FN_ATOMICALLY: FnDef = FnDef {
+    name: "atomically",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_atomically
+};
+
+pub static FN_RETRY: FnDef = FnDef {
+    name: "retry",
+    arity: 0,
+    strict: 0,
+    fn_ref: eval_retry
+};
+
+pub static FN_OR_ELSE: FnDef = FnDef {
+    name: "orElse",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_or_else
+};
+
+static STM_NEW_TVAR: FnDef = FnDef {
+    name: "newTVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_new_tvar
+};
+
+static STM_READ_TVAR: FnDef = FnDef {
+    name: "readTVar",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_read_tvar
+};
+
+static IO_READ_TVAR_IO: FnDef = FnDef {
+    name: "readTVarIO",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_read_tvar_io
+};
+
+static STM_WRITE_TVAR: FnDef = FnDef {
+    name: "writeTVar",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_write_tvar
+};
+
+static IO_ATOMICALLY: FnDef = FnDef {
+    name: "atomically",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_atomically
+};
+
+static STM_RETRY: FnDef = FnDef {
+    name: "retry",
+    arity: 0,
+    strict: 0,
+    fn_ref: perform_retry
+};
+
+static STM_OR_ELSE: FnDef = FnDef {
+    name: "orElse",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_or_else
+};
+
+/// `newTVar` and `newTVarIO` alike, a transaction having nothing to undo
+/// about a `TVar` nothing else can have seen.
+pub fn eval_new_tvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(STM_NEW_TVAR);
+    Ok(())
+}
+
+pub fn eval_read_tvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(STM_READ_TVAR);
+    Ok(())
+}
+
+pub fn eval_read_tvar_io(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_READ_TVAR_IO);
+    Ok(())
+}
+
+pub fn eval_write_tvar(state: &mut State) -> EvalResult {
+    state.mk_io_prim(STM_WRITE_TVAR);
+    Ok(())
+}
+
+pub fn eval_atomically(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_ATOMICALLY);
+    Ok(())
+}
+
+pub fn eval_retry(state: &mut State) -> EvalResult {
+    state.mk_io_prim(STM_RETRY);
+    Ok(())
+}
+
+pub fn eval_or_else(state: &mut State) -> EvalResult {
+    state.mk_io_prim(STM_OR_ELSE);
+    Ok(())
+}
+
+/// The initial value is stored as it is, unevaluated.
+fn perform_new_tvar(state: &mut State) -> EvalResult {
+    state.push_tvar();
+    Ok(())
+}
+
+fn perform_read_tvar(state: &mut State) -> EvalResult {
+    state.read_tvar("readTVar")
+}
+
+fn perform_read_tvar_io(state: &mut State) -> EvalResult {
+    state.read_tvar("readTVarIO")
+}
+
+fn perform_write_tvar(state: &mut State) -> EvalResult {
+    let mut tvar = state.pop_tvar("writeTVar")?;
+    let value = state.stack_pop();
+    if let Some(transaction) = &mut state.transaction {
+        transaction.writes.push((tvar, tvar.value));
+    }
+    tvar.as_mut().value = value;
+    state.push_unit();
+    Ok(())
+}
+
+/// Runs the transaction on top of the stack, committing its writes if it
+/// returns. If it throws they are rolled back and the exception goes on
+/// propagating, if it retries they are rolled back and the driver blocks
+/// the thread on what it read, to perform `atomically` again later.
+fn perform_atomically(state: &mut State) -> EvalResult {
+    if state.transaction.is_some() {
+        return Err(EvalError::Error("Control.Monad.STM.atomically was nested".to_string()));
+    }
+    let stm = state.stack_pop();
+    state.transaction = Some(Transaction::default());
+    let result = state.run_io(stm);
+    let mut transaction = state.transaction.take().expect("the transaction is kept until it ends");
+    match result {
+        Ok(value) => {
+            transaction.commit();
+            state.stack_push(value);
+            Ok(())
+        },
+        Err(err) => {
+            transaction.roll_back_to(0);
+            if *err.cause() != EvalError::Retry {
+                return Err(err);
+            }
+            state.scheduler.blocked = Some(Blocked::Retry(transaction.reads));
+            Ok(())
+        }
+    }
+}
+
+fn perform_retry(_state: &mut State) -> EvalResult {
+    Err(EvalError::Retry)
+}
+
+/// Runs the first transaction, and if it retries, rolls back its writes and
+/// runs the second instead. What the first read still counts if the second
+/// retries too, either changing could make the whole go ahead.
+fn perform_or_else(state: &mut State) -> EvalResult {
+    let first = state.stack_pop();
+    let len = state.transaction.as_ref().map_or(0, |transaction| transaction.writes.len());
+    // the second stays on the stack while the first is run
+    let result = match state.run_io(first) {
+        Err(err) if *err.cause() == EvalError::Retry => {
+            if let Some(transaction) = &mut state.transaction {
+                transaction.roll_back_to(len);
+            }
+            let second = state.stack_pop();
+            state.run_io(second)?
+        },
+        result => {
+            state.stack_pop();
+            result?
+        }
+    };
+    state.stack_push(result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::concurrent::FN_FORK_IO;
+    use crate::exception::{FN_CATCH, FN_THROW_IO, FN_TO_EXCEPTION};
+    use crate::action::*;
+    use super::*;
+
+    /// Applies `fn_def` to the `n` nodes on top of the stack, the first on top.
+    fn apply(state: &mut State, fn_def: FnDef, n: usize) {
+        state.push_fn(fn_def);
+        for _ in 0..n {
+            state.mk_ap();
+        }
+    }
+
+    /// A function of the `TVar` that builds its body on top of it.
+    fn lambda(name: &'static str, fn_ref: StateFn) -> FnDef {
+        FnDef { name, arity: 1, strict: 0, fn_ref }
+    }
+
+    /// Pushes `m >> n`, `n` having been pushed first and `m` pushed by `push_m`.
+    fn then(state: &mut State, push_m: impl FnOnce(&mut State)) {
+        push_m(state);
+        apply(state, FN_THEN_IO, 2);
+    }
+
+    /// Pushes `writeTVar t n`.
+    fn write(state: &mut State, t: Node, n: i64) {
+        state.push_int(n);
+        state.push(t);
+        apply(state, FN_WRITE_TVAR, 2);
+    }
+
+    /// Runs `newTVarIO 0 >>= body`.
+    fn run_with_tvar(body: FnDef) -> EvalResult<Node> {
+        let mut state = State::new();
+        state.push_fn(body);
+        state.push_int(0);
+        apply(&mut state, FN_NEW_TVAR_IO, 1);
+        apply(&mut state, FN_BIND_IO, 2);
+        let main = state.stack_pop();
+        let result = state.run_io(main).and_then(|result| state.try_eval(result));
+        assert_eq!(state.stack_size(), 0);
+        result
+    }
+
+    /// Replaces the `TVar` below the body with the body.
+    fn drop_tvar(state: &mut State) -> EvalResult {
+        state.stack_swap();
+        state.stack_pop();
+        Ok(())
+    }
+
+    /// `\t -> atomically (writeTVar t 1 >> writeTVar t 2) >> readTVarIO t`
+    fn commit_body(state: &mut State) -> EvalResult {
+        let t = *state.stack_peek();
+        state.push(t);
+        apply(state, FN_READ_TVAR_IO, 1);
+        write(state, t, 2);
+        then(state, |state| write(state, t, 1));
+        apply(state, FN_ATOMICALLY, 1);
+        apply(state, FN_THEN_IO, 2);
+        drop_tvar(state)
+    }
+
+    /// `\t -> catch (atomically (writeTVar t 1 >> throwIO (toException 0))) return >> readTVarIO t`
+    fn throw_body(state: &mut State) -> EvalResult {
+        let t = *state.stack_peek();
+        state.push(t);
+        apply(state, FN_READ_TVAR_IO, 1);
+        state.push_fn(FN_RETURN_IO);
+        state.push_int(0);
+        apply(state, FN_TO_EXCEPTION, 1);
+        apply(state, FN_THROW_IO, 1);
+        then(state, |state| write(state, t, 1));
+        apply(state, FN_ATOMICALLY, 1);
+        apply(state, FN_CATCH, 2);
+        apply(state, FN_THEN_IO, 2);
+        drop_tvar(state)
+    }
+
+    static CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+    /// `\v -> if v == 0 then retry else return v`, counting its calls.
+    fn check_body(state: &mut State) -> EvalResult {
+        CHECKS.fetch_add(1, Ordering::SeqCst);
+        let v = state.stack_pop();
+        match state.try_eval(v)? {
+            Node::Int(0) => state.push_fn(FN_RETRY),
+            v => {
+                state.push(v);
+                apply(state, FN_RETURN_IO, 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// `\t -> forkIO (atomically (writeTVar t 5)) >> atomically (readTVar t >>= check)`
+    fn retry_body(state: &mut State) -> EvalResult {
+        let t = *state.stack_peek();
+        state.push_fn(lambda("check", check_body));
+        state.push(t);
+        apply(state, FN_READ_TVAR, 1);
+        apply(state, FN_BIND_IO, 2);
+        apply(state, FN_ATOMICALLY, 1);
+        write(state, t, 5);
+        apply(state, FN_ATOMICALLY, 1);
+        apply(state, FN_FORK_IO, 1);
+        apply(state, FN_THEN_IO, 2);
+        drop_tvar(state)
+    }
+
+    /// `\t -> atomically retry`
+    fn deadlock_body(state: &mut State) -> EvalResult {
+        state.push_fn(FN_RETRY);
+        apply(state, FN_ATOMICALLY, 1);
+        drop_tvar(state)
+    }
+
+    /// `\t -> atomically (writeTVar t 1 >> ((writeTVar t 2 >> retry) `orElse` return ())) >> readTVarIO t`
+    fn or_else_body(state: &mut State) -> EvalResult {
+        let t = *state.stack_peek();
+        state.push(t);
+        apply(state, FN_READ_TVAR_IO, 1);
+        state.push_unit();
+        apply(state, FN_RETURN_IO, 1);
+        state.push_fn(FN_RETRY);
+        then(state, |state| write(state, t, 2));
+        apply(state, FN_OR_ELSE, 2);
+        then(state, |state| write(state, t, 1));
+        apply(state, FN_ATOMICALLY, 1);
+        apply(state, FN_THEN_IO, 2);
+        drop_tvar(state)
+    }
+
+    #[test]
+    fn atomically_commits_writes() {
+        assert!(matches!(run_with_tvar(lambda("commit", commit_body)), Ok(Node::Int(2))));
+    }
+
+    #[test]
+    fn atomically_rolls_back_when_thrown_through() {
+        assert!(matches!(run_with_tvar(lambda("throw", throw_body)), Ok(Node::Int(0))));
+    }
+
+    #[test]
+    fn retry_blocks_until_a_write() {
+        assert!(matches!(run_with_tvar(lambda("retry", retry_body)), Ok(Node::Int(5))));
+        // run once on the value it started with, and again once woken
+        assert_eq!(CHECKS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn retry_nobody_wakes_is_deadlock() {
+        let result = run_with_tvar(lambda("deadlock", deadlock_body));
+        assert!(matches!(result.as_ref().map_err(EvalError::cause), Err(EvalError::BlockedIndefinitelyOnStm)));
+    }
+
+    #[test]
+    fn or_else_rolls_back_only_the_branch_that_retried() {
+        assert!(matches!(run_with_tvar(lambda("orElse", or_else_body)), Ok(Node::Int(1))));
+    }
+}
@@ -0,0 +1,45 @@
+use crate::builtins::*;
+use crate::read::read;
+
+// Helpers for the tests: values are written as `show` writes them, read
+// with `read`, and results given as `State::render` renders them.
+
+pub fn push_value(state: &mut State, s: &str) {
+    state.push(read(s).expect("a value"));
+}
+
+/// Applies `fn_def` to the values given, the first argument first, and
+/// renders the result.
+pub fn call(state: &mut State, fn_def: FnDef, args: &[&str]) -> EvalResult<String> {
+    for arg in args.iter().rev() {
+        push_value(state, arg);
+    }
+    state.push_fn(fn_def);
+    state.apply(args.len())?;
+    let result = state.stack_pop();
+    state.render(result)
+}
+
+/// Performs the IO action `fn_def` makes of the values given and renders
+/// its result.
+#[cfg(feature = "std")]
+pub fn run_action(state: &mut State, fn_def: FnDef, args: &[&str]) -> EvalResult<String> {
+    for arg in args.iter().rev() {
+        push_value(state, arg);
+    }
+    state.push_fn(fn_def);
+    for _ in args {
+        state.mk_ap();
+    }
+    let main = state.stack_pop();
+    let result = state.run_io(main)?;
+    state.render(result)
+}
+
+/// A path in the temporary directory for a test's file, distinct for
+/// every test process.
+#[cfg(feature = "std")]
+pub fn temp_path(name: &str) -> String {
+    let file = format!("haskelite-{}-{}", std::process::id(), name);
+    std::env::temp_dir().join(file).to_string_lossy().into_owned()
+}
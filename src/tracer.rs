@@ -0,0 +1,64 @@
+use crate::builtins::*;
+
+/// Observes reduction as it happens, e.g. to step through a program.
+/// Installed with `State::set_tracer`; every callback does nothing unless
+/// overridden.
+pub trait Tracer {
+    /// A thunk is about to be forced, reducing `expr`.
+    fn on_thunk_enter(&mut self, _thunk: &Thunk, _expr: &Node) {}
+
+    /// A thunk has been overwritten with its value.
+    fn on_thunk_update(&mut self, _thunk: &Thunk) {}
+
+    /// A function, builtin or compiled, is entered with `args` in
+    /// application order.
+    fn on_builtin_call(&mut self, _fn_def: &FnDef, _args: &[Node]) {}
+
+    /// A garbage collection is about to start.
+    fn on_gc_start(&mut self) {}
+
+    /// A garbage collection has finished, leaving `live` objects on the heap.
+    fn on_gc_end(&mut self, _live: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Tracer for Recorder {
+        fn on_thunk_enter(&mut self, thunk: &Thunk, _expr: &Node) {
+            self.0.borrow_mut().push(format!("enter {}", thunk.label.unwrap_or("?")));
+        }
+
+        fn on_thunk_update(&mut self, thunk: &Thunk) {
+            self.0.borrow_mut().push(format!("update {}", thunk.label.unwrap_or("?")));
+        }
+
+        fn on_builtin_call(&mut self, fn_def: &FnDef, args: &[Node]) {
+            let args: Vec<String> = args.iter().map(|arg| format!("{}", arg)).collect();
+            self.0.borrow_mut().push(format!("call {} {}", fn_def.name, args.join(" ")));
+        }
+    }
+
+    #[test]
+    fn reductions_are_reported_in_order() {
+        let mut state = State::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        state.set_tracer(Box::new(Recorder(events.clone())));
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        state.label_thunk("sum");
+        let node = *state.stack_peek();
+        state.try_eval(node).expect("a sum");
+        assert_eq!(*events.borrow(), ["enter sum", "call add 1 2", "update sum"]);
+        assert!(state.take_tracer().is_some());
+    }
+}
@@ -0,0 +1,72 @@
+use std::io;
+use wasm_bindgen::prelude::*;
+
+// With the `wasm` feature the runtime is built for `wasm32-unknown-unknown`,
+// where the standard library has no streams, clock or process to offer:
+// writes to stdout go nowhere and asking the time panics. What the program
+// does with the outside world is handed through `wasm-bindgen` to the
+// functions of a `haskelite` object the page defines globally instead:
+//
+//     writeStdout(bytes), writeStderr(bytes)
+//     readStdin(max) -> at most `max` bytes, none at the end of the input
+//     now() -> milliseconds since the Unix epoch, as `Date.now()`
+//     monotonicNow() -> milliseconds from an arbitrary start, as `performance.now()`
+//     exit(code), not returning, e.g. by throwing
+//
+// The bytes are `Uint8Array`s of UTF-8. Files, the environment and
+// `State::eval_with_timeout` are not available: opening a file or setting
+// a variable fails with an `IOException`. The program's `main` is the
+// module's start function once the bindings are generated:
+//
+//     cargo build --release --target wasm32-unknown-unknown --features wasm
+//     wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/runtime.wasm
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = haskelite, js_name = writeStdout)]
+    fn js_write_stdout(bytes: &[u8]);
+    #[wasm_bindgen(js_namespace = haskelite, js_name = writeStderr)]
+    fn js_write_stderr(bytes: &[u8]);
+    #[wasm_bindgen(js_namespace = haskelite, js_name = readStdin)]
+    fn js_read_stdin(max: usize) -> Vec<u8>;
+    #[wasm_bindgen(js_namespace = haskelite, js_name = now)]
+    fn js_now() -> f64;
+    #[wasm_bindgen(js_namespace = haskelite, js_name = monotonicNow)]
+    fn js_monotonic_now() -> f64;
+    #[wasm_bindgen(js_namespace = haskelite, js_name = exit)]
+    fn js_exit(code: i32);
+}
+
+pub fn write_stdout(bytes: &[u8]) -> io::Result<()> {
+    js_write_stdout(bytes);
+    Ok(())
+}
+
+pub fn write_stderr(bytes: &[u8]) -> io::Result<()> {
+    js_write_stderr(bytes);
+    Ok(())
+}
+
+pub fn read_stdin(buf: &mut [u8]) -> io::Result<usize> {
+    let bytes = js_read_stdin(buf.len());
+    if bytes.len() > buf.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "readStdin gave more than asked for"));
+    }
+    buf[..bytes.len()].copy_from_slice(&bytes);
+    Ok(bytes.len())
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> f64 {
+    js_now() / 1000.0
+}
+
+/// Seconds from an arbitrary start, never going backwards.
+pub fn monotonic_now() -> f64 {
+    js_monotonic_now() / 1000.0
+}
+
+pub fn exit(code: i32) -> ! {
+    js_exit(code);
+    unreachable!("the page's exit returned")
+}
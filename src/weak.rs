@@ -0,0 +1,463 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::builtins::*;
+use crate::gc::*;
+
+// A weak pointer `mkWeak k v f` keeps `v`, and the finalizer `f`, alive
+// only for as long as the key `k` is, as in GHC: neither counts as a
+// reference to the key, so a value or finalizer mentioning its own key
+// does not keep it from being collected. Keys are the heap objects nodes
+// refer to, thunks, `IORef`s and so on; a key that is not on the heap,
+// e.g. an `Int`, never dies.
+//
+// The state keeps every weak pointer whose key is alive, reachable or
+// not, and after marking everything else marks the values and finalizers
+// of those whose keys were, until that marks no more keys. The rest have
+// their values dropped and their finalizers queued: IO actions to be
+// forked by the IO driver at the end of a time slice, or run by the host
+// with `State::run_finalizers`, and Rust closures called straight after
+// the collection. As in GHC, finalizer threads still running when the main
+// thread is done are dropped with the rest.
+
+/// A finalizer attached from Rust with `State::add_finalizer`.
+pub type NativeFinalizer = Box<dyn FnOnce()>;
+
+enum Finalizer {
+    Io(Node),
+    Native(NativeFinalizer)
+}
+
+pub struct WeakRef {
+    /// What the key refers to, `None` for a key that always lives.
+    key: Option<Gc<dyn Trace>>,
+    /// `None` once the key has died or the weak pointer was finalized.
+    value: Option<Node>,
+    finalizer: Option<Finalizer>
+}
+
+impl WeakRef {
+    /// Whether the key has been marked by the collection in progress.
+    fn key_marked(&self) -> bool {
+        self.key.is_none_or(|key| key.is_marked())
+    }
+
+    /// Whether the key might still be alive, i.e. the weak pointer is
+    /// neither dead nor finalized.
+    fn is_live(&self) -> bool {
+        self.value.is_some() || self.finalizer.is_some()
+    }
+}
+
+impl Trace for WeakRef {
+    /// Marks nothing, what the weak pointer holds is marked by
+    /// `WeakTable::mark` if its key is alive.
+    fn mark_refs(&self, _worklist: &mut Worklist) {}
+
+    fn census_key(&self) -> (&'static str, Option<&'static str>) {
+        ("Weak", None)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct WeakTable {
+    live: Vec<Gc<WeakRef>>,
+    /// The IO finalizers of the keys that have died, waiting to be run.
+    pending: Vec<Node>,
+    /// The Rust finalizers of the keys that died in the collection in
+    /// progress, to call once it is over.
+    native: Vec<NativeFinalizer>
+}
+
+impl WeakTable {
+    /// Marks what the weak pointers hold for the keys found alive by the
+    /// collection in progress, everything else already marked from the
+    /// roots, and queues the finalizers of the rest.
+    pub(crate) fn mark(&mut self, worklist: &mut Worklist) {
+        for action in self.pending.iter() {
+            action.mark_refs(worklist);
+        }
+        self.live.retain(|weak| weak.is_live());
+        for weak in self.live.iter() {
+            weak.mark_into(worklist);
+        }
+        GcAlloc::mark(worklist);
+
+        // marking a value can mark the key of another
+        loop {
+            for weak in self.live.iter().filter(|weak| weak.key_marked()) {
+                if let Some(value) = &weak.value {
+                    value.mark_refs(worklist);
+                }
+                if let Some(Finalizer::Io(action)) = &weak.finalizer {
+                    action.mark_refs(worklist);
+                }
+            }
+            if worklist.is_empty() {
+                break;
+            }
+            GcAlloc::mark(worklist);
+        }
+
+        let (live, dead) = self.live.drain(..).partition(|weak| weak.key_marked());
+        self.live = live;
+        for mut weak in dead {
+            let weak = weak.as_mut();
+            weak.value = None;
+            match weak.finalizer.take() {
+                Some(Finalizer::Io(action)) => {
+                    action.mark_refs(worklist);
+                    self.pending.push(action);
+                },
+                Some(Finalizer::Native(finalizer)) => self.native.push(finalizer),
+                None => {}
+            }
+        }
+        GcAlloc::mark(worklist);
+    }
+
+    /// Calls the Rust finalizers queued by the collection that just ended.
+    pub(crate) fn run_native(&mut self) {
+        for finalizer in self.native.drain(..) {
+            finalizer();
+        }
+    }
+}
+
+/// What a weak pointer keyed on `node` is keyed on.
+fn heap_object(node: &Node) -> Option<Gc<dyn Trace>> {
+    let object = match *node {
+        Node::Int(_) | Node::Double(_) | Node::Bool(_) | Node::Char(_) | Node::FnDef(_) | Node::Nil => return None,
+        Node::Integer(integer) => integer.erase(),
+        Node::Ratio(ratio) => ratio.erase(),
+        Node::Pap(_, fields) | Node::Data { fields, .. } | Node::Tuple(fields) => fields.erase(),
+        Node::App(node, _) | Node::Cons(node, _) | Node::NodeRef(node) | Node::IORef(node) => node.erase(),
+        Node::Text(text) => text.erase(),
+        Node::ThunkRef(thunk) => thunk.erase(),
+        Node::IO(action) => action.erase(),
+        #[cfg(feature = "std")]
+        Node::Handle(handle) => handle.erase(),
+        Node::IOArray(array) | Node::Array(array) => array.erase(),
+        Node::Map(tree) | Node::Set(tree) => tree.erase(),
+        Node::MVar(mvar) => mvar.erase(),
+        Node::TVar(tvar) => tvar.erase(),
+        Node::Weak(weak) => weak.erase(),
+        Node::StableName(name) => name.erase()
+    };
+    Some(object)
+}
+
+impl State {
+    /// Makes a weak pointer from the key and value on top of the stack, and
+    /// the IO finalizer below them if `io_finalizer`, popping them.
+    fn mk_weak(&mut self, io_finalizer: bool, native: Option<NativeFinalizer>) {
+        // what it is to hold stays on the stack until it holds it, it marks nothing itself
+        let mut weak = self.alloc(WeakRef { key: None, value: None, finalizer: None });
+        let key = heap_object(&self.stack_pop());
+        let value = self.stack_pop();
+        let finalizer = if io_finalizer {
+            Some(Finalizer::Io(self.stack_pop()))
+        } else {
+            native.map(Finalizer::Native)
+        };
+        *weak.as_mut() = WeakRef { key, value: Some(value), finalizer };
+        self.weaks.live.push(weak);
+        self.stack_push(Node::Weak(weak));
+    }
+
+    /// Arranges for `finalizer` to be called once the heap object `key`
+    /// refers to has been collected.
+    pub fn add_finalizer(&mut self, key: Node, finalizer: NativeFinalizer) {
+        // the key is its own value, keeping nothing else alive
+        self.stack_push(key);
+        self.stack_push(key);
+        self.mk_weak(false, Some(finalizer));
+        self.stack_pop();
+    }
+
+    /// Performs the IO finalizers of the keys that have died so far,
+    /// stopping at the first to fail. The IO driver forks them itself at the
+    /// end of each time slice.
+    pub fn run_finalizers(&mut self) -> EvalResult {
+        while let Some(action) = self.weaks.pending.pop() {
+            self.run_io(action)?;
+        }
+        Ok(())
+    }
+
+    /// Hands the IO finalizers waiting to be run to the scheduler, each to
+    /// be run as a thread of its own.
+    pub(crate) fn fork_finalizers(&mut self) {
+        while let Some(action) = self.weaks.pending.pop() {
+            self.stack_push(action);
+            self.fork_thread();
+        }
+    }
+
+    fn peek_weak(&mut self, context: &'static str) -> EvalResult<Gc<WeakRef>> {
+        self.eval()?;
+        match *self.stack_peek() {
+            Node::Weak(weak) => Ok(weak),
+            node => Err(EvalError::type_mismatch(context, "Weak", &node))
+        }
+    }
+}
+
+pub static FN_MK_WEAK: FnDef = FnDef {
+    name: "mkWeak",
+    arity: 3,
+    strict: 0,
+    fn_ref: eval_mk_weak
+};
+
+pub static FN_MK_WEAK_PTR: FnDef = FnDef {
+    name: "mkWeakPtr",
+    arity: 2,
+    strict: 0,
+    fn_ref: eval_mk_weak_ptr
+};
+
+pub static FN_DE_REF_WEAK: FnDef = FnDef {
+    name: "deRefWeak",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_de_ref_weak
+};
+
+pub static FN_FINALIZE: FnDef = FnDef {
+    name: "finalize",
+    arity: 1,
+    strict: 0,
+    fn_ref: eval_finalize
+};
+
+static IO_MK_WEAK: FnDef = FnDef {
+    name: "mkWeak",
+    arity: 3,
+    strict: 0,
+    fn_ref: perform_mk_weak
+};
+
+static IO_MK_WEAK_PTR: FnDef = FnDef {
+    name: "mkWeakPtr",
+    arity: 2,
+    strict: 0,
+    fn_ref: perform_mk_weak_ptr
+};
+
+static IO_DE_REF_WEAK: FnDef = FnDef {
+    name: "deRefWeak",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_de_ref_weak
+};
+
+static IO_FINALIZE: FnDef = FnDef {
+    name: "finalize",
+    arity: 1,
+    strict: 0,
+    fn_ref: perform_finalize
+};
+
+pub fn eval_mk_weak(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MK_WEAK);
+    Ok(())
+}
+
+pub fn eval_mk_weak_ptr(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_MK_WEAK_PTR);
+    Ok(())
+}
+
+pub fn eval_de_ref_weak(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_DE_REF_WEAK);
+    Ok(())
+}
+
+pub fn eval_finalize(state: &mut State) -> EvalResult {
+    state.mk_io_prim(IO_FINALIZE);
+    Ok(())
+}
+
+/// Forces the `Maybe (IO ())` finalizer below the key and value on top
+/// of the stack, leaving the action in its place if there is one, and
+/// makes the weak pointer.
+fn mk_weak_with(state: &mut State) -> EvalResult {
+    state.stack_bring_up(2);
+    let io_finalizer = state.pop_maybe()?;
+    if io_finalizer {
+        state.stack_bring_up(2);
+        state.stack_bring_up(2);
+    }
+    state.mk_weak(io_finalizer, None);
+    Ok(())
+}
+
+/// The key and value are kept as they are, unevaluated.
+fn perform_mk_weak(state: &mut State) -> EvalResult {
+    mk_weak_with(state)
+}
+
+/// `mkWeak` with the key as the value.
+fn perform_mk_weak_ptr(state: &mut State) -> EvalResult {
+    let key = *state.stack_peek();
+    state.stack_push(key);
+    mk_weak_with(state)
+}
+
+fn perform_de_ref_weak(state: &mut State) -> EvalResult {
+    let weak = state.peek_weak("deRefWeak")?;
+    state.stack_pop();
+    match weak.value {
+        Some(value) => {
+            state.stack_push(value);
+            state.mk_just();
+        },
+        None => state.push_nothing()
+    }
+    Ok(())
+}
+
+/// Drops the value and runs the finalizer straight away, if the key has
+/// not died already.
+fn perform_finalize(state: &mut State) -> EvalResult {
+    let mut weak = state.peek_weak("finalize")?;
+    state.stack_pop();
+    let weak = weak.as_mut();
+    weak.value = None;
+    match weak.finalizer.take() {
+        Some(Finalizer::Io(action)) => {
+            state.run_io(action)?;
+        },
+        Some(Finalizer::Native(finalizer)) => finalizer(),
+        None => {}
+    }
+    state.push_unit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use crate::ioref::*;
+    use crate::maybe::{TAG_JUST, TAG_NOTHING};
+    use super::*;
+
+    /// Performs the action on top of the stack, popping it.
+    fn perform(state: &mut State) -> EvalResult<Node> {
+        let action = state.stack_pop();
+        state.run_io(action)
+    }
+
+    fn push_new_ioref(state: &mut State, value: i64) {
+        state.push_int(value);
+        state.push_fn(FN_NEW_IOREF);
+        state.mk_ap();
+        let ioref = perform(state).expect("an IORef");
+        state.push(ioref);
+    }
+
+    /// Applies `fn_def` to the node `n` below the top of the stack, then
+    /// to what is on top if `fn_def` takes a second argument.
+    fn push_action(state: &mut State, fn_def: FnDef, n: usize) {
+        state.push(*state.stack_peek_nth(n));
+        state.push_fn(fn_def);
+        state.mk_ap();
+        if fn_def.arity == 2 {
+            state.mk_ap();
+        }
+    }
+
+    fn read_ioref(state: &mut State, n: usize) -> Node {
+        push_action(state, FN_READ_IOREF, n);
+        let value = perform(state).expect("the value");
+        state.try_eval(value).expect("the value")
+    }
+
+    fn de_ref_weak(state: &mut State, n: usize) -> Node {
+        push_action(state, FN_DE_REF_WEAK, n);
+        perform(state).expect("a Maybe")
+    }
+
+    /// Pushes `mkWeak key key (Just (modifyIORef' flag (+1)))`, performed,
+    /// for the key on top of the stack and the flag below it.
+    fn push_counting_weak(state: &mut State) {
+        state.push_int(1);
+        state.mk_pap(FN_ADD, 1);
+        push_action(state, FN_MODIFY_IOREF_STRICT, 2);
+        state.mk_just();
+        state.push(*state.stack_peek_nth(1));
+        state.push(*state.stack_peek_nth(2));
+        state.push_fn(FN_MK_WEAK);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_ap();
+        let weak = perform(state).expect("a weak pointer");
+        state.push(weak);
+    }
+
+    #[test]
+    fn native_finalizers_run_once_the_key_is_collected() {
+        let mut state = State::new();
+        let finalized = Rc::new(Cell::new(0));
+        state.push_int(2);
+        state.push_int(1);
+        state.push_fn(FN_ADD);
+        state.mk_ap();
+        state.mk_ap();
+        state.mk_thunk();
+        let seen = finalized.clone();
+        state.add_finalizer(*state.stack_peek(), Box::new(move || seen.set(seen.get() + 1)));
+        let seen = finalized.clone();
+        state.add_finalizer(Node::Int(1), Box::new(move || seen.set(seen.get() + 10)));
+
+        state.collect(Vec::new());
+        assert_eq!(finalized.get(), 0);
+        state.stack_pop();
+        state.collect(Vec::new());
+        assert_eq!(finalized.get(), 1);
+        state.collect(Vec::new());
+        assert_eq!(finalized.get(), 1);
+    }
+
+    #[test]
+    fn values_live_only_as_long_as_their_keys() {
+        let mut state = State::new();
+        push_new_ioref(&mut state, 0);
+        push_new_ioref(&mut state, 0);
+        push_counting_weak(&mut state);
+        // weak, key, flag
+        state.collect(Vec::new());
+        state.run_finalizers().expect("no finalizers");
+        assert!(matches!(de_ref_weak(&mut state, 0), Node::Data { tag: TAG_JUST, .. }));
+        assert!(matches!(read_ioref(&mut state, 2), Node::Int(0)));
+
+        // the value is the key itself, which doesn't keep it alive
+        state.stack_swap();
+        state.stack_pop();
+        state.collect(Vec::new());
+        assert!(matches!(de_ref_weak(&mut state, 0), Node::Data { tag: TAG_NOTHING, .. }));
+        state.run_finalizers().expect("the finalizer");
+        assert!(matches!(read_ioref(&mut state, 1), Node::Int(1)));
+    }
+
+    #[test]
+    fn finalize_runs_the_finalizer_once() {
+        let mut state = State::new();
+        push_new_ioref(&mut state, 0);
+        push_new_ioref(&mut state, 0);
+        push_counting_weak(&mut state);
+        push_action(&mut state, FN_FINALIZE, 0);
+        perform(&mut state).expect("finalized");
+        push_action(&mut state, FN_FINALIZE, 0);
+        perform(&mut state).expect("already finalized");
+        assert!(matches!(de_ref_weak(&mut state, 0), Node::Data { tag: TAG_NOTHING, .. }));
+        assert!(matches!(read_ioref(&mut state, 2), Node::Int(1)));
+
+        state.stack_swap();
+        state.stack_pop();
+        state.collect(Vec::new());
+        state.run_finalizers().expect("no finalizers");
+        assert!(matches!(read_ioref(&mut state, 1), Node::Int(1)));
+    }
+}